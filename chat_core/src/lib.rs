@@ -17,9 +17,34 @@ pub struct User {
     pub ws_name: String,
     pub fullname: String,
     pub email: String,
+    /// url of the avatar image in the chat file store
+    #[sqlx(default)]
+    #[serde(default)]
+    pub avatar: Option<String>,
+    #[sqlx(default)]
+    #[serde(default)]
+    pub status_message: Option<String>,
+    /// IANA time zone name, e.g. `Europe/Berlin`
+    #[sqlx(default)]
+    #[serde(default)]
+    pub time_zone: Option<String>,
     #[sqlx(default)]
     #[serde(skip)]
     pub password_hash: Option<String>,
+    /// set in tokens of users who must enroll in two-factor authentication first, these tokens
+    /// only work for the enrollment endpoints
+    #[sqlx(default)]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub enroll_two_factor: bool,
+    /// bots can not sign in, they use access tokens
+    #[sqlx(default)]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_bot: bool,
+    /// set if the request was authenticated with an access token, it may only do what the
+    /// scopes allow
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<TokenScope>>,
     pub created_at: DateTime<Utc>,
 }
 
@@ -36,6 +61,30 @@ pub struct ChatUser {
     pub id: i64,
     pub fullname: String,
     pub email: String,
+    #[sqlx(default)]
+    #[serde(default)]
+    pub avatar: Option<String>,
+    #[sqlx(default)]
+    #[serde(default)]
+    pub status_message: Option<String>,
+    #[sqlx(default)]
+    #[serde(default)]
+    pub time_zone: Option<String>,
+    #[sqlx(default)]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_bot: bool,
+}
+
+/// What an access token may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "token_scope", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum TokenScope {
+    /// list chats, users and messages, download files
+    ReadChats,
+    /// send messages to chats the token user is a member of
+    PostMessages,
+    UploadFiles,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, PartialOrd, sqlx::Type, ToSchema)]
@@ -59,12 +108,45 @@ pub struct Chat {
     #[serde(alias = "wsId", alias = "ws_id")]
     pub ws_id: i64,
     pub name: Option<String>,
+    #[serde(default)]
+    pub topic: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// url of the avatar image in the chat file store
+    #[serde(default)]
+    pub avatar: Option<String>,
     pub r#type: ChatType,
     pub members: Vec<i64>,
+    /// archived chats are read-only and hidden from the chat list by default
+    #[serde(default, alias = "archivedAt", alias = "archived_at")]
+    pub archived_at: Option<DateTime<Utc>>,
     #[serde(alias = "createdAt", alias = "created_at")]
     pub created_at: DateTime<Utc>,
 }
 
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "message_format", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum MessageFormat {
+    #[default]
+    Plain,
+    Markdown,
+}
+
+/// Metadata of a file attached to a message
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Attachment {
+    pub url: String,
+    /// original file name given by the uploader
+    pub filename: String,
+    pub mime: String,
+    pub size: u64,
+    /// pixel dimensions, only for images
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone, PartialEq, ToSchema)]
 #[serde(rename_all(serialize = "camelCase"))]
 pub struct Message {
@@ -73,10 +155,34 @@ pub struct Message {
     pub chat_id: i64,
     #[serde(alias = "senderId")]
     pub sender_id: i64,
+    /// shown instead of the name of the sender, set by incoming webhooks
+    #[sqlx(default)]
+    #[serde(default, alias = "senderName", skip_serializing_if = "Option::is_none")]
+    pub sender_name: Option<String>,
+    /// set on messages asking a poll, its options and tally are at the polls endpoint
+    #[sqlx(default)]
+    #[serde(default, alias = "pollId", skip_serializing_if = "Option::is_none")]
+    pub poll_id: Option<i64>,
     pub content: String,
     pub files: Vec<String>,
+    #[serde(default)]
+    pub format: MessageFormat,
+    /// sanitized html rendered from content, only for markdown messages
+    #[serde(default)]
+    pub html: Option<String>,
+    #[sqlx(json)]
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    /// ephemeral messages are deleted after this time
+    #[serde(default, alias = "expiresAt")]
+    pub expires_at: Option<DateTime<Utc>>,
     #[serde(alias = "createdAt")]
     pub created_at: DateTime<Utc>,
+    /// set on replies to slash commands that only this user sees, they are not stored and have
+    /// no id
+    #[sqlx(skip)]
+    #[serde(default, alias = "visibleTo", skip_serializing_if = "Option::is_none")]
+    pub visible_to: Option<i64>,
 }
 
 impl User {
@@ -87,7 +193,13 @@ impl User {
             ws_name: "".to_string(),
             fullname: fullname.to_string(),
             email: email.to_string(),
+            avatar: None,
+            status_message: None,
+            time_zone: None,
             password_hash: None,
+            enroll_two_factor: false,
+            is_bot: false,
+            scopes: None,
             created_at: chrono::Utc::now(),
         }
     }
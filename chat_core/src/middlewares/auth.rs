@@ -11,7 +11,7 @@ use axum_extra::{
 use serde::Deserialize;
 use tracing::warn;
 
-use crate::TokenVerify;
+use crate::{TokenVerify, TwoFactorPolicy, User};
 
 #[derive(Debug, Deserialize)]
 struct Params {
@@ -43,7 +43,7 @@ where
                 }
             }
         };
-    let req = match state.verify(&token) {
+    let req = match state.verify(&token).await {
         Ok(user) => {
             let mut req = Request::from_parts(parts, body);
             req.extensions_mut().insert(user);
@@ -59,6 +59,37 @@ where
     next.run(req).await
 }
 
+/// Reject users who have to enroll in two-factor authentication first, must run after
+/// `verify_token`. The `enroll_two_factor` claim of the token is only a hint for clients.
+pub async fn require_two_factor_enrolled<T>(
+    State(state): State<T>,
+    req: Request,
+    next: Next,
+) -> Response
+where
+    T: Clone + Send + Sync + 'static + TwoFactorPolicy,
+{
+    let pending = match req.extensions().get::<User>() {
+        Some(user) => match state.two_factor_pending(user).await {
+            Ok(pending) => pending,
+            Err(e) => {
+                let msg = format!("failed to check two-factor enrollment: {:?}", e);
+                warn!(msg);
+                return (StatusCode::INTERNAL_SERVER_ERROR, msg).into_response();
+            }
+        },
+        None => true,
+    };
+    if pending {
+        return (
+            StatusCode::FORBIDDEN,
+            "two-factor authentication enrollment is required",
+        )
+            .into_response();
+    }
+    next.run(req).await
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
@@ -68,7 +99,7 @@ mod tests {
     };
     use tower::ServiceExt as _;
 
-    use crate::{DecodingKey, EncodingKey, User};
+    use crate::{DecodingKey, EncodingKey};
 
     use super::*;
 
@@ -77,13 +108,21 @@ mod tests {
     struct AppStateInner {
         ek: EncodingKey,
         dk: DecodingKey,
+        /// users who have to enroll in 2FA
+        pending: Vec<i64>,
     }
     impl TokenVerify for AppState {
         type Error = ();
-        fn verify(&self, token: &str) -> Result<User, Self::Error> {
+        async fn verify(&self, token: &str) -> Result<User, Self::Error> {
             self.0.dk.verify(token).map_err(|_| ())
         }
     }
+    impl TwoFactorPolicy for AppState {
+        type Error = ();
+        async fn two_factor_pending(&self, user: &User) -> Result<bool, Self::Error> {
+            Ok(self.0.pending.contains(&user.id))
+        }
+    }
 
     async fn handler(_req: Request) -> impl IntoResponse {
         (StatusCode::OK, "ok")
@@ -95,7 +134,11 @@ mod tests {
         let decoding_pem = include_str!("../../fixtures/decoding.pem");
         let ek = EncodingKey::load(encoding_pem)?;
         let dk = DecodingKey::load(decoding_pem)?;
-        let state = AppState(Arc::new(AppStateInner { ek, dk }));
+        let state = AppState(Arc::new(AppStateInner {
+            ek,
+            dk,
+            pending: vec![],
+        }));
         let user = User::new(1, "zzq", "zzq@gmail.com");
         let token = state.0.ek.sign(user)?;
         let app = Router::new()
@@ -134,4 +177,48 @@ mod tests {
         assert_eq!(res.status(), StatusCode::FORBIDDEN);
         Ok(())
     }
+    #[tokio::test]
+    async fn require_two_factor_enrolled_should_work() -> anyhow::Result<()> {
+        let encoding_pem = include_str!("../../fixtures/encoding.pem");
+        let decoding_pem = include_str!("../../fixtures/decoding.pem");
+        let ek = EncodingKey::load(encoding_pem)?;
+        let dk = DecodingKey::load(decoding_pem)?;
+        let state = AppState(Arc::new(AppStateInner {
+            ek,
+            dk,
+            pending: vec![1],
+        }));
+        let app = Router::new()
+            .route("/", get(handler))
+            .layer(from_fn_with_state(
+                state.clone(),
+                require_two_factor_enrolled::<AppState>,
+            ))
+            .route("/enroll", get(handler))
+            .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
+            .with_state(state.clone());
+
+        // the policy decides, not the claim of the token
+        let token = state.0.ek.sign(User::new(1, "zzq", "zzq@gmail.com"))?;
+        let req = Request::builder()
+            .uri(format!("/?token={}", token))
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let req = Request::builder()
+            .uri(format!("/enroll?token={}", token))
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut user = User::new(2, "zzq", "zzq@gmail.com");
+        user.enroll_two_factor = true;
+        let token = state.0.ek.sign(user)?;
+        let req = Request::builder()
+            .uri(format!("/?token={}", token))
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        Ok(())
+    }
 }
@@ -1,8 +1,9 @@
 mod auth;
+mod rate_limit;
 mod request_id;
 mod server_time;
 
-use std::fmt;
+use std::{fmt, future::Future};
 
 use crate::User;
 
@@ -17,11 +18,25 @@ use tower_http::{
 };
 use tracing::Level;
 
-pub use auth::verify_token;
+pub use auth::{require_two_factor_enrolled, verify_token};
+pub use rate_limit::{
+    MemoryBuckets, RateLimitLayer, RateLimitMiddleware, RateLimitQuota, RateLimitState,
+    RateLimitStore,
+};
 
 pub trait TokenVerify {
     type Error: fmt::Debug;
-    fn verify(&self, token: &str) -> Result<User, Self::Error>;
+    fn verify(&self, token: &str) -> impl Future<Output = Result<User, Self::Error>> + Send;
+}
+
+/// Whether a user still has to enroll in two-factor authentication, looked up on every request
+/// so a changed workspace policy or enrollment applies to tokens issued before
+pub trait TwoFactorPolicy {
+    type Error: fmt::Debug;
+    fn two_factor_pending(
+        &self,
+        user: &User,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send;
 }
 
 const REQUEST_ID_HEADER: &str = "x-request-id";
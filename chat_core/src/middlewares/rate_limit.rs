@@ -0,0 +1,352 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse as _, Response},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tower::{Layer, Service};
+use tracing::warn;
+
+use crate::User;
+
+/// the memory store drops buckets that refilled once it holds this many
+const MAX_MEMORY_BUCKETS: usize = 10_000;
+
+/// `requests` within `per_secs`, both above 0, as a token bucket that holds `requests` tokens and
+/// refills them evenly over `per_secs`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitQuota {
+    pub requests: u32,
+    pub per_secs: u64,
+}
+
+/// The bucket of a client after a request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitState {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// seconds until the bucket is full again
+    pub reset_secs: u64,
+    /// seconds until the next request is allowed, 0 if it was allowed
+    pub retry_after_secs: u64,
+}
+
+/// Where buckets are kept: in memory for a single instance, or in Postgres so all instances of
+/// a deployment share them
+#[derive(Clone)]
+pub enum RateLimitStore {
+    Memory(MemoryBuckets),
+    Postgres(PgPool),
+}
+
+#[derive(Clone, Default)]
+pub struct MemoryBuckets(Arc<Mutex<HashMap<String, Bucket>>>);
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+    full_at: Instant,
+}
+
+/// Limits requests per authenticated user, or per client address for anonymous requests.
+/// `scope` keeps the buckets of different routes apart, without a quota requests just pass.
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    scope: Arc<str>,
+    quota: Option<RateLimitQuota>,
+    store: RateLimitStore,
+}
+
+#[derive(Clone)]
+pub struct RateLimitMiddleware<S> {
+    inner: S,
+    layer: RateLimitLayer,
+}
+
+impl RateLimitQuota {
+    fn refill_per_sec(&self) -> f64 {
+        self.requests as f64 / self.per_secs.max(1) as f64
+    }
+
+    fn state(&self, tokens: f64, allowed: bool) -> RateLimitState {
+        let per_token = self.per_secs as f64 / self.requests.max(1) as f64;
+        let secs = |tokens: f64| (tokens.max(0.0) * per_token).ceil() as u64;
+        RateLimitState {
+            allowed,
+            limit: self.requests,
+            remaining: tokens.max(0.0).floor() as u32,
+            reset_secs: secs(self.requests as f64 - tokens),
+            retry_after_secs: if allowed {
+                0
+            } else {
+                secs(1.0 - tokens).max(1)
+            },
+        }
+    }
+}
+
+impl RateLimitStore {
+    pub fn memory() -> Self {
+        Self::Memory(MemoryBuckets::default())
+    }
+
+    pub fn postgres(pool: PgPool) -> Self {
+        Self::Postgres(pool)
+    }
+
+    /// Take a token from the bucket of `key` if there is one
+    pub async fn check(
+        &self,
+        key: &str,
+        quota: RateLimitQuota,
+    ) -> Result<RateLimitState, sqlx::Error> {
+        match self {
+            Self::Memory(buckets) => Ok(buckets.check(key, quota)),
+            Self::Postgres(pool) => {
+                // the refill and the take happen in one statement, so concurrent requests from
+                // other instances can not both take the last token
+                let (tokens, allowed): (f64, bool) = sqlx::query_as(
+                    r#"
+                INSERT INTO rate_limit_buckets AS b (key, tokens, allowed, updated_at)
+                VALUES ($1, $2 - 1, $2 >= 1, now())
+                ON CONFLICT (key) DO UPDATE SET
+                    tokens = LEAST($2, b.tokens + EXTRACT(EPOCH FROM now() - b.updated_at)::float8 * $3)
+                        - CASE WHEN LEAST($2, b.tokens + EXTRACT(EPOCH FROM now() - b.updated_at)::float8 * $3) >= 1
+                          THEN 1 ELSE 0 END,
+                    allowed = LEAST($2, b.tokens + EXTRACT(EPOCH FROM now() - b.updated_at)::float8 * $3) >= 1,
+                    updated_at = now()
+                RETURNING tokens, allowed
+                "#,
+                )
+                .bind(key)
+                .bind(quota.requests as f64)
+                .bind(quota.refill_per_sec())
+                .fetch_one(pool)
+                .await?;
+                Ok(quota.state(tokens, allowed))
+            }
+        }
+    }
+}
+
+impl MemoryBuckets {
+    fn check(&self, key: &str, quota: RateLimitQuota) -> RateLimitState {
+        let now = Instant::now();
+        let limit = quota.requests as f64;
+        let rate = quota.refill_per_sec();
+        let mut buckets = self.0.lock().unwrap();
+        if buckets.len() >= MAX_MEMORY_BUCKETS {
+            // a full bucket is the same as a missing one
+            buckets.retain(|_, bucket| bucket.full_at > now);
+        }
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: limit,
+            updated_at: now,
+            full_at: now,
+        });
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(limit);
+        bucket.updated_at = now;
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        bucket.full_at = now + Duration::from_secs_f64((limit - bucket.tokens) / rate);
+        quota.state(bucket.tokens, allowed)
+    }
+}
+
+impl RateLimitLayer {
+    pub fn new(
+        scope: impl Into<String>,
+        quota: Option<RateLimitQuota>,
+        store: RateLimitStore,
+    ) -> Self {
+        Self {
+            scope: scope.into().into(),
+            quota,
+            store,
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitMiddleware {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+impl<S> Service<Request> for RateLimitMiddleware<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        // the clone may not be ready, keep the one that was polled
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let Some(quota) = self.layer.quota else {
+            return Box::pin(inner.call(request));
+        };
+        let key = format!("{}:{}", self.layer.scope, client_key(&request));
+        let store = self.layer.store.clone();
+        Box::pin(async move {
+            let state = match store.check(&key, quota).await {
+                Ok(state) => state,
+                Err(e) => {
+                    // rather serve than fail every request while the store is down
+                    warn!("rate limit check for {} failed: {}", key, e);
+                    return inner.call(request).await;
+                }
+            };
+            let mut response = if state.allowed {
+                inner.call(request).await?
+            } else {
+                let mut response =
+                    (StatusCode::TOO_MANY_REQUESTS, "too many requests").into_response();
+                response
+                    .headers_mut()
+                    .insert("retry-after", state.retry_after_secs.into());
+                response
+            };
+            set_rate_limit_headers(response.headers_mut(), quota, &state);
+            Ok(response)
+        })
+    }
+}
+
+/// The authenticated user, or the client address if the request is anonymous
+fn client_key(request: &Request) -> String {
+    if let Some(user) = request.extensions().get::<User>() {
+        return format!("user:{}", user.id);
+    }
+    match request.extensions().get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(addr)) => format!("ip:{}", addr.ip()),
+        None => "anonymous".to_string(),
+    }
+}
+
+/// `RateLimit-*` headers of the IETF ratelimit headers draft
+fn set_rate_limit_headers(headers: &mut HeaderMap, quota: RateLimitQuota, state: &RateLimitState) {
+    headers.insert("ratelimit-limit", state.limit.into());
+    headers.insert("ratelimit-remaining", state.remaining.into());
+    headers.insert("ratelimit-reset", state.reset_secs.into());
+    if let Ok(policy) = HeaderValue::from_str(&format!("{};w={}", quota.requests, quota.per_secs)) {
+        headers.insert("ratelimit-policy", policy);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body, middleware::from_fn, middleware::Next, response::IntoResponse, routing::get,
+        Router,
+    };
+    use tower::ServiceExt as _;
+
+    use super::*;
+
+    const QUOTA: RateLimitQuota = RateLimitQuota {
+        requests: 2,
+        per_secs: 60,
+    };
+
+    async fn handler() -> impl IntoResponse {
+        (StatusCode::OK, "ok")
+    }
+
+    async fn set_user(mut req: Request, next: Next) -> Response {
+        let id = req.uri().query().unwrap_or("0").parse().unwrap_or(0);
+        req.extensions_mut()
+            .insert(User::new(id, "zzq", "zzq@gmail.com"));
+        next.run(req).await
+    }
+
+    #[test]
+    fn memory_buckets_should_refill() {
+        let buckets = MemoryBuckets::default();
+        let state = buckets.check("a", QUOTA);
+        assert!(state.allowed);
+        assert_eq!(state.remaining, 1);
+        assert_eq!(state.reset_secs, 30);
+        assert!(buckets.check("a", QUOTA).allowed);
+        let state = buckets.check("a", QUOTA);
+        assert!(!state.allowed);
+        assert_eq!(state.retry_after_secs, 30);
+        // other keys have their own bucket
+        assert!(buckets.check("b", QUOTA).allowed);
+
+        let quota = RateLimitQuota {
+            requests: 1000,
+            per_secs: 1,
+        };
+        assert!(buckets.check("c", quota).allowed);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(buckets.check("c", quota).remaining, 999);
+    }
+
+    #[tokio::test]
+    async fn rate_limit_layer_should_limit_per_user() -> anyhow::Result<()> {
+        let layer = RateLimitLayer::new("test", Some(QUOTA), RateLimitStore::memory());
+        let app = Router::new()
+            .route("/", get(handler).layer(layer))
+            .layer(from_fn(set_user));
+
+        for _ in 0..2 {
+            let req = Request::builder().uri("/?1").body(Body::empty())?;
+            let res = app.clone().oneshot(req).await?;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers()["ratelimit-limit"], "2");
+            assert_eq!(res.headers()["ratelimit-policy"], "2;w=60");
+        }
+        let req = Request::builder().uri("/?1").body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["ratelimit-remaining"], "0");
+        assert_eq!(res.headers()["retry-after"], "30");
+
+        let req = Request::builder().uri("/?2").body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rate_limit_layer_without_quota_should_pass() -> anyhow::Result<()> {
+        let layer = RateLimitLayer::new("test", None, RateLimitStore::memory());
+        let app = Router::new().route("/", get(handler).layer(layer));
+        for _ in 0..5 {
+            let req = Request::builder().uri("/").body(Body::empty())?;
+            let res = app.clone().oneshot(req).await?;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert!(!res.headers().contains_key("ratelimit-limit"));
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
 mod jwt;
+mod outbound;
 
 pub use jwt::{DecodingKey, EncodingKey};
+pub use outbound::OutboundPolicy;
@@ -0,0 +1,194 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use ipnet::IpNet;
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect, ClientBuilder, Url,
+};
+use serde::{Deserialize, Serialize};
+
+/// Where the servers may send requests users configured, e.g. webhooks and slash commands.
+/// Loopback, private and link-local addresses are blocked unless allowed. Entries are host
+/// names (`*.acme.org` for subdomains), addresses or CIDR ranges, deny wins over allow.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutboundPolicy {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl OutboundPolicy {
+    /// Whether a request to `host` may connect to `ip`
+    pub fn allows(&self, host: &str, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|e| matches(e, host, Some(ip))) {
+            return false;
+        }
+        self.allow.iter().any(|e| matches(e, host, Some(ip))) || !is_internal(ip)
+    }
+
+    /// Check a url before sending to it, the addresses of host names are checked as they are
+    /// resolved by clients from `client_builder`
+    pub fn check_url(&self, url: &Url) -> Result<(), String> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("{} is not an http or https url", url));
+        }
+        let Some(host) = url.host_str() else {
+            return Err(format!("{} has no host", url));
+        };
+        let allowed = match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+            Ok(ip) => self.allows(host, ip),
+            Err(_) => !self.deny.iter().any(|e| matches(e, host, None)),
+        };
+        if !allowed {
+            return Err(format!("{} is not an allowed destination", host));
+        }
+        Ok(())
+    }
+
+    /// A client that only connects to allowed addresses and does not follow redirects, which
+    /// could lead anywhere
+    pub fn client_builder(&self) -> ClientBuilder {
+        reqwest::Client::builder()
+            .redirect(redirect::Policy::none())
+            .dns_resolver(Arc::new(PolicyResolver(Arc::new(self.clone()))))
+    }
+}
+
+struct PolicyResolver(Arc<OutboundPolicy>);
+
+impl Resolve for PolicyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(resolve(self.0.clone(), name))
+    }
+}
+
+async fn resolve(
+    policy: Arc<OutboundPolicy>,
+    name: Name,
+) -> Result<Addrs, Box<dyn std::error::Error + Send + Sync>> {
+    let host = name.as_str();
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0))
+        .await?
+        .filter(|addr| policy.allows(host, addr.ip()))
+        .collect();
+    if addrs.is_empty() {
+        return Err(format!("{} has no allowed address", host).into());
+    }
+    Ok(Box::new(addrs.into_iter()) as Addrs)
+}
+
+fn matches(entry: &str, host: &str, ip: Option<IpAddr>) -> bool {
+    if let Ok(net) = entry.parse::<IpNet>() {
+        return ip.is_some_and(|ip| net.contains(&ip));
+    }
+    if let Ok(addr) = entry.parse::<IpAddr>() {
+        return ip == Some(addr);
+    }
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    let entry = entry.to_ascii_lowercase();
+    match entry.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|sub| sub.ends_with('.')),
+        None => host == entry,
+    }
+}
+
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_internal_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_internal_v4(ip),
+            None => {
+                let first = ip.segments()[0];
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // unique local fc00::/7 and link-local fe80::/10
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80
+            }
+        },
+    }
+}
+
+fn is_internal_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        // "this network" 0.0.0.0/8 and shared address space 100.64.0.0/10
+        || a == 0
+        || (a == 100 && b & 0xc0 == 64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(allow: &[&str], deny: &[&str]) -> OutboundPolicy {
+        OutboundPolicy {
+            allow: allow.iter().map(|v| v.to_string()).collect(),
+            deny: deny.iter().map(|v| v.to_string()).collect(),
+        }
+    }
+
+    fn check(policy: &OutboundPolicy, url: &str) -> bool {
+        policy.check_url(&Url::parse(url).unwrap()).is_ok()
+    }
+
+    #[test]
+    fn internal_addresses_should_be_blocked_by_default() {
+        let policy = OutboundPolicy::default();
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!policy.allows("host", ip.parse().unwrap()), "{}", ip);
+        }
+        assert!(policy.allows("host", "93.184.216.34".parse().unwrap()));
+        assert!(policy.allows("host", "2606:2800:220:1::1".parse().unwrap()));
+        assert!(!check(&policy, "http://127.0.0.1:8080/hook"));
+        assert!(!check(&policy, "http://[::1]/hook"));
+        assert!(check(&policy, "https://hooks.acme.org/chat"));
+        assert!(!check(&policy, "ftp://hooks.acme.org/chat"));
+    }
+
+    #[test]
+    fn allow_and_deny_lists_should_work() {
+        let policy = policy(
+            &["127.0.0.1", "10.0.0.0/8", "*.corp.acme.org"],
+            &["10.0.0.1", "evil.org", "*.acme.org"],
+        );
+        assert!(policy.allows("localhost", "127.0.0.1".parse().unwrap()));
+        assert!(policy.allows("host", "10.2.3.4".parse().unwrap()));
+        assert!(!policy.allows("host", "10.0.0.1".parse().unwrap()));
+        assert!(!policy.allows("evil.org", "93.184.216.34".parse().unwrap()));
+        // deny wins
+        assert!(!policy.allows("ci.corp.acme.org", "192.168.1.1".parse().unwrap()));
+        assert!(check(&policy, "http://127.0.0.1:8080/hook"));
+        assert!(!check(&policy, "http://Evil.org/hook"));
+        assert!(!check(&policy, "http://www.acme.org/hook"));
+        assert!(check(&policy, "http://acme.org.example.com/hook"));
+
+        let policy = self::policy(&["*.corp.acme.org"], &[]);
+        assert!(policy.allows("ci.corp.acme.org", "192.168.1.1".parse().unwrap()));
+        assert!(!policy.allows("corp.acme.org", "192.168.1.1".parse().unwrap()));
+        assert!(!policy.allows("xcorp.acme.org", "192.168.1.1".parse().unwrap()));
+    }
+}
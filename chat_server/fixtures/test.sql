@@ -12,15 +12,22 @@ VALUES (1, 'zzq@zzq.com', 'zzq', '$argon2id$v=19$m=19456,t=2,p=1$6oJfE3UiponQts0
 (1, 'd@zzq.com', 'd', '$argon2id$v=19$m=19456,t=2,p=1$6oJfE3UiponQts0znvyM4g$MLggmVmAxtLBi7uqAh6LSTotu2isSpYsCnO660jbfdE');
 
 
-insert into chats (ws_id, name, type, members)
-VALUES (1, 'general', 'public_channel', '{1,2,3,4,5}'),
-(1, 'private', 'private_channel', '{1,2,3}');
+insert into chats (ws_id, name, type)
+VALUES (1, 'general', 'public_channel'),
+(1, 'private', 'private_channel');
 
 
 -- insert unnamed chat
-INSERT INTO chats (ws_id, type, members)
-VALUES (1, 'single', '{1,2}'),
-(1, 'group', '{1,2,3}');
+INSERT INTO chats (ws_id, type)
+VALUES (1, 'single'),
+(1, 'group');
+
+-- insert chat members
+INSERT INTO chat_members (chat_id, user_id)
+VALUES (1, 1), (1, 2), (1, 3), (1, 4), (1, 5),
+(2, 1), (2, 2), (2, 3),
+(3, 1), (3, 2),
+(4, 1), (4, 2), (4, 3);
 
 INSERT INTO messages (chat_id, sender_id, content)
 VALUES (1, 1, 'hello'),
@@ -1,24 +1,166 @@
-use std::{fs::File, path::PathBuf};
+use std::{collections::HashMap, fs::File, path::PathBuf};
 
 use anyhow::{bail, Result};
+use chat_core::{OutboundPolicy, RateLimitQuota};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppConfig {
     pub server: ServerConfig,
     pub auth: AuthConfig,
+    /// required, so a deployment does not silently drop verification and reset mails
+    pub mail: MailConfig,
+    /// single sign-on with an OpenID Connect provider, off if not set
+    #[serde(default)]
+    pub oidc: Option<OidcConfig>,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub files: FileConfig,
+    /// where uploaded files live, under `server.base_dir` if not set
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// where webhooks and slash commands may send requests to
+    #[serde(default)]
+    pub outbound: OutboundPolicy,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthConfig {
     pub sk: String,
     pub pk: String,
+    /// refuse to sign in users until they verified their email
+    #[serde(default)]
+    pub require_verified_email: bool,
 }
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MailConfig {
+    /// sender of all mails, e.g. `Chat <noreply@acme.org>`
+    pub from: String,
+    /// links in mails point to the web client here
+    pub base_url: String,
+    #[serde(flatten)]
+    pub backend: MailBackend,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum MailBackend {
+    Smtp {
+        host: String,
+        port: u16,
+        username: String,
+        password: String,
+        /// upgrade a plain connection instead of connecting with TLS
+        #[serde(default)]
+        starttls: bool,
+    },
+    File {
+        dir: PathBuf,
+    },
+    Memory,
+}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcConfig {
+    /// discovery is read from `{issuer}/.well-known/openid-configuration`
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// must be registered at the provider, e.g. `https://chat.acme.org/api/oidc/callback`
+    pub redirect_url: String,
+    #[serde(default = "default_oidc_scopes")]
+    pub scopes: Vec<String>,
+    /// the web client gets the token as `{post_login_url}#token=...`, without it the callback
+    /// returns json
+    #[serde(default)]
+    pub post_login_url: Option<String>,
+    /// new users join the workspace named by this claim of the ID token
+    #[serde(default)]
+    pub workspace_claim: Option<String>,
+    /// otherwise the workspace of their email domain, e.g. `acme.org: acme`
+    #[serde(default)]
+    pub domain_workspaces: HashMap<String, String>,
+    /// otherwise this workspace, without one such users are refused
+    #[serde(default)]
+    pub default_workspace: Option<String>,
+    /// treat emails as verified even without the `email_verified` claim
+    #[serde(default)]
+    pub trust_email: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    #[serde(default)]
+    pub backend: RateLimitBackend,
+    /// quota per route, e.g. `send_message` or `upload`, routes without one are not limited
+    #[serde(default)]
+    pub routes: HashMap<String, RateLimitQuota>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitBackend {
+    /// per instance
+    #[default]
+    Memory,
+    /// shared by all instances using the database
+    Postgres,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum StorageConfig {
+    /// files under `server.base_dir`, only for a single instance
+    #[default]
+    Local,
+    /// a bucket of an S3-compatible service, e.g. MinIO, shared by all instances
+    S3 {
+        /// e.g. `https://s3.eu-central-1.amazonaws.com` or `http://localhost:9000`
+        endpoint: String,
+        region: String,
+        bucket: String,
+        access_key: String,
+        secret_key: String,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileConfig {
+    /// uploads no message references are removed after this long, clients usually send the
+    /// message right after uploading
+    #[serde(default = "default_file_gc_grace_secs")]
+    pub gc_grace_secs: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServerConfig {
     pub port: u16,
     pub db_url: String,
     pub base_dir: PathBuf,
+    /// serve `/metrics` on this port, only reachable from the monitoring network, off if not set
+    #[serde(default)]
+    pub metrics_port: Option<u16>,
+}
+
+impl Default for FileConfig {
+    fn default() -> Self {
+        Self {
+            gc_grace_secs: default_file_gc_grace_secs(),
+        }
+    }
+}
+
+fn default_file_gc_grace_secs() -> u64 {
+    60 * 60 * 24
+}
+
+fn default_oidc_scopes() -> Vec<String> {
+    vec![
+        "openid".to_string(),
+        "email".to_string(),
+        "profile".to_string(),
+    ]
 }
 
 impl AppConfig {
@@ -34,6 +176,23 @@ impl AppConfig {
             (_, _, Ok(f)) => serde_yaml::from_reader(File::open(f)?),
             _ => bail!("Config file not found"),
         };
-        Ok(ret?)
+        let config: Self = ret?;
+        config.rate_limit.verify()?;
+        Ok(config)
+    }
+}
+
+impl RateLimitConfig {
+    /// A quota without requests or time would never refill its bucket
+    fn verify(&self) -> Result<()> {
+        for (route, quota) in &self.routes {
+            if quota.requests == 0 || quota.per_secs == 0 {
+                bail!(
+                    "rate_limit of {} needs requests and per_secs above 0",
+                    route
+                );
+            }
+        }
+        Ok(())
     }
 }
@@ -35,6 +35,57 @@ pub enum AppError {
     #[error("chat does not exist")]
     ChatDoesNotExist,
 
+    #[error("chat archive error: {0}")]
+    ChatArchiveError(String),
+
+    #[error("pin message error: {0}")]
+    PinMessageError(String),
+
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("bookmark error: {0}")]
+    BookmarkError(String),
+
+    #[error("retention error: {0}")]
+    RetentionError(String),
+
+    #[error("import error: {0}")]
+    ImportError(String),
+
+    #[error("update user error: {0}")]
+    UpdateUserError(String),
+
+    #[error("mail error: {0}")]
+    MailError(String),
+
+    #[error("two-factor error: {0}")]
+    TwoFactorError(String),
+
+    #[error("single sign-on error: {0}")]
+    OidcError(String),
+
+    #[error("access token error: {0}")]
+    AccessTokenError(String),
+
+    #[error("webhook error: {0}")]
+    WebhookError(String),
+
+    #[error("slash command error: {0}")]
+    CommandError(String),
+
+    #[error("poll error: {0}")]
+    PollError(String),
+
+    #[error("storage error: {0}")]
+    StorageError(String),
+
+    #[error("zip error: {0}")]
+    ZipError(#[from] async_zip::error::ZipError),
+
+    #[error("json error: {0}")]
+    SerdeJsonError(#[from] serde_json::Error),
+
     #[error("sql error: {0}")]
     SqlxError(#[from] sqlx::Error),
 
@@ -70,6 +121,23 @@ impl IntoResponse for AppError {
             AppError::MessageCreateError(_) => StatusCode::BAD_REQUEST,
             AppError::ChatFileError(_) => StatusCode::BAD_REQUEST,
             AppError::ChatDoesNotExist => StatusCode::NOT_FOUND,
+            AppError::ChatArchiveError(_) => StatusCode::BAD_REQUEST,
+            AppError::PinMessageError(_) => StatusCode::BAD_REQUEST,
+            AppError::PermissionDenied(_) => StatusCode::FORBIDDEN,
+            AppError::BookmarkError(_) => StatusCode::BAD_REQUEST,
+            AppError::RetentionError(_) => StatusCode::BAD_REQUEST,
+            AppError::ImportError(_) => StatusCode::BAD_REQUEST,
+            AppError::UpdateUserError(_) => StatusCode::BAD_REQUEST,
+            AppError::MailError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::TwoFactorError(_) => StatusCode::BAD_REQUEST,
+            AppError::OidcError(_) => StatusCode::BAD_REQUEST,
+            AppError::AccessTokenError(_) => StatusCode::BAD_REQUEST,
+            AppError::WebhookError(_) => StatusCode::BAD_REQUEST,
+            AppError::CommandError(_) => StatusCode::BAD_REQUEST,
+            AppError::PollError(_) => StatusCode::BAD_REQUEST,
+            AppError::StorageError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::ZipError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::SerdeJsonError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
         (status, Json(json!(ErrorOutput::new(self.to_string())))).into_response()
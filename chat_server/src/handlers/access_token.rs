@@ -0,0 +1,112 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+
+use crate::{AppError, AppState, CreateAccessToken, CreateBot};
+use chat_core::User;
+
+/// Create an access token for scripts, for me or for a bot of my workspace.
+#[utoipa::path(
+    post,
+    path = "/api/tokens",
+    request_body = CreateAccessToken,
+    responses(
+        (status = 201, description = "Token created, it is only shown once", body = CreatedAccessToken),
+        (status = 400, description = "Invalid name, scopes or expiry", body = ErrorOutput),
+        (status = 403, description = "Only the workspace owner can create bot tokens", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_access_token_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateAccessToken>,
+) -> Result<impl IntoResponse, AppError> {
+    let token = state.create_access_token(&user, input).await?;
+    Ok((StatusCode::CREATED, Json(token)))
+}
+
+/// List my access tokens and the bot tokens I created.
+#[utoipa::path(
+    get,
+    path = "/api/tokens",
+    responses(
+        (status = 200, description = "Access tokens, without the secret", body = Vec<AccessToken>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_access_tokens_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let tokens = state.list_access_tokens(user.id as _).await?;
+    Ok(Json(tokens))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/tokens/{id}",
+    params(("id" = u64, Path, description = "Access token id")),
+    responses(
+        (status = 204, description = "Token revoked"),
+        (status = 404, description = "No such token of mine", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn revoke_access_token_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.revoke_access_token(id, user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Add a bot to my workspace, only the workspace owner can do it.
+#[utoipa::path(
+    post,
+    path = "/api/bots",
+    request_body = CreateBot,
+    responses(
+        (status = 201, description = "Bot created", body = User),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_bot_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateBot>,
+) -> Result<impl IntoResponse, AppError> {
+    let bot = state.create_bot(&user, input).await?;
+    Ok((StatusCode::CREATED, Json(bot)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/bots",
+    responses(
+        (status = 200, description = "Bots of my workspace", body = Vec<ChatUser>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_bots_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let bots = state.list_bots(user.ws_id as _).await?;
+    Ok(Json(bots))
+}
@@ -1,21 +1,48 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
+use tracing::warn;
+
+use chat_core::User;
+
 use crate::{
     models::{CreateUser, SigninUser},
-    AppError, AppState, ErrorOutput,
+    AppError, AppState, ErrorOutput, ForgotPassword, ResetPassword, SigninOutcome,
+    TwoFactorChallenge, VerifyEmail,
 };
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct AuthOutput {
-    token: String,
+    pub(crate) token: String,
+}
+
+/// Returned by signup instead of a token while the email is not verified
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SignupPending {
+    /// where the verification link was sent
+    pub(crate) email: String,
+    pub(crate) message: String,
+}
+
+/// A verified user either gets a token or still needs the second factor
+pub(crate) enum Signin {
+    Token(String),
+    Challenge(String),
 }
 #[utoipa::path(
         post,
         path = "/api/signup",
         responses(
-            (status = 200, description = "User Created", body = AuthOutput)
+            (status = 201, description = "User Created", body = AuthOutput),
+            (status = 202, description = "User Created, the email needs to be verified before signing in", body = SignupPending)
         )
     )]
 /// Create a new user with email and password
@@ -23,37 +50,94 @@ pub struct AuthOutput {
 /// - if the email is already in use, return 409
 /// - if the workspace does not exist, create one
 /// - if the workspace name is empty, return 400
+/// - a verification link is mailed, if verified emails are required no token is issued yet
 pub(crate) async fn signup_handler(
     State(state): State<AppState>,
     Json(input): Json<CreateUser>,
 ) -> Result<impl IntoResponse, AppError> {
-    let user = state.create_user(&input).await?;
+    let mut user = state.create_user(&input).await?;
+    if let Err(e) = state.send_email_verification(user.id as _).await {
+        warn!("failed to send verification mail to {}: {}", user.email, e);
+    }
+    if state.config.auth.require_verified_email {
+        let body = Json(SignupPending {
+            email: user.email,
+            message: "Verify your email to sign in".to_string(),
+        });
+        return Ok((StatusCode::ACCEPTED, body).into_response());
+    }
+    user.enroll_two_factor = state.is_two_factor_required(user.ws_id as _).await?;
     let token = state.ek.sign(user)?;
     /* let mut header = HeaderMap::new();
     header.insert("X-Token", HeaderValue::from_str(&token)?);
     Ok((StatusCode::CREATED, header)) */
 
     let body = Json(AuthOutput { token });
-    Ok((StatusCode::CREATED, body))
+    Ok((StatusCode::CREATED, body).into_response())
 }
 
 #[utoipa::path(
         post,
         path = "/api/signin",
         responses(
-            (status = 200, description = "User Signed in", body = AuthOutput)
+            (status = 200, description = "User Signed in", body = AuthOutput),
+            (status = 202, description = "Two-factor code needed, continue with /api/signin/2fa", body = TwoFactorChallenge),
+            (status = 429, description = "Too many failed attempts, retry after the `Retry-After` seconds", body = ErrorOutput)
         )
     )]
+/// Sign in with email and password
+///
+/// - repeated failures lock the email and the client address for a while, doubling each time
+/// - with 2FA enabled, return a challenge for `/api/signin/2fa` instead of a token
+/// - if the workspace requires 2FA and the user has not enrolled, the token only allows enrolling
 pub(crate) async fn signin_handler(
     State(state): State<AppState>,
+    addr: Option<ConnectInfo<SocketAddr>>,
     Json(input): Json<SigninUser>,
 ) -> Result<impl IntoResponse, AppError> {
+    let ip = addr.map(|ConnectInfo(addr)| addr.ip());
+    if let Some(secs) = state.signin_retry_after(&input.email, ip).await? {
+        return Ok(too_many_attempts(secs));
+    }
     let user = state.verify_user(&input).await?;
+    let outcome = match &user {
+        // recorded once the code is checked, a known password alone must not lift the lockout
+        Some(user) if state.is_two_factor_enabled(user.id as _).await? => None,
+        Some(_) => Some(SigninOutcome::Succeeded),
+        None => Some(SigninOutcome::Failed),
+    };
+    if let Some(outcome) = outcome {
+        let owner = match &user {
+            Some(user) => Some(user.clone()),
+            None => state.find_user_by_email(&input.email).await?,
+        };
+        state
+            .record_signin(&input.email, owner.as_ref(), ip, outcome)
+            .await?;
+    }
     match user {
-        Some(user) => {
-            let token = state.ek.sign(user)?;
-            Ok((StatusCode::OK, Json(AuthOutput { token })).into_response())
+        Some(user)
+            if state.config.auth.require_verified_email
+                && !state.is_email_verified(user.id as _).await? =>
+        {
+            // the earlier link may be lost or expired
+            state.send_email_verification(user.id as _).await?;
+            Ok((
+                StatusCode::FORBIDDEN,
+                Json(ErrorOutput::new(
+                    "Email is not verified, a new verification link was sent",
+                )),
+            )
+                .into_response())
         }
+        Some(user) => match signin_user(&state, user).await? {
+            Signin::Token(token) => {
+                Ok((StatusCode::OK, Json(AuthOutput { token })).into_response())
+            }
+            Signin::Challenge(challenge) => {
+                Ok((StatusCode::ACCEPTED, Json(TwoFactorChallenge { challenge })).into_response())
+            }
+        },
         None => Ok((
             StatusCode::FORBIDDEN,
             Json(ErrorOutput::new("Invalid email or password")),
@@ -62,11 +146,86 @@ pub(crate) async fn signin_handler(
     }
 }
 
+/// Sign in a verified user, with 2FA enabled only a challenge is issued
+pub(crate) async fn signin_user(state: &AppState, mut user: User) -> Result<Signin, AppError> {
+    if state.is_two_factor_enabled(user.id as _).await? {
+        return Ok(Signin::Challenge(state.two_factor_challenge(&user).await?));
+    }
+    user.enroll_two_factor = state.is_two_factor_required(user.ws_id as _).await?;
+    Ok(Signin::Token(state.ek.sign(user)?))
+}
+
+/// The lockout response, clients should wait for the `Retry-After` seconds
+pub(crate) fn too_many_attempts(secs: u64) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, secs.to_string())],
+        Json(ErrorOutput::new(
+            "Too many failed attempts, try again later",
+        )),
+    )
+        .into_response()
+}
+
+/// Mail a password reset link, the response does not tell whether the email exists.
+#[utoipa::path(
+    post,
+    path = "/api/password/forgot",
+    request_body = ForgotPassword,
+    responses(
+        (status = 202, description = "Reset link sent if the email exists"),
+    )
+)]
+pub(crate) async fn forgot_password_handler(
+    State(state): State<AppState>,
+    Json(input): Json<ForgotPassword>,
+) -> Result<impl IntoResponse, AppError> {
+    state.forgot_password(input).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Set a new password with the token from the reset link.
+#[utoipa::path(
+    post,
+    path = "/api/password/reset",
+    request_body = ResetPassword,
+    responses(
+        (status = 204, description = "Password changed"),
+        (status = 400, description = "Invalid or expired token", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn reset_password_handler(
+    State(state): State<AppState>,
+    Json(input): Json<ResetPassword>,
+) -> Result<impl IntoResponse, AppError> {
+    state.reset_password(input).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Verify an email with the token from the verification link.
+#[utoipa::path(
+    post,
+    path = "/api/email/verify",
+    request_body = VerifyEmail,
+    responses(
+        (status = 204, description = "Email verified"),
+        (status = 400, description = "Invalid or expired token", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn verify_email_handler(
+    State(state): State<AppState>,
+    Json(input): Json<VerifyEmail>,
+) -> Result<impl IntoResponse, AppError> {
+    state.verify_email(&input.token).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
 #[cfg(test)]
 mod tests {
     use http_body_util::BodyExt;
 
     use super::*;
+    use crate::AppConfig;
 
     #[tokio::test]
     async fn signup_duplicate_should_409() -> anyhow::Result<()> {
@@ -85,11 +244,29 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn signup_should_wait_for_verification_if_required() -> anyhow::Result<()> {
+        let mut config = AppConfig::load()?;
+        config.auth.require_verified_email = true;
+        let (_tdb, state) = AppState::new_for_test_with(config).await?;
+        let input = CreateUser::new("none", "zzq21", "zzq21@zzq.com", "zzq");
+        let ret = signup_handler(State(state.clone()), Json(input))
+            .await?
+            .into_response();
+
+        assert_eq!(ret.status(), StatusCode::ACCEPTED);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let ret: SignupPending = serde_json::from_slice(&body)?;
+        assert_eq!(ret.email, "zzq21@zzq.com");
+        assert_eq!(state.mailer.sent().len(), 1);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn signup_should_work() -> anyhow::Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
         let input = CreateUser::new("none", "zzq21", "zzq21@zzq.com", "zzq");
-        let ret = signup_handler(State(state), Json(input))
+        let ret = signup_handler(State(state.clone()), Json(input))
             .await?
             .into_response();
 
@@ -98,6 +275,9 @@ mod tests {
         let body = ret.into_body().collect().await?.to_bytes();
         let ret: AuthOutput = serde_json::from_slice(&body)?;
         assert_ne!(ret.token, "");
+        let mails = state.mailer.sent();
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0].to, "zzq21@zzq.com");
         Ok(())
     }
 
@@ -108,7 +288,7 @@ mod tests {
         state.create_user(&user).await?;
         let input = SigninUser::new("zzq21@zzq.com", "zzq");
 
-        let ret = signin_handler(State(state), Json(input))
+        let ret = signin_handler(State(state), None, Json(input))
             .await?
             .into_response();
 
@@ -124,7 +304,7 @@ mod tests {
 
         let input = SigninUser::new("zzq21@zzq.com", "zzq");
 
-        let ret = signin_handler(State(state), Json(input))
+        let ret = signin_handler(State(state), None, Json(input))
             .await?
             .into_response();
 
@@ -134,4 +314,43 @@ mod tests {
         assert_eq!(ret.error, "Invalid email or password");
         Ok(())
     }
+
+    #[tokio::test]
+    async fn signin_should_lock_after_failures() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // known and unknown emails lock alike
+        for email in ["zzq@zzq.com", "nobody@zzq.com"] {
+            for _ in 0..5 {
+                let input = SigninUser::new(email, "wrong");
+                let ret = signin_handler(State(state.clone()), None, Json(input))
+                    .await?
+                    .into_response();
+                assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+            }
+            // even the right password has to wait
+            let input = SigninUser::new(email, "123456");
+            let ret = signin_handler(State(state.clone()), None, Json(input))
+                .await?
+                .into_response();
+            assert_eq!(ret.status(), StatusCode::TOO_MANY_REQUESTS);
+            assert!(ret.headers().contains_key(header::RETRY_AFTER));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn forgot_password_should_not_reveal_emails() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        for email in ["zzq@zzq.com", "nobody@zzq.com"] {
+            let input = ForgotPassword {
+                email: email.to_string(),
+            };
+            let ret = forgot_password_handler(State(state.clone()), Json(input))
+                .await?
+                .into_response();
+            assert_eq!(ret.status(), StatusCode::ACCEPTED);
+        }
+        assert_eq!(state.mailer.sent().len(), 1);
+        Ok(())
+    }
 }
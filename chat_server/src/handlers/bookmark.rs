@@ -0,0 +1,70 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+
+use crate::{AppError, AppState, CreateBookmark};
+use chat_core::User;
+
+/// List saved messages of the current user.
+#[utoipa::path(
+    get,
+    path = "/api/bookmarks",
+    responses(
+        (status = 200, description = "List of bookmarks", body = Vec<Bookmark>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_bookmarks_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let bookmarks = state.list_bookmarks(user.id as _).await?;
+    Ok(Json(bookmarks))
+}
+
+/// Save a message for later, with an optional note and reminder.
+#[utoipa::path(
+    post,
+    path = "/api/bookmarks",
+    responses(
+        (status = 201, description = "Bookmark created", body = Bookmark),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_bookmark_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateBookmark>,
+) -> Result<impl IntoResponse, AppError> {
+    let bookmark = state.create_bookmark(input, user.id as _).await?;
+    Ok((StatusCode::CREATED, Json(bookmark)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/bookmarks/{id}",
+    params(("id" = u64, Path, description = "Bookmark id")),
+    responses(
+        (status = 204, description = "Bookmark deleted"),
+        (status = 404, description = "Bookmark not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn delete_bookmark_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.delete_bookmark(id, user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
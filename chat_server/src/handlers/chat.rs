@@ -1,16 +1,17 @@
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     response::IntoResponse,
     Extension, Json,
 };
 
-use crate::{AppError, AppState, CreateChat, UpdateChat};
+use crate::{AppError, AppState, CreateChat, ListChats, UpdateChat, UpdateChatMember};
 use chat_core::User;
 
 #[utoipa::path(
         get,
         path = "/api/chats",
+        params(ListChats),
         responses(
             (status = 200, description = "List of chats", body = Vec<Chat>),
         ),
@@ -21,8 +22,11 @@ use chat_core::User;
 pub(crate) async fn list_chat_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
+    Query(input): Query<ListChats>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chat = state.fetch_chats(user.id as _, user.ws_id as _).await?;
+    let chat = state
+        .fetch_chats(input, user.id as _, user.ws_id as _)
+        .await?;
     Ok((StatusCode::OK, Json(chat)))
 }
 
@@ -69,12 +73,14 @@ pub(crate) async fn get_chat_handler(
         None => Err(AppError::NotFound(format!("chat with id {} not found", id))),
     }
 }
+/// Update the members or the details of a chat, only admins of the chat can do it.
 #[utoipa::path(
         patch,
         path = "/api/chats/{id}",
         params(("id"=u64, Path, description="Chat ID"), UpdateChat),
         responses(
             (status = 200, description = "Chat Updated", body = Chat),
+            (status = 403, description = "Not an admin of the chat", body = ErrorOutput),
             (status = 404, description = "Chat not found", body = ErrorOutput)
         ),
         security(
@@ -82,10 +88,12 @@ pub(crate) async fn get_chat_handler(
         )
     )]
 pub(crate) async fn update_chat_handler(
+    Extension(user): Extension<User>,
     State(state): State<AppState>,
     Path(id): Path<u64>,
     Json(input): Json<UpdateChat>,
 ) -> Result<impl IntoResponse, AppError> {
+    state.verify_chat_admin(id, user.id as _).await?;
     let chat = state.update_chat_by_id(input, id as _).await?;
     match chat {
         Some(chat) => Ok((StatusCode::OK, Json(chat))),
@@ -93,12 +101,96 @@ pub(crate) async fn update_chat_handler(
     }
 }
 
+/// Change the role of a chat member, only owners of the chat and the workspace owner can do it.
+#[utoipa::path(
+        put,
+        path = "/api/chats/{id}/members/{uid}",
+        params(
+            ("id"=u64, Path, description="Chat ID"),
+            ("uid"=u64, Path, description="User ID of the member")
+        ),
+        request_body = UpdateChatMember,
+        responses(
+            (status = 204, description = "Role changed"),
+            (status = 400, description = "The chat would have no owner", body = ErrorOutput),
+            (status = 403, description = "Not an owner of the chat", body = ErrorOutput),
+            (status = 404, description = "Not a member of the chat", body = ErrorOutput)
+        ),
+        security(
+            ("token" = [])
+        )
+    )]
+pub(crate) async fn update_chat_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, uid)): Path<(u64, u64)>,
+    Json(input): Json<UpdateChatMember>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .update_chat_member_role(id, uid, input.role, &user)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Archive the chat, it keeps its history but becomes read-only. Only admins of the chat can do it.
 #[utoipa::path(
         delete,
         path = "/api/chats/{id}",
         params(("id"=u64, Path, description="Chat ID")),
+        responses(
+            (status = 200, description = "Chat Archived", body = Chat),
+            (status = 400, description = "Chat already archived", body = ErrorOutput),
+            (status = 403, description = "Not an admin of the chat", body = ErrorOutput),
+            (status = 404, description = "Chat not found", body = ErrorOutput)
+        ),
+        security(
+            ("token" = [])
+        )
+    )]
+pub(crate) async fn archive_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.verify_chat_admin(id, user.id as _).await?;
+    let chat = state.archive_chat(id).await?;
+    Ok((StatusCode::OK, Json(chat)))
+}
+
+/// Restore an archived chat, only admins of the chat can do it.
+#[utoipa::path(
+        post,
+        path = "/api/chats/{id}/unarchive",
+        params(("id"=u64, Path, description="Chat ID")),
+        responses(
+            (status = 200, description = "Chat Restored", body = Chat),
+            (status = 400, description = "Chat not archived", body = ErrorOutput),
+            (status = 403, description = "Not an admin of the chat", body = ErrorOutput),
+            (status = 404, description = "Chat not found", body = ErrorOutput)
+        ),
+        security(
+            ("token" = [])
+        )
+    )]
+pub(crate) async fn unarchive_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.verify_chat_admin(id, user.id as _).await?;
+    let chat = state.unarchive_chat(id).await?;
+    Ok((StatusCode::OK, Json(chat)))
+}
+
+/// Permanently delete a chat of my workspace with its messages, only the workspace owner can do it.
+#[utoipa::path(
+        delete,
+        path = "/api/workspace/chats/{id}",
+        params(("id"=u64, Path, description="Chat ID")),
         responses(
             (status = 200, description = "Chat Deleted", body = Chat),
+            (status = 400, description = "Chat under legal hold", body = ErrorOutput),
+            (status = 403, description = "Not the workspace owner", body = ErrorOutput),
             (status = 404, description = "Chat not found", body = ErrorOutput)
         ),
         security(
@@ -106,9 +198,12 @@ pub(crate) async fn update_chat_handler(
         )
     )]
 pub(crate) async fn delete_chat_handler(
+    Extension(user): Extension<User>,
     State(state): State<AppState>,
     Path(id): Path<u64>,
 ) -> Result<impl IntoResponse, AppError> {
+    state.verify_workspace_owner(&user).await?;
+    state.verify_workspace_chat(&user, id).await?;
     let chat = state.delete_chat_by_id(id as _).await?;
     match chat {
         Some(chat) => Ok((StatusCode::OK, Json(chat))),
@@ -0,0 +1,73 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+
+use crate::{AppError, AppState, RegisterCommand};
+use chat_core::User;
+
+/// List the slash commands of the workspace, built-in ones first.
+#[utoipa::path(
+    get,
+    path = "/api/commands",
+    responses(
+        (status = 200, description = "Slash commands", body = Vec<SlashCommand>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_commands_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let commands = state.list_commands(user.ws_id as _).await?;
+    Ok(Json(commands))
+}
+
+/// Register a slash command answered by an external service.
+#[utoipa::path(
+    post,
+    path = "/api/commands",
+    request_body = RegisterCommand,
+    responses(
+        (status = 201, description = "Registered, the token is only shown once", body = CreatedCommand),
+        (status = 400, description = "Invalid or taken name, or invalid url", body = ErrorOutput),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn register_command_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<RegisterCommand>,
+) -> Result<impl IntoResponse, AppError> {
+    let command = state.register_command(&user, input).await?;
+    Ok((StatusCode::CREATED, Json(command)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/commands/{id}",
+    params(("id" = u64, Path, description = "Command id")),
+    responses(
+        (status = 204, description = "Command removed"),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+        (status = 404, description = "Command not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn delete_command_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.delete_command(id, &user).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,73 @@
+use std::io;
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap},
+    response::IntoResponse,
+    Extension,
+};
+use futures::{channel::oneshot, future, stream, StreamExt as _};
+use tokio_util::io::ReaderStream;
+use tracing::warn;
+
+use crate::{AppError, AppState, ExportChat};
+use chat_core::User;
+
+/// Export the full chat history as a zip archive, only admins of the chat can do it.
+/// The archive is streamed, if writing it fails the response ends with an error instead of
+/// the rest of the zip.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/export",
+    params(("id" = u64, Path, description = "Chat id"), ExportChat),
+    responses(
+        (status = 200, description = "Zip archive of the chat", body = bytes, content_type = "application/zip"),
+        (status = 403, description = "Not an admin of the chat", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn export_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Query(input): Query<ExportChat>,
+) -> Result<impl IntoResponse, AppError> {
+    state.verify_chat_admin(id, user.id as _).await?;
+    let chat = state.verify_workspace_chat(&user, id).await?;
+
+    // the archive is written into one end of the pipe while the body streams the other
+    let (reader, writer) = tokio::io::duplex(64 * 1024);
+    let (done_tx, done_rx) = oneshot::channel();
+    tokio::spawn(async move {
+        let ret = state.export_chat(id, input.format, writer).await;
+        if let Err(e) = &ret {
+            warn!("failed to export chat {}: {}", id, e);
+        }
+        let _ = done_tx.send(ret);
+    });
+    // a failed export errors the body once the pipe is drained, so the connection is aborted
+    // and the client does not take the truncated zip for a complete one
+    let done = stream::once(async move {
+        match done_rx.await {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(Err(io::Error::other(e.to_string()))),
+            Err(_) => Some(Err(io::Error::other("export ended unexpectedly"))),
+        }
+    })
+    .filter_map(future::ready);
+    let body = ReaderStream::new(reader).chain(done);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, "application/zip".parse().unwrap());
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        format!("attachment; filename=\"chat-{}.zip\"", chat.id)
+            .parse()
+            .unwrap(),
+    );
+    Ok((headers, Body::from_stream(body)))
+}
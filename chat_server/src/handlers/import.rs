@@ -0,0 +1,60 @@
+use axum::{
+    extract::{Multipart, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use tokio::{
+    fs::File,
+    io::{AsyncSeekExt as _, AsyncWriteExt as _, BufReader},
+};
+
+use crate::{AppError, AppState};
+use chat_core::User;
+
+/// Max size of an uploaded slack export
+pub(crate) const IMPORT_BODY_LIMIT: usize = 512 * 1024 * 1024;
+
+/// Import a slack export zip into my workspace, only the workspace owner can do it.
+/// Re-running an import only adds what was not imported before. Threads are flattened into
+/// their chat, files not in the archive are listed as skipped with their slack url.
+#[utoipa::path(
+    post,
+    path = "/api/workspace/import",
+    responses(
+        (status = 200, description = "Import summary", body = ImportSummary),
+        (status = 400, description = "Not a slack export", body = ErrorOutput),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn import_slack_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, AppError> {
+    state.verify_workspace_owner(&user).await?;
+    let Some(mut field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| AppError::ImportError(e.to_string()))?
+    else {
+        return Err(AppError::ImportError("no archive uploaded".to_string()));
+    };
+    // spooled to an anonymous temp file, it is removed once closed
+    let mut file = File::from_std(tempfile::tempfile()?);
+    while let Some(chunk) = field
+        .chunk()
+        .await
+        .map_err(|e| AppError::ImportError(e.to_string()))?
+    {
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    file.rewind().await?;
+    let summary = state
+        .import_slack(user.ws_id as _, BufReader::new(file))
+        .await?;
+    Ok(Json(summary))
+}
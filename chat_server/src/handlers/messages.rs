@@ -1,3 +1,5 @@
+use std::str::FromStr;
+
 use axum::{
     extract::{Multipart, Path, Query, State},
     http::{HeaderMap, StatusCode},
@@ -5,11 +7,9 @@ use axum::{
     Extension, Json,
 };
 
-use tokio::fs::{self};
-
 use tracing::warn;
 
-use crate::{AppError, AppState, ChatFile, CreateMessage, ListMessages};
+use crate::{storage::FileStorage, AppError, AppState, ChatFile, CreateMessage, ListMessages};
 use chat_core::User;
 
 /// Send a new message in the chat.
@@ -20,7 +20,8 @@ use chat_core::User;
         ("id" = u64, Path, description = "Chat id")
     ),
     responses(
-        (status = 200, description = "List of messages", body = Message),
+        (status = 201, description = "Message sent", body = Message),
+        (status = 200, description = "Reply to a slash command only the sender sees", body = Message),
         (status = 400, description = "Invalid input", body = ErrorOutput),
     ),
     security(
@@ -34,7 +35,12 @@ pub(crate) async fn send_message_handler(
     Json(input): Json<CreateMessage>,
 ) -> Result<impl IntoResponse, AppError> {
     let msg = state.create_message(input, id, user.id as _).await?;
-    Ok((StatusCode::CREATED, Json(msg)))
+    // replies to slash commands are not stored
+    let status = match msg.visible_to {
+        Some(_) => StatusCode::OK,
+        None => StatusCode::CREATED,
+    };
+    Ok((status, Json(msg)))
 }
 
 /// List all messages in the chat.
@@ -78,14 +84,15 @@ pub(crate) async fn file_handler(
             "File does not exist or you don't have permission".to_string(),
         ));
     }
-    let base_dir = state.config.server.base_dir.join(ws_id.to_string());
-    let path = base_dir.join(path);
-    if !path.exists() {
+    // parsed, so the path can not leave the files of the workspace
+    let Ok(file) = ChatFile::from_str(&format!("/files/{}/{}", ws_id, path)) else {
         return Err(AppError::NotFound("File does not exist".to_string()));
-    }
-    let mime = mime_guess::from_path(&path).first_or_octet_stream();
+    };
+    let Some(body) = state.storage.get(&file.hash_to_path()).await? else {
+        return Err(AppError::NotFound("File does not exist".to_string()));
+    };
+    let mime = mime_guess::from_ext(&file.ext).first_or_octet_stream();
 
-    let body = fs::read(path).await?;
     let mut headers = HeaderMap::new();
     headers.insert("Content-Type", mime.to_string().parse().unwrap());
     Ok((headers, body))
@@ -105,7 +112,6 @@ pub(crate) async fn upload_handler(
     mut multipart: Multipart,
 ) -> Result<impl IntoResponse, AppError> {
     let ws_id = user.ws_id as u64;
-    let base_dir = &state.config.server.base_dir;
     let mut files = vec![];
     while let Some(field) = multipart.next_field().await.unwrap() {
         let filename = field.file_name().map(|name| name.to_string());
@@ -113,14 +119,9 @@ pub(crate) async fn upload_handler(
             warn!("Failed to read multipart field");
             continue;
         };
-        let file = ChatFile::new(ws_id, &filename, &data);
-        let path = file.path(base_dir);
-        if path.exists() {
-            warn!("File {} already exists: {:?}", filename, path);
-        } else {
-            fs::create_dir_all(path.parent().expect("file path parent should exists")).await?;
-            fs::write(path, data).await?;
-        }
+        let file = state
+            .store_file(ws_id, Some(user.id), &filename, &data)
+            .await?;
         files.push(file.url());
     }
 
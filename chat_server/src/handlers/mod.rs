@@ -1,14 +1,50 @@
+mod access_token;
 mod auth;
+mod bookmark;
 mod chat;
+mod command;
+mod export;
+mod import;
 mod messages;
+mod oidc;
+mod outgoing_webhook;
+mod pin;
+mod poll;
+mod preferences;
+mod profile;
+mod retention;
+mod scheduled;
+mod two_factor;
+mod webhook;
 mod workspace;
 
+pub(crate) use access_token::*;
 pub(crate) use auth::*;
-use axum::response::IntoResponse;
+use axum::{extract::State, response::IntoResponse};
+pub(crate) use bookmark::*;
 pub(crate) use chat::*;
+pub(crate) use command::*;
+pub(crate) use export::*;
+pub(crate) use import::*;
 pub(crate) use messages::*;
+pub(crate) use oidc::*;
+pub(crate) use outgoing_webhook::*;
+pub(crate) use pin::*;
+pub(crate) use poll::*;
+pub(crate) use preferences::*;
+pub(crate) use profile::*;
+pub(crate) use retention::*;
+pub(crate) use scheduled::*;
+pub(crate) use two_factor::*;
+pub(crate) use webhook::*;
 pub(crate) use workspace::*;
 
+use crate::AppState;
+
 pub(crate) async fn index_handler() -> impl IntoResponse {
     "index"
 }
+
+pub(crate) async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+    state.metrics.render()
+}
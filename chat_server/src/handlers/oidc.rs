@@ -0,0 +1,103 @@
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::{headers::Cookie, TypedHeader};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{AppError, AppState, TwoFactorChallenge};
+
+use super::{signin_user, AuthOutput, Signin};
+
+const NONCE_COOKIE: &str = "oidc_nonce";
+const NONCE_COOKIE_SECS: u64 = 600;
+
+#[derive(Debug, Serialize, Deserialize, IntoParams, ToSchema)]
+pub struct OidcCallback {
+    code: String,
+    state: String,
+}
+
+/// Start single sign-on, redirects to the identity provider.
+#[utoipa::path(
+    get,
+    path = "/api/oidc/login",
+    responses(
+        (status = 302, description = "Redirect to the identity provider"),
+        (status = 404, description = "Single sign-on is not configured", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn oidc_login_handler(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let login = state.oidc_login().await?;
+    let cookie = format!(
+        "{}={}; Path=/api/oidc; HttpOnly; SameSite=Lax; Max-Age={}",
+        NONCE_COOKIE, login.nonce, NONCE_COOKIE_SECS
+    );
+    Ok((
+        StatusCode::FOUND,
+        [(header::LOCATION, login.url), (header::SET_COOKIE, cookie)],
+    ))
+}
+
+/// Finish single sign-on, the identity provider redirects here.
+///
+/// - new users are created in the workspace mapped from the ID token
+/// - an existing account with the same verified email is linked
+/// - with `post_login_url` configured, redirect there with `#token=` or `#challenge=`
+#[utoipa::path(
+    get,
+    path = "/api/oidc/callback",
+    params(OidcCallback),
+    responses(
+        (status = 200, description = "User signed in", body = AuthOutput),
+        (status = 202, description = "Two-factor code needed, continue with /api/signin/2fa", body = TwoFactorChallenge),
+        (status = 302, description = "Redirect to the web client"),
+        (status = 400, description = "Login failed", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn oidc_callback_handler(
+    State(state): State<AppState>,
+    cookies: Option<TypedHeader<Cookie>>,
+    Query(input): Query<OidcCallback>,
+) -> Result<Response, AppError> {
+    let nonce = cookies
+        .as_ref()
+        .and_then(|TypedHeader(cookies)| cookies.get(NONCE_COOKIE));
+    let user = state
+        .oidc_callback(&input.code, &input.state, nonce)
+        .await?;
+    let signin = signin_user(&state, user).await?;
+    let post_login_url = state
+        .config
+        .oidc
+        .as_ref()
+        .and_then(|config| config.post_login_url.as_deref());
+    let res = match (post_login_url, signin) {
+        (Some(url), Signin::Token(token)) => (
+            StatusCode::FOUND,
+            [(header::LOCATION, format!("{}#token={}", url, token))],
+        )
+            .into_response(),
+        (Some(url), Signin::Challenge(challenge)) => (
+            StatusCode::FOUND,
+            [(header::LOCATION, format!("{}#challenge={}", url, challenge))],
+        )
+            .into_response(),
+        (None, Signin::Token(token)) => {
+            (StatusCode::OK, Json(AuthOutput { token })).into_response()
+        }
+        (None, Signin::Challenge(challenge)) => {
+            (StatusCode::ACCEPTED, Json(TwoFactorChallenge { challenge })).into_response()
+        }
+    };
+    let clear = format!(
+        "{}=; Path=/api/oidc; HttpOnly; SameSite=Lax; Max-Age=0",
+        NONCE_COOKIE
+    );
+    Ok(([(header::SET_COOKIE, clear)], res).into_response())
+}
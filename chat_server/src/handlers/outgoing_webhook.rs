@@ -0,0 +1,127 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+
+use crate::{AppError, AppState, CreateWebhookSubscription, ListWebhookDeliveries};
+use chat_core::User;
+
+/// Subscribe a service to events of a chat or of the public channels of the workspace.
+#[utoipa::path(
+    post,
+    path = "/api/webhooks",
+    request_body = CreateWebhookSubscription,
+    responses(
+        (status = 201, description = "Subscribed, the secret is only shown once", body = CreatedWebhookSubscription),
+        (status = 400, description = "Invalid url or event", body = ErrorOutput),
+        (status = 403, description = "Only the workspace owner can subscribe to the workspace", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_webhook_subscription_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateWebhookSubscription>,
+) -> Result<impl IntoResponse, AppError> {
+    let subscription = state.create_webhook_subscription(&user, input).await?;
+    Ok((StatusCode::CREATED, Json(subscription)))
+}
+
+/// List webhook subscriptions, all of the workspace for its owner.
+#[utoipa::path(
+    get,
+    path = "/api/webhooks",
+    responses(
+        (status = 200, description = "Subscriptions, without their secret", body = Vec<WebhookSubscription>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_webhook_subscriptions_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let subscriptions = state.list_webhook_subscriptions(&user).await?;
+    Ok(Json(subscriptions))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/webhooks/{id}",
+    params(("id" = u64, Path, description = "Subscription id")),
+    responses(
+        (status = 204, description = "Unsubscribed"),
+        (status = 403, description = "Not the creator or the workspace owner", body = ErrorOutput),
+        (status = 404, description = "Subscription not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn delete_webhook_subscription_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.delete_webhook_subscription(id, &user).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// List recent deliveries of a subscription, `?status=dead` for the dead-letter list.
+#[utoipa::path(
+    get,
+    path = "/api/webhooks/{id}/deliveries",
+    params(
+        ("id" = u64, Path, description = "Subscription id"),
+        ListWebhookDeliveries
+    ),
+    responses(
+        (status = 200, description = "Deliveries, newest first", body = Vec<WebhookDelivery>),
+        (status = 403, description = "Not the creator or the workspace owner", body = ErrorOutput),
+        (status = 404, description = "Subscription not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_webhook_deliveries_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Query(input): Query<ListWebhookDeliveries>,
+) -> Result<impl IntoResponse, AppError> {
+    let deliveries = state.list_webhook_deliveries(id, &user, input).await?;
+    Ok(Json(deliveries))
+}
+
+/// Queue a delivery again, e.g. a dead one after the receiver was fixed.
+#[utoipa::path(
+    post,
+    path = "/api/webhooks/{id}/deliveries/{did}/redeliver",
+    params(
+        ("id" = u64, Path, description = "Subscription id"),
+        ("did" = u64, Path, description = "Delivery id")
+    ),
+    responses(
+        (status = 202, description = "Delivery queued", body = WebhookDelivery),
+        (status = 400, description = "Delivery not found or still pending", body = ErrorOutput),
+        (status = 403, description = "Not the creator or the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn redeliver_webhook_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, did)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let delivery = state.redeliver_webhook(id, did, &user).await?;
+    Ok((StatusCode::ACCEPTED, Json(delivery)))
+}
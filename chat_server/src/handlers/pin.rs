@@ -0,0 +1,82 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+
+use crate::{AppError, AppState};
+use chat_core::User;
+
+/// List pinned messages of the chat, latest pinned first.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/pins",
+    params(("id" = u64, Path, description = "Chat id")),
+    responses(
+        (status = 200, description = "List of pinned messages", body = Vec<PinnedMessage>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_pins_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let pins = state.list_pins(id).await?;
+    Ok(Json(pins))
+}
+
+/// Pin a message in the chat.
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/pins/{mid}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("mid" = u64, Path, description = "Message id")
+    ),
+    responses(
+        (status = 201, description = "Message pinned", body = PinnedMessage),
+        (status = 400, description = "Already pinned or too many pins", body = ErrorOutput),
+        (status = 403, description = "Not allowed to pin", body = ErrorOutput),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn pin_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let pin = state.pin_message(id, mid, user.id as _).await?;
+    Ok((StatusCode::CREATED, Json(pin)))
+}
+
+/// Unpin a message in the chat.
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/pins/{mid}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("mid" = u64, Path, description = "Message id")
+    ),
+    responses(
+        (status = 204, description = "Message unpinned"),
+        (status = 403, description = "Not allowed to unpin", body = ErrorOutput),
+        (status = 404, description = "Message not pinned", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn unpin_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, mid)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state.unpin_message(id, mid, user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
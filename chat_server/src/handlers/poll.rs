@@ -0,0 +1,141 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+
+use crate::{AppError, AppState, CreatePoll, UnvotePoll, VotePoll};
+use chat_core::User;
+
+/// Ask a poll in the chat, posted as a message of the user.
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/polls",
+    params(("id" = u64, Path, description = "Chat id")),
+    request_body = CreatePoll,
+    responses(
+        (status = 201, description = "Poll posted", body = Poll),
+        (status = 400, description = "Invalid question, options or closing time", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_poll_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<CreatePoll>,
+) -> Result<impl IntoResponse, AppError> {
+    let poll = state.create_poll(input, id, user.id as _).await?;
+    Ok((StatusCode::CREATED, Json(poll)))
+}
+
+/// Get a poll with its tally.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/polls/{pid}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("pid" = u64, Path, description = "Poll id")
+    ),
+    responses(
+        (status = 200, description = "The poll", body = Poll),
+        (status = 404, description = "Poll not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn get_poll_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, pid)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let poll = state.get_poll(id, pid, user.id as _).await?;
+    Ok(Json(poll))
+}
+
+/// Vote for options of an open poll.
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/polls/{pid}/votes",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("pid" = u64, Path, description = "Poll id")
+    ),
+    request_body = VotePoll,
+    responses(
+        (status = 200, description = "The poll with the vote", body = Poll),
+        (status = 400, description = "Poll closed or invalid options", body = ErrorOutput),
+        (status = 404, description = "Poll not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn vote_poll_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, pid)): Path<(u64, u64)>,
+    Json(input): Json<VotePoll>,
+) -> Result<impl IntoResponse, AppError> {
+    let poll = state.vote_poll(id, pid, user.id as _, input).await?;
+    Ok(Json(poll))
+}
+
+/// Take back votes on an open poll.
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/polls/{pid}/votes",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("pid" = u64, Path, description = "Poll id"),
+        UnvotePoll
+    ),
+    responses(
+        (status = 200, description = "The poll without the votes", body = Poll),
+        (status = 400, description = "Poll closed", body = ErrorOutput),
+        (status = 404, description = "Poll not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn unvote_poll_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, pid)): Path<(u64, u64)>,
+    Query(input): Query<UnvotePoll>,
+) -> Result<impl IntoResponse, AppError> {
+    let poll = state.unvote_poll(id, pid, user.id as _, input).await?;
+    Ok(Json(poll))
+}
+
+/// Close a poll before its closing time.
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/polls/{pid}/close",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("pid" = u64, Path, description = "Poll id")
+    ),
+    responses(
+        (status = 200, description = "The closed poll", body = Poll),
+        (status = 400, description = "Poll already closed", body = ErrorOutput),
+        (status = 403, description = "Not the creator of the poll", body = ErrorOutput),
+        (status = 404, description = "Poll not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn close_poll_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, pid)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let poll = state.close_poll(id, pid, user.id as _).await?;
+    Ok(Json(poll))
+}
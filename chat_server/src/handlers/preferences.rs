@@ -0,0 +1,54 @@
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+
+use crate::{AppError, AppState, UpdateChatPreferences};
+use chat_core::User;
+
+/// Get my preferences for the chat.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/preferences",
+    params(("id" = u64, Path, description = "Chat id")),
+    responses(
+        (status = 200, description = "Chat preferences", body = ChatPreferences),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn get_chat_preferences_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let preferences = state.get_chat_preferences(id, user.id as _).await?;
+    Ok(Json(preferences))
+}
+
+/// Update my preferences for the chat: mute, notification level, pin, order or hide it.
+#[utoipa::path(
+    patch,
+    path = "/api/chats/{id}/preferences",
+    params(("id" = u64, Path, description = "Chat id")),
+    request_body = UpdateChatPreferences,
+    responses(
+        (status = 200, description = "Chat preferences updated", body = ChatPreferences),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_chat_preferences_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<UpdateChatPreferences>,
+) -> Result<impl IntoResponse, AppError> {
+    let preferences = state
+        .update_chat_preferences(input, id, user.id as _)
+        .await?;
+    Ok(Json(preferences))
+}
@@ -0,0 +1,110 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension, Json};
+
+use crate::{AppError, AppState, ChangeEmail, ChangePassword, ConfirmEmail, UpdateProfile};
+use chat_core::User;
+
+/// Get my profile.
+#[utoipa::path(
+    get,
+    path = "/api/me",
+    responses(
+        (status = 200, description = "My profile", body = User),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn get_profile_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let profile = state.get_profile(user.id as _).await?;
+    Ok(Json(profile))
+}
+
+/// Update my name, avatar, status message or time zone.
+#[utoipa::path(
+    patch,
+    path = "/api/me",
+    request_body = UpdateProfile,
+    responses(
+        (status = 200, description = "Profile updated", body = User),
+        (status = 400, description = "Invalid profile", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_profile_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<UpdateProfile>,
+) -> Result<impl IntoResponse, AppError> {
+    let profile = state.update_profile(input, user.id as _).await?;
+    Ok(Json(profile))
+}
+
+/// Change my password, the current password is required.
+#[utoipa::path(
+    post,
+    path = "/api/me/password",
+    request_body = ChangePassword,
+    responses(
+        (status = 204, description = "Password changed"),
+        (status = 403, description = "Current password is incorrect", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn change_password_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<ChangePassword>,
+) -> Result<impl IntoResponse, AppError> {
+    state.change_password(input, user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Request an email change, it takes effect once confirmed with the token sent to the new email.
+#[utoipa::path(
+    post,
+    path = "/api/me/email",
+    request_body = ChangeEmail,
+    responses(
+        (status = 202, description = "Confirmation sent"),
+        (status = 403, description = "Current password is incorrect", body = ErrorOutput),
+        (status = 409, description = "Email already in use", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn change_email_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<ChangeEmail>,
+) -> Result<impl IntoResponse, AppError> {
+    let email = input.email.trim().to_string();
+    let token = state.request_email_change(input, user.id as _).await?;
+    state.send_email_change(&email, &token).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Confirm an email change with the token sent to the new email.
+#[utoipa::path(
+    post,
+    path = "/api/confirm-email",
+    request_body = ConfirmEmail,
+    responses(
+        (status = 200, description = "Email changed", body = User),
+        (status = 400, description = "Invalid or expired token", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn confirm_email_handler(
+    State(state): State<AppState>,
+    Json(input): Json<ConfirmEmail>,
+) -> Result<impl IntoResponse, AppError> {
+    let profile = state.confirm_email_change(&input.token).await?;
+    Ok(Json(profile))
+}
@@ -0,0 +1,147 @@
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+
+use crate::{AppError, AppState, RetentionPolicy};
+use chat_core::User;
+
+#[utoipa::path(
+    get,
+    path = "/api/workspace/retention",
+    responses(
+        (status = 200, description = "Retention policy of my workspace", body = RetentionPolicy),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn get_workspace_retention_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let policy = state.get_workspace_retention(user.ws_id as _).await?;
+    Ok(Json(policy))
+}
+
+/// Update retention policy of my workspace, only the workspace owner can do it.
+#[utoipa::path(
+    put,
+    path = "/api/workspace/retention",
+    responses(
+        (status = 200, description = "Retention policy updated", body = RetentionPolicy),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_workspace_retention_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<RetentionPolicy>,
+) -> Result<impl IntoResponse, AppError> {
+    state.verify_workspace_owner(&user).await?;
+    let policy = state
+        .update_workspace_retention(user.ws_id as _, input)
+        .await?;
+    Ok(Json(policy))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/retention",
+    params(("id" = u64, Path, description = "Chat id")),
+    responses(
+        (status = 200, description = "Retention policy of the chat", body = RetentionPolicy),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn get_chat_retention_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let policy = state.get_chat_retention(id).await?;
+    Ok(Json(policy))
+}
+
+/// Update retention policy of the chat, only the workspace owner can do it.
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/retention",
+    params(("id" = u64, Path, description = "Chat id")),
+    responses(
+        (status = 200, description = "Retention policy updated", body = RetentionPolicy),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_chat_retention_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<RetentionPolicy>,
+) -> Result<impl IntoResponse, AppError> {
+    state.verify_workspace_owner(&user).await?;
+    let policy = state.update_chat_retention(id, input).await?;
+    Ok(Json(policy))
+}
+
+/// Retention policy of any chat of my workspace, only the workspace owner can see it.
+#[utoipa::path(
+    get,
+    path = "/api/workspace/chats/{id}/retention",
+    params(("id" = u64, Path, description = "Chat id")),
+    responses(
+        (status = 200, description = "Retention policy of the chat", body = RetentionPolicy),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn get_workspace_chat_retention_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.verify_workspace_owner(&user).await?;
+    state.verify_workspace_chat(&user, id).await?;
+    let policy = state.get_chat_retention(id).await?;
+    Ok(Json(policy))
+}
+
+/// Update retention policy of any chat of my workspace, including chats I am not a member of.
+/// Only the workspace owner can do it.
+#[utoipa::path(
+    put,
+    path = "/api/workspace/chats/{id}/retention",
+    params(("id" = u64, Path, description = "Chat id")),
+    responses(
+        (status = 200, description = "Retention policy updated", body = RetentionPolicy),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_workspace_chat_retention_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<RetentionPolicy>,
+) -> Result<impl IntoResponse, AppError> {
+    state.verify_workspace_owner(&user).await?;
+    state.verify_workspace_chat(&user, id).await?;
+    let policy = state.update_chat_retention(id, input).await?;
+    Ok(Json(policy))
+}
@@ -0,0 +1,82 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+
+use crate::{AppError, AppState, CreateScheduledMessage};
+use chat_core::User;
+
+/// List my pending scheduled messages in the chat.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/scheduled",
+    params(("id" = u64, Path, description = "Chat id")),
+    responses(
+        (status = 200, description = "List of scheduled messages", body = Vec<ScheduledMessage>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_scheduled_messages_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let scheduled = state.list_scheduled_messages(id, user.id as _).await?;
+    Ok(Json(scheduled))
+}
+
+/// Schedule a message to be posted in the chat at `sendAt`.
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/scheduled",
+    params(("id" = u64, Path, description = "Chat id")),
+    responses(
+        (status = 201, description = "Message scheduled", body = ScheduledMessage),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_scheduled_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<CreateScheduledMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let scheduled = state
+        .create_scheduled_message(input, id, user.id as _)
+        .await?;
+    Ok((StatusCode::CREATED, Json(scheduled)))
+}
+
+/// Cancel a pending scheduled message.
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/scheduled/{sid}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("sid" = u64, Path, description = "Scheduled message id")
+    ),
+    responses(
+        (status = 200, description = "Scheduled message canceled", body = ScheduledMessage),
+        (status = 404, description = "Scheduled message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn cancel_scheduled_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, sid)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let scheduled = state
+        .cancel_scheduled_message(sid, id, user.id as _)
+        .await?;
+    Ok(Json(scheduled))
+}
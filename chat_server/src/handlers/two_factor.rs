@@ -0,0 +1,169 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    AppError, AppState, DisableTwoFactor, TwoFactorCode, TwoFactorSignin, WorkspaceTwoFactor,
+};
+use chat_core::User;
+
+use super::{too_many_attempts, AuthOutput};
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TwoFactorEnabled {
+    /// shown once, each code signs in once without the authenticator app
+    recovery_codes: Vec<String>,
+    /// replaces a token that only allowed enrolling
+    token: String,
+}
+
+/// Start enrolling in two-factor authentication.
+#[utoipa::path(
+    post,
+    path = "/api/me/2fa/enroll",
+    responses(
+        (status = 200, description = "Secret for the authenticator app", body = TwoFactorEnrollment),
+        (status = 400, description = "Already enabled", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn enroll_two_factor_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let enrollment = state.enroll_two_factor(user.id as _).await?;
+    Ok(Json(enrollment))
+}
+
+/// Enable two-factor authentication with a code from the authenticator app.
+#[utoipa::path(
+    post,
+    path = "/api/me/2fa/enable",
+    request_body = TwoFactorCode,
+    responses(
+        (status = 200, description = "Enabled", body = TwoFactorEnabled),
+        (status = 400, description = "Invalid code", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn enable_two_factor_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<TwoFactorCode>,
+) -> Result<impl IntoResponse, AppError> {
+    let recovery_codes = state.enable_two_factor(user.id as _, &input.code).await?;
+    let token = state.ek.sign(state.get_profile(user.id as _).await?)?;
+    Ok(Json(TwoFactorEnabled {
+        recovery_codes,
+        token,
+    }))
+}
+
+/// Disable two-factor authentication, not allowed if the workspace requires it.
+#[utoipa::path(
+    post,
+    path = "/api/me/2fa/disable",
+    request_body = DisableTwoFactor,
+    responses(
+        (status = 204, description = "Disabled"),
+        (status = 403, description = "Wrong password or required by the workspace", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn disable_two_factor_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<DisableTwoFactor>,
+) -> Result<impl IntoResponse, AppError> {
+    state.disable_two_factor(&user, input).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Second signin step: exchange the challenge and a code for a token.
+///
+/// A challenge works once, after a wrong code sign in again. Wrong codes count towards the
+/// signin lockout.
+#[utoipa::path(
+    post,
+    path = "/api/signin/2fa",
+    request_body = TwoFactorSignin,
+    responses(
+        (status = 200, description = "User signed in", body = AuthOutput),
+        (status = 403, description = "Invalid challenge or code", body = ErrorOutput),
+        (status = 429, description = "Too many failed attempts, retry after the `Retry-After` seconds", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn signin_two_factor_handler(
+    State(state): State<AppState>,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    Json(input): Json<TwoFactorSignin>,
+) -> Result<impl IntoResponse, AppError> {
+    let ip = addr.map(|ConnectInfo(addr)| addr.ip());
+    let user = state.take_two_factor_challenge(&input.challenge).await?;
+    if let Some(secs) = state.signin_retry_after(&user.email, ip).await? {
+        return Ok(too_many_attempts(secs));
+    }
+    state
+        .verify_two_factor_signin(&user, &input.code, ip)
+        .await?;
+    let token = state.ek.sign(user)?;
+    Ok(Json(AuthOutput { token }).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspace/2fa",
+    responses(
+        (status = 200, description = "Two-factor policy of my workspace", body = WorkspaceTwoFactor),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn get_workspace_two_factor_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let policy = state.get_workspace_two_factor(user.ws_id as _).await?;
+    Ok(Json(policy))
+}
+
+/// Require two-factor authentication for all members, only the workspace owner can do it.
+/// Members without 2FA must enroll at their next signin.
+#[utoipa::path(
+    put,
+    path = "/api/workspace/2fa",
+    request_body = WorkspaceTwoFactor,
+    responses(
+        (status = 200, description = "Two-factor policy updated", body = WorkspaceTwoFactor),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_workspace_two_factor_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<WorkspaceTwoFactor>,
+) -> Result<impl IntoResponse, AppError> {
+    state.verify_workspace_owner(&user).await?;
+    let policy = state
+        .update_workspace_two_factor(user.ws_id as _, input)
+        .await?;
+    Ok(Json(policy))
+}
@@ -0,0 +1,210 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+
+use crate::{
+    AppError, AppState, CreateIncomingWebhook, ErrorOutput, IncomingWebhookPayload,
+    UpdateIncomingWebhook,
+};
+use chat_core::User;
+
+/// Add an incoming webhook to the chat, scripts post to its secret url.
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/hooks",
+    params(("id" = u64, Path, description = "Chat id")),
+    request_body = CreateIncomingWebhook,
+    responses(
+        (status = 201, description = "Webhook created, the url is only shown once", body = CreatedIncomingWebhook),
+        (status = 400, description = "Invalid name", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn create_incoming_webhook_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<CreateIncomingWebhook>,
+) -> Result<impl IntoResponse, AppError> {
+    let webhook = state.create_incoming_webhook(id, &user, input).await?;
+    Ok((StatusCode::CREATED, Json(webhook)))
+}
+
+/// List incoming webhooks of the chat.
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/hooks",
+    params(("id" = u64, Path, description = "Chat id")),
+    responses(
+        (status = 200, description = "Webhooks, without their url", body = Vec<IncomingWebhook>),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_incoming_webhooks_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let webhooks = state.list_incoming_webhooks(id).await?;
+    Ok(Json(webhooks))
+}
+
+/// Rename, disable or enable an incoming webhook.
+#[utoipa::path(
+    patch,
+    path = "/api/chats/{id}/hooks/{hid}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("hid" = u64, Path, description = "Webhook id")
+    ),
+    request_body = UpdateIncomingWebhook,
+    responses(
+        (status = 200, description = "Webhook updated", body = IncomingWebhook),
+        (status = 403, description = "Not the creator or an admin of the chat", body = ErrorOutput),
+        (status = 404, description = "Webhook not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn update_incoming_webhook_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, hid)): Path<(u64, u64)>,
+    Json(input): Json<UpdateIncomingWebhook>,
+) -> Result<impl IntoResponse, AppError> {
+    let webhook = state.update_incoming_webhook(id, hid, &user, input).await?;
+    Ok(Json(webhook))
+}
+
+/// Replace the secret url of an incoming webhook.
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/hooks/{hid}/regenerate",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("hid" = u64, Path, description = "Webhook id")
+    ),
+    responses(
+        (status = 200, description = "New url, the old one stops working", body = CreatedIncomingWebhook),
+        (status = 403, description = "Not the creator or an admin of the chat", body = ErrorOutput),
+        (status = 404, description = "Webhook not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn regenerate_incoming_webhook_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, hid)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let webhook = state.regenerate_incoming_webhook(id, hid, &user).await?;
+    Ok(Json(webhook))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/hooks/{hid}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("hid" = u64, Path, description = "Webhook id")
+    ),
+    responses(
+        (status = 204, description = "Webhook deleted"),
+        (status = 403, description = "Not the creator or an admin of the chat", body = ErrorOutput),
+        (status = 404, description = "Webhook not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn delete_incoming_webhook_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, hid)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    state.delete_incoming_webhook(id, hid, &user).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Post a message through an incoming webhook, the secret in the url authenticates.
+#[utoipa::path(
+    post,
+    path = "/hooks/{id}/{secret}",
+    params(
+        ("id" = u64, Path, description = "Webhook id"),
+        ("secret" = String, Path, description = "Webhook secret")
+    ),
+    request_body = IncomingWebhookPayload,
+    responses(
+        (status = 201, description = "Message posted", body = Message),
+        (status = 400, description = "Invalid payload", body = ErrorOutput),
+        (status = 403, description = "Webhook disabled", body = ErrorOutput),
+        (status = 404, description = "No such webhook", body = ErrorOutput),
+        (status = 429, description = "Too many messages, see Retry-After", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn post_incoming_webhook_handler(
+    State(state): State<AppState>,
+    Path((id, secret)): Path<(u64, String)>,
+    Json(payload): Json<IncomingWebhookPayload>,
+) -> Result<impl IntoResponse, AppError> {
+    let webhook = state.verify_incoming_webhook(id, &secret).await?;
+    if let Some(secs) = state.incoming_webhook_retry_after(webhook.id).await? {
+        return Ok((
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, secs.to_string())],
+            Json(ErrorOutput::new("Too many messages, try again later")),
+        )
+            .into_response());
+    }
+    let message = state.post_incoming_webhook(&webhook, payload).await?;
+    Ok((StatusCode::CREATED, Json(message)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn post_incoming_webhook_handler_should_check_secret() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        let input = CreateIncomingWebhook {
+            name: "CI".to_string(),
+        };
+        let created = state.create_incoming_webhook(1, &user, input).await?;
+        let secret = created.url.rsplit('/').next().unwrap().to_string();
+        let payload = IncomingWebhookPayload {
+            text: "build passed".to_string(),
+            ..Default::default()
+        };
+
+        let ret = post_incoming_webhook_handler(
+            State(state.clone()),
+            Path((created.webhook.id as _, secret)),
+            Json(payload.clone()),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::CREATED);
+
+        let ret = post_incoming_webhook_handler(
+            State(state),
+            Path((created.webhook.id as _, "wrong".to_string())),
+            Json(payload),
+        )
+        .await
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::NOT_FOUND);
+        Ok(())
+    }
+}
@@ -1,4 +1,9 @@
-use axum::{extract::State, response::IntoResponse, Extension, Json};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
 
 use crate::{AppError, AppState};
 use chat_core::User;
@@ -20,3 +25,48 @@ pub(crate) async fn list_chat_users_handler(
     let users = state.fetch_chat_users(user.ws_id as _).await?;
     Ok(Json(users))
 }
+
+/// Recent signin attempts of workspace members, only the workspace owner can see them.
+#[utoipa::path(
+    get,
+    path = "/api/workspace/signin-attempts",
+    responses(
+        (status = 200, description = "Signin attempts, newest first", body = Vec<SigninAttempt>),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn list_signin_attempts_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    state.verify_workspace_owner(&user).await?;
+    let attempts = state.list_signin_attempts(user.ws_id as _).await?;
+    Ok(Json(attempts))
+}
+
+/// Lift the signin lockout of a member, only the workspace owner can do it.
+#[utoipa::path(
+    post,
+    path = "/api/workspace/users/{id}/unlock",
+    params(("id" = u64, Path, description = "User id")),
+    responses(
+        (status = 204, description = "Unlocked"),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+        (status = 404, description = "No such member", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    )
+)]
+pub(crate) async fn unlock_user_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    state.verify_workspace_owner(&user).await?;
+    state.unlock_user(user.ws_id as _, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
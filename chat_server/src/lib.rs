@@ -1,30 +1,43 @@
 mod config;
 mod error;
 mod handlers;
+mod mailer;
+mod metrics;
 mod middlewares;
 mod models;
 mod openapi;
+mod storage;
+mod tasks;
 
 use anyhow::Context;
-use chat_core::{set_layer, verify_token, DecodingKey, EncodingKey, TokenVerify, User};
+use chat_core::{
+    require_two_factor_enrolled, set_layer, verify_token, DecodingKey, EncodingKey, RateLimitLayer,
+    RateLimitStore, TokenVerify, TwoFactorPolicy, User,
+};
+use config::RateLimitBackend;
 use handlers::*;
-use middlewares::verify_chat;
+use metrics::Metrics;
+use middlewares::{verify_chat, verify_token_scope};
 use openapi::OpenApiRouter;
 use sqlx::PgPool;
 use std::{fmt, ops::Deref, sync::Arc};
-use tokio::fs;
+use storage::Storage;
 use tower_http::cors::{Any, CorsLayer};
 
 pub use error::{AppError, ErrorOutput};
+pub use mailer::{Mail, Mailer};
 pub use models::*;
+pub use tasks::spawn_background_tasks;
 
 use axum::{
+    extract::DefaultBodyLimit,
+    handler::Handler,
     http::Method,
-    middleware::from_fn_with_state,
-    routing::{get, post},
+    middleware::{from_fn, from_fn_with_state},
+    routing::{delete, get, patch, post, put},
     Router,
 };
-pub use config::AppConfig;
+pub use config::{AppConfig, OidcConfig};
 
 #[derive(Debug, Clone)]
 pub struct AppState {
@@ -37,6 +50,12 @@ pub struct AppStateInner {
     pub(crate) dk: DecodingKey,
     pub(crate) ek: EncodingKey,
     pub(crate) pool: PgPool,
+    pub(crate) mailer: Mailer,
+    pub(crate) rate_limits: RateLimitStore,
+    pub(crate) storage: Storage,
+    pub(crate) metrics: Metrics,
+    /// for requests to user configured services, e.g. slash commands
+    pub(crate) http: reqwest::Client,
 }
 
 pub async fn get_router(state: AppState) -> Result<Router, AppError> {
@@ -45,10 +64,53 @@ pub async fn get_router(state: AppState) -> Result<Router, AppError> {
             "/:id",
             get(get_chat_handler)
                 .patch(update_chat_handler)
-                .delete(delete_chat_handler)
-                .post(send_message_handler),
+                .delete(archive_chat_handler)
+                .post(send_message_handler.layer(state.rate_limit("send_message"))),
+        )
+        .route("/:id/unarchive", post(unarchive_chat_handler))
+        .route("/:id/members/:uid", put(update_chat_member_handler))
+        .route(
+            "/:id/preferences",
+            get(get_chat_preferences_handler).patch(update_chat_preferences_handler),
         )
         .route("/:id/messages", get(list_message_handler))
+        .route("/:id/export", get(export_chat_handler))
+        .route("/:id/pins", get(list_pins_handler))
+        .route(
+            "/:id/pins/:mid",
+            post(pin_message_handler).delete(unpin_message_handler),
+        )
+        .route("/:id/polls", post(create_poll_handler))
+        .route("/:id/polls/:pid", get(get_poll_handler))
+        .route(
+            "/:id/polls/:pid/votes",
+            post(vote_poll_handler).delete(unvote_poll_handler),
+        )
+        .route("/:id/polls/:pid/close", post(close_poll_handler))
+        .route(
+            "/:id/scheduled",
+            get(list_scheduled_messages_handler).post(create_scheduled_message_handler),
+        )
+        .route(
+            "/:id/scheduled/:sid",
+            delete(cancel_scheduled_message_handler),
+        )
+        .route(
+            "/:id/hooks",
+            get(list_incoming_webhooks_handler).post(create_incoming_webhook_handler),
+        )
+        .route(
+            "/:id/hooks/:hid",
+            patch(update_incoming_webhook_handler).delete(delete_incoming_webhook_handler),
+        )
+        .route(
+            "/:id/hooks/:hid/regenerate",
+            post(regenerate_incoming_webhook_handler),
+        )
+        .route(
+            "/:id/retention",
+            get(get_chat_retention_handler).put(update_chat_retention_handler),
+        )
         .layer(from_fn_with_state(state.clone(), verify_chat))
         .route("/", get(list_chat_handler).post(create_chat_handler));
 
@@ -65,21 +127,106 @@ pub async fn get_router(state: AppState) -> Result<Router, AppError> {
 
     let api = Router::new()
         .route("/users", get(list_chat_users_handler))
+        .route(
+            "/me",
+            get(get_profile_handler).patch(update_profile_handler),
+        )
+        .route("/me/password", post(change_password_handler))
+        .route("/me/email", post(change_email_handler))
+        .route(
+            "/workspace/retention",
+            get(get_workspace_retention_handler).put(update_workspace_retention_handler),
+        )
+        .route("/workspace/chats/:id", delete(delete_chat_handler))
+        .route(
+            "/workspace/chats/:id/retention",
+            get(get_workspace_chat_retention_handler).put(update_workspace_chat_retention_handler),
+        )
+        .route(
+            "/workspace/signin-attempts",
+            get(list_signin_attempts_handler),
+        )
+        .route("/workspace/users/:id/unlock", post(unlock_user_handler))
+        .route(
+            "/workspace/import",
+            post(import_slack_handler).layer(DefaultBodyLimit::max(IMPORT_BODY_LIMIT)),
+        )
         .nest("/chats", chat)
-        .route("/upload", post(upload_handler))
+        .route(
+            "/bookmarks",
+            get(list_bookmarks_handler).post(create_bookmark_handler),
+        )
+        .route("/bookmarks/:id", delete(delete_bookmark_handler))
+        .route(
+            "/upload",
+            post(upload_handler).layer(state.rate_limit("upload")),
+        )
         .route("/files/:ws_id/*path", get(file_handler))
+        .route("/me/2fa/disable", post(disable_two_factor_handler))
+        .route(
+            "/tokens",
+            get(list_access_tokens_handler).post(create_access_token_handler),
+        )
+        .route("/tokens/:id", delete(revoke_access_token_handler))
+        .route("/bots", get(list_bots_handler).post(create_bot_handler))
+        .route(
+            "/webhooks",
+            get(list_webhook_subscriptions_handler).post(create_webhook_subscription_handler),
+        )
+        .route("/webhooks/:id", delete(delete_webhook_subscription_handler))
+        .route(
+            "/webhooks/:id/deliveries",
+            get(list_webhook_deliveries_handler),
+        )
+        .route(
+            "/webhooks/:id/deliveries/:did/redeliver",
+            post(redeliver_webhook_handler),
+        )
+        .route(
+            "/commands",
+            get(list_commands_handler).post(register_command_handler),
+        )
+        .route("/commands/:id", delete(delete_command_handler))
+        .route(
+            "/workspace/2fa",
+            get(get_workspace_two_factor_handler).put(update_workspace_two_factor_handler),
+        )
+        .layer(from_fn_with_state(
+            state.clone(),
+            require_two_factor_enrolled::<AppState>,
+        ))
+        // reachable with a token that only allows enrolling
+        .route("/me/2fa/enroll", post(enroll_two_factor_handler))
+        .route("/me/2fa/enable", post(enable_two_factor_handler))
+        .layer(from_fn(verify_token_scope))
         .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
         .route("/signin", post(signin_handler))
+        .route("/signin/2fa", post(signin_two_factor_handler))
         .route("/signup", post(signup_handler))
+        .route("/confirm-email", post(confirm_email_handler))
+        .route("/password/forgot", post(forgot_password_handler))
+        .route("/password/reset", post(reset_password_handler))
+        .route("/email/verify", post(verify_email_handler))
+        .route("/oidc/login", get(oidc_login_handler))
+        .route("/oidc/callback", get(oidc_callback_handler))
         .layer(cors);
     let app = Router::new()
         .openapi()
         .route("/", get(index_handler))
         .nest("/api", api)
+        // the secret in the url authenticates, scripts post here without a token
+        .route("/hooks/:id/:secret", post(post_incoming_webhook_handler))
         .with_state(state);
     Ok(set_layer(app))
 }
 
+/// Internal endpoints served on `server.metrics_port`, apart from the public api
+pub fn get_metrics_router(state: AppState) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics_handler))
+        .with_state(state)
+}
+
 impl Deref for AppState {
     type Target = AppStateInner;
 
@@ -91,30 +238,60 @@ impl Deref for AppState {
 impl TokenVerify for AppState {
     type Error = AppError;
 
-    fn verify(&self, token: &str) -> Result<User, Self::Error> {
+    async fn verify(&self, token: &str) -> Result<User, Self::Error> {
+        if is_access_token(token) {
+            return self.verify_access_token(token).await;
+        }
         Ok(self.dk.verify(token)?)
     }
 }
 
+impl TwoFactorPolicy for AppState {
+    type Error = AppError;
+
+    async fn two_factor_pending(&self, user: &User) -> Result<bool, Self::Error> {
+        self.is_two_factor_pending(user.id as _).await
+    }
+}
+
 impl AppState {
     pub async fn try_new(config: AppConfig) -> Result<Self, AppError> {
-        fs::create_dir_all(&config.server.base_dir)
-            .await
-            .context("create base dir failed")?;
         let dk = DecodingKey::load(&config.auth.pk).context("load pd failed")?;
         let ek = EncodingKey::load(&config.auth.sk).context("load sk failed")?;
         let pool = PgPool::connect(&config.server.db_url)
             .await
             .context("connect to db failed")?;
+        let mailer = Mailer::try_new(&config.mail)?;
+        let rate_limits = match config.rate_limit.backend {
+            RateLimitBackend::Memory => RateLimitStore::memory(),
+            RateLimitBackend::Postgres => RateLimitStore::postgres(pool.clone()),
+        };
+        let storage = Storage::try_new(&config).await?;
+        let http = config
+            .outbound
+            .client_builder()
+            .build()
+            .context("build http client failed")?;
         Ok(Self {
             inner: Arc::new(AppStateInner {
                 config,
                 dk,
                 ek,
                 pool,
+                mailer,
+                rate_limits,
+                storage,
+                metrics: Metrics::default(),
+                http,
             }),
         })
     }
+
+    /// Rate limit of a route, it only limits if the config has a quota for it
+    pub(crate) fn rate_limit(&self, route: &str) -> RateLimitLayer {
+        let quota = self.config.rate_limit.routes.get(route).copied();
+        RateLimitLayer::new(route, quota, self.rate_limits.clone())
+    }
 }
 
 impl fmt::Debug for AppStateInner {
@@ -133,18 +310,33 @@ mod test_util {
     use super::*;
     impl AppState {
         pub async fn new_for_test() -> Result<(TestPg, Self), AppError> {
-            let config = AppConfig::load()?;
+            Self::new_for_test_with(AppConfig::load()?).await
+        }
+
+        /// Like `new_for_test` but with a config the test adjusted, e.g. to point at mock servers
+        pub async fn new_for_test_with(config: AppConfig) -> Result<(TestPg, Self), AppError> {
             let dk = DecodingKey::load(&config.auth.pk).context("load pd failed")?;
             let ek = EncodingKey::load(&config.auth.sk).context("load sk failed")?;
             let post = config.server.db_url.rfind('/').unwrap();
             let server_url = &config.server.db_url[..post];
             let (tdb, pool) = get_test_pool(Some(server_url)).await;
+            let storage = Storage::try_new(&config).await?;
+            let http = config
+                .outbound
+                .client_builder()
+                .build()
+                .context("build http client failed")?;
             let state = Self {
                 inner: Arc::new(AppStateInner {
                     config,
                     dk,
                     ek,
                     pool,
+                    mailer: Mailer::memory(),
+                    rate_limits: RateLimitStore::memory(),
+                    storage,
+                    metrics: Metrics::default(),
+                    http,
                 }),
             };
             Ok((tdb, state))
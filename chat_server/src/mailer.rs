@@ -0,0 +1,150 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use chrono::Utc;
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message as Email, Tokio1Executor,
+};
+use tracing::info;
+
+use crate::{
+    config::{MailBackend, MailConfig},
+    AppError,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Sends plain text mails through the backend chosen in the config
+pub enum Mailer {
+    Smtp {
+        transport: AsyncSmtpTransport<Tokio1Executor>,
+        from: Mailbox,
+    },
+    /// one `.eml` file per mail, for development
+    File { dir: PathBuf, from: String },
+    /// keeps sent mails, for tests
+    Memory(Arc<Mutex<Vec<Mail>>>),
+}
+
+impl Mailer {
+    pub fn try_new(config: &MailConfig) -> Result<Self, AppError> {
+        let mailer = match &config.backend {
+            MailBackend::Smtp {
+                host,
+                port,
+                username,
+                password,
+                starttls,
+            } => {
+                let builder = if *starttls {
+                    AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+                } else {
+                    AsyncSmtpTransport::<Tokio1Executor>::relay(host)
+                }
+                .map_err(|e| AppError::MailError(e.to_string()))?;
+                let transport = builder
+                    .port(*port)
+                    .credentials(Credentials::new(username.clone(), password.clone()))
+                    .build();
+                let from = config
+                    .from
+                    .parse()
+                    .map_err(|e| AppError::MailError(format!("invalid from address: {}", e)))?;
+                Self::Smtp { transport, from }
+            }
+            MailBackend::File { dir } => Self::File {
+                dir: dir.clone(),
+                from: config.from.clone(),
+            },
+            MailBackend::Memory => Self::memory(),
+        };
+        Ok(mailer)
+    }
+
+    pub fn memory() -> Self {
+        Self::Memory(Default::default())
+    }
+
+    pub async fn send(&self, mail: Mail) -> Result<(), AppError> {
+        match self {
+            Self::Smtp { transport, from } => {
+                let to = mail
+                    .to
+                    .parse()
+                    .map_err(|e| AppError::MailError(format!("invalid address: {}", e)))?;
+                let email = Email::builder()
+                    .from(from.clone())
+                    .to(to)
+                    .subject(mail.subject)
+                    .body(mail.body)
+                    .map_err(|e| AppError::MailError(e.to_string()))?;
+                transport
+                    .send(email)
+                    .await
+                    .map_err(|e| AppError::MailError(e.to_string()))?;
+            }
+            Self::File { dir, from } => {
+                tokio::fs::create_dir_all(dir).await?;
+                let path = dir.join(format!(
+                    "{}.eml",
+                    Utc::now().timestamp_nanos_opt().unwrap_or_default()
+                ));
+                let content = format!(
+                    "From: {}\r\nTo: {}\r\nSubject: {}\r\n\r\n{}\r\n",
+                    from, mail.to, mail.subject, mail.body
+                );
+                tokio::fs::write(&path, content).await?;
+                info!("mail to {} written to {:?}", mail.to, path);
+            }
+            Self::Memory(sent) => {
+                info!("mail to {} kept in memory: {}", mail.to, mail.subject);
+                sent.lock().expect("mailer lock poisoned").push(mail);
+            }
+        }
+        Ok(())
+    }
+
+    /// Mails sent so far by the memory backend
+    pub fn sent(&self) -> Vec<Mail> {
+        match self {
+            Self::Memory(sent) => sent.lock().expect("mailer lock poisoned").clone(),
+            _ => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn file_mailer_should_write_eml() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("chat-mail-{}", std::process::id()));
+        let config = MailConfig {
+            backend: MailBackend::File { dir: dir.clone() },
+            ..Default::default()
+        };
+        let mailer = Mailer::try_new(&config)?;
+        let mail = Mail {
+            to: "alice@acme.org".to_string(),
+            subject: "hello".to_string(),
+            body: "hello alice".to_string(),
+        };
+        mailer.send(mail).await?;
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        let entry = entries.next_entry().await?.expect("no mail written");
+        let content = tokio::fs::read_to_string(entry.path()).await?;
+        assert!(content.contains("To: alice@acme.org"));
+        assert!(content.ends_with("hello alice\r\n"));
+        tokio::fs::remove_dir_all(&dir).await?;
+        Ok(())
+    }
+}
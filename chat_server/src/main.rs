@@ -1,5 +1,7 @@
+use std::net::SocketAddr;
+
 use anyhow::Result;
-use chat_server::{get_router, AppConfig, AppState};
+use chat_server::{get_metrics_router, get_router, spawn_background_tasks, AppConfig, AppState};
 use tokio::net::TcpListener;
 use tracing::{info, level_filters::LevelFilter};
 use tracing_subscriber::{
@@ -12,12 +14,26 @@ async fn main() -> Result<()> {
     tracing_subscriber::registry().with(layer).init();
     let config = AppConfig::load()?;
     let addr = format!("0.0.0.0:{}", config.server.port);
+    let metrics_port = config.server.metrics_port;
 
     let state = AppState::try_new(config).await?;
+    spawn_background_tasks(state.clone());
+
+    if let Some(port) = metrics_port {
+        let addr = format!("0.0.0.0:{}", port);
+        let listener = TcpListener::bind(&addr).await?;
+        info!("Serving metrics on: {}", addr);
+        let app = get_metrics_router(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+    }
 
     let app = get_router(state).await?;
     let listener = TcpListener::bind(&addr).await?;
     info!("Listening on: {}", addr);
-    axum::serve(listener, app.into_make_service()).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
     Ok(())
 }
@@ -0,0 +1,91 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicI64, AtomicU64, Ordering},
+};
+
+use chrono::Utc;
+
+use crate::PurgeReport;
+
+/// Counters of the background jobs, served in the Prometheus text format at `/metrics`
+/// of the internal listener
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    retention_purges: AtomicU64,
+    retention_purged_messages: AtomicU64,
+    retention_purged_files: AtomicU64,
+    retention_last_purge: AtomicI64,
+}
+
+impl Metrics {
+    pub(crate) fn record_purge(&self, report: &PurgeReport) {
+        self.retention_purges.fetch_add(1, Ordering::Relaxed);
+        self.retention_purged_messages
+            .fetch_add(report.messages, Ordering::Relaxed);
+        self.retention_purged_files
+            .fetch_add(report.files, Ordering::Relaxed);
+        self.retention_last_purge
+            .store(Utc::now().timestamp(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn render(&self) -> String {
+        let mut out = String::new();
+        let metrics = [
+            (
+                "chat_retention_purges_total",
+                "counter",
+                "Retention purge runs",
+                self.retention_purges.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "chat_retention_purged_messages_total",
+                "counter",
+                "Messages deleted by the retention purge",
+                self.retention_purged_messages.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "chat_retention_purged_files_total",
+                "counter",
+                "Files removed by the retention purge",
+                self.retention_purged_files.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "chat_retention_last_purge_timestamp_seconds",
+                "gauge",
+                "Unix time the last retention purge finished, 0 if none has",
+                self.retention_last_purge.load(Ordering::Relaxed),
+            ),
+        ];
+        for (name, kind, help, value) in metrics {
+            // writing to a String can not fail
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_should_render_purges() {
+        let metrics = Metrics::default();
+        let report = PurgeReport {
+            chats: 1,
+            messages: 10,
+            files: 2,
+        };
+        metrics.record_purge(&report);
+        metrics.record_purge(&report);
+        let out = metrics.render();
+        assert!(out.contains(
+            "# TYPE chat_retention_purges_total counter\nchat_retention_purges_total 2\n"
+        ));
+        assert!(out.contains("\nchat_retention_purged_messages_total 20\n"));
+        assert!(out.contains("\nchat_retention_purged_files_total 4\n"));
+        assert!(!out.contains("chat_retention_last_purge_timestamp_seconds 0\n"));
+    }
+}
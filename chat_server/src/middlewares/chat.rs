@@ -4,12 +4,20 @@ use axum::{
     response::{IntoResponse as _, Response},
 };
 
+use serde::Deserialize;
+
 use crate::{AppError, AppState};
 use chat_core::User;
 
+/// Only the chat id is needed, other path params (e.g. message id) are ignored
+#[derive(Debug, Deserialize)]
+struct ChatPath {
+    id: u64,
+}
+
 pub async fn verify_chat(State(state): State<AppState>, req: Request, next: Next) -> Response {
     let (mut parts, body) = req.into_parts();
-    let Path(chat_id) = Path::<u64>::from_request_parts(&mut parts, &state)
+    let Path(ChatPath { id: chat_id }) = Path::<ChatPath>::from_request_parts(&mut parts, &state)
         .await
         .unwrap();
     let user = parts.extensions.get::<User>().unwrap();
@@ -50,6 +58,7 @@ mod tests {
         let token = state.ek.sign(user)?;
         let app = Router::new()
             .route("/chat/:id/messages", get(handler))
+            .route("/chat/:id/pins/:mid", get(handler))
             .layer(from_fn_with_state(state.clone(), verify_chat))
             .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
             .with_state(state);
@@ -70,6 +79,14 @@ mod tests {
         let res = app.clone().oneshot(req).await?;
         assert_eq!(res.status(), StatusCode::BAD_REQUEST);
 
+        // extra path params are ignored
+        let req = Request::builder()
+            .uri("/chat/1/pins/2")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
         Ok(())
     }
 }
@@ -1,3 +1,5 @@
 mod chat;
+mod token_scope;
 
 pub use chat::*;
+pub use token_scope::*;
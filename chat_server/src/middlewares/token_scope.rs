@@ -0,0 +1,82 @@
+use axum::{
+    extract::{OriginalUri, Request},
+    middleware::Next,
+    response::{IntoResponse as _, Response},
+};
+
+use crate::AppError;
+use chat_core::{TokenScope, User};
+
+/// Access tokens only reach the routes their scopes allow, everything else needs a session
+/// token. Must run after `verify_token`.
+pub async fn verify_token_scope(req: Request, next: Next) -> Response {
+    let Some(scopes) = req
+        .extensions()
+        .get::<User>()
+        .and_then(|u| u.scopes.as_ref())
+    else {
+        return next.run(req).await;
+    };
+    // nested routers see the path without their prefix
+    let path = match req.extensions().get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri.path(),
+        None => req.uri().path(),
+    };
+    match required_scope(req.method().as_str(), path) {
+        Some(scope) if scopes.contains(&scope) => {}
+        _ => {
+            let msg = format!("access token can not {} {}", req.method(), path);
+            return AppError::PermissionDenied(msg).into_response();
+        }
+    }
+    next.run(req).await
+}
+
+fn required_scope(method: &str, path: &str) -> Option<TokenScope> {
+    let path = path.strip_prefix("/api").unwrap_or(path);
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        ("GET", ["users"])
+        | ("GET", ["chats"])
+        | ("GET", ["chats", _])
+        | ("GET", ["chats", _, "messages"])
+        | ("GET", ["files", ..]) => Some(TokenScope::ReadChats),
+        ("POST", ["chats", _]) => Some(TokenScope::PostMessages),
+        ("POST", ["upload"]) => Some(TokenScope::UploadFiles),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn required_scope_should_only_cover_token_routes() {
+        assert_eq!(
+            required_scope("GET", "/api/chats/1/messages"),
+            Some(TokenScope::ReadChats)
+        );
+        assert_eq!(
+            required_scope("GET", "/api/files/1/abc/def/0123.png"),
+            Some(TokenScope::ReadChats)
+        );
+        assert_eq!(
+            required_scope("POST", "/api/chats/1"),
+            Some(TokenScope::PostMessages)
+        );
+        assert_eq!(
+            required_scope("POST", "/api/upload"),
+            Some(TokenScope::UploadFiles)
+        );
+        for (method, path) in [
+            ("PATCH", "/api/chats/1"),
+            ("POST", "/api/chats"),
+            ("POST", "/api/tokens"),
+            ("GET", "/api/me"),
+            ("POST", "/api/chats/1/pins/2"),
+        ] {
+            assert_eq!(required_scope(method, path), None);
+        }
+    }
+}
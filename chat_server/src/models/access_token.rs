@@ -0,0 +1,323 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use crate::{AppError, AppState};
+use chat_core::{ChatUser, TokenScope, User};
+
+use super::profile::hash_token;
+use super::user::hash_password;
+
+const TOKEN_PREFIX: &str = "chat_pat_";
+const MAX_TOKEN_NAME_LEN: usize = 64;
+const MAX_EXPIRES_IN_DAYS: u32 = 366;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateAccessToken {
+    /// what the token is for, e.g. `deploy notifications`
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    /// the token never expires if not given
+    #[serde(default)]
+    pub expires_in_days: Option<u32>,
+    /// create the token for a bot of my workspace instead of me, only the workspace owner can
+    #[serde(default)]
+    pub bot_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessToken {
+    pub id: i64,
+    /// the user the token acts as
+    pub user_id: i64,
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedAccessToken {
+    #[serde(flatten)]
+    pub access_token: AccessToken,
+    /// only shown once, use it as `Authorization: Bearer <token>`
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreateBot {
+    pub fullname: String,
+}
+
+impl AppState {
+    pub async fn create_access_token(
+        &self,
+        user: &User,
+        input: CreateAccessToken,
+    ) -> Result<CreatedAccessToken, AppError> {
+        let name = input.name.trim();
+        if name.is_empty() || name.chars().count() > MAX_TOKEN_NAME_LEN {
+            return Err(AppError::AccessTokenError(format!(
+                "Name must be between 1 and {} characters",
+                MAX_TOKEN_NAME_LEN
+            )));
+        }
+        let mut scopes = input.scopes;
+        scopes.sort_by_key(|scope| *scope as u8);
+        scopes.dedup();
+        if scopes.is_empty() {
+            return Err(AppError::AccessTokenError(
+                "At least one scope is required".to_string(),
+            ));
+        }
+        let expires_at = match input.expires_in_days {
+            Some(days) if days == 0 || days > MAX_EXPIRES_IN_DAYS => {
+                return Err(AppError::AccessTokenError(format!(
+                    "Tokens expire within 1 to {} days",
+                    MAX_EXPIRES_IN_DAYS
+                )));
+            }
+            Some(days) => Some(Utc::now() + Duration::days(days as _)),
+            None => None,
+        };
+        let user_id = match input.bot_id {
+            Some(bot_id) => {
+                self.verify_workspace_owner(user).await?;
+                match self.find_user_by_id(bot_id).await? {
+                    Some(bot) if bot.is_bot && bot.ws_id == user.ws_id => bot.id,
+                    _ => return Err(AppError::NotFound(format!("bot {}", bot_id))),
+                }
+            }
+            None => user.id,
+        };
+
+        let token = format!("{}{}", TOKEN_PREFIX, random_hex(32));
+        let access_token = sqlx::query_as(
+            r#"
+        INSERT INTO access_tokens (user_id, created_by, name, token_hash, scopes, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, user_id, name, scopes, expires_at, last_used_at, created_at
+        "#,
+        )
+        .bind(user_id)
+        .bind(user.id)
+        .bind(name)
+        .bind(hash_token(&token))
+        .bind(&scopes)
+        .bind(expires_at)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(CreatedAccessToken {
+            access_token,
+            token,
+        })
+    }
+
+    /// My tokens and the bot tokens I created
+    pub async fn list_access_tokens(&self, user_id: u64) -> Result<Vec<AccessToken>, AppError> {
+        let tokens = sqlx::query_as(
+            r#"
+        SELECT id, user_id, name, scopes, expires_at, last_used_at, created_at
+        FROM access_tokens
+        WHERE user_id = $1 OR created_by = $1
+        ORDER BY id
+        "#,
+        )
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(tokens)
+    }
+
+    pub async fn revoke_access_token(&self, id: u64, user_id: u64) -> Result<(), AppError> {
+        let ret = sqlx::query(
+            "DELETE FROM access_tokens WHERE id = $1 AND (user_id = $2 OR created_by = $2)",
+        )
+        .bind(id as i64)
+        .bind(user_id as i64)
+        .execute(&self.pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("access token {}", id)));
+        }
+        Ok(())
+    }
+
+    /// The user of a valid access token, with the scopes of the token
+    pub async fn verify_access_token(&self, token: &str) -> Result<User, AppError> {
+        let ret: Option<(i64, Vec<TokenScope>)> = sqlx::query_as(
+            r#"
+        UPDATE access_tokens SET last_used_at = now()
+        WHERE token_hash = $1 AND (expires_at IS NULL OR expires_at > now())
+        RETURNING user_id, scopes
+        "#,
+        )
+        .bind(hash_token(token))
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some((user_id, scopes)) = ret else {
+            return Err(AppError::PermissionDenied(
+                "invalid or expired access token".to_string(),
+            ));
+        };
+        let mut user = self.get_profile(user_id as _).await?;
+        user.scopes = Some(scopes);
+        Ok(user)
+    }
+
+    /// Add a bot to the workspace of the owner, it acts through access tokens only
+    pub async fn create_bot(&self, owner: &User, input: CreateBot) -> Result<User, AppError> {
+        self.verify_workspace_owner(owner).await?;
+        let fullname = input.fullname.trim();
+        if fullname.is_empty() || fullname.chars().count() > 64 {
+            return Err(AppError::AccessTokenError(
+                "Bot name must be between 1 and 64 characters".to_string(),
+            ));
+        }
+        let id = self.insert_bot(owner.ws_id, fullname).await?;
+        self.get_profile(id as _).await
+    }
+
+    /// A bot user in the workspace, also used by incoming webhooks to post as
+    pub(crate) async fn insert_bot(&self, ws_id: i64, fullname: &str) -> Result<i64, AppError> {
+        // bots need a unique email but never receive mail or sign in
+        let email = format!("bot-{}@bots.invalid", random_hex(8));
+        let password_hash = hash_password(&random_hex(16))?;
+        let (id,): (i64,) = sqlx::query_as(
+            r#"
+        INSERT INTO users (ws_id, email, fullname, password_hash, is_bot, email_verified_at)
+        VALUES ($1, $2, $3, $4, true, now())
+        RETURNING id
+        "#,
+        )
+        .bind(ws_id)
+        .bind(email)
+        .bind(fullname)
+        .bind(password_hash)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(id)
+    }
+
+    pub async fn list_bots(&self, ws_id: u64) -> Result<Vec<ChatUser>, AppError> {
+        let bots = self
+            .fetch_chat_users(ws_id)
+            .await?
+            .into_iter()
+            .filter(|user| user.is_bot)
+            .collect();
+        Ok(bots)
+    }
+}
+
+/// Access tokens are opaque, unlike the session JWTs
+pub fn is_access_token(token: &str) -> bool {
+    token.starts_with(TOKEN_PREFIX)
+}
+
+pub(crate) fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn input(scopes: Vec<TokenScope>) -> CreateAccessToken {
+        CreateAccessToken {
+            name: "ci".to_string(),
+            scopes,
+            expires_in_days: Some(30),
+            bot_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn access_token_should_verify_until_revoked() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        let created = state
+            .create_access_token(&user, input(vec![TokenScope::PostMessages]))
+            .await?;
+        assert!(is_access_token(&created.token));
+        assert!(created.access_token.last_used_at.is_none());
+
+        let verified = state.verify_access_token(&created.token).await?;
+        assert_eq!(verified.id, user.id);
+        assert_eq!(verified.scopes, Some(vec![TokenScope::PostMessages]));
+        let tokens = state.list_access_tokens(user.id as _).await?;
+        assert_eq!(tokens.len(), 1);
+        assert!(tokens[0].last_used_at.is_some());
+
+        // only the owner of the token can revoke it
+        assert!(state
+            .revoke_access_token(created.access_token.id as _, 2)
+            .await
+            .is_err());
+        state
+            .revoke_access_token(created.access_token.id as _, user.id as _)
+            .await?;
+        assert!(state.verify_access_token(&created.token).await.is_err());
+        assert!(state.verify_access_token("chat_pat_bogus").await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_access_token_should_validate_input() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        assert!(state
+            .create_access_token(&user, input(vec![]))
+            .await
+            .is_err());
+        let mut bad = input(vec![TokenScope::ReadChats]);
+        bad.expires_in_days = Some(0);
+        assert!(state.create_access_token(&user, bad).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bot_tokens_should_act_as_the_bot() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        let member = state.find_user_by_id(2).await?.unwrap();
+        let bot = state
+            .create_bot(
+                &owner,
+                CreateBot {
+                    fullname: "CI".to_string(),
+                },
+            )
+            .await?;
+        assert!(bot.is_bot);
+        assert_eq!(state.list_bots(owner.ws_id as _).await?.len(), 1);
+        let create = CreateBot {
+            fullname: "CI".to_string(),
+        };
+        assert!(state.create_bot(&member, create).await.is_err());
+
+        let mut create = input(vec![TokenScope::PostMessages]);
+        create.bot_id = Some(bot.id);
+        assert!(state
+            .create_access_token(&member, create.clone())
+            .await
+            .is_err());
+        let created = state.create_access_token(&owner, create).await?;
+        let verified = state.verify_access_token(&created.token).await?;
+        assert_eq!(verified.id, bot.id);
+        assert!(verified.is_bot);
+        // the creator sees and may revoke it
+        assert_eq!(state.list_access_tokens(owner.id as _).await?.len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,243 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use crate::{AppError, AppState};
+use chat_core::Message;
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Bookmark {
+    #[sqlx(rename = "bookmark_id")]
+    pub id: i64,
+    pub user_id: i64,
+    pub note: Option<String>,
+    pub remind_at: Option<DateTime<Utc>>,
+    pub reminded_at: Option<DateTime<Utc>>,
+    pub bookmarked_at: DateTime<Utc>,
+    #[sqlx(flatten)]
+    pub message: Message,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateBookmark {
+    pub message_id: u64,
+    #[serde(default)]
+    pub note: Option<String>,
+    /// when to remind the user about the message
+    #[serde(default)]
+    pub remind_at: Option<DateTime<Utc>>,
+}
+
+impl AppState {
+    pub async fn create_bookmark(
+        &self,
+        input: CreateBookmark,
+        user_id: u64,
+    ) -> Result<Bookmark, AppError> {
+        let chat_id: Option<(i64,)> =
+            sqlx::query_as(r#"SELECT chat_id FROM messages WHERE id = $1"#)
+                .bind(input.message_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+        // do not leak messages of chats the user can not read
+        let readable = match chat_id {
+            Some((chat_id,)) => self.is_chat_member(chat_id as _, user_id).await?,
+            None => false,
+        };
+        if !readable {
+            return Err(AppError::NotFound(format!(
+                "message {} not found",
+                input.message_id
+            )));
+        }
+        if let Some(note) = &input.note {
+            if note.chars().count() > 256 {
+                return Err(AppError::BookmarkError(
+                    "Note must be at most 256 characters".to_string(),
+                ));
+            }
+        }
+
+        let (id,): (i64,) = sqlx::query_as(
+            r#"
+        INSERT INTO bookmarks (user_id, message_id, note, remind_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (user_id, message_id)
+        DO UPDATE SET note = EXCLUDED.note, remind_at = EXCLUDED.remind_at, reminded_at = NULL
+        RETURNING id
+        "#,
+        )
+        .bind(user_id as i64)
+        .bind(input.message_id as i64)
+        .bind(&input.note)
+        .bind(input.remind_at)
+        .fetch_one(&self.pool)
+        .await?;
+        self.get_bookmark_by_id(id as _, user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("bookmark {} not found", id)))
+    }
+
+    pub async fn list_bookmarks(&self, user_id: u64) -> Result<Vec<Bookmark>, AppError> {
+        let bookmarks = sqlx::query_as(
+            r#"
+        SELECT b.id as bookmark_id, b.user_id, b.note, b.remind_at, b.reminded_at,
+            b.created_at as bookmarked_at, m.id, m.chat_id, m.sender_id, m.sender_name,
+            m.poll_id, m.content, m.files, m.format, m.html, m.attachments, m.expires_at, m.created_at
+        FROM bookmarks b
+        JOIN messages m ON m.id = b.message_id
+        JOIN chat_members cm ON cm.chat_id = m.chat_id AND cm.user_id = b.user_id
+        WHERE b.user_id = $1 AND (m.expires_at IS NULL OR m.expires_at > now())
+        ORDER BY b.created_at DESC
+        "#,
+        )
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(bookmarks)
+    }
+
+    pub async fn get_bookmark_by_id(
+        &self,
+        id: u64,
+        user_id: u64,
+    ) -> Result<Option<Bookmark>, AppError> {
+        let bookmark = sqlx::query_as(
+            r#"
+        SELECT b.id as bookmark_id, b.user_id, b.note, b.remind_at, b.reminded_at,
+            b.created_at as bookmarked_at, m.id, m.chat_id, m.sender_id, m.sender_name,
+            m.poll_id, m.content, m.files, m.format, m.html, m.attachments, m.expires_at, m.created_at
+        FROM bookmarks b
+        JOIN messages m ON m.id = b.message_id
+        WHERE b.id = $1 AND b.user_id = $2
+        "#,
+        )
+        .bind(id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(bookmark)
+    }
+
+    pub async fn delete_bookmark(&self, id: u64, user_id: u64) -> Result<(), AppError> {
+        let ret = sqlx::query(r#"DELETE FROM bookmarks WHERE id = $1 AND user_id = $2"#)
+            .bind(id as i64)
+            .bind(user_id as i64)
+            .execute(&self.pool)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("bookmark {} not found", id)));
+        }
+        Ok(())
+    }
+
+    /// Mark due reminders as sent, the db trigger notifies the owners.
+    /// Rows are claimed with `SKIP LOCKED` so multiple servers won't send twice.
+    pub async fn send_due_bookmark_reminders(&self) -> Result<u64, AppError> {
+        let ret = sqlx::query(
+            r#"
+        UPDATE bookmarks SET reminded_at = now()
+        WHERE id IN (
+            SELECT id FROM bookmarks
+            WHERE reminded_at IS NULL AND remind_at <= now()
+            ORDER BY remind_at
+            LIMIT 100
+            FOR UPDATE SKIP LOCKED
+        )
+        "#,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(ret.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::CreateMessage;
+
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn create_and_list_bookmarks_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateBookmark {
+            message_id: 2,
+            note: Some("read later".to_string()),
+            ..Default::default()
+        };
+        let bookmark = state.create_bookmark(input, 1).await?;
+        assert_eq!(bookmark.user_id, 1);
+        assert_eq!(bookmark.message.id, 2);
+        assert_eq!(bookmark.note.as_deref(), Some("read later"));
+
+        // bookmark the same message again updates the note
+        let input = CreateBookmark {
+            message_id: 2,
+            note: Some("updated".to_string()),
+            ..Default::default()
+        };
+        let bookmark = state.create_bookmark(input, 1).await?;
+        assert_eq!(bookmark.note.as_deref(), Some("updated"));
+
+        let bookmarks = state.list_bookmarks(1).await?;
+        assert_eq!(bookmarks.len(), 1);
+        // bookmarks are private to the user
+        let bookmarks = state.list_bookmarks(2).await?;
+        assert!(bookmarks.is_empty());
+
+        state.delete_bookmark(bookmark.id as _, 1).await?;
+        let bookmarks = state.list_bookmarks(1).await?;
+        assert!(bookmarks.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bookmark_unreadable_message_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // a message in chat 3 (user 1 and 2) can't be bookmarked by user 4
+        let message = state
+            .create_message(
+                CreateMessage {
+                    content: "secret".to_string(),
+                    ..Default::default()
+                },
+                3,
+                1,
+            )
+            .await?;
+        let input = CreateBookmark {
+            message_id: message.id as _,
+            ..Default::default()
+        };
+        let err = state.create_bookmark(input, 4).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_due_bookmark_reminders_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateBookmark {
+            message_id: 1,
+            remind_at: Some(Utc::now() - chrono::Duration::minutes(1)),
+            ..Default::default()
+        };
+        state.create_bookmark(input, 1).await?;
+        let input = CreateBookmark {
+            message_id: 2,
+            remind_at: Some(Utc::now() + chrono::Duration::hours(1)),
+            ..Default::default()
+        };
+        state.create_bookmark(input, 1).await?;
+
+        assert_eq!(state.send_due_bookmark_reminders().await?, 1);
+        // reminders are only sent once
+        assert_eq!(state.send_due_bookmark_reminders().await?, 0);
+        Ok(())
+    }
+}
@@ -1,15 +1,25 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use utoipa::{IntoParams, ToSchema};
 
 use crate::{AppError, AppState};
 
-use chat_core::{Chat, ChatType};
+use chat_core::{Chat, ChatType, User};
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default, ToSchema)]
 pub struct CreateChat {
     pub name: Option<String>,
     pub members: Vec<i64>,
     pub public: bool,
+    #[serde(default)]
+    pub topic: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// url of an uploaded image, e.g. `/files/1/abc/def/0123.png`
+    #[serde(default)]
+    pub avatar: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default, ToSchema, IntoParams)]
@@ -17,6 +27,43 @@ pub struct UpdateChat {
     pub name: Option<String>,
     pub members: Option<Vec<i64>>,
     pub public: Option<bool>,
+    /// an empty string clears the topic
+    #[serde(default)]
+    pub topic: Option<String>,
+    /// an empty string clears the description
+    #[serde(default)]
+    pub description: Option<String>,
+    /// an empty string clears the avatar
+    #[serde(default)]
+    pub avatar: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "chat_member_role", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum ChatMemberRole {
+    /// manages the chat, a chat keeps at least one
+    Owner,
+    /// manages the chat but not its owners
+    Admin,
+    #[default]
+    Member,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct UpdateChatMember {
+    pub role: ChatMemberRole,
+}
+
+const MAX_TOPIC_LEN: usize = 250;
+const MAX_DESCRIPTION_LEN: usize = 4000;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, ToSchema, IntoParams)]
+pub struct ListChats {
+    #[serde(default)]
+    pub include_archived: bool,
+    #[serde(default)]
+    pub include_hidden: bool,
 }
 
 #[allow(dead_code)]
@@ -51,6 +98,15 @@ impl AppState {
                 "A group chat with more than 8 members must have a name".to_string(),
             ));
         }
+        verify_chat_text("topic", input.topic.as_deref(), MAX_TOPIC_LEN)?;
+        verify_chat_text(
+            "description",
+            input.description.as_deref(),
+            MAX_DESCRIPTION_LEN,
+        )?;
+        if let Some(avatar) = &input.avatar {
+            self.verify_image_file(avatar, ws_id).await?;
+        }
         let users = self.fetch_chat_user_by_ids(&input.members).await?;
         if users.len() != len {
             return Err(AppError::CreateChatError(
@@ -68,36 +124,172 @@ impl AppState {
                 }
             }
         };
-        let chat = sqlx::query_as(
-            r#"insert into chats (ws_id, name, type, members) values ($1, $2, $3, $4) returning id, ws_id, name, type, members, created_at"#,
+        let mut tx = self.pool.begin().await?;
+        let (id,): (i64,) = sqlx::query_as(
+            r#"insert into chats (ws_id, name, type, topic, description, avatar) values ($1, $2, $3, $4, $5, $6) returning id"#,
         )
         .bind(ws_id as i64)
         .bind(&input.name)
         .bind(chat_type)
+        .bind(&input.topic)
+        .bind(&input.description)
+        .bind(&input.avatar)
+        .fetch_one(&mut *tx)
+        .await?;
+        // the creator owns the chat
+        sqlx::query(
+            r#"
+        insert into chat_members (chat_id, user_id, role)
+        select $1, user_id, case when user_id = $3 then 'owner' else 'member' end::chat_member_role
+        from unnest($2::bigint[]) as user_id
+        on conflict (chat_id, user_id) do nothing
+        "#,
+        )
+        .bind(id)
         .bind(&input.members)
-        .fetch_one(&self.pool)
+        .bind(user_id as i64)
+        .execute(&mut *tx)
+        .await?;
+        let chat = sqlx::query_as(
+            r#"select id, ws_id, name, topic, description, avatar, type, chat_member_ids(id) as members, archived_at, created_at from chats where id = $1"#,
+        )
+        .bind(id)
+        .fetch_one(&mut *tx)
         .await?;
+        tx.commit().await?;
         Ok(chat)
     }
 
-    pub async fn fetch_chats(&self, user_id: u64, ws_id: u64) -> Result<Vec<Chat>, AppError> {
+    pub async fn fetch_chats(
+        &self,
+        input: ListChats,
+        user_id: u64,
+        ws_id: u64,
+    ) -> Result<Vec<Chat>, AppError> {
+        // pinned first, then manually ordered, then by last activity
         let chats = sqlx::query_as(
-            r#"select id, ws_id, name, type, members, created_at from chats where ws_id = $1 AND $2 = ANY(members)"#,
+            r#"
+        SELECT c.id, c.ws_id, c.name, c.topic, c.description, c.avatar, c.type, chat_member_ids(c.id) as members, c.archived_at,
+            c.created_at
+        FROM chats c
+        JOIN chat_members cm ON cm.chat_id = c.id AND cm.user_id = $2
+        WHERE c.ws_id = $1
+        AND ($3 OR c.archived_at IS NULL)
+        AND ($4 OR NOT cm.hidden)
+        ORDER BY cm.pinned DESC, cm.sort_order ASC NULLS LAST,
+            COALESCE(c.last_message_at, c.created_at) DESC,
+            c.id
+        "#,
         )
         .bind(ws_id as i64)
         .bind(user_id as i64)
+        .bind(input.include_archived)
+        .bind(input.include_hidden)
         .fetch_all(&self.pool)
         .await?;
         Ok(chats)
     }
-    pub async fn delete_chat_by_id(&self, id: u64) -> Result<Option<Chat>, AppError> {
+    /// Archive the chat, it keeps its history but becomes read-only
+    pub async fn archive_chat(&self, id: u64) -> Result<Chat, AppError> {
         let chat = sqlx::query_as(
-            r#"delete from chats where id = $1 returning id, ws_id, name, type, members, created_at"#,
+            r#"update chats set archived_at = now() where id = $1 and archived_at is null returning id, ws_id, name, topic, description, avatar, type, chat_member_ids(id) as members, archived_at, created_at"#,
         )
         .bind(id as i64)
         .fetch_optional(&self.pool)
         .await?;
-        Ok(chat)
+        match chat {
+            Some(chat) => Ok(chat),
+            None => match self.get_chat_by_id(id).await? {
+                Some(_) => Err(AppError::ChatArchiveError(format!(
+                    "chat {} is already archived",
+                    id
+                ))),
+                None => Err(AppError::ChatDoesNotExist),
+            },
+        }
+    }
+    /// Restore an archived chat
+    pub async fn unarchive_chat(&self, id: u64) -> Result<Chat, AppError> {
+        let chat = sqlx::query_as(
+            r#"update chats set archived_at = null where id = $1 and archived_at is not null returning id, ws_id, name, topic, description, avatar, type, chat_member_ids(id) as members, archived_at, created_at"#,
+        )
+        .bind(id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        match chat {
+            Some(chat) => Ok(chat),
+            None => match self.get_chat_by_id(id).await? {
+                Some(_) => Err(AppError::ChatArchiveError(format!(
+                    "chat {} is not archived",
+                    id
+                ))),
+                None => Err(AppError::ChatDoesNotExist),
+            },
+        }
+    }
+    /// Permanently delete the chat with its messages in a transaction, then remove files no
+    /// other message uses. Chats under legal hold can not be deleted.
+    pub async fn delete_chat_by_id(&self, id: u64) -> Result<Option<Chat>, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let hold: Option<(bool,)> = sqlx::query_as(
+            r#"
+        SELECT c.legal_hold OR w.legal_hold FROM chats c JOIN workspaces w ON w.id = c.ws_id
+        WHERE c.id = $1
+        FOR UPDATE OF c
+        "#,
+        )
+        .bind(id as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+        match hold {
+            None => return Ok(None),
+            Some((true,)) => {
+                return Err(AppError::ChatArchiveError(format!(
+                    "chat {} is under legal hold",
+                    id
+                )))
+            }
+            Some((false,)) => {}
+        }
+        let chat: Chat = sqlx::query_as(
+            r#"select id, ws_id, name, topic, description, avatar, type, chat_member_ids(id) as members, archived_at, created_at from chats where id = $1"#,
+        )
+        .bind(id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        let deleted: Vec<(i64, Vec<String>)> =
+            sqlx::query_as(r#"delete from messages where chat_id = $1 returning id, files"#)
+                .bind(id as i64)
+                .fetch_all(&mut *tx)
+                .await?;
+        let (ids, files): (Vec<i64>, Vec<Vec<String>>) = deleted.into_iter().unzip();
+        // so a later import can bring the chat back
+        sqlx::query(
+            r#"
+        delete from import_mappings
+        where (kind = 'chat' and local_id = $1) or (kind = 'message' and local_id = any($2))
+        "#,
+        )
+        .bind(id as i64)
+        .bind(&ids)
+        .execute(&mut *tx)
+        .await?;
+        // members and settings cascade
+        sqlx::query(r#"delete from chats where id = $1"#)
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        let files: HashSet<String> = files
+            .into_iter()
+            .flatten()
+            .chain(chat.avatar.clone())
+            .collect();
+        for url in files {
+            self.remove_orphaned_file(&url).await?;
+        }
+        Ok(Some(chat))
     }
     pub async fn update_chat_by_id(
         &self,
@@ -108,12 +300,33 @@ impl AppState {
             Some(chat) => chat,
             None => return Err(AppError::ChatDoesNotExist),
         };
+        if old_chat.archived_at.is_some() {
+            return Err(AppError::ChatArchiveError(format!(
+                "chat {} is archived and read-only",
+                id
+            )));
+        }
         if let Some(name) = input.name {
             old_chat.name = Some(name);
         }
-
-        if let Some(members) = input.members {
-            old_chat.members = members;
+        if let Some(topic) = input.topic {
+            old_chat.topic = Some(topic).filter(|v| !v.is_empty());
+            verify_chat_text("topic", old_chat.topic.as_deref(), MAX_TOPIC_LEN)?;
+        }
+        if let Some(description) = input.description {
+            old_chat.description = Some(description).filter(|v| !v.is_empty());
+            verify_chat_text(
+                "description",
+                old_chat.description.as_deref(),
+                MAX_DESCRIPTION_LEN,
+            )?;
+        }
+        let old_avatar = old_chat.avatar.clone();
+        if let Some(avatar) = input.avatar {
+            if !avatar.is_empty() {
+                self.verify_image_file(&avatar, old_chat.ws_id as _).await?;
+            }
+            old_chat.avatar = Some(avatar).filter(|v| !v.is_empty());
         }
 
         if let Some(public) = input.public {
@@ -123,36 +336,228 @@ impl AppState {
                 ChatType::PrivateChannel
             };
         }
-        let chat = sqlx::query_as(
-            r#"update chats set name = $1, type = $2, members = $3 where id = $4 returning id, ws_id, name, type, members, created_at"#,
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"update chats set name = $1, type = $2, topic = $3, description = $4, avatar = $5 where id = $6"#,
         )
         .bind(&old_chat.name)
         .bind(old_chat.r#type)
-        .bind(&old_chat.members)
+        .bind(&old_chat.topic)
+        .bind(&old_chat.description)
+        .bind(&old_chat.avatar)
         .bind(id as i64)
-        .fetch_one(&self.pool)
+        .execute(&mut *tx)
         .await?;
+        // only the difference is written, remaining members keep their settings
+        if let Some(members) = input.members {
+            let users = self.fetch_chat_user_by_ids(&members).await?;
+            if members.is_empty() || users.len() != members.len() {
+                return Err(AppError::CreateChatError(
+                    "One or more members do not exist".to_string(),
+                ));
+            }
+            sqlx::query(r#"delete from chat_members where chat_id = $1 and not user_id = any($2)"#)
+                .bind(id as i64)
+                .bind(&members)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(
+                r#"
+            insert into chat_members (chat_id, user_id)
+            select $1, user_id from unnest($2::bigint[]) as user_id
+            on conflict (chat_id, user_id) do nothing
+            "#,
+            )
+            .bind(id as i64)
+            .bind(&members)
+            .execute(&mut *tx)
+            .await?;
+            promote_chat_owner(id, &mut *tx).await?;
+        }
+        let chat = sqlx::query_as(
+            r#"select id, ws_id, name, topic, description, avatar, type, chat_member_ids(id) as members, archived_at, created_at from chats where id = $1"#,
+        )
+        .bind(id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        if let Some(url) = old_avatar.filter(|url| old_chat.avatar.as_ref() != Some(url)) {
+            self.remove_orphaned_file(&url).await?;
+        }
         Ok(Some(chat))
     }
     pub async fn get_chat_by_id(&self, id: u64) -> Result<Option<Chat>, AppError> {
         let chat = sqlx::query_as(
-            r#"select id, ws_id, name, type, members, created_at from chats where id = $1"#,
+            r#"select id, ws_id, name, topic, description, avatar, type, chat_member_ids(id) as members, archived_at, created_at from chats where id = $1"#,
         )
         .bind(id as i64)
         .fetch_optional(&self.pool)
         .await?;
         Ok(chat)
     }
-    pub async fn is_chat_member(&self, chat_id: u64, user_id: u64) -> Result<bool, AppError> {
-        let is_member = sqlx::query(r#"select 1 from chats where id = $1 and $2 = ANY(members)"#)
+    /// Make sure the chat exists in the user's workspace
+    pub async fn verify_workspace_chat(&self, user: &User, chat_id: u64) -> Result<Chat, AppError> {
+        match self.get_chat_by_id(chat_id).await? {
+            Some(chat) if chat.ws_id == user.ws_id => Ok(chat),
+            _ => Err(AppError::ChatDoesNotExist),
+        }
+    }
+    /// Make sure the chat exists and is not archived before changing its content. In a
+    /// transaction the chat row stays locked against archiving until it ends
+    pub async fn verify_chat_writable<'e, E>(
+        &self,
+        chat_id: u64,
+        executor: E,
+    ) -> Result<(), AppError>
+    where
+        E: sqlx::PgExecutor<'e>,
+    {
+        let chat: Option<(Option<DateTime<Utc>>,)> =
+            sqlx::query_as("select archived_at from chats where id = $1 for share")
+                .bind(chat_id as i64)
+                .fetch_optional(executor)
+                .await?;
+        match chat {
+            Some((Some(_),)) => Err(AppError::ChatArchiveError(format!(
+                "chat {} is archived and read-only",
+                chat_id
+            ))),
+            Some((None,)) => Ok(()),
+            None => Err(AppError::ChatDoesNotExist),
+        }
+    }
+    pub async fn get_chat_member_role(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<Option<ChatMemberRole>, AppError> {
+        let role: Option<(ChatMemberRole,)> =
+            sqlx::query_as(r#"select role from chat_members where chat_id = $1 and user_id = $2"#)
+                .bind(chat_id as i64)
+                .bind(user_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(role.map(|(role,)| role))
+    }
+    /// Owners and admins manage the chat, the workspace owner manages every chat of the workspace
+    pub async fn is_chat_admin(&self, chat_id: u64, user_id: u64) -> Result<bool, AppError> {
+        let (is_admin,): (bool,) = sqlx::query_as(
+            r#"
+        select exists (
+            select 1 from chat_members
+            where chat_id = $1 and user_id = $2 and role in ('owner', 'admin')
+        ) or exists (
+            select 1 from chats c join workspaces w on w.id = c.ws_id
+            where c.id = $1 and w.owner_id = $2
+        )
+        "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(is_admin)
+    }
+    pub async fn verify_chat_admin(&self, chat_id: u64, user_id: u64) -> Result<(), AppError> {
+        if !self.is_chat_admin(chat_id, user_id).await? {
+            return Err(AppError::PermissionDenied(format!(
+                "User {} is not an admin of chat {}",
+                user_id, chat_id
+            )));
+        }
+        Ok(())
+    }
+    /// Change the role of a member, only owners of the chat and the workspace owner can do it
+    pub async fn update_chat_member_role(
+        &self,
+        chat_id: u64,
+        member_id: u64,
+        role: ChatMemberRole,
+        user: &User,
+    ) -> Result<(), AppError> {
+        let is_owner =
+            self.get_chat_member_role(chat_id, user.id as _).await? == Some(ChatMemberRole::Owner);
+        if !is_owner {
+            self.verify_workspace_owner(user).await?;
+        }
+        let mut tx = self.pool.begin().await?;
+        // concurrent role changes can not both drop an owner
+        sqlx::query(r#"select id from chats where id = $1 for update"#)
             .bind(chat_id as i64)
-            .bind(user_id as i64)
-            .fetch_optional(&self.pool)
+            .execute(&mut *tx)
             .await?;
+        let ret =
+            sqlx::query(r#"update chat_members set role = $3 where chat_id = $1 and user_id = $2"#)
+                .bind(chat_id as i64)
+                .bind(member_id as i64)
+                .bind(role)
+                .execute(&mut *tx)
+                .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!(
+                "User {} is not a member of chat {}",
+                member_id, chat_id
+            )));
+        }
+        let (has_owner,): (bool,) = sqlx::query_as(
+            r#"select exists (select 1 from chat_members where chat_id = $1 and role = 'owner')"#,
+        )
+        .bind(chat_id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        if !has_owner {
+            return Err(AppError::CreateChatError(
+                "A chat must keep at least one owner".to_string(),
+            ));
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+    pub async fn is_chat_member(&self, chat_id: u64, user_id: u64) -> Result<bool, AppError> {
+        let is_member =
+            sqlx::query(r#"select 1 from chat_members where chat_id = $1 and user_id = $2"#)
+                .bind(chat_id as i64)
+                .bind(user_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
         Ok(is_member.is_some())
     }
 }
 
+/// Make the longest standing admin, or member if there is none, owner of a chat whose owners
+/// all left
+async fn promote_chat_owner(
+    chat_id: u64,
+    executor: impl sqlx::PgExecutor<'_>,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+    update chat_members set role = 'owner'
+    where chat_id = $1
+    and user_id = (
+        select user_id from chat_members where chat_id = $1
+        order by role, joined_at, user_id
+        limit 1
+    )
+    and not exists (select 1 from chat_members where chat_id = $1 and role = 'owner')
+    "#,
+    )
+    .bind(chat_id as i64)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+fn verify_chat_text(field: &str, value: Option<&str>, max: usize) -> Result<(), AppError> {
+    match value {
+        Some(value) if value.chars().count() > max => Err(AppError::CreateChatError(format!(
+            "Chat {} must be at most {} characters",
+            field, max
+        ))),
+        _ => Ok(()),
+    }
+}
+
 #[cfg(test)]
 impl CreateChat {
     pub fn new(name: Option<String>, members: &[i64], public: bool) -> Self {
@@ -160,6 +565,7 @@ impl CreateChat {
             name,
             members: members.to_vec(),
             public,
+            ..Default::default()
         }
     }
 }
@@ -171,6 +577,7 @@ impl UpdateChat {
             name,
             members,
             public,
+            ..Default::default()
         }
     }
 }
@@ -179,6 +586,7 @@ impl UpdateChat {
 mod tests {
 
     use super::*;
+    use crate::ChatFile;
 
     #[tokio::test]
     async fn create_single_chat_should_work() -> anyhow::Result<()> {
@@ -236,7 +644,7 @@ mod tests {
             .expect("create chat failed");
         let input = UpdateChat::new(
             Some("general chat".to_string()),
-            Some(vec![5, 6, 7, 8]),
+            Some(vec![2, 3, 4, 5]),
             Some(false),
         );
         let chat = state
@@ -251,6 +659,131 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn update_chat_members_should_keep_member_settings() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateChat::new(Some("aaa".to_string()), &[1, 2, 3], true);
+        let chat = state.create_chat(input, 1, 1).await?;
+        let (role,): (String,) = sqlx::query_as(
+            "SELECT role::text FROM chat_members WHERE chat_id = $1 AND user_id = 1",
+        )
+        .bind(chat.id)
+        .fetch_one(&state.pool)
+        .await?;
+        assert_eq!(role, "owner");
+
+        let input = crate::UpdateChatPreferences {
+            pinned: Some(true),
+            ..Default::default()
+        };
+        state
+            .update_chat_preferences(input, chat.id as _, 2)
+            .await?;
+        let input = UpdateChat::new(None, Some(vec![1, 2, 4]), None);
+        let chat = state.update_chat_by_id(input, chat.id as _).await?.unwrap();
+        assert_eq!(chat.members, vec![1, 2, 4]);
+        assert!(state.get_chat_preferences(chat.id as _, 2).await?.pinned);
+        assert!(!state.is_chat_member(chat.id as _, 3).await?);
+
+        let input = UpdateChat::new(None, Some(vec![1, 42]), None);
+        assert!(state.update_chat_by_id(input, chat.id as _).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_member_roles_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateChat::new(Some("aaa".to_string()), &[1, 2, 3], true);
+        let chat = state.create_chat(input, 1, 1).await?;
+        let chat_id = chat.id as u64;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        let member = state.find_user_by_id(2).await?.unwrap();
+        assert!(state.is_chat_admin(chat_id, 1).await?);
+        assert!(!state.is_chat_admin(chat_id, 2).await?);
+
+        // members can not change roles
+        let ret = state
+            .update_chat_member_role(chat_id, 2, ChatMemberRole::Admin, &member)
+            .await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        state
+            .update_chat_member_role(chat_id, 3, ChatMemberRole::Admin, &owner)
+            .await?;
+        assert!(state.is_chat_admin(chat_id, 3).await?);
+
+        // the last owner stays
+        let ret = state
+            .update_chat_member_role(chat_id, 1, ChatMemberRole::Member, &owner)
+            .await;
+        assert!(matches!(ret, Err(AppError::CreateChatError(_))));
+
+        // removing the owner hands the chat to the admin
+        let input = UpdateChat::new(None, Some(vec![2, 3]), None);
+        state.update_chat_by_id(input, chat_id).await?;
+        assert_eq!(
+            state.get_chat_member_role(chat_id, 3).await?,
+            Some(ChatMemberRole::Owner)
+        );
+        assert_eq!(
+            state.get_chat_member_role(chat_id, 2).await?,
+            Some(ChatMemberRole::Member)
+        );
+
+        // the workspace owner manages every chat
+        state.update_workspace_owner(1, 2).await?;
+        assert!(state.is_chat_admin(chat_id, 2).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_chat_metadata_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let file = ChatFile::new(1, "avatar.png", b"not really a png");
+        let path = file.path(&state.config.server.base_dir);
+        tokio::fs::create_dir_all(path.parent().unwrap()).await?;
+        tokio::fs::write(&path, b"not really a png").await?;
+        let input = CreateChat {
+            topic: Some("release planning".to_string()),
+            avatar: Some(file.url()),
+            ..CreateChat::new(Some("releases".to_string()), &[1, 2], false)
+        };
+        let chat = state.create_chat(input, 1, 1).await?;
+        assert_eq!(chat.topic.as_deref(), Some("release planning"));
+        assert_eq!(chat.avatar, Some(file.url()));
+
+        // the avatar must be an existing image
+        let text = ChatFile::new(1, "notes.txt", b"notes");
+        for url in [text.url(), "/files/1/abc/def/missing.png".to_string()] {
+            let input = UpdateChat {
+                avatar: Some(url),
+                ..Default::default()
+            };
+            assert!(state.update_chat_by_id(input, chat.id as _).await.is_err());
+        }
+
+        // empty strings clear, missing fields are kept
+        let input = UpdateChat {
+            description: Some("x".repeat(10)),
+            topic: Some("".to_string()),
+            avatar: Some("".to_string()),
+            ..Default::default()
+        };
+        let chat = state.update_chat_by_id(input, chat.id as _).await?.unwrap();
+        assert_eq!(chat.topic, None);
+        assert_eq!(chat.avatar, None);
+        assert_eq!(chat.description, Some("x".repeat(10)));
+        assert_eq!(chat.name.as_deref(), Some("releases"));
+        // the old avatar is no longer used
+        assert!(!path.exists());
+
+        let input = UpdateChat {
+            topic: Some("x".repeat(MAX_TOPIC_LEN + 1)),
+            ..Default::default()
+        };
+        assert!(state.update_chat_by_id(input, chat.id as _).await.is_err());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn chat_get_by_id_should_work() -> anyhow::Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
@@ -268,13 +801,83 @@ mod tests {
     async fn chat_fetch_all_should_work() -> anyhow::Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
         let chats = state
-            .fetch_chats(1, 1)
+            .fetch_chats(Default::default(), 1, 1)
             .await
             .expect("fetch all chats failed");
         assert_eq!(chats.len(), 4);
         Ok(())
     }
 
+    #[tokio::test]
+    async fn archive_chat_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let chat = state.archive_chat(1).await?;
+        assert!(chat.archived_at.is_some());
+        assert!(matches!(
+            state.archive_chat(1).await,
+            Err(AppError::ChatArchiveError(_))
+        ));
+
+        // archived chats are hidden by default
+        let chats = state.fetch_chats(Default::default(), 1, 1).await?;
+        assert_eq!(chats.len(), 3);
+        let input = ListChats {
+            include_archived: true,
+            ..Default::default()
+        };
+        let chats = state.fetch_chats(input, 1, 1).await?;
+        assert_eq!(chats.len(), 4);
+
+        // archived chats are read-only
+        let input = crate::CreateMessage {
+            content: "hello".to_string(),
+            ..Default::default()
+        };
+        let ret = state.create_message(input.clone(), 1, 1).await;
+        assert!(matches!(ret, Err(AppError::ChatArchiveError(_))));
+        let ret = state
+            .update_chat_by_id(UpdateChat::new(Some("new".to_string()), None, None), 1)
+            .await;
+        assert!(matches!(ret, Err(AppError::ChatArchiveError(_))));
+
+        let chat = state.unarchive_chat(1).await?;
+        assert!(chat.archived_at.is_none());
+        state.create_message(input, 1, 1).await?;
+        assert!(matches!(
+            state.unarchive_chat(1).await,
+            Err(AppError::ChatArchiveError(_))
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_chat_should_remove_messages() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let chat = state.delete_chat_by_id(1).await?.unwrap();
+        assert_eq!(chat.id, 1);
+        let (count,): (i64,) = sqlx::query_as("SELECT count(*) FROM messages WHERE chat_id = 1")
+            .fetch_one(&state.pool)
+            .await?;
+        assert_eq!(count, 0);
+        assert!(state.get_chat_by_id(1).await?.is_none());
+        assert!(state.delete_chat_by_id(1).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_chat_under_legal_hold_should_fail() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let policy = crate::RetentionPolicy {
+            retention_days: None,
+            legal_hold: true,
+        };
+        state.update_chat_retention(1, policy).await?;
+        let ret = state.delete_chat_by_id(1).await;
+        assert!(matches!(ret, Err(AppError::ChatArchiveError(_))));
+        assert!(state.get_chat_by_id(1).await?.is_some());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn chat_is_member_should_work() -> anyhow::Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
@@ -0,0 +1,700 @@
+use std::time::Duration;
+
+use chat_core::{Message, MessageFormat, User};
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{AppError, AppState, CreateMessage, CreatePoll, UpdateChat};
+
+use super::access_token::random_hex;
+use super::messages::render_markdown;
+
+const MAX_COMMAND_NAME_LEN: usize = 32;
+const MAX_COMMAND_DESCRIPTION_LEN: usize = 256;
+const MAX_COMMAND_URL_LEN: usize = 2048;
+/// replies go through pg_notify, which limits the payload to 8000 bytes
+const MAX_REPLY_LEN: usize = 2000;
+const COMMAND_TIMEOUT_SECS: u64 = 5;
+const SHRUG: &str = r"¯\_(ツ)_/¯";
+
+/// Commands every workspace has
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BuiltinCommand {
+    Help,
+    Me,
+    Poll,
+    Shrug,
+    Topic,
+}
+
+/// A command as clients list it, e.g. for completion
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SlashCommand {
+    /// without the leading slash
+    pub name: String,
+    pub description: String,
+    /// id of a registered command, none for built-in ones
+    pub id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RegisterCommand {
+    /// without the leading slash, lowercase letters, digits, `-` and `_`
+    pub name: String,
+    /// invocations are POSTed here
+    pub url: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RegisteredCommand {
+    pub id: i64,
+    pub ws_id: i64,
+    pub name: String,
+    pub url: String,
+    pub description: String,
+    pub created_by: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedCommand {
+    #[serde(flatten)]
+    pub command: RegisteredCommand,
+    /// only shown once, sent as `X-Command-Token` with every invocation
+    pub token: String,
+}
+
+/// What registered commands receive
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandInvocation {
+    pub command: String,
+    /// everything after the command name
+    pub text: String,
+    pub ws_id: i64,
+    pub chat_id: i64,
+    pub user_id: i64,
+    pub user_name: String,
+}
+
+/// What registered commands answer, an empty body just acknowledges the command
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandResponse {
+    #[serde(default)]
+    pub text: String,
+    #[serde(default)]
+    pub response_type: CommandResponseType,
+    #[serde(default)]
+    pub format: MessageFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandResponseType {
+    /// only the invoker sees it
+    #[default]
+    Ephemeral,
+    /// posted to the chat as the invoker
+    InChannel,
+}
+
+/// What running a command results in
+enum CommandOutput {
+    Post(CreateMessage),
+    Poll(CreatePoll),
+    Reply(String, MessageFormat),
+}
+
+#[derive(Debug, Serialize)]
+struct CommandReplyNotice<'a> {
+    user_id: i64,
+    message: &'a Message,
+}
+
+impl AppState {
+    /// Built-in commands and the ones registered for the workspace
+    pub async fn list_commands(&self, ws_id: u64) -> Result<Vec<SlashCommand>, AppError> {
+        let mut commands: Vec<SlashCommand> = BuiltinCommand::ALL
+            .iter()
+            .map(|command| SlashCommand {
+                name: command.name().to_string(),
+                description: command.description().to_string(),
+                id: None,
+            })
+            .collect();
+        let registered: Vec<(i64, String, String)> = sqlx::query_as(
+            "SELECT id, name, description FROM slash_commands WHERE ws_id = $1 ORDER BY name",
+        )
+        .bind(ws_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        commands.extend(
+            registered
+                .into_iter()
+                .map(|(id, name, description)| SlashCommand {
+                    name,
+                    description,
+                    id: Some(id),
+                }),
+        );
+        Ok(commands)
+    }
+
+    /// Add a command answered by an external service, only the workspace owner can
+    pub async fn register_command(
+        &self,
+        owner: &User,
+        input: RegisterCommand,
+    ) -> Result<CreatedCommand, AppError> {
+        self.verify_workspace_owner(owner).await?;
+        if !is_command_name(&input.name) {
+            return Err(AppError::CommandError(format!(
+                "invalid name {}, use up to {} lowercase letters, digits, - and _",
+                input.name, MAX_COMMAND_NAME_LEN
+            )));
+        }
+        if BuiltinCommand::find(&input.name).is_some() {
+            return Err(AppError::CommandError(format!(
+                "/{} is a built-in command",
+                input.name
+            )));
+        }
+        if input.url.len() > MAX_COMMAND_URL_LEN {
+            return Err(AppError::CommandError(format!(
+                "url must be at most {} characters",
+                MAX_COMMAND_URL_LEN
+            )));
+        }
+        let url = Url::parse(&input.url)
+            .map_err(|_| AppError::CommandError(format!("invalid url {}", input.url)))?;
+        self.config
+            .outbound
+            .check_url(&url)
+            .map_err(AppError::CommandError)?;
+        let description = input.description.trim();
+        if description.chars().count() > MAX_COMMAND_DESCRIPTION_LEN {
+            return Err(AppError::CommandError(format!(
+                "description must be at most {} characters",
+                MAX_COMMAND_DESCRIPTION_LEN
+            )));
+        }
+
+        let token = random_hex(24);
+        let command: Option<RegisteredCommand> = sqlx::query_as(
+            r#"
+        INSERT INTO slash_commands (ws_id, name, url, description, token, created_by)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (ws_id, name) DO NOTHING
+        RETURNING id, ws_id, name, url, description, created_by, created_at
+        "#,
+        )
+        .bind(owner.ws_id)
+        .bind(&input.name)
+        .bind(&input.url)
+        .bind(description)
+        .bind(&token)
+        .bind(owner.id)
+        .fetch_optional(&self.pool)
+        .await?;
+        match command {
+            Some(command) => Ok(CreatedCommand { command, token }),
+            None => Err(AppError::CommandError(format!(
+                "/{} is already registered",
+                input.name
+            ))),
+        }
+    }
+
+    pub async fn delete_command(&self, id: u64, owner: &User) -> Result<(), AppError> {
+        self.verify_workspace_owner(owner).await?;
+        let ret = sqlx::query("DELETE FROM slash_commands WHERE id = $1 AND ws_id = $2")
+            .bind(id as i64)
+            .bind(owner.ws_id)
+            .execute(&self.pool)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("command {}", id)));
+        }
+        Ok(())
+    }
+
+    /// Run the command a message starts with. The message it posts, or a reply only the sender
+    /// sees, which also goes to their other sessions through notify_server. `None` if the
+    /// message should be posted as is, e.g. bots and webhooks do not run commands.
+    pub(crate) async fn run_command(
+        &self,
+        input: &CreateMessage,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<Option<Message>, AppError> {
+        if input.sender_name.is_some() {
+            return Ok(None);
+        }
+        let Some((name, text)) = parse_command(&input.content) else {
+            return Ok(None);
+        };
+        let user = self.get_profile(user_id).await?;
+        if user.is_bot {
+            return Ok(None);
+        }
+        self.verify_chat_writable(chat_id, &self.pool).await?;
+
+        let output = match BuiltinCommand::find(name) {
+            Some(command) => {
+                self.run_builtin(command, text, input, chat_id, &user)
+                    .await?
+            }
+            None => self.run_registered(name, text, chat_id, &user).await?,
+        };
+        let message = match output {
+            CommandOutput::Post(input) => {
+                self.insert_message(input, chat_id, user_id, &self.pool)
+                    .await?
+            }
+            CommandOutput::Poll(input) => match self.create_poll(input, chat_id, user_id).await {
+                Ok(poll) => self
+                    .get_message_by_id(poll.message_id as _, chat_id)
+                    .await?
+                    .ok_or_else(|| AppError::NotFound(format!("message {}", poll.message_id)))?,
+                Err(AppError::PollError(e)) => {
+                    self.send_command_reply(e, MessageFormat::Plain, chat_id, user.id)
+                        .await
+                }
+                Err(e) => return Err(e),
+            },
+            CommandOutput::Reply(content, format) => {
+                self.send_command_reply(content, format, chat_id, user.id)
+                    .await
+            }
+        };
+        Ok(Some(message))
+    }
+
+    async fn run_builtin(
+        &self,
+        command: BuiltinCommand,
+        text: &str,
+        input: &CreateMessage,
+        chat_id: u64,
+        user: &User,
+    ) -> Result<CommandOutput, AppError> {
+        let post = |content: String, format: MessageFormat| {
+            // files and expiry of the message stay
+            CommandOutput::Post(CreateMessage {
+                content,
+                format,
+                ..input.clone()
+            })
+        };
+        let output = match command {
+            BuiltinCommand::Help => {
+                let commands = self.list_commands(user.ws_id as _).await?;
+                let lines: Vec<String> = commands
+                    .iter()
+                    .map(|command| format!("- `/{}` {}", command.name, command.description))
+                    .collect();
+                CommandOutput::Reply(lines.join("\n"), MessageFormat::Markdown)
+            }
+            BuiltinCommand::Me if text.is_empty() => usage(command),
+            BuiltinCommand::Me => post(format!("_{}_", text), MessageFormat::Markdown),
+            BuiltinCommand::Poll => match split_args(text) {
+                Some(mut args) if args.len() >= 3 => {
+                    let question = args.remove(0);
+                    CommandOutput::Poll(CreatePoll {
+                        question,
+                        options: args,
+                        ..Default::default()
+                    })
+                }
+                _ => usage(command),
+            },
+            BuiltinCommand::Shrug if text.is_empty() => {
+                post(SHRUG.to_string(), MessageFormat::Plain)
+            }
+            BuiltinCommand::Shrug => post(format!("{} {}", text, SHRUG), MessageFormat::Plain),
+            BuiltinCommand::Topic if text.is_empty() => {
+                let topic = self
+                    .get_chat_by_id(chat_id)
+                    .await?
+                    .and_then(|chat| chat.topic);
+                let reply = match topic {
+                    Some(topic) => format!("The topic is: {}", topic),
+                    None => "There is no topic, set one with /topic <text>".to_string(),
+                };
+                CommandOutput::Reply(reply, MessageFormat::Plain)
+            }
+            BuiltinCommand::Topic if !self.is_chat_admin(chat_id, user.id as _).await? => {
+                CommandOutput::Reply(
+                    "Only admins of the chat can set the topic".to_string(),
+                    MessageFormat::Plain,
+                )
+            }
+            BuiltinCommand::Topic => {
+                let input = UpdateChat {
+                    topic: Some(text.to_string()),
+                    ..Default::default()
+                };
+                self.update_chat_by_id(input, chat_id).await?;
+                CommandOutput::Reply(format!("Topic set to: {}", text), MessageFormat::Plain)
+            }
+        };
+        Ok(output)
+    }
+
+    /// Ask the service of a registered command, failures are replied to the invoker
+    async fn run_registered(
+        &self,
+        name: &str,
+        text: &str,
+        chat_id: u64,
+        user: &User,
+    ) -> Result<CommandOutput, AppError> {
+        let command: Option<(String, String)> =
+            sqlx::query_as("SELECT url, token FROM slash_commands WHERE ws_id = $1 AND name = $2")
+                .bind(user.ws_id)
+                .bind(name)
+                .fetch_optional(&self.pool)
+                .await?;
+        let Some((url, token)) = command else {
+            return Ok(CommandOutput::Reply(
+                format!("Unknown command /{}, see /help", name),
+                MessageFormat::Plain,
+            ));
+        };
+        let invocation = CommandInvocation {
+            command: name.to_string(),
+            text: text.to_string(),
+            ws_id: user.ws_id,
+            chat_id: chat_id as _,
+            user_id: user.id,
+            user_name: user.fullname.clone(),
+        };
+        // the policy may have changed since the command was registered
+        let checked = Url::parse(&url)
+            .map_err(|e| e.to_string())
+            .and_then(|url| self.config.outbound.check_url(&url).map(|_| url));
+        let ret = match checked {
+            Ok(url) => self
+                .http
+                .post(url)
+                .timeout(Duration::from_secs(COMMAND_TIMEOUT_SECS))
+                .header("x-command-token", token)
+                .json(&invocation)
+                .send()
+                .await
+                .and_then(|res| res.error_for_status())
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+        let response = match ret {
+            Ok(res) => res.json::<CommandResponse>().await.unwrap_or_default(),
+            Err(e) => {
+                warn!("slash command /{} at {} failed: {}", name, url, e);
+                return Ok(CommandOutput::Reply(
+                    format!("/{} did not respond, try again later", name),
+                    MessageFormat::Plain,
+                ));
+            }
+        };
+        let output = match response.response_type {
+            _ if response.text.is_empty() => {
+                CommandOutput::Reply(format!("/{} done", name), MessageFormat::Plain)
+            }
+            CommandResponseType::Ephemeral => CommandOutput::Reply(response.text, response.format),
+            CommandResponseType::InChannel => CommandOutput::Post(CreateMessage {
+                content: response.text,
+                format: response.format,
+                ..Default::default()
+            }),
+        };
+        Ok(output)
+    }
+
+    /// A message only the user sees, it is not stored
+    async fn send_command_reply(
+        &self,
+        mut content: String,
+        format: MessageFormat,
+        chat_id: u64,
+        user_id: i64,
+    ) -> Message {
+        if let Some((i, _)) = content.char_indices().nth(MAX_REPLY_LEN) {
+            content.truncate(i);
+        }
+        let message = Message {
+            id: 0,
+            chat_id: chat_id as _,
+            // nobody, the server answers
+            sender_id: 0,
+            sender_name: None,
+            poll_id: None,
+            html: (format == MessageFormat::Markdown).then(|| render_markdown(&content)),
+            content,
+            files: vec![],
+            format,
+            attachments: vec![],
+            expires_at: None,
+            created_at: Utc::now(),
+            visible_to: Some(user_id),
+        };
+        let notice = CommandReplyNotice {
+            user_id,
+            message: &message,
+        };
+        let ret = match serde_json::to_string(&notice) {
+            Ok(payload) => sqlx::query("SELECT pg_notify('command_reply', $1)")
+                .bind(payload)
+                .execute(&self.pool)
+                .await
+                .map(|_| ()),
+            Err(e) => Err(sqlx::Error::Decode(e.into())),
+        };
+        if let Err(e) = ret {
+            // the invoker still gets the reply in the response
+            warn!("failed to notify command reply to user {}: {}", user_id, e);
+        }
+        message
+    }
+}
+
+impl BuiltinCommand {
+    const ALL: [Self; 5] = [Self::Help, Self::Me, Self::Poll, Self::Shrug, Self::Topic];
+
+    fn find(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|command| command.name() == name)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Help => "help",
+            Self::Me => "me",
+            Self::Poll => "poll",
+            Self::Shrug => "shrug",
+            Self::Topic => "topic",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Self::Help => "list the commands",
+            Self::Me => "<action> post in the third person",
+            Self::Poll => "\"<question>\" \"<option>\" \"<option>\"... post a poll",
+            Self::Shrug => "[message] append ¯\\_(ツ)_/¯",
+            Self::Topic => "[topic] show or set the topic of the chat",
+        }
+    }
+}
+
+fn usage(command: BuiltinCommand) -> CommandOutput {
+    CommandOutput::Reply(
+        format!("Usage: /{} {}", command.name(), command.description()),
+        MessageFormat::Plain,
+    )
+}
+
+/// Name and text of `/name text`. Content like `/usr/bin` or `//` is not a command.
+fn parse_command(content: &str) -> Option<(&str, &str)> {
+    let rest = content.strip_prefix('/')?;
+    let (name, text) = match rest.find(char::is_whitespace) {
+        Some(i) => (&rest[..i], rest[i..].trim()),
+        None => (rest, ""),
+    };
+    is_command_name(name).then_some((name, text))
+}
+
+/// Words of the text, text in double quotes is one. `None` if a quote is not closed.
+fn split_args(text: &str) -> Option<Vec<String>> {
+    let mut args = vec![];
+    let mut chars = text.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let arg: String = match chars.next() {
+            None => return Some(args),
+            Some('"') => {
+                let mut arg = String::new();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        c => arg.push(c),
+                    }
+                }
+                arg
+            }
+            Some(c) => std::iter::once(c)
+                .chain(chars.by_ref().take_while(|c| !c.is_whitespace()))
+                .collect(),
+        };
+        args.push(arg);
+    }
+}
+
+fn is_command_name(name: &str) -> bool {
+    name.len() <= MAX_COMMAND_NAME_LEN
+        && name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppConfig;
+    use anyhow::Result;
+    use axum::{http::HeaderMap, routing::post, Json, Router};
+    use tokio::net::TcpListener;
+
+    fn message(content: &str) -> CreateMessage {
+        CreateMessage {
+            content: content.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse_command_should_work() {
+        assert_eq!(parse_command("/shrug"), Some(("shrug", "")));
+        assert_eq!(
+            parse_command("/topic  release day "),
+            Some(("topic", "release day"))
+        );
+        assert_eq!(parse_command("/usr/bin is gone"), None);
+        assert_eq!(parse_command("// comment"), None);
+        assert_eq!(parse_command("hello /shrug"), None);
+    }
+
+    #[test]
+    fn split_args_should_work() {
+        assert_eq!(
+            split_args(r#" "Lunch at?"  noon "half past 12" "#),
+            Some(vec![
+                "Lunch at?".to_string(),
+                "noon".to_string(),
+                "half past 12".to_string()
+            ])
+        );
+        assert_eq!(split_args(""), Some(vec![]));
+        assert_eq!(split_args(r#""open"#), None);
+    }
+
+    #[tokio::test]
+    async fn builtin_commands_should_post_or_reply() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let posted = state.create_message(message("/shrug ok"), 1, 1).await?;
+        assert_eq!(posted.content, format!("ok {}", SHRUG));
+        assert!(posted.id > 0);
+        assert_eq!(posted.visible_to, None);
+
+        let reply = state.create_message(message("/help"), 1, 1).await?;
+        assert_eq!(reply.id, 0);
+        assert_eq!(reply.visible_to, Some(1));
+        assert!(reply.content.contains("/topic"));
+
+        let reply = state
+            .create_message(message("/topic ship it"), 1, 1)
+            .await?;
+        assert!(reply.content.starts_with("Only admins"));
+        let chat = state.get_chat_by_id(1).await?.unwrap();
+        assert_eq!(chat.topic, None);
+        sqlx::query("update chat_members set role = 'admin' where chat_id = 1 and user_id = 1")
+            .execute(&state.pool)
+            .await?;
+        let reply = state
+            .create_message(message("/topic ship it"), 1, 1)
+            .await?;
+        assert_eq!(reply.visible_to, Some(1));
+        let chat = state.get_chat_by_id(1).await?.unwrap();
+        assert_eq!(chat.topic.as_deref(), Some("ship it"));
+
+        let posted = state
+            .create_message(message(r#"/poll "Lunch?" pizza "sushi rolls""#), 1, 1)
+            .await?;
+        assert_eq!(posted.content, "Lunch?");
+        let poll = state.get_poll(1, posted.poll_id.unwrap() as _, 1).await?;
+        assert_eq!(poll.options[1].text, "sushi rolls");
+        let reply = state
+            .create_message(message("/poll \"Lunch?\" pizza pizza"), 1, 1)
+            .await?;
+        assert_eq!(reply.visible_to, Some(1));
+        let reply = state.create_message(message("/poll Lunch?"), 1, 1).await?;
+        assert!(reply.content.starts_with("Usage: /poll"));
+
+        let reply = state.create_message(message("/nope"), 1, 1).await?;
+        assert!(reply.content.starts_with("Unknown command"));
+        // not a command, posted as is
+        let posted = state.create_message(message("/usr/bin"), 1, 1).await?;
+        assert_eq!(posted.content, "/usr/bin");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn registered_command_should_call_its_service() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let app = Router::new().route(
+            "/deploy",
+            post(
+                |headers: HeaderMap, Json(invocation): Json<CommandInvocation>| async move {
+                    assert!(headers.contains_key("x-command-token"));
+                    Json(CommandResponse {
+                        text: format!("deploying {}", invocation.text),
+                        response_type: CommandResponseType::InChannel,
+                        format: MessageFormat::Plain,
+                    })
+                },
+            ),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let mut config = AppConfig::load()?;
+        config.outbound.allow = vec!["127.0.0.1".to_string()];
+        let (_tdb, state) = AppState::new_for_test_with(config).await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        let internal = RegisterCommand {
+            name: "deploy".to_string(),
+            url: "http://169.254.169.254/latest".to_string(),
+            description: "".to_string(),
+        };
+        assert!(state.register_command(&owner, internal).await.is_err());
+        let input = RegisterCommand {
+            name: "deploy".to_string(),
+            url: format!("http://{}/deploy", addr),
+            description: "ship a service".to_string(),
+        };
+        let created = state.register_command(&owner, input.clone()).await?;
+        assert!(state.register_command(&owner, input).await.is_err());
+        let builtin = RegisterCommand {
+            name: "help".to_string(),
+            url: format!("http://{}/deploy", addr),
+            description: "".to_string(),
+        };
+        assert!(state.register_command(&owner, builtin).await.is_err());
+        assert!(state
+            .list_commands(1)
+            .await?
+            .iter()
+            .any(|command| command.id == Some(created.command.id)));
+
+        let posted = state.create_message(message("/deploy api"), 1, 2).await?;
+        assert_eq!(posted.content, "deploying api");
+        assert_eq!(posted.sender_id, 2);
+
+        state
+            .delete_command(created.command.id as _, &owner)
+            .await?;
+        let reply = state.create_message(message("/deploy api"), 1, 2).await?;
+        assert!(reply.content.starts_with("Unknown command"));
+        Ok(())
+    }
+}
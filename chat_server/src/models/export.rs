@@ -0,0 +1,254 @@
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+use async_zip::{tokio::write::ZipFileWriter, Compression, ZipEntryBuilder};
+use futures::{AsyncWriteExt as _, TryStreamExt as _};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWrite;
+use tracing::warn;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{storage::FileStorage, AppError, AppState, ChatFile};
+use chat_core::{Chat, Message, MessageFormat};
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Html,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, IntoParams)]
+pub struct ExportChat {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+impl AppState {
+    /// Write the chat archive as a zip into `writer`: `chat.json`, `members.json`,
+    /// `messages.json` (or `index.html`) and attached files under `files/`.
+    /// Messages and files are streamed, nothing is loaded into memory as a whole.
+    pub async fn export_chat<W>(
+        &self,
+        chat_id: u64,
+        format: ExportFormat,
+        writer: W,
+    ) -> Result<(), AppError>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let chat = self
+            .get_chat_by_id(chat_id)
+            .await?
+            .ok_or(AppError::ChatDoesNotExist)?;
+        let members = self.fetch_chat_user_by_ids(&chat.members).await?;
+        let names: HashMap<i64, String> =
+            members.iter().map(|u| (u.id, u.fullname.clone())).collect();
+
+        let mut zip = ZipFileWriter::with_tokio(writer);
+        zip.write_entry_whole(entry("chat.json"), &serde_json::to_vec_pretty(&chat)?)
+            .await?;
+        zip.write_entry_whole(entry("members.json"), &serde_json::to_vec_pretty(&members)?)
+            .await?;
+
+        let name = match format {
+            ExportFormat::Json => "messages.json",
+            ExportFormat::Html => "index.html",
+        };
+        let mut w = zip.write_entry_stream(entry(name)).await?;
+        match format {
+            ExportFormat::Json => w.write_all(b"[").await?,
+            ExportFormat::Html => w.write_all(html_header(&chat, &names).as_bytes()).await?,
+        }
+        let mut files: HashSet<String> = chat.avatar.iter().cloned().collect();
+        let mut messages = sqlx::query_as::<_, Message>(
+            r#"
+        SELECT id, chat_id, sender_id, sender_name, poll_id, content, files, format, html,
+            attachments, expires_at, created_at
+        FROM messages
+        WHERE chat_id = $1 AND (expires_at IS NULL OR expires_at > now())
+        ORDER BY id
+        "#,
+        )
+        .bind(chat_id as i64)
+        .fetch(&self.pool);
+        let mut first = true;
+        while let Some(message) = messages.try_next().await? {
+            files.extend(message.files.iter().cloned());
+            match format {
+                ExportFormat::Json => {
+                    if !first {
+                        w.write_all(b",").await?;
+                    }
+                    w.write_all(&serde_json::to_vec(&message)?).await?;
+                }
+                ExportFormat::Html => {
+                    w.write_all(html_message(&message, &names).as_bytes())
+                        .await?;
+                }
+            }
+            first = false;
+        }
+        match format {
+            ExportFormat::Json => w.write_all(b"]").await?,
+            ExportFormat::Html => w.write_all(b"</ol>\n</body>\n</html>\n").await?,
+        }
+        w.close().await?;
+
+        for url in files {
+            let Ok(file) = ChatFile::from_str(&url) else {
+                warn!("invalid file url in chat {}: {}", chat_id, url);
+                continue;
+            };
+            let key = file.hash_to_path();
+            let data = match self.storage.get(&key).await {
+                Ok(Some(data)) => data,
+                Ok(None) => {
+                    warn!("file {} of chat {} is not stored", key, chat_id);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("failed to read {} for export: {}", key, e);
+                    continue;
+                }
+            };
+            let name = format!("files/{}", key);
+            let mut w = zip.write_entry_stream(entry(&name)).await?;
+            w.write_all(&data).await?;
+            w.close().await?;
+        }
+        zip.close().await?;
+        Ok(())
+    }
+}
+
+fn entry(name: &str) -> ZipEntryBuilder {
+    ZipEntryBuilder::new(name.to_string().into(), Compression::Deflate)
+}
+
+fn html_header(chat: &Chat, names: &HashMap<i64, String>) -> String {
+    let title = ammonia::clean_text(chat.name.as_deref().unwrap_or("chat"));
+    let mut members: Vec<_> = names.iter().collect();
+    members.sort();
+    let members: String = members
+        .into_iter()
+        .map(|(id, name)| format!("<li id=\"user-{}\">{}</li>", id, ammonia::clean_text(name)))
+        .collect();
+    let about: String = [&chat.topic, &chat.description]
+        .into_iter()
+        .flatten()
+        .map(|text| format!("<p>{}</p>\n", ammonia::clean_text(text)))
+        .collect();
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n</head>\n<body>\n<h1>{title}</h1>\n{about}<h2>Members</h2>\n<ul>{members}</ul>\n<h2>Messages</h2>\n<ol>\n"
+    )
+}
+
+fn html_message(message: &Message, names: &HashMap<i64, String>) -> String {
+    let sender = match names.get(&message.sender_id) {
+        Some(name) => ammonia::clean_text(name),
+        None => format!("user {}", message.sender_id),
+    };
+    // html is sanitized when the message is created
+    let body = match (message.format, &message.html) {
+        (MessageFormat::Markdown, Some(html)) => html.clone(),
+        _ => format!("<p>{}</p>", ammonia::clean_text(&message.content)),
+    };
+    let files: String = message
+        .files
+        .iter()
+        .filter_map(|url| ChatFile::from_str(url).ok())
+        .map(|file| {
+            let path = file.hash_to_path();
+            format!("<li><a href=\"files/{}\">{}</a></li>", path, path)
+        })
+        .collect();
+    format!(
+        "<li id=\"message-{}\"><b>{}</b> <time>{}</time>{}<ul>{}</ul></li>\n",
+        message.id,
+        sender,
+        message.created_at.to_rfc3339(),
+        body,
+        files
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use async_zip::base::read::mem::ZipFileReader;
+
+    use crate::CreateMessage;
+
+    use super::*;
+    use anyhow::Result;
+
+    async fn export(state: &AppState, chat_id: u64, format: ExportFormat) -> Result<ZipFileReader> {
+        let mut data = Vec::new();
+        state.export_chat(chat_id, format, &mut data).await?;
+        Ok(ZipFileReader::new(data).await?)
+    }
+
+    async fn read_entry(zip: &ZipFileReader, name: &str) -> Result<String> {
+        let index = zip
+            .file()
+            .entries()
+            .iter()
+            .position(|e| e.filename().as_str().unwrap() == name)
+            .expect("entry should exist");
+        let mut content = String::new();
+        zip.reader_with_entry(index)
+            .await?
+            .read_to_string_checked(&mut content)
+            .await?;
+        Ok(content)
+    }
+
+    #[tokio::test]
+    async fn export_chat_json_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let file = ChatFile::new(1, "export.txt", b"export me");
+        let path = file.path(&state.config.server.base_dir);
+        tokio::fs::create_dir_all(path.parent().unwrap()).await?;
+        tokio::fs::write(&path, b"export me").await?;
+        let input = CreateMessage {
+            content: "with file".to_string(),
+            files: vec![file.url()],
+            ..Default::default()
+        };
+        state.create_message(input, 1, 1).await?;
+
+        let zip = export(&state, 1, ExportFormat::Json).await?;
+        let chat: Chat = serde_json::from_str(&read_entry(&zip, "chat.json").await?)?;
+        assert_eq!(chat.id, 1);
+        let members: Vec<serde_json::Value> =
+            serde_json::from_str(&read_entry(&zip, "members.json").await?)?;
+        assert_eq!(members.len(), 5);
+        let messages: Vec<Message> =
+            serde_json::from_str(&read_entry(&zip, "messages.json").await?)?;
+        assert_eq!(messages.len(), 11);
+        assert!(messages.windows(2).all(|w| w[0].id < w[1].id));
+        let content = read_entry(&zip, &format!("files/{}", file.hash_to_path())).await?;
+        assert_eq!(content, "export me");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn export_chat_html_should_escape_content() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "<script>alert(1)</script>".to_string(),
+            ..Default::default()
+        };
+        state.create_message(input, 2, 1).await?;
+
+        let zip = export(&state, 2, ExportFormat::Html).await?;
+        let html = read_entry(&zip, "index.html").await?;
+        assert!(html.contains("<h1>private</h1>"));
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(!html.contains("<script>"));
+        Ok(())
+    }
+}
@@ -3,24 +3,70 @@ use std::{
     str::FromStr,
 };
 
-use crate::{AppError, ChatFile};
+use crate::{storage::FileStorage, AppError, AppState, ChatFile};
+use chat_core::Attachment;
 use sha1::{Digest, Sha1};
+use sqlx::PgExecutor;
+use tracing::warn;
+
+/// unreferenced files looked at per run of the garbage collection
+const FILE_GC_BATCH_SIZE: i64 = 100;
 
 impl ChatFile {
     pub fn new(ws_id: u64, filename: &str, data: &[u8]) -> Self {
         let hash = Sha1::digest(data);
+        let ext = filename
+            .rsplit_once('.')
+            .map(|(_, ext)| ext)
+            .filter(|ext| is_file_ext(ext))
+            .unwrap_or("txt");
         Self {
             ws_id,
-            ext: filename.rsplit('.').next().unwrap_or("txt").to_string(),
+            ext: ext.to_string(),
             hash: hex::encode(hash),
         }
     }
     pub fn url(&self) -> String {
         format!("/files/{}", self.hash_to_path())
     }
+    /// Where the local storage keeps the file
     pub fn path(&self, base_dir: &Path) -> PathBuf {
         base_dir.join(self.hash_to_path())
     }
+    /// Build attachment metadata from the stored file, `filename` falls back to the hash
+    pub(crate) async fn attachment(
+        &self,
+        storage: &impl FileStorage,
+        filename: Option<&str>,
+    ) -> Result<Attachment, AppError> {
+        let Some(data) = storage.get(&self.hash_to_path()).await? else {
+            return Err(AppError::ChatFileError(format!(
+                "File does not exist: {}",
+                self.url()
+            )));
+        };
+        let mime = mime_guess::from_ext(&self.ext).first_or_octet_stream();
+        let (width, height) = if mime.type_() == mime_guess::mime::IMAGE {
+            match imagesize::blob_size(&data) {
+                Ok(dim) => (Some(dim.width as u32), Some(dim.height as u32)),
+                Err(_) => (None, None),
+            }
+        } else {
+            (None, None)
+        };
+        let filename = match filename {
+            Some(name) => name.to_string(),
+            None => format!("{}.{}", self.hash, self.ext),
+        };
+        Ok(Attachment {
+            url: self.url(),
+            filename,
+            mime: mime.to_string(),
+            size: data.len() as u64,
+            width,
+            height,
+        })
+    }
     pub fn hash_to_path(&self) -> String {
         let (part1, part2) = self.hash.split_at(3);
         let (part2, part3) = part2.split_at(3);
@@ -28,6 +74,178 @@ impl ChatFile {
     }
 }
 
+impl AppState {
+    /// Avatars must be uploaded images of the workspace
+    pub(crate) async fn verify_image_file(&self, url: &str, ws_id: u64) -> Result<(), AppError> {
+        let file = ChatFile::from_str(url)?;
+        let is_image = mime_guess::from_ext(&file.ext)
+            .first()
+            .is_some_and(|mime| mime.type_() == mime_guess::mime::IMAGE);
+        if file.ws_id != ws_id || !is_image {
+            return Err(AppError::ChatFileError(format!(
+                "Avatar must be an uploaded image: {}",
+                url
+            )));
+        }
+        if !self.storage.exists(&file.hash_to_path()).await? {
+            return Err(AppError::ChatFileError(format!(
+                "File does not exist: {}",
+                url
+            )));
+        }
+        Ok(())
+    }
+    /// Store uploaded content and track it, uploading the same content again
+    /// restarts its grace period
+    pub(crate) async fn store_file(
+        &self,
+        ws_id: u64,
+        uploaded_by: Option<i64>,
+        filename: &str,
+        data: &[u8],
+    ) -> Result<ChatFile, AppError> {
+        let file = ChatFile::new(ws_id, filename, data);
+        let mime = mime_guess::from_ext(&file.ext).first_or_octet_stream();
+        let mut tx = self.pool.begin().await?;
+        // the content is not removed while the row is tracked and written
+        lock_file(&file.url(), &mut *tx).await?;
+        sqlx::query(
+            r#"
+        INSERT INTO files (url, ws_id, hash, uploaded_by, size, mime)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (url) DO UPDATE SET uploaded_at = now()
+        "#,
+        )
+        .bind(file.url())
+        .bind(ws_id as i64)
+        .bind(&file.hash)
+        .bind(uploaded_by)
+        .bind(data.len() as i64)
+        .bind(mime.to_string())
+        .execute(&mut *tx)
+        .await?;
+        let key = file.hash_to_path();
+        if self.storage.exists(&key).await? {
+            warn!("File {} already exists: {}", filename, key);
+        } else {
+            self.storage.put(&key, data).await?;
+        }
+        tx.commit().await?;
+        Ok(file)
+    }
+    /// Remove the stored file if no message, pending scheduled message or avatar uses it
+    pub(crate) async fn remove_orphaned_file(&self, url: &str) -> Result<bool, AppError> {
+        if file_in_use(url, &self.pool).await? {
+            return Ok(false);
+        }
+        sqlx::query("DELETE FROM files WHERE url = $1")
+            .bind(url)
+            .execute(&self.pool)
+            .await?;
+        self.remove_stored_file(url).await
+    }
+    /// Remove files no message referenced for the grace period, e.g. abandoned uploads. The rows
+    /// are deleted first, a content that could not be removed afterwards is only left behind.
+    pub async fn collect_unreferenced_files(&self) -> Result<u64, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let candidates: Vec<(String,)> = sqlx::query_as(
+            r#"
+        SELECT url FROM files
+        WHERE ref_count = 0 AND uploaded_at <= now() - make_interval(secs => $1)
+        ORDER BY uploaded_at
+        LIMIT $2
+        FOR UPDATE SKIP LOCKED
+        "#,
+        )
+        .bind(self.config.files.gc_grace_secs as f64)
+        .bind(FILE_GC_BATCH_SIZE)
+        .fetch_all(&mut *tx)
+        .await?;
+        let mut unused = Vec::with_capacity(candidates.len());
+        for (url,) in candidates {
+            if file_in_use(&url, &mut *tx).await? {
+                // an avatar or a pending scheduled message, look again after another grace period
+                sqlx::query("UPDATE files SET uploaded_at = now() WHERE url = $1")
+                    .bind(&url)
+                    .execute(&mut *tx)
+                    .await?;
+                continue;
+            }
+            sqlx::query("DELETE FROM files WHERE url = $1")
+                .bind(&url)
+                .execute(&mut *tx)
+                .await?;
+            unused.push(url);
+        }
+        tx.commit().await?;
+
+        let mut removed = 0;
+        for url in unused {
+            if self.remove_stored_file(&url).await? {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+    /// Remove the content of a file whose row is gone, unless it was uploaded again meanwhile
+    async fn remove_stored_file(&self, url: &str) -> Result<bool, AppError> {
+        let Ok(file) = ChatFile::from_str(url) else {
+            warn!("invalid file url in message: {}", url);
+            return Ok(false);
+        };
+        let mut tx = self.pool.begin().await?;
+        lock_file(url, &mut *tx).await?;
+        let (tracked,): (bool,) =
+            sqlx::query_as("SELECT EXISTS (SELECT 1 FROM files WHERE url = $1)")
+                .bind(url)
+                .fetch_one(&mut *tx)
+                .await?;
+        if tracked {
+            return Ok(false);
+        }
+        let removed = self.storage.delete(&file.hash_to_path()).await?;
+        tx.commit().await?;
+        Ok(removed)
+    }
+}
+
+/// Storing and removing the content of a url take turns, until the transaction ends
+async fn lock_file<'e, E>(url: &str, executor: E) -> Result<(), AppError>
+where
+    E: PgExecutor<'e>,
+{
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended($1, 0))")
+        .bind(url)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// Whether a message, a pending scheduled message or an avatar uses the file
+async fn file_in_use<'e, E>(url: &str, executor: E) -> Result<bool, AppError>
+where
+    E: PgExecutor<'e>,
+{
+    let (in_use,): (bool,) = sqlx::query_as(
+        r#"
+    SELECT EXISTS (SELECT 1 FROM messages WHERE files @> ARRAY[$1::text])
+        OR EXISTS (SELECT 1 FROM chats WHERE avatar = $1)
+        OR EXISTS (SELECT 1 FROM users WHERE avatar = $1)
+        OR EXISTS (
+            SELECT 1 FROM scheduled_messages
+            WHERE status = 'pending' AND (
+                message->'files' ? $1
+                OR jsonb_path_exists(message, '$.attachments[*] ? (@.url == $url)', jsonb_build_object('url', $1))
+            )
+        )
+    "#,
+    )
+    .bind(url)
+    .fetch_one(executor)
+    .await?;
+    Ok(in_use)
+}
+
 impl FromStr for ChatFile {
     type Err = AppError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -49,13 +267,19 @@ impl FromStr for ChatFile {
                 parts[1]
             )));
         };
-        let Some((part3, ext)) = parts[3].split_once('.') else {
+        let Some((part3, ext)) = parts[3].split_once('.').filter(|(_, ext)| is_file_ext(ext))
+        else {
             return Err(AppError::ChatFileError(format!(
                 "Invalid file name: {}",
                 parts[3]
             )));
         };
         let hash = format!("{}{}{}", parts[1], parts[2], part3);
+        // the path must be the one `hash_to_path` builds
+        let is_hash = hash.len() == 40 && hash.bytes().all(|b| b.is_ascii_hexdigit());
+        if parts[1].len() != 3 || parts[2].len() != 3 || !is_hash {
+            return Err(AppError::ChatFileError(format!("Invalid file path: {}", s)));
+        }
         Ok(Self {
             ws_id,
             ext: ext.to_string(),
@@ -64,9 +288,15 @@ impl FromStr for ChatFile {
     }
 }
 
+/// Extensions end up in storage keys and paths, so no `.`, `/` or `..` may sneak in
+fn is_file_ext(ext: &str) -> bool {
+    (1..=16).contains(&ext.len()) && ext.bytes().all(|b| b.is_ascii_alphanumeric())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{storage::LocalStorage, CreateMessage};
 
     #[test]
     fn chat_file_new_should_work() {
@@ -74,5 +304,90 @@ mod tests {
         assert_eq!(file.ws_id, 1);
         assert_eq!(file.ext, "txt");
         assert_eq!(file.hash, "a94a8fe5ccb19ba61c4c0873d391e987982fbbd3");
+        for name in [
+            "README",
+            "x./../../etc",
+            "x.tar gz",
+            "x.abcdefghijklmnopq",
+            "x.",
+        ] {
+            assert_eq!(ChatFile::new(1, name, b"test").ext, "txt", "{}", name);
+        }
+    }
+
+    #[test]
+    fn chat_file_from_str_should_check_ext() {
+        let url = "/files/1/a94/a8f/e5ccb19ba61c4c0873d391e987982fbbd3";
+        assert!(format!("{}.txt", url).parse::<ChatFile>().is_ok());
+        for ext in ["", ".", "./..", "t/xt", "abcdefghijklmnopq"] {
+            assert!(
+                format!("{}.{}", url, ext).parse::<ChatFile>().is_err(),
+                "{}",
+                ext
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn chat_file_attachment_should_work() -> anyhow::Result<()> {
+        let base_dir = std::env::temp_dir().join("chat_file_attachment");
+        let storage = LocalStorage::try_new(base_dir).await?;
+        let file = ChatFile::new(1, "notes.txt", b"hello attachment");
+        storage
+            .put(&file.hash_to_path(), b"hello attachment")
+            .await?;
+
+        let attachment = file.attachment(&storage, Some("notes.txt")).await?;
+        assert_eq!(attachment.url, file.url());
+        assert_eq!(attachment.filename, "notes.txt");
+        assert_eq!(attachment.mime, "text/plain");
+        assert_eq!(attachment.size, 16);
+        assert_eq!(attachment.width, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unreferenced_files_should_be_collected() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let kept = state.store_file(1, Some(1), "kept.txt", b"kept").await?;
+        let abandoned = state
+            .store_file(1, Some(1), "abandoned.txt", b"abandoned")
+            .await?;
+        let base_dir = &state.config.server.base_dir;
+        for url in [kept.url(), kept.url()] {
+            let input = CreateMessage {
+                content: "file".to_string(),
+                files: vec![url],
+                ..Default::default()
+            };
+            state.create_message(input, 1, 1).await?;
+        }
+        assert_eq!(ref_count(&state, &kept.url()).await?, 2);
+
+        // within the grace period nothing goes
+        assert_eq!(state.collect_unreferenced_files().await?, 0);
+        sqlx::query("UPDATE files SET uploaded_at = now() - interval '2 days'")
+            .execute(&state.pool)
+            .await?;
+        assert_eq!(state.collect_unreferenced_files().await?, 1);
+        assert!(!abandoned.path(base_dir).exists());
+        assert!(kept.path(base_dir).exists());
+
+        sqlx::query("DELETE FROM messages WHERE $1 = ANY(files)")
+            .bind(kept.url())
+            .execute(&state.pool)
+            .await?;
+        assert_eq!(ref_count(&state, &kept.url()).await?, 0);
+        assert_eq!(state.collect_unreferenced_files().await?, 1);
+        assert!(!kept.path(base_dir).exists());
+        Ok(())
+    }
+
+    async fn ref_count(state: &AppState, url: &str) -> anyhow::Result<i32> {
+        let (count,): (i32,) = sqlx::query_as("SELECT ref_count FROM files WHERE url = $1")
+            .bind(url)
+            .fetch_one(&state.pool)
+            .await?;
+        Ok(count)
     }
 }
@@ -0,0 +1,754 @@
+use std::collections::HashMap;
+
+use argon2::password_hash::{rand_core::OsRng, SaltString};
+use async_zip::tokio::read::seek::ZipFileReader;
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sqlx::{types::Json, PgExecutor};
+use tokio::io::{AsyncBufRead, AsyncSeek};
+use utoipa::ToSchema;
+
+use super::user::hash_password;
+use crate::{AppError, AppState};
+use chat_core::{Attachment, ChatType};
+
+/// Conversation lists of a slack export and the chat type they are imported as
+const SLACK_CONVERSATIONS: [(&str, ChatType); 4] = [
+    ("channels.json", ChatType::PublicChannel),
+    ("groups.json", ChatType::PrivateChannel),
+    ("mpims.json", ChatType::Group),
+    ("dms.json", ChatType::Single),
+];
+
+/// Message subtypes carrying user content, the rest (joins, topic changes...) are skipped
+const SLACK_CONTENT_SUBTYPES: [&str; 3] = ["thread_broadcast", "file_share", "me_message"];
+
+/// What an import created, and what it could not import with the reason
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportSummary {
+    pub users: u64,
+    pub chats: u64,
+    pub messages: u64,
+    pub files: u64,
+    /// replies are flattened into the chat in chronological order
+    pub thread_replies: u64,
+    /// objects mapped by a previous run or to existing users with the same email
+    pub existing: u64,
+    pub skipped: Vec<ImportSkipped>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct ImportSkipped {
+    pub kind: String,
+    /// slack id of the object, `<channel id>:<ts>` for messages
+    pub id: String,
+    pub reason: String,
+    /// where slack serves a file that is not in the archive, fetched with a slack token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "import_kind", rename_all = "snake_case")]
+enum ImportKind {
+    User,
+    Chat,
+    Message,
+}
+
+#[derive(Debug, Deserialize)]
+struct SlackUser {
+    id: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    real_name: Option<String>,
+    #[serde(default)]
+    is_bot: bool,
+    #[serde(default)]
+    profile: SlackProfile,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct SlackProfile {
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(default)]
+    real_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SlackConversation {
+    id: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    created: i64,
+    #[serde(default)]
+    members: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SlackMessage {
+    #[serde(default)]
+    subtype: Option<String>,
+    #[serde(default)]
+    user: Option<String>,
+    #[serde(default)]
+    text: String,
+    ts: String,
+    #[serde(default)]
+    thread_ts: Option<String>,
+    #[serde(default)]
+    files: Vec<SlackFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SlackFile {
+    id: String,
+    #[serde(default)]
+    name: Option<String>,
+    /// `tombstone` for deleted files, `hidden_by_limit` for files the plan no longer shows
+    #[serde(default)]
+    mode: Option<String>,
+    #[serde(default)]
+    url_private: Option<String>,
+}
+
+/// Slack export zip. Slack only links files with `url_private`, their contents are imported if
+/// the archive has them under `__uploads/<file id>/<name>`, e.g. added by export tools, and
+/// reported as skipped with their url otherwise.
+/// Entries are read from the source one at a time, the archive is never in memory as a whole.
+struct SlackArchive<R> {
+    zip: ZipFileReader<R>,
+    entries: HashMap<String, usize>,
+}
+
+struct SlackImporter<'a, R> {
+    state: &'a AppState,
+    ws_id: i64,
+    archive: SlackArchive<R>,
+    /// slack user id -> local user id
+    users: HashMap<String, i64>,
+    summary: ImportSummary,
+}
+
+impl AppState {
+    /// Import a slack export zip into the workspace. Every imported object is recorded in
+    /// `import_mappings`, so running it again with the same archive only adds what is missing.
+    /// Slack threads are flattened, replies become messages of the chat in chronological order.
+    pub async fn import_slack<R>(&self, ws_id: u64, source: R) -> Result<ImportSummary, AppError>
+    where
+        R: AsyncBufRead + AsyncSeek + Unpin,
+    {
+        let archive = SlackArchive::new(source).await?;
+        if !archive.entries.contains_key("users.json") {
+            return Err(AppError::ImportError(
+                "users.json not found, not a slack export".to_string(),
+            ));
+        }
+        let mut importer = SlackImporter {
+            state: self,
+            ws_id: ws_id as _,
+            archive,
+            users: HashMap::new(),
+            summary: ImportSummary::default(),
+        };
+        importer.import_users().await?;
+        for (list, chat_type) in SLACK_CONVERSATIONS {
+            let conversations: Vec<SlackConversation> = importer.archive.read_json(list).await?;
+            for conversation in conversations {
+                let Some(chat_id) = importer.import_chat(&conversation, chat_type).await? else {
+                    continue;
+                };
+                // dms are stored under their id, everything else under the name
+                let folder = match chat_type {
+                    ChatType::Single => &conversation.id,
+                    _ => conversation.name.as_ref().unwrap_or(&conversation.id),
+                };
+                importer
+                    .import_messages(chat_id, &conversation.id, folder)
+                    .await?;
+            }
+        }
+        Ok(importer.summary)
+    }
+
+    async fn find_import_mapping<'e, E>(
+        &self,
+        ws_id: i64,
+        kind: ImportKind,
+        external_id: &str,
+        executor: E,
+    ) -> Result<Option<i64>, AppError>
+    where
+        E: PgExecutor<'e>,
+    {
+        let id: Option<(i64,)> = sqlx::query_as(
+            r#"SELECT local_id FROM import_mappings WHERE ws_id = $1 AND kind = $2 AND external_id = $3"#,
+        )
+        .bind(ws_id)
+        .bind(kind)
+        .bind(external_id)
+        .fetch_optional(executor)
+        .await?;
+        Ok(id.map(|(id,)| id))
+    }
+
+    async fn insert_import_mapping<'e, E>(
+        &self,
+        ws_id: i64,
+        kind: ImportKind,
+        external_id: &str,
+        local_id: i64,
+        executor: E,
+    ) -> Result<(), AppError>
+    where
+        E: PgExecutor<'e>,
+    {
+        sqlx::query(
+            r#"INSERT INTO import_mappings (ws_id, kind, external_id, local_id) VALUES ($1, $2, $3, $4)"#,
+        )
+        .bind(ws_id)
+        .bind(kind)
+        .bind(external_id)
+        .bind(local_id)
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+}
+
+impl<R> SlackImporter<'_, R>
+where
+    R: AsyncBufRead + AsyncSeek + Unpin,
+{
+    async fn import_users(&mut self) -> Result<(), AppError> {
+        let state = self.state;
+        let users: Vec<SlackUser> = self.archive.read_json("users.json").await?;
+        for user in users {
+            if let Some(id) = state
+                .find_import_mapping(self.ws_id, ImportKind::User, &user.id, &state.pool)
+                .await?
+            {
+                self.users.insert(user.id, id);
+                self.summary.existing += 1;
+                continue;
+            }
+            if user.is_bot {
+                self.skip("user", &user.id, "bot user");
+                continue;
+            }
+            let Some(email) = user
+                .profile
+                .email
+                .filter(|v| !v.is_empty() && v.len() <= 64)
+            else {
+                self.skip("user", &user.id, "missing or invalid email");
+                continue;
+            };
+            let mut tx = state.pool.begin().await?;
+            let id = match state.find_user_by_email(&email).await? {
+                Some(existing) if existing.ws_id == self.ws_id => {
+                    self.summary.existing += 1;
+                    existing.id
+                }
+                Some(_) => {
+                    self.skip("user", &user.id, "email is used in another workspace");
+                    continue;
+                }
+                None => {
+                    let fullname = user
+                        .profile
+                        .real_name
+                        .or(user.real_name)
+                        .filter(|v| !v.is_empty())
+                        .unwrap_or(user.name);
+                    // imported users can not sign in with a password until they reset it
+                    let password_hash = hash_password(SaltString::generate(&mut OsRng).as_str())?;
+                    let (id,): (i64,) = sqlx::query_as(
+                        r#"INSERT INTO users (ws_id, email, fullname, password_hash) VALUES ($1, $2, $3, $4) RETURNING id"#,
+                    )
+                    .bind(self.ws_id)
+                    .bind(&email)
+                    .bind(fullname.chars().take(64).collect::<String>())
+                    .bind(password_hash)
+                    .fetch_one(&mut *tx)
+                    .await?;
+                    self.summary.users += 1;
+                    id
+                }
+            };
+            state
+                .insert_import_mapping(self.ws_id, ImportKind::User, &user.id, id, &mut *tx)
+                .await?;
+            tx.commit().await?;
+            self.users.insert(user.id, id);
+        }
+        Ok(())
+    }
+
+    async fn import_chat(
+        &mut self,
+        conversation: &SlackConversation,
+        chat_type: ChatType,
+    ) -> Result<Option<i64>, AppError> {
+        let state = self.state;
+        if let Some(id) = state
+            .find_import_mapping(self.ws_id, ImportKind::Chat, &conversation.id, &state.pool)
+            .await?
+        {
+            self.summary.existing += 1;
+            return Ok(Some(id));
+        }
+        let mut members: Vec<i64> = conversation
+            .members
+            .iter()
+            .filter_map(|id| self.users.get(id).copied())
+            .collect();
+        members.sort();
+        members.dedup();
+        if members.len() < 2 {
+            self.skip("chat", &conversation.id, "less than two imported members");
+            return Ok(None);
+        }
+        let (name, chat_type) = match chat_type {
+            ChatType::Single | ChatType::Group if members.len() == 2 => (None, ChatType::Single),
+            ChatType::Single | ChatType::Group => (None, ChatType::Group),
+            _ => {
+                let name: Option<String> = conversation
+                    .name
+                    .as_ref()
+                    .map(|v| v.chars().take(64).collect());
+                (name, chat_type)
+            }
+        };
+        let created_at =
+            DateTime::from_timestamp(conversation.created, 0).filter(|_| conversation.created > 0);
+        let mut tx = state.pool.begin().await?;
+        let id: Option<(i64,)> = sqlx::query_as(
+            r#"
+        INSERT INTO chats (ws_id, name, type, created_at)
+        VALUES ($1, $2, $3, COALESCE($4, CURRENT_TIMESTAMP))
+        ON CONFLICT (ws_id, name) DO NOTHING
+        RETURNING id
+        "#,
+        )
+        .bind(self.ws_id)
+        .bind(&name)
+        .bind(chat_type)
+        .bind(created_at)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some((id,)) = id else {
+            self.skip("chat", &conversation.id, "a chat with the same name exists");
+            return Ok(None);
+        };
+        sqlx::query(
+            r#"
+        INSERT INTO chat_members (chat_id, user_id, joined_at)
+        SELECT $1, user_id, COALESCE($3, CURRENT_TIMESTAMP) FROM unnest($2::bigint[]) AS user_id
+        "#,
+        )
+        .bind(id)
+        .bind(&members)
+        .bind(created_at)
+        .execute(&mut *tx)
+        .await?;
+        state
+            .insert_import_mapping(self.ws_id, ImportKind::Chat, &conversation.id, id, &mut *tx)
+            .await?;
+        tx.commit().await?;
+        self.summary.chats += 1;
+        Ok(Some(id))
+    }
+
+    /// Import the daily message files under `folder` in one transaction per chat
+    async fn import_messages(
+        &mut self,
+        chat_id: i64,
+        conversation_id: &str,
+        folder: &str,
+    ) -> Result<(), AppError> {
+        let state = self.state;
+        let prefix = format!("{}/", folder);
+        let mut days: Vec<String> = self
+            .archive
+            .entries
+            .keys()
+            .filter(|name| {
+                name.strip_prefix(&prefix)
+                    .is_some_and(|day| day.ends_with(".json") && !day.contains('/'))
+            })
+            .cloned()
+            .collect();
+        days.sort();
+
+        let mut tx = state.pool.begin().await?;
+        // history is not announced as new messages, see the add_to_message trigger
+        sqlx::query("SELECT set_config('chat.importing', 'on', true)")
+            .execute(&mut *tx)
+            .await?;
+        for day in days {
+            let messages: Vec<SlackMessage> = self.archive.read_json(&day).await?;
+            for message in messages {
+                let external_id = format!("{}:{}", conversation_id, message.ts);
+                if state
+                    .find_import_mapping(self.ws_id, ImportKind::Message, &external_id, &mut *tx)
+                    .await?
+                    .is_some()
+                {
+                    self.summary.existing += 1;
+                    continue;
+                }
+                if let Some(subtype) = &message.subtype {
+                    if !SLACK_CONTENT_SUBTYPES.contains(&subtype.as_str()) {
+                        let reason = format!("unsupported subtype {}", subtype);
+                        self.skip("message", &external_id, &reason);
+                        continue;
+                    }
+                }
+                let sender_id = message.user.as_ref().and_then(|id| self.users.get(id));
+                let Some(sender_id) = sender_id.copied() else {
+                    self.skip("message", &external_id, "sender was not imported");
+                    continue;
+                };
+                let Some(created_at) = parse_slack_ts(&message.ts) else {
+                    self.skip("message", &external_id, "invalid timestamp");
+                    continue;
+                };
+                let attachments = self.import_files(&message.files).await?;
+                let mut content = message.text;
+                if content.is_empty() {
+                    // also names files that were skipped, so the message is not lost
+                    let names: Vec<&str> = message
+                        .files
+                        .iter()
+                        .map(|f| f.name.as_deref().unwrap_or(&f.id))
+                        .collect();
+                    content = names.join(", ");
+                }
+                if content.is_empty() {
+                    self.skip("message", &external_id, "empty message");
+                    continue;
+                }
+                let files: Vec<&str> = attachments.iter().map(|v| v.url.as_str()).collect();
+                let (id,): (i64,) = sqlx::query_as(
+                    r#"
+                INSERT INTO messages (chat_id, sender_id, content, files, attachments, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                RETURNING id
+                "#,
+                )
+                .bind(chat_id)
+                .bind(sender_id)
+                .bind(content)
+                .bind(&files)
+                .bind(Json(&attachments))
+                .bind(created_at)
+                .fetch_one(&mut *tx)
+                .await?;
+                state
+                    .insert_import_mapping(
+                        self.ws_id,
+                        ImportKind::Message,
+                        &external_id,
+                        id,
+                        &mut *tx,
+                    )
+                    .await?;
+                if message.thread_ts.is_some_and(|ts| ts != message.ts) {
+                    self.summary.thread_replies += 1;
+                }
+                self.summary.messages += 1;
+            }
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Store files of a message in the content addressed store under the workspace
+    async fn import_files(&mut self, files: &[SlackFile]) -> Result<Vec<Attachment>, AppError> {
+        let storage = &self.state.storage;
+        let mut attachments = Vec::with_capacity(files.len());
+        for f in files {
+            if let Some(mode) = f.mode.as_deref().filter(|v| *v != "hosted") {
+                let reason = format!("file is {} in slack", mode);
+                self.skip("file", &f.id, &reason);
+                continue;
+            }
+            let Some(entry) = self.archive.upload(&f.id) else {
+                self.summary.skipped.push(ImportSkipped {
+                    kind: "file".to_string(),
+                    id: f.id.clone(),
+                    reason: "file content is not in the archive".to_string(),
+                    url: f.url_private.clone(),
+                });
+                continue;
+            };
+            let filename = match &f.name {
+                Some(name) => name.clone(),
+                None => entry.rsplit('/').next().unwrap_or_default().to_string(),
+            };
+            let data = self.archive.read(&entry).await?.unwrap_or_default();
+            let file = self
+                .state
+                .store_file(self.ws_id as _, None, &filename, &data)
+                .await?;
+            attachments.push(file.attachment(storage, Some(&filename)).await?);
+            self.summary.files += 1;
+        }
+        Ok(attachments)
+    }
+
+    fn skip(&mut self, kind: &str, id: &str, reason: &str) {
+        self.summary.skipped.push(ImportSkipped {
+            kind: kind.to_string(),
+            id: id.to_string(),
+            reason: reason.to_string(),
+            url: None,
+        });
+    }
+}
+
+impl<R> SlackArchive<R>
+where
+    R: AsyncBufRead + AsyncSeek + Unpin,
+{
+    async fn new(source: R) -> Result<Self, AppError> {
+        let zip = ZipFileReader::with_tokio(source).await?;
+        let entries = zip
+            .file()
+            .entries()
+            .iter()
+            .enumerate()
+            .filter_map(|(i, e)| Some((e.filename().as_str().ok()?.to_string(), i)))
+            .collect();
+        Ok(Self { zip, entries })
+    }
+
+    async fn read(&mut self, name: &str) -> Result<Option<Vec<u8>>, AppError> {
+        let Some(&index) = self.entries.get(name) else {
+            return Ok(None);
+        };
+        let mut data = Vec::new();
+        self.zip
+            .reader_with_entry(index)
+            .await?
+            .read_to_end_checked(&mut data)
+            .await?;
+        Ok(Some(data))
+    }
+
+    /// Parse a json file of the archive, a missing file is empty
+    async fn read_json<T: DeserializeOwned + Default>(
+        &mut self,
+        name: &str,
+    ) -> Result<T, AppError> {
+        match self.read(name).await? {
+            Some(data) => serde_json::from_slice(&data)
+                .map_err(|e| AppError::ImportError(format!("invalid {}: {}", name, e))),
+            None => Ok(T::default()),
+        }
+    }
+
+    fn upload(&self, file_id: &str) -> Option<String> {
+        let prefix = format!("__uploads/{}/", file_id);
+        self.entries
+            .keys()
+            .find(|name| name.starts_with(&prefix) && name.len() > prefix.len())
+            .cloned()
+    }
+}
+
+/// Slack timestamps are `<unix seconds>.<microseconds>`
+fn parse_slack_ts(ts: &str) -> Option<DateTime<Utc>> {
+    let (secs, micros) = ts.split_once('.').unwrap_or((ts, "0"));
+    DateTime::from_timestamp(secs.parse().ok()?, micros.parse::<u32>().ok()? * 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, time::Duration};
+
+    use async_zip::{tokio::write::ZipFileWriter, Compression, ZipEntryBuilder};
+    use serde_json::json;
+    use sqlx::postgres::PgListener;
+
+    use super::*;
+    use anyhow::Result;
+
+    async fn slack_export() -> Result<Cursor<Vec<u8>>> {
+        let entries = [
+            (
+                "users.json",
+                json!([
+                    {"id": "U1", "name": "zzq", "profile": {"email": "zzq@zzq.com"}},
+                    {"id": "U2", "name": "alice", "real_name": "Alice", "profile": {"email": "alice@slack.com"}},
+                    {"id": "U3", "name": "bob", "profile": {"email": "bob@slack.com", "real_name": "Bob"}},
+                    {"id": "B1", "name": "deploybot", "is_bot": true, "profile": {}},
+                    {"id": "U4", "name": "beta", "profile": {"email": "other@zzq.com"}},
+                ])
+                .to_string(),
+            ),
+            (
+                "channels.json",
+                json!([
+                    {"id": "C1", "name": "launch", "created": 1609459200, "members": ["U1", "U2", "U3"]},
+                    {"id": "C2", "name": "general", "created": 1609459200, "members": ["U1", "U2"]},
+                ])
+                .to_string(),
+            ),
+            (
+                "dms.json",
+                json!([{"id": "D1", "created": 1609459200, "members": ["U2", "U3"]}]).to_string(),
+            ),
+            (
+                "launch/2021-01-01.json",
+                json!([
+                    {"type": "message", "subtype": "channel_join", "user": "U2", "text": "joined", "ts": "1609459201.000100"},
+                    {"type": "message", "user": "U2", "text": "ship it", "ts": "1609459202.000200", "thread_ts": "1609459202.000200"},
+                    {"type": "message", "user": "U3", "text": "", "ts": "1609459203.000300", "thread_ts": "1609459202.000200",
+                        "files": [
+                            {"id": "F1", "name": "plan.txt", "mode": "hosted"},
+                            {"id": "F2", "name": "lost.png", "url_private": "https://files.slack.com/files-pri/T1-F2/lost.png"},
+                            {"id": "F3", "name": "gone.pdf", "mode": "tombstone"},
+                        ]},
+                    {"type": "message", "subtype": "bot_message", "text": "deployed", "ts": "1609459204.000400"},
+                ])
+                .to_string(),
+            ),
+            (
+                "D1/2021-01-02.json",
+                json!([{"type": "message", "user": "U3", "text": "hi alice", "ts": "1609545600.000000"}])
+                    .to_string(),
+            ),
+            ("__uploads/F1/plan.txt", "the plan".to_string()),
+        ];
+        let mut data = Vec::new();
+        let mut zip = ZipFileWriter::with_tokio(&mut data);
+        for (name, content) in entries {
+            let entry = ZipEntryBuilder::new(name.to_string().into(), Compression::Deflate);
+            zip.write_entry_whole(entry, content.as_bytes()).await?;
+        }
+        zip.close().await?;
+        Ok(Cursor::new(data))
+    }
+
+    #[tokio::test]
+    async fn import_slack_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        sqlx::query("UPDATE users SET ws_id = 2, email = 'other@zzq.com' WHERE id = 5")
+            .execute(&state.pool)
+            .await?;
+
+        let mut listener = PgListener::connect_with(&state.pool).await?;
+        listener.listen("chat_message_added").await?;
+        let summary = state.import_slack(1, slack_export().await?).await?;
+        // imported history is not sent to members as new messages
+        let notification = tokio::time::timeout(Duration::from_millis(500), listener.recv()).await;
+        assert!(notification.is_err());
+        assert_eq!(summary.users, 2);
+        // zzq already exists in the workspace
+        assert_eq!(summary.existing, 1);
+        // general already exists in the workspace
+        assert_eq!(summary.chats, 2);
+        assert_eq!(summary.messages, 3);
+        assert_eq!(summary.files, 1);
+        assert_eq!(summary.thread_replies, 1);
+        let skipped: Vec<_> = summary
+            .skipped
+            .iter()
+            .map(|v| (v.kind.as_str(), v.id.as_str()))
+            .collect();
+        assert_eq!(
+            skipped,
+            [
+                ("user", "B1"),
+                ("user", "U4"),
+                ("message", "C1:1609459201.000100"),
+                ("file", "F2"),
+                ("file", "F3"),
+                ("message", "C1:1609459204.000400"),
+                ("chat", "C2"),
+            ]
+        );
+
+        assert_eq!(
+            summary.skipped[3].url.as_deref(),
+            Some("https://files.slack.com/files-pri/T1-F2/lost.png")
+        );
+
+        let alice = state.find_user_by_email("alice@slack.com").await?.unwrap();
+        assert_eq!(alice.fullname, "Alice");
+        assert_eq!(alice.ws_id, 1);
+        let chats = state
+            .fetch_chats(Default::default(), alice.id as _, 1)
+            .await?;
+        assert_eq!(chats.len(), 2);
+        let launch = chats.iter().find(|c| c.name.as_deref() == Some("launch"));
+        let launch = launch.unwrap();
+        assert_eq!(launch.r#type, ChatType::PublicChannel);
+        assert_eq!(launch.created_at.timestamp(), 1609459200);
+        let dm = chats.iter().find(|c| c.r#type == ChatType::Single).unwrap();
+        assert_eq!(dm.members.len(), 2);
+
+        let messages = state
+            .list_messages(
+                crate::ListMessages {
+                    last_id: None,
+                    limit: 0,
+                },
+                launch.id as _,
+            )
+            .await?;
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].content, "plan.txt, lost.png, gone.pdf");
+        assert_eq!(messages[0].attachments.len(), 1);
+        assert_eq!(messages[0].attachments[0].filename, "plan.txt");
+        assert_eq!(
+            messages[0].created_at,
+            parse_slack_ts("1609459203.000300").unwrap()
+        );
+        assert_eq!(messages[1].content, "ship it");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn import_slack_should_be_idempotent() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let data = slack_export().await?;
+        let first = state.import_slack(1, data.clone()).await?;
+        let second = state.import_slack(1, data).await?;
+        assert_eq!(second.users, 0);
+        assert_eq!(second.chats, 0);
+        assert_eq!(second.messages, 0);
+        assert_eq!(
+            second.existing,
+            first.existing + first.users + first.chats + first.messages
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn import_non_slack_archive_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut data = Vec::new();
+        let mut zip = ZipFileWriter::with_tokio(&mut data);
+        let entry = ZipEntryBuilder::new("readme.txt".to_string().into(), Compression::Stored);
+        zip.write_entry_whole(entry, b"hello").await?;
+        zip.close().await?;
+        let ret = state.import_slack(1, Cursor::new(data)).await;
+        assert!(matches!(ret, Err(AppError::ImportError(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_slack_ts_should_work() {
+        let ts = parse_slack_ts("1609459200.000100").unwrap();
+        assert_eq!(ts.timestamp(), 1609459200);
+        assert_eq!(ts.timestamp_subsec_micros(), 100);
+        assert!(parse_slack_ts("yesterday").is_none());
+    }
+}
@@ -1,15 +1,40 @@
-use std::str::FromStr;
+use std::{collections::HashSet, str::FromStr};
 
+use pulldown_cmark::{html, Options, Parser};
 use serde::{Deserialize, Serialize};
+use sqlx::{types::Json, Acquire, Postgres};
 use utoipa::{IntoParams, ToSchema};
 
-use crate::{AppError, AppState, ChatFile};
-use chat_core::Message;
-#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+use crate::{storage::FileStorage, AppError, AppState, ChatFile};
+use chat_core::{Attachment, Message, MessageFormat};
+/// Max lifetime of an ephemeral message, 7 days
+const MAX_EXPIRES_IN: u64 = 60 * 60 * 24 * 7;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
 pub struct CreateMessage {
     pub content: String,
     #[serde(default)]
     pub files: Vec<String>,
+    #[serde(default)]
+    pub format: MessageFormat,
+    /// files with their original names, merged with `files`
+    #[serde(default)]
+    pub attachments: Vec<CreateAttachment>,
+    /// seconds after posting to self-destruct the message
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+    /// shown instead of the sender name, only incoming webhooks set it
+    #[serde(skip)]
+    pub sender_name: Option<String>,
+    /// the poll the message asks, only set when creating polls
+    #[serde(skip)]
+    pub poll_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreateAttachment {
+    pub url: String,
+    pub filename: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema, IntoParams)]
@@ -28,36 +53,98 @@ impl AppState {
         chat_id: u64,
         user_id: u64,
     ) -> Result<Message, AppError> {
-        let base_dir = &self.config.server.base_dir;
+        // `/name text` runs the command instead of being posted
+        if let Some(message) = self.run_command(&input, chat_id, user_id).await? {
+            return Ok(message);
+        }
+        self.insert_message(input, chat_id, user_id, &self.pool)
+            .await
+    }
+
+    /// Same as `create_message`, but runs on the given connection (e.g. a transaction)
+    pub(crate) async fn insert_message<'c, A>(
+        &self,
+        input: CreateMessage,
+        chat_id: u64,
+        user_id: u64,
+        conn: A,
+    ) -> Result<Message, AppError>
+    where
+        A: Acquire<'c, Database = Postgres>,
+    {
+        let mut conn = conn.acquire().await?;
+        self.verify_chat_writable(chat_id, &mut *conn).await?;
+        let (files, attachments) = self.verify_message(&input).await?;
+        let html = match input.format {
+            MessageFormat::Plain => None,
+            MessageFormat::Markdown => Some(render_markdown(&input.content)),
+        };
+
+        let message: Message = sqlx::query_as(
+            r#"
+        INSERT INTO messages (chat_id, sender_id, content, files, format, html, attachments, expires_at,
+            sender_name, poll_id)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, now() + make_interval(secs => $8), $9, $10)
+        RETURNING id, chat_id, sender_id, sender_name, poll_id, content, files, format, html,
+            attachments, expires_at, created_at
+        "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(input.content)
+        .bind(&files)
+        .bind(input.format)
+        .bind(html)
+        .bind(Json(&attachments))
+        .bind(input.expires_in.map(|v| v as f64))
+        .bind(input.sender_name)
+        .bind(input.poll_id)
+        .fetch_one(&mut *conn)
+        .await?;
+        Ok(message)
+    }
+
+    /// Verify content and files of a message, return all file urls and their attachment metadata
+    pub(crate) async fn verify_message(
+        &self,
+        input: &CreateMessage,
+    ) -> Result<(Vec<String>, Vec<Attachment>), AppError> {
         // verify content - not empty
         if input.content.is_empty() {
             return Err(AppError::MessageCreateError("content is empty".to_string()));
         }
+        if let Some(expires_in) = input.expires_in {
+            if expires_in == 0 || expires_in > MAX_EXPIRES_IN {
+                return Err(AppError::MessageCreateError(format!(
+                    "expires_in must be between 1 and {} seconds",
+                    MAX_EXPIRES_IN
+                )));
+            }
+        }
+        let mut files = input.files.clone();
+        for attachment in &input.attachments {
+            if !files.contains(&attachment.url) {
+                files.push(attachment.url.clone());
+            }
+        }
         // verify files - all files should exist
-        for s in &input.files {
+        let mut attachments = Vec::with_capacity(files.len());
+        for s in &files {
             let file = ChatFile::from_str(s)?;
-            if !file.path(base_dir).exists() {
+            if !self.storage.exists(&file.hash_to_path()).await? {
                 return Err(AppError::MessageCreateError(format!(
                     "File does not exist: {}",
                     s
                 )));
             }
+            let filename = input
+                .attachments
+                .iter()
+                .find(|v| &v.url == s)
+                .and_then(|v| v.filename.as_deref());
+            attachments.push(file.attachment(&self.storage, filename).await?);
         }
-
-        let message: Message = sqlx::query_as(
-            r#"
-        INSERT INTO messages (chat_id, sender_id, content, files)
-        VALUES ($1, $2, $3, $4)
-        RETURNING id, chat_id, sender_id, content, files, created_at
-        "#,
-        )
-        .bind(chat_id as i64)
-        .bind(user_id as i64)
-        .bind(input.content)
-        .bind(&input.files)
-        .fetch_one(&self.pool)
-        .await?;
-        Ok(message)
+        Ok((files, attachments))
     }
 
     pub async fn list_messages(
@@ -72,10 +159,12 @@ impl AppState {
         };
         let messages = sqlx::query_as(
             r#"
-        SELECT id, chat_id, sender_id, content, files, created_at
+        SELECT id, chat_id, sender_id, sender_name, poll_id, content, files, format, html,
+            attachments, expires_at, created_at
         FROM messages
         WHERE chat_id = $1
         AND id < $2
+        AND (expires_at IS NULL OR expires_at > now())
         ORDER BY id DESC
         LIMIT $3
         "#,
@@ -87,6 +176,62 @@ impl AppState {
         .await?;
         Ok(messages)
     }
+
+    pub async fn get_message_by_id(
+        &self,
+        id: u64,
+        chat_id: u64,
+    ) -> Result<Option<Message>, AppError> {
+        let message = sqlx::query_as(
+            r#"
+        SELECT id, chat_id, sender_id, sender_name, poll_id, content, files, format, html,
+            attachments, expires_at, created_at
+        FROM messages
+        WHERE id = $1 AND chat_id = $2 AND (expires_at IS NULL OR expires_at > now())
+        "#,
+        )
+        .bind(id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(message)
+    }
+}
+
+impl AppState {
+    /// Hard delete expired ephemeral messages and the files only they used
+    pub async fn reap_expired_messages(&self) -> Result<u64, AppError> {
+        let deleted: Vec<(Vec<String>,)> = sqlx::query_as(
+            r#"
+        DELETE FROM messages WHERE id IN (
+            SELECT id FROM messages
+            WHERE expires_at <= now()
+            LIMIT 1000
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING files
+        "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let files: HashSet<String> = deleted.iter().flat_map(|(v,)| v.clone()).collect();
+        for url in files {
+            self.remove_orphaned_file(&url).await?;
+        }
+        Ok(deleted.len() as u64)
+    }
+}
+
+/// Render markdown into html and strip anything unsafe (scripts, event handlers, etc.)
+pub(crate) fn render_markdown(content: &str) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TASKLISTS);
+    let parser = Parser::new_ext(content, options);
+    let mut output = String::new();
+    html::push_html(&mut output, parser);
+    ammonia::clean(&output)
 }
 
 #[cfg(test)]
@@ -105,15 +250,18 @@ mod tests {
             .await?;
         let input = CreateMessage {
             content: "Hello".to_string(),
-            files: vec![],
+            ..Default::default()
         };
         let message = state.create_message(input, chat.id as _, 1).await?;
         assert_eq!(message.content, "Hello");
+        assert_eq!(message.format, MessageFormat::Plain);
+        assert_eq!(message.html, None);
 
         // invalid files should fail
         let input = CreateMessage {
             content: "Hello".to_string(),
             files: vec!["1".to_string()],
+            ..Default::default()
         };
         let err = state
             .create_message(input, chat.id as _, 1)
@@ -126,13 +274,83 @@ mod tests {
         let input = CreateMessage {
             content: "Hello".to_string(),
             files: vec![url],
+            ..Default::default()
         };
         let message = state.create_message(input, chat.id as _, 1).await?;
         assert_eq!(message.content, "Hello");
         assert_eq!(message.files.len(), 1);
+        assert_eq!(message.attachments.len(), 1);
+        assert_eq!(message.attachments[0].size, 11);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_markdown_message_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let url = upload_dummy_file(&state)?;
+        let input = CreateMessage {
+            content: "**Hello** <script>alert(1)</script>".to_string(),
+            format: MessageFormat::Markdown,
+            attachments: vec![CreateAttachment {
+                url: url.clone(),
+                filename: Some("hello.txt".to_string()),
+            }],
+            ..Default::default()
+        };
+        let message = state.create_message(input, 1, 1).await?;
+        assert_eq!(message.format, MessageFormat::Markdown);
+        assert_eq!(message.files, vec![url]);
+        assert_eq!(message.attachments[0].filename, "hello.txt");
+        assert_eq!(message.attachments[0].mime, "text/plain");
+        let html = message.html.unwrap();
+        assert!(html.contains("<strong>Hello</strong>"));
+        assert!(!html.contains("<script>"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ephemeral_message_should_expire() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "secret".to_string(),
+            expires_in: Some(60),
+            ..Default::default()
+        };
+        let message = state.create_message(input, 1, 1).await?;
+        assert!(message.expires_at.unwrap() > message.created_at);
+
+        let input = CreateMessage {
+            content: "forever".to_string(),
+            expires_in: Some(0),
+            ..Default::default()
+        };
+        assert!(state.create_message(input, 1, 1).await.is_err());
+
+        // not expired yet
+        assert_eq!(state.reap_expired_messages().await?, 0);
+        sqlx::query("UPDATE messages SET expires_at = now() WHERE id = $1")
+            .bind(message.id)
+            .execute(&state.pool)
+            .await?;
+        // expired messages are hidden before being reaped
+        let input = ListMessages {
+            last_id: None,
+            limit: 0,
+        };
+        let messages = state.list_messages(input, 1).await?;
+        assert!(messages.iter().all(|m| m.id != message.id));
+
+        assert_eq!(state.reap_expired_messages().await?, 1);
         Ok(())
     }
 
+    #[test]
+    fn render_markdown_should_sanitize() {
+        let html = render_markdown("[x](javascript:alert(1)) <img src=x onerror=alert(1)>");
+        assert!(!html.contains("javascript:"));
+        assert!(!html.contains("onerror"));
+    }
+
     #[tokio::test]
     async fn list_messages_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
@@ -1,13 +1,47 @@
+mod access_token;
+mod bookmark;
 mod chat;
+mod command;
+mod export;
 mod file;
+mod import;
 mod messages;
+mod oidc;
+mod outgoing_webhook;
+mod pin;
+mod poll;
+mod preferences;
+mod profile;
+mod retention;
+mod scheduled;
+mod signin_guard;
+mod two_factor;
 mod user;
+mod verification;
+mod webhook;
 mod workspace;
 
+pub use access_token::*;
+pub use bookmark::*;
 pub use chat::*;
+pub use command::*;
+pub use export::*;
+pub use import::*;
 pub use messages::*;
+pub use oidc::*;
+pub use outgoing_webhook::*;
+pub use pin::*;
+pub use poll::*;
+pub use preferences::*;
+pub use profile::*;
+pub use retention::*;
+pub use scheduled::*;
 use serde::{Deserialize, Serialize};
+pub use signin_guard::*;
+pub use two_factor::*;
 pub use user::{CreateUser, SigninUser};
+pub use verification::*;
+pub use webhook::*;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChatFile {
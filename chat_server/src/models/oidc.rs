@@ -0,0 +1,405 @@
+use std::collections::HashMap;
+
+use argon2::password_hash::{rand_core::OsRng, SaltString};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jwt_simple::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{config::OidcConfig, AppError, AppState, CreateUser};
+use chat_core::User;
+
+const OIDC_STATE: &str = "oidc_state";
+const OIDC_STATE_MINUTES: u64 = 10;
+
+/// Where to send the browser, `nonce` must come back with the callback
+#[derive(Debug, Clone)]
+pub struct OidcLogin {
+    pub url: String,
+    pub nonce: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Jwk {
+    kty: String,
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default)]
+    e: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// ID token claims besides the standard ones, `other` has the rest for `workspace_claim`
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct IdTokenClaims {
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: Option<bool>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(flatten)]
+    other: HashMap<String, Value>,
+}
+
+impl AppState {
+    /// Start an authorization code flow at the provider
+    pub async fn oidc_login(&self) -> Result<OidcLogin, AppError> {
+        let config = self.oidc_config()?;
+        let discovery = discover(config).await?;
+        let nonce = SaltString::generate(&mut OsRng).as_str().to_string();
+        // the state is stateless for us, it is signed and bound to the nonce cookie
+        let state = self.sign_action(
+            OIDC_STATE,
+            0,
+            &nonce,
+            Duration::from_mins(OIDC_STATE_MINUTES),
+        )?;
+        let scope = config.scopes.join(" ");
+        let url = reqwest::Url::parse_with_params(
+            &discovery.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", config.client_id.as_str()),
+                ("redirect_uri", config.redirect_url.as_str()),
+                ("scope", scope.as_str()),
+                ("state", state.as_str()),
+                ("nonce", nonce.as_str()),
+            ],
+        )
+        .map_err(oidc_error)?;
+        Ok(OidcLogin {
+            url: url.to_string(),
+            nonce,
+        })
+    }
+
+    /// Exchange the code for a verified ID token and find, link or create its user
+    pub async fn oidc_callback(
+        &self,
+        code: &str,
+        state: &str,
+        nonce: Option<&str>,
+    ) -> Result<User, AppError> {
+        let config = self.oidc_config()?;
+        let claims = self
+            .verify_action(OIDC_STATE, state)
+            .map_err(|_| AppError::OidcError("invalid or expired state".to_string()))?;
+        // the login must finish in the browser that started it
+        if nonce != Some(claims.bind.as_str()) {
+            return Err(AppError::OidcError("state does not match".to_string()));
+        }
+        let discovery = discover(config).await?;
+        let client = reqwest::Client::new();
+        let token: TokenResponse = client
+            .post(&discovery.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", config.redirect_url.as_str()),
+                ("client_id", config.client_id.as_str()),
+                ("client_secret", config.client_secret.as_str()),
+            ])
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(oidc_error)?
+            .json()
+            .await
+            .map_err(oidc_error)?;
+        let id_token =
+            verify_id_token(&client, &discovery, config, &token.id_token, &claims.bind).await?;
+        let Some(subject) = id_token.subject else {
+            return Err(AppError::OidcError("ID token has no subject".to_string()));
+        };
+        self.find_or_create_oidc_user(config, &discovery.issuer, &subject, id_token.custom)
+            .await
+    }
+
+    fn oidc_config(&self) -> Result<&OidcConfig, AppError> {
+        self.config
+            .oidc
+            .as_ref()
+            .ok_or_else(|| AppError::NotFound("single sign-on is not configured".to_string()))
+    }
+
+    /// A known identity signs in as its user, otherwise a verified email links to the account
+    /// with that email or provisions a new one
+    async fn find_or_create_oidc_user(
+        &self,
+        config: &OidcConfig,
+        issuer: &str,
+        subject: &str,
+        claims: IdTokenClaims,
+    ) -> Result<User, AppError> {
+        let linked: Option<(i64,)> = sqlx::query_as(
+            "SELECT user_id FROM user_identities WHERE issuer = $1 AND subject = $2",
+        )
+        .bind(issuer)
+        .bind(subject)
+        .fetch_optional(&self.pool)
+        .await?;
+        if let Some((user_id,)) = linked {
+            return self.get_profile(user_id as _).await;
+        }
+
+        // linking to an unverified email would hand the account to whoever typed it in
+        let email_verified = config.trust_email || claims.email_verified == Some(true);
+        let Some(email) = claims
+            .email
+            .as_deref()
+            .map(str::trim)
+            .filter(|email| email_verified && email.contains('@'))
+        else {
+            return Err(AppError::OidcError(
+                "the identity provider did not return a verified email".to_string(),
+            ));
+        };
+        let user = match self.find_user_by_email(email).await? {
+            Some(user) => user,
+            None => {
+                let fullname = match claims.name.as_deref().map(str::trim) {
+                    Some(name) if !name.is_empty() => name.to_string(),
+                    _ => email.split('@').next().unwrap_or(email).to_string(),
+                };
+                let input = CreateUser {
+                    fullname,
+                    email: email.to_string(),
+                    workspace: oidc_workspace(config, email, &claims.other)?,
+                    // they sign in with the provider, a reset link can set a password later
+                    password: SaltString::generate(&mut OsRng).as_str().to_string(),
+                };
+                self.create_user(&input).await?
+            }
+        };
+        sqlx::query(
+            r#"
+        INSERT INTO user_identities (issuer, subject, user_id) VALUES ($1, $2, $3)
+        ON CONFLICT (issuer, subject) DO NOTHING
+        "#,
+        )
+        .bind(issuer)
+        .bind(subject)
+        .bind(user.id)
+        .execute(&self.pool)
+        .await?;
+        sqlx::query(
+            "UPDATE users SET email_verified_at = COALESCE(email_verified_at, now()) WHERE id = $1",
+        )
+        .bind(user.id)
+        .execute(&self.pool)
+        .await?;
+        self.get_profile(user.id as _).await
+    }
+}
+
+async fn discover(config: &OidcConfig) -> Result<Discovery, AppError> {
+    let url = format!(
+        "{}/.well-known/openid-configuration",
+        config.issuer.trim_end_matches('/')
+    );
+    let discovery: Discovery = reqwest::get(url)
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(oidc_error)?
+        .json()
+        .await
+        .map_err(oidc_error)?;
+    if discovery.issuer.trim_end_matches('/') != config.issuer.trim_end_matches('/') {
+        return Err(AppError::OidcError(format!(
+            "discovery is for issuer {}",
+            discovery.issuer
+        )));
+    }
+    Ok(discovery)
+}
+
+/// Check the signature with the provider keys, then issuer, audience, expiry and nonce
+async fn verify_id_token(
+    client: &reqwest::Client,
+    discovery: &Discovery,
+    config: &OidcConfig,
+    id_token: &str,
+    nonce: &str,
+) -> Result<JWTClaims<IdTokenClaims>, AppError> {
+    let metadata = Token::decode_metadata(id_token).map_err(oidc_error)?;
+    if metadata.algorithm() != "RS256" {
+        return Err(AppError::OidcError(format!(
+            "unsupported ID token algorithm {}",
+            metadata.algorithm()
+        )));
+    }
+    let jwks: Jwks = client
+        .get(&discovery.jwks_uri)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(oidc_error)?
+        .json()
+        .await
+        .map_err(oidc_error)?;
+    let jwk = jwks.keys.into_iter().find(|key| {
+        key.kty == "RSA" && (metadata.key_id().is_none() || key.kid.as_deref() == metadata.key_id())
+    });
+    let Some(Jwk {
+        n: Some(n),
+        e: Some(e),
+        ..
+    }) = jwk
+    else {
+        return Err(AppError::OidcError(
+            "no provider key for the ID token".to_string(),
+        ));
+    };
+    let n = URL_SAFE_NO_PAD.decode(n).map_err(oidc_error)?;
+    let e = URL_SAFE_NO_PAD.decode(e).map_err(oidc_error)?;
+    let key = RS256PublicKey::from_components(&n, &e).map_err(oidc_error)?;
+    let options = VerificationOptions {
+        allowed_issuers: Some(HashSet::from_strings(&[&discovery.issuer])),
+        allowed_audiences: Some(HashSet::from_strings(&[&config.client_id])),
+        required_nonce: Some(nonce.to_string()),
+        ..Default::default()
+    };
+    key.verify_token::<IdTokenClaims>(id_token, Some(options))
+        .map_err(oidc_error)
+}
+
+/// The workspace of a new user: the workspace claim, their email domain, then the default
+fn oidc_workspace(
+    config: &OidcConfig,
+    email: &str,
+    claims: &HashMap<String, Value>,
+) -> Result<String, AppError> {
+    let claimed = config
+        .workspace_claim
+        .as_ref()
+        .and_then(|claim| claims.get(claim))
+        .and_then(Value::as_str)
+        .map(str::trim)
+        .filter(|name| !name.is_empty());
+    if let Some(name) = claimed {
+        return Ok(name.to_string());
+    }
+    let domain = email
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.to_lowercase());
+    if let Some(name) = domain.and_then(|domain| config.domain_workspaces.get(&domain)) {
+        return Ok(name.clone());
+    }
+    config
+        .default_workspace
+        .clone()
+        .ok_or_else(|| AppError::PermissionDenied(format!("no workspace for {}", email)))
+}
+
+fn oidc_error(e: impl std::fmt::Display) -> AppError {
+    AppError::OidcError(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use serde_json::json;
+
+    fn config() -> OidcConfig {
+        OidcConfig {
+            issuer: "https://idp.acme.org".to_string(),
+            client_id: "chat".to_string(),
+            client_secret: "secret".to_string(),
+            redirect_url: "http://localhost:6688/api/oidc/callback".to_string(),
+            scopes: vec!["openid".to_string()],
+            post_login_url: None,
+            workspace_claim: Some("org".to_string()),
+            domain_workspaces: HashMap::from([("acme.org".to_string(), "acme".to_string())]),
+            default_workspace: None,
+            trust_email: false,
+        }
+    }
+
+    fn claims(email: &str, verified: bool) -> IdTokenClaims {
+        IdTokenClaims {
+            email: Some(email.to_string()),
+            email_verified: Some(verified),
+            name: Some("Alice".to_string()),
+            other: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn oidc_workspace_should_use_claim_then_domain_then_default() -> Result<()> {
+        let mut config = config();
+        let claims = HashMap::from([("org".to_string(), json!("wonderland"))]);
+        assert_eq!(
+            oidc_workspace(&config, "alice@acme.org", &claims)?,
+            "wonderland"
+        );
+        assert_eq!(
+            oidc_workspace(&config, "alice@ACME.org", &HashMap::new())?,
+            "acme"
+        );
+        assert!(oidc_workspace(&config, "alice@other.org", &HashMap::new()).is_err());
+        config.default_workspace = Some("guests".to_string());
+        assert_eq!(
+            oidc_workspace(&config, "alice@other.org", &HashMap::new())?,
+            "guests"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn oidc_user_should_be_provisioned_then_found() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let config = config();
+        let issuer = config.issuer.clone();
+        let user = state
+            .find_or_create_oidc_user(&config, &issuer, "sub-1", claims("alice@acme.org", true))
+            .await?;
+        assert_eq!(user.email, "alice@acme.org");
+        assert_eq!(user.ws_name, "acme");
+        assert!(state.is_email_verified(user.id as _).await?);
+
+        // the identity is kept even if the provider changes the email
+        let found = state
+            .find_or_create_oidc_user(&config, &issuer, "sub-1", claims("a@acme.org", false))
+            .await?;
+        assert_eq!(found.id, user.id);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn oidc_user_should_link_verified_email_only() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let config = config();
+        let issuer = config.issuer.clone();
+        let ret = state
+            .find_or_create_oidc_user(&config, &issuer, "sub-2", claims("zzq@zzq.com", false))
+            .await;
+        assert!(matches!(ret, Err(AppError::OidcError(_))));
+
+        let user = state
+            .find_or_create_oidc_user(&config, &issuer, "sub-2", claims("zzq@zzq.com", true))
+            .await?;
+        assert_eq!(user.id, 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,380 @@
+use chat_core::{OutboundPolicy, User};
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{AppError, AppState};
+
+use super::access_token::random_hex;
+
+/// events notify_server delivers, named like the SSE events
+pub const WEBHOOK_EVENTS: &[&str] = &[
+    "NewChat",
+    "AddToChat",
+    "RemoveFromChat",
+    "NewMessage",
+    "ChatMetadataUpdated",
+    "PinsChanged",
+    "MessageDeleted",
+    "PollUpdated",
+    "UserUpdated",
+];
+const MAX_WEBHOOK_URL_LEN: usize = 2048;
+const MAX_LISTED_DELIVERIES: i64 = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateWebhookSubscription {
+    /// receives a POST for every event, must be http or https
+    pub url: String,
+    /// only events of this chat, otherwise events of all public channels of the workspace,
+    /// which only the workspace owner can subscribe to
+    #[serde(default)]
+    pub chat_id: Option<i64>,
+    /// e.g. `NewMessage`, all events if empty
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookSubscription {
+    pub id: i64,
+    pub ws_id: i64,
+    pub chat_id: Option<i64>,
+    pub url: String,
+    pub events: Vec<String>,
+    pub created_by: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedWebhookSubscription {
+    #[serde(flatten)]
+    pub subscription: WebhookSubscription,
+    /// only shown once, deliveries carry `X-Webhook-Signature: v1=<hex>`, the HMAC-SHA256 of
+    /// `{X-Webhook-Timestamp}.{body}` with this secret
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "webhook_delivery_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookDeliveryStatus {
+    Pending,
+    Delivered,
+    /// out of attempts, only a redelivery sends it again
+    Dead,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub subscription_id: i64,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub status: WebhookDeliveryStatus,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    /// http status of the last attempt, none if it did not get a response
+    pub last_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema, IntoParams)]
+pub struct ListWebhookDeliveries {
+    /// e.g. `dead` for the dead-letter list
+    #[serde(default)]
+    pub status: Option<WebhookDeliveryStatus>,
+}
+
+impl AppState {
+    pub async fn create_webhook_subscription(
+        &self,
+        user: &User,
+        input: CreateWebhookSubscription,
+    ) -> Result<CreatedWebhookSubscription, AppError> {
+        verify_webhook_url(&self.config.outbound, &input.url)?;
+        let mut events = input.events;
+        events.sort();
+        events.dedup();
+        if let Some(event) = events
+            .iter()
+            .find(|e| !WEBHOOK_EVENTS.contains(&e.as_str()))
+        {
+            return Err(AppError::WebhookError(format!("unknown event {}", event)));
+        }
+        match input.chat_id {
+            Some(chat_id) => {
+                let ws_id = self.get_chat_by_id(chat_id as _).await?.map(|c| c.ws_id);
+                if ws_id != Some(user.ws_id)
+                    || !self.is_chat_member(chat_id as _, user.id as _).await?
+                {
+                    return Err(AppError::NotFound(format!("chat {}", chat_id)));
+                }
+            }
+            None => self.verify_workspace_owner(user).await?,
+        }
+
+        let secret = format!("whsec_{}", random_hex(24));
+        let subscription = sqlx::query_as(
+            r#"
+        INSERT INTO webhook_subscriptions (ws_id, chat_id, url, secret, events, created_by)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, ws_id, chat_id, url, events, created_by, created_at
+        "#,
+        )
+        .bind(user.ws_id)
+        .bind(input.chat_id)
+        .bind(&input.url)
+        .bind(&secret)
+        .bind(&events)
+        .bind(user.id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(CreatedWebhookSubscription {
+            subscription,
+            secret,
+        })
+    }
+
+    /// All subscriptions of the workspace for its owner, otherwise the ones I created
+    pub async fn list_webhook_subscriptions(
+        &self,
+        user: &User,
+    ) -> Result<Vec<WebhookSubscription>, AppError> {
+        let is_owner = self.verify_workspace_owner(user).await.is_ok();
+        let subscriptions = sqlx::query_as(
+            r#"
+        SELECT id, ws_id, chat_id, url, events, created_by, created_at
+        FROM webhook_subscriptions
+        WHERE ws_id = $1 AND ($2 OR created_by = $3)
+        ORDER BY id
+        "#,
+        )
+        .bind(user.ws_id)
+        .bind(is_owner)
+        .bind(user.id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(subscriptions)
+    }
+
+    /// Pending deliveries of the subscription are dropped with it
+    pub async fn delete_webhook_subscription(&self, id: u64, user: &User) -> Result<(), AppError> {
+        let subscription = self.get_managed_subscription(id, user).await?;
+        sqlx::query("DELETE FROM webhook_subscriptions WHERE id = $1")
+            .bind(subscription.id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Latest deliveries of the subscription, newest first
+    pub async fn list_webhook_deliveries(
+        &self,
+        id: u64,
+        user: &User,
+        input: ListWebhookDeliveries,
+    ) -> Result<Vec<WebhookDelivery>, AppError> {
+        let subscription = self.get_managed_subscription(id, user).await?;
+        let deliveries = sqlx::query_as(
+            r#"
+        SELECT id, subscription_id, event, payload, status, attempts, next_attempt_at,
+            last_status, last_error, delivered_at, created_at
+        FROM webhook_deliveries
+        WHERE subscription_id = $1 AND ($2::webhook_delivery_status IS NULL OR status = $2)
+        ORDER BY id DESC
+        LIMIT $3
+        "#,
+        )
+        .bind(subscription.id)
+        .bind(input.status)
+        .bind(MAX_LISTED_DELIVERIES)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(deliveries)
+    }
+
+    /// Queue a delivery again with fresh attempts, e.g. from the dead-letter list
+    pub async fn redeliver_webhook(
+        &self,
+        id: u64,
+        delivery_id: u64,
+        user: &User,
+    ) -> Result<WebhookDelivery, AppError> {
+        let subscription = self.get_managed_subscription(id, user).await?;
+        let delivery = sqlx::query_as(
+            r#"
+        UPDATE webhook_deliveries
+        SET status = 'pending', attempts = 0, next_attempt_at = now()
+        WHERE id = $1 AND subscription_id = $2 AND status <> 'pending'
+        RETURNING id, subscription_id, event, payload, status, attempts, next_attempt_at,
+            last_status, last_error, delivered_at, created_at
+        "#,
+        )
+        .bind(delivery_id as i64)
+        .bind(subscription.id)
+        .fetch_optional(&self.pool)
+        .await?;
+        delivery.ok_or_else(|| {
+            AppError::WebhookError(format!(
+                "delivery {} does not exist or is still pending",
+                delivery_id
+            ))
+        })
+    }
+
+    /// Only its creator or the workspace owner manage a subscription
+    async fn get_managed_subscription(
+        &self,
+        id: u64,
+        user: &User,
+    ) -> Result<WebhookSubscription, AppError> {
+        let subscription: Option<WebhookSubscription> = sqlx::query_as(
+            r#"
+        SELECT id, ws_id, chat_id, url, events, created_by, created_at
+        FROM webhook_subscriptions
+        WHERE id = $1 AND ws_id = $2
+        "#,
+        )
+        .bind(id as i64)
+        .bind(user.ws_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(subscription) = subscription else {
+            return Err(AppError::NotFound(format!("webhook subscription {}", id)));
+        };
+        if subscription.created_by != user.id {
+            self.verify_workspace_owner(user).await?;
+        }
+        Ok(subscription)
+    }
+}
+
+fn verify_webhook_url(policy: &OutboundPolicy, url: &str) -> Result<(), AppError> {
+    if url.len() > MAX_WEBHOOK_URL_LEN {
+        return Err(AppError::WebhookError(format!(
+            "url must be at most {} characters",
+            MAX_WEBHOOK_URL_LEN
+        )));
+    }
+    let url =
+        Url::parse(url).map_err(|_| AppError::WebhookError(format!("invalid url {}", url)))?;
+    policy.check_url(&url).map_err(AppError::WebhookError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn input(chat_id: Option<i64>, events: &[&str]) -> CreateWebhookSubscription {
+        CreateWebhookSubscription {
+            url: "http://localhost:9000/events".to_string(),
+            chat_id,
+            events: events.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn verify_webhook_url_should_work() {
+        let policy = OutboundPolicy::default();
+        assert!(verify_webhook_url(&policy, "https://hooks.acme.org/chat").is_ok());
+        assert!(verify_webhook_url(&policy, "ftp://hooks.acme.org/chat").is_err());
+        assert!(verify_webhook_url(&policy, "not a url").is_err());
+        assert!(verify_webhook_url(&policy, "http://169.254.169.254/latest").is_err());
+        let policy = OutboundPolicy {
+            deny: vec!["*.acme.org".to_string()],
+            ..Default::default()
+        };
+        assert!(verify_webhook_url(&policy, "https://hooks.acme.org/chat").is_err());
+    }
+
+    #[tokio::test]
+    async fn webhook_subscription_should_check_scope() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        let created = state
+            .create_webhook_subscription(&user, input(Some(1), &["NewMessage"]))
+            .await?;
+        assert!(created.secret.starts_with("whsec_"));
+        assert_eq!(created.subscription.events, vec!["NewMessage"]);
+
+        // the workspace scope is for the owner only
+        assert!(state
+            .create_webhook_subscription(&user, input(None, &[]))
+            .await
+            .is_err());
+        state.update_workspace_owner(1, 1).await?;
+        state
+            .create_webhook_subscription(&user, input(None, &[]))
+            .await?;
+        assert!(state
+            .create_webhook_subscription(&user, input(None, &["Nope"]))
+            .await
+            .is_err());
+
+        let other = state.find_user_by_id(2).await?.unwrap();
+        assert!(state.list_webhook_subscriptions(&other).await?.is_empty());
+        assert_eq!(state.list_webhook_subscriptions(&user).await?.len(), 2);
+        assert!(state
+            .delete_webhook_subscription(created.subscription.id as _, &other)
+            .await
+            .is_err());
+        state
+            .delete_webhook_subscription(created.subscription.id as _, &user)
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dead_webhook_delivery_should_be_redelivered() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        let created = state
+            .create_webhook_subscription(&user, input(Some(1), &[]))
+            .await?;
+        let id = created.subscription.id;
+        let (delivery_id,): (i64,) = sqlx::query_as(
+            r#"
+        INSERT INTO webhook_deliveries (subscription_id, event, payload, status, attempts)
+        VALUES ($1, 'NewMessage', '{}', 'dead', 8)
+        RETURNING id
+        "#,
+        )
+        .bind(id)
+        .fetch_one(&state.pool)
+        .await?;
+
+        let dead = ListWebhookDeliveries {
+            status: Some(WebhookDeliveryStatus::Dead),
+        };
+        let deliveries = state
+            .list_webhook_deliveries(id as _, &user, dead.clone())
+            .await?;
+        assert_eq!(deliveries.len(), 1);
+
+        let delivery = state
+            .redeliver_webhook(id as _, delivery_id as _, &user)
+            .await?;
+        assert_eq!(delivery.status, WebhookDeliveryStatus::Pending);
+        assert_eq!(delivery.attempts, 0);
+        assert!(state
+            .list_webhook_deliveries(id as _, &user, dead)
+            .await?
+            .is_empty());
+        // pending deliveries are already queued
+        assert!(state
+            .redeliver_webhook(id as _, delivery_id as _, &user)
+            .await
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use crate::{AppError, AppState};
+use chat_core::{ChatType, Message};
+
+/// Max number of pinned messages in a single chat
+const MAX_PINS_PER_CHAT: i64 = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PinnedMessage {
+    pub pinned_by: i64,
+    pub pinned_at: DateTime<Utc>,
+    #[sqlx(flatten)]
+    pub message: Message,
+}
+
+impl AppState {
+    pub async fn pin_message(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<PinnedMessage, AppError> {
+        let message = self.get_pin_target(chat_id, message_id, user_id).await?;
+        let mut tx = self.pool.begin().await?;
+        // concurrent pins of the chat wait here, so the count below stays accurate
+        sqlx::query("SELECT id FROM chats WHERE id = $1 FOR UPDATE")
+            .bind(chat_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        let ret = sqlx::query(
+            r#"
+        INSERT INTO chat_pins (chat_id, message_id, pinned_by)
+        SELECT $1, $2, $3
+        WHERE (SELECT count(*) FROM chat_pins WHERE chat_id = $1) < $4
+        ON CONFLICT DO NOTHING
+        RETURNING chat_id
+        "#,
+        )
+        .bind(chat_id as i64)
+        .bind(message.id)
+        .bind(user_id as i64)
+        .bind(MAX_PINS_PER_CHAT)
+        .fetch_optional(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        if ret.is_none() {
+            let msg = if self.get_pin(chat_id, message_id).await?.is_some() {
+                format!("Message {} is already pinned", message_id)
+            } else {
+                format!(
+                    "A chat can have at most {} pinned messages",
+                    MAX_PINS_PER_CHAT
+                )
+            };
+            return Err(AppError::PinMessageError(msg));
+        }
+        self.get_pin(chat_id, message_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("pin for message {}", message_id)))
+    }
+
+    pub async fn unpin_message(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        self.get_pin_target(chat_id, message_id, user_id).await?;
+        let ret = sqlx::query(r#"DELETE FROM chat_pins WHERE chat_id = $1 AND message_id = $2"#)
+            .bind(chat_id as i64)
+            .bind(message_id as i64)
+            .execute(&self.pool)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!(
+                "Message {} is not pinned",
+                message_id
+            )));
+        }
+        Ok(())
+    }
+
+    pub async fn list_pins(&self, chat_id: u64) -> Result<Vec<PinnedMessage>, AppError> {
+        let pins = sqlx::query_as(
+            r#"
+        SELECT p.pinned_by, p.created_at as pinned_at, m.id, m.chat_id, m.sender_id, m.sender_name,
+            m.poll_id, m.content, m.files, m.format, m.html, m.attachments, m.expires_at, m.created_at
+        FROM chat_pins p JOIN messages m ON m.id = p.message_id
+        WHERE p.chat_id = $1 AND (m.expires_at IS NULL OR m.expires_at > now())
+        ORDER BY p.created_at DESC
+        "#,
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(pins)
+    }
+
+    async fn get_pin(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+    ) -> Result<Option<PinnedMessage>, AppError> {
+        let pin = sqlx::query_as(
+            r#"
+        SELECT p.pinned_by, p.created_at as pinned_at, m.id, m.chat_id, m.sender_id, m.sender_name,
+            m.poll_id, m.content, m.files, m.format, m.html, m.attachments, m.expires_at, m.created_at
+        FROM chat_pins p JOIN messages m ON m.id = p.message_id
+        WHERE p.chat_id = $1 AND p.message_id = $2
+        "#,
+        )
+        .bind(chat_id as i64)
+        .bind(message_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(pin)
+    }
+
+    /// Find the message to (un)pin and check the user may do it:
+    /// anyone in direct/group chats, only the sender or admins of the chat in channels
+    async fn get_pin_target(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+    ) -> Result<Message, AppError> {
+        let Some(message) = self.get_message_by_id(message_id, chat_id).await? else {
+            return Err(AppError::NotFound(format!(
+                "message {} in chat {}",
+                message_id, chat_id
+            )));
+        };
+        let Some(chat) = self.get_chat_by_id(chat_id).await? else {
+            return Err(AppError::ChatDoesNotExist);
+        };
+        if chat.archived_at.is_some() {
+            return Err(AppError::ChatArchiveError(format!(
+                "chat {} is archived and read-only",
+                chat_id
+            )));
+        }
+        let allowed = match chat.r#type {
+            ChatType::Single | ChatType::Group => true,
+            ChatType::PrivateChannel | ChatType::PublicChannel => {
+                message.sender_id == user_id as i64 || self.is_chat_admin(chat_id, user_id).await?
+            }
+        };
+        if !allowed {
+            return Err(AppError::PermissionDenied(format!(
+                "User {} can not manage pins of chat {}",
+                user_id, chat_id
+            )));
+        }
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::CreateMessage;
+
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn pin_and_unpin_message_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // message 1 in chat 1 is sent by user 1
+        let pin = state.pin_message(1, 1, 1).await?;
+        assert_eq!(pin.pinned_by, 1);
+        assert_eq!(pin.message.id, 1);
+
+        let err = state.pin_message(1, 1, 1).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "pin message error: Message 1 is already pinned"
+        );
+
+        let pins = state.list_pins(1).await?;
+        assert_eq!(pins.len(), 1);
+
+        state.unpin_message(1, 1, 1).await?;
+        let pins = state.list_pins(1).await?;
+        assert!(pins.is_empty());
+
+        let err = state.unpin_message(1, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expired_message_should_not_be_listed_as_pin() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.pin_message(1, 1, 1).await?;
+        // expired but not reaped yet
+        sqlx::query("UPDATE messages SET expires_at = now() WHERE id = 1")
+            .execute(&state.pool)
+            .await?;
+        assert!(state.list_pins(1).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pin_others_message_in_channel_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // message 2 in public channel 1 is sent by user 2
+        let err = state.pin_message(1, 2, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+
+        // unless the user is an admin of the chat
+        sqlx::query("UPDATE chat_members SET role = 'admin' WHERE chat_id = 1 AND user_id = 1")
+            .execute(&state.pool)
+            .await?;
+        state.pin_message(1, 2, 1).await?;
+
+        // message in another chat can not be pinned
+        let err = state.pin_message(2, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pin_over_limit_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        for _ in 0..MAX_PINS_PER_CHAT {
+            let input = CreateMessage {
+                content: "pin me".to_string(),
+                ..Default::default()
+            };
+            let message = state.create_message(input, 3, 1).await?;
+            state.pin_message(3, message.id as _, 2).await?;
+        }
+        let input = CreateMessage {
+            content: "one too many".to_string(),
+            ..Default::default()
+        };
+        let message = state.create_message(input, 3, 1).await?;
+        let err = state.pin_message(3, message.id as _, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::PinMessageError(_)));
+        Ok(())
+    }
+}
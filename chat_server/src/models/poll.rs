@@ -0,0 +1,524 @@
+use std::collections::BTreeSet;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgExecutor};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{AppError, AppState, CreateMessage};
+
+const MIN_POLL_OPTIONS: usize = 2;
+const MAX_POLL_OPTIONS: usize = 10;
+const MAX_QUESTION_LEN: usize = 512;
+const MAX_OPTION_LEN: usize = 128;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatePoll {
+    pub question: String,
+    pub options: Vec<String>,
+    /// voters may pick more than one option
+    #[serde(default)]
+    pub multiple: bool,
+    /// nobody sees who voted for what
+    #[serde(default)]
+    pub anonymous: bool,
+    /// closed automatically at this time, open until closed by its creator if not set
+    #[serde(default)]
+    pub closes_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct VotePoll {
+    /// indexes of the options, exactly one unless the poll is multiple choice. A vote on a
+    /// single choice poll replaces the previous one.
+    pub options: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema, IntoParams)]
+pub struct UnvotePoll {
+    /// index of the option to take back, all votes of the user if not set
+    #[serde(default)]
+    pub option: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Poll {
+    pub id: i64,
+    pub chat_id: i64,
+    /// the message asking the poll
+    pub message_id: i64,
+    pub question: String,
+    pub options: Vec<PollOption>,
+    pub multiple: bool,
+    pub anonymous: bool,
+    pub closes_at: Option<DateTime<Utc>>,
+    pub closed_at: Option<DateTime<Utc>>,
+    pub created_by: i64,
+    pub created_at: DateTime<Utc>,
+    /// how many users voted
+    pub voters: i64,
+    /// indexes of the options the requesting user voted for
+    pub voted: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PollOption {
+    pub text: String,
+    pub votes: i64,
+    /// who voted for it in vote order, always empty for anonymous polls
+    pub voters: Vec<i64>,
+}
+
+#[derive(Debug, FromRow)]
+struct PollRow {
+    id: i64,
+    chat_id: i64,
+    message_id: i64,
+    question: String,
+    options: Vec<String>,
+    multiple: bool,
+    anonymous: bool,
+    closes_at: Option<DateTime<Utc>>,
+    closed_at: Option<DateTime<Utc>>,
+    created_by: i64,
+    created_at: DateTime<Utc>,
+}
+
+impl AppState {
+    /// Post a poll to the chat, it is asked by a new message of the user
+    pub async fn create_poll(
+        &self,
+        input: CreatePoll,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<Poll, AppError> {
+        let question = input.question.trim();
+        if question.is_empty() || question.chars().count() > MAX_QUESTION_LEN {
+            return Err(AppError::PollError(format!(
+                "question must be 1 to {} characters",
+                MAX_QUESTION_LEN
+            )));
+        }
+        let options: Vec<&str> = input.options.iter().map(|v| v.trim()).collect();
+        if !(MIN_POLL_OPTIONS..=MAX_POLL_OPTIONS).contains(&options.len()) {
+            return Err(AppError::PollError(format!(
+                "a poll has {} to {} options",
+                MIN_POLL_OPTIONS, MAX_POLL_OPTIONS
+            )));
+        }
+        if let Some(option) = options
+            .iter()
+            .find(|v| v.is_empty() || v.chars().count() > MAX_OPTION_LEN)
+        {
+            return Err(AppError::PollError(format!(
+                "option \"{}\" must be 1 to {} characters",
+                option, MAX_OPTION_LEN
+            )));
+        }
+        if options.iter().collect::<BTreeSet<_>>().len() != options.len() {
+            return Err(AppError::PollError("options must be unique".to_string()));
+        }
+        if matches!(input.closes_at, Some(closes_at) if closes_at <= Utc::now()) {
+            return Err(AppError::PollError(
+                "closing time must be in the future".to_string(),
+            ));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        self.verify_chat_writable(chat_id, &mut *tx).await?;
+        let (id,): (i64,) = sqlx::query_as(
+            r#"
+        INSERT INTO polls (chat_id, question, options, multiple, anonymous, closes_at, created_by)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id
+        "#,
+        )
+        .bind(chat_id as i64)
+        .bind(question)
+        .bind(&options)
+        .bind(input.multiple)
+        .bind(input.anonymous)
+        .bind(input.closes_at)
+        .bind(user_id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        let message = CreateMessage {
+            content: question.to_string(),
+            poll_id: Some(id),
+            ..Default::default()
+        };
+        self.insert_message(message, chat_id, user_id, &mut *tx)
+            .await?;
+        tx.commit().await?;
+        self.get_poll(chat_id, id as _, user_id).await
+    }
+
+    pub async fn get_poll(
+        &self,
+        chat_id: u64,
+        poll_id: u64,
+        user_id: u64,
+    ) -> Result<Poll, AppError> {
+        let row = fetch_poll_row(chat_id, poll_id, false, &self.pool).await?;
+        let votes: Vec<(i64, i32)> = sqlx::query_as(
+            "SELECT user_id, option FROM poll_votes WHERE poll_id = $1 ORDER BY created_at, user_id",
+        )
+        .bind(row.id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut options: Vec<PollOption> = row
+            .options
+            .into_iter()
+            .map(|text| PollOption {
+                text,
+                votes: 0,
+                voters: vec![],
+            })
+            .collect();
+        let mut voters = BTreeSet::new();
+        let mut voted = vec![];
+        for (voter, option) in votes {
+            let Some(entry) = options.get_mut(option as usize) else {
+                continue;
+            };
+            entry.votes += 1;
+            if !row.anonymous {
+                entry.voters.push(voter);
+            }
+            voters.insert(voter);
+            if voter == user_id as i64 {
+                voted.push(option as u32);
+            }
+        }
+        voted.sort_unstable();
+        Ok(Poll {
+            id: row.id,
+            chat_id: row.chat_id,
+            message_id: row.message_id,
+            question: row.question,
+            options,
+            multiple: row.multiple,
+            anonymous: row.anonymous,
+            closes_at: row.closes_at,
+            closed_at: row.closed_at,
+            created_by: row.created_by,
+            created_at: row.created_at,
+            voters: voters.len() as i64,
+            voted,
+        })
+    }
+
+    pub async fn vote_poll(
+        &self,
+        chat_id: u64,
+        poll_id: u64,
+        user_id: u64,
+        input: VotePoll,
+    ) -> Result<Poll, AppError> {
+        let options: BTreeSet<u32> = input.options.into_iter().collect();
+        let mut tx = self.pool.begin().await?;
+        self.verify_chat_writable(chat_id, &mut *tx).await?;
+        // locked, so the poll is not closed while voting
+        let row = fetch_poll_row(chat_id, poll_id, true, &mut *tx).await?;
+        verify_poll_open(&row)?;
+        if options.is_empty() || (!row.multiple && options.len() > 1) {
+            return Err(AppError::PollError(match row.multiple {
+                true => "pick at least one option".to_string(),
+                false => "pick exactly one option".to_string(),
+            }));
+        }
+        if let Some(option) = options.iter().find(|v| **v as usize >= row.options.len()) {
+            return Err(AppError::PollError(format!(
+                "poll {} has no option {}",
+                poll_id, option
+            )));
+        }
+
+        if !row.multiple {
+            sqlx::query("DELETE FROM poll_votes WHERE poll_id = $1 AND user_id = $2")
+                .bind(row.id)
+                .bind(user_id as i64)
+                .execute(&mut *tx)
+                .await?;
+        }
+        let options: Vec<i32> = options.into_iter().map(|v| v as i32).collect();
+        sqlx::query(
+            r#"
+        INSERT INTO poll_votes (poll_id, user_id, option)
+        SELECT $1, $2, unnest($3::int[])
+        ON CONFLICT DO NOTHING
+        "#,
+        )
+        .bind(row.id)
+        .bind(user_id as i64)
+        .bind(&options)
+        .execute(&mut *tx)
+        .await?;
+        notify_poll_updated(row.id, &mut *tx).await?;
+        tx.commit().await?;
+        self.get_poll(chat_id, poll_id, user_id).await
+    }
+
+    pub async fn unvote_poll(
+        &self,
+        chat_id: u64,
+        poll_id: u64,
+        user_id: u64,
+        input: UnvotePoll,
+    ) -> Result<Poll, AppError> {
+        let mut tx = self.pool.begin().await?;
+        self.verify_chat_writable(chat_id, &mut *tx).await?;
+        let row = fetch_poll_row(chat_id, poll_id, true, &mut *tx).await?;
+        verify_poll_open(&row)?;
+        let ret = sqlx::query(
+            r#"
+        DELETE FROM poll_votes
+        WHERE poll_id = $1 AND user_id = $2 AND ($3::int IS NULL OR option = $3)
+        "#,
+        )
+        .bind(row.id)
+        .bind(user_id as i64)
+        .bind(input.option.map(|v| v as i32))
+        .execute(&mut *tx)
+        .await?;
+        if ret.rows_affected() > 0 {
+            notify_poll_updated(row.id, &mut *tx).await?;
+        }
+        tx.commit().await?;
+        self.get_poll(chat_id, poll_id, user_id).await
+    }
+
+    /// Close the poll before its time, only its creator can
+    pub async fn close_poll(
+        &self,
+        chat_id: u64,
+        poll_id: u64,
+        user_id: u64,
+    ) -> Result<Poll, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let row = fetch_poll_row(chat_id, poll_id, true, &mut *tx).await?;
+        if row.created_by != user_id as i64 {
+            return Err(AppError::PermissionDenied(
+                "only the creator can close the poll".to_string(),
+            ));
+        }
+        verify_poll_open(&row)?;
+        sqlx::query("UPDATE polls SET closed_at = now() WHERE id = $1")
+            .bind(row.id)
+            .execute(&mut *tx)
+            .await?;
+        notify_poll_updated(row.id, &mut *tx).await?;
+        tx.commit().await?;
+        self.get_poll(chat_id, poll_id, user_id).await
+    }
+
+    /// Close polls past their closing time
+    pub async fn close_due_polls(&self) -> Result<u64, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let closed: Vec<(i64,)> = sqlx::query_as(
+            r#"
+        UPDATE polls SET closed_at = now()
+        WHERE id IN (
+            SELECT id FROM polls
+            WHERE closed_at IS NULL AND closes_at <= now()
+            LIMIT 1000
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id
+        "#,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        for (id,) in &closed {
+            notify_poll_updated(*id, &mut *tx).await?;
+        }
+        tx.commit().await?;
+        Ok(closed.len() as u64)
+    }
+}
+
+async fn fetch_poll_row<'e, E>(
+    chat_id: u64,
+    poll_id: u64,
+    lock: bool,
+    executor: E,
+) -> Result<PollRow, AppError>
+where
+    E: PgExecutor<'e>,
+{
+    let sql = format!(
+        r#"
+    SELECT p.id, p.chat_id, m.id as message_id, p.question, p.options, p.multiple, p.anonymous,
+        p.closes_at, p.closed_at, p.created_by, p.created_at
+    FROM polls p JOIN messages m ON m.poll_id = p.id
+    WHERE p.id = $1 AND p.chat_id = $2
+    {}
+    "#,
+        if lock { "FOR UPDATE OF p" } else { "" }
+    );
+    sqlx::query_as(&sql)
+        .bind(poll_id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(executor)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("poll {}", poll_id)))
+}
+
+/// Polls past their closing time count as closed before the background task gets to them
+fn verify_poll_open(row: &PollRow) -> Result<(), AppError> {
+    let due = matches!(row.closes_at, Some(closes_at) if closes_at <= Utc::now());
+    if row.closed_at.is_some() || due {
+        return Err(AppError::PollError(format!("poll {} is closed", row.id)));
+    }
+    Ok(())
+}
+
+/// Send the tally to the chat members, on commit of the transaction it runs in. Only counts are
+/// sent, who voted is fetched from the poll.
+async fn notify_poll_updated<'e, E>(poll_id: i64, executor: E) -> Result<(), AppError>
+where
+    E: PgExecutor<'e>,
+{
+    sqlx::query(
+        r#"
+    SELECT pg_notify('poll_updated', json_build_object(
+        'poll_id', p.id,
+        'chat_id', p.chat_id,
+        'counts', ARRAY(
+            SELECT count(v.user_id)
+            FROM generate_subscripts(p.options, 1) AS i
+            LEFT JOIN poll_votes v ON v.poll_id = p.id AND v.option = i - 1
+            GROUP BY i
+            ORDER BY i),
+        'voters', (SELECT count(DISTINCT user_id) FROM poll_votes WHERE poll_id = p.id),
+        'closed_at', p.closed_at,
+        'members', chat_member_ids(p.chat_id)
+    ) :: text)
+    FROM polls p WHERE p.id = $1
+    "#,
+    )
+    .bind(poll_id)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn lunch(multiple: bool, anonymous: bool) -> CreatePoll {
+        CreatePoll {
+            question: "Lunch?".to_string(),
+            options: vec![
+                "pizza".to_string(),
+                "ramen".to_string(),
+                "tacos".to_string(),
+            ],
+            multiple,
+            anonymous,
+            closes_at: None,
+        }
+    }
+
+    fn vote(options: &[u32]) -> VotePoll {
+        VotePoll {
+            options: options.to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn create_poll_should_post_a_message() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let poll = state.create_poll(lunch(false, false), 1, 1).await?;
+        assert_eq!(poll.options.len(), 3);
+        assert_eq!(poll.voters, 0);
+        let message = state
+            .get_message_by_id(poll.message_id as _, 1)
+            .await?
+            .unwrap();
+        assert_eq!(message.poll_id, Some(poll.id));
+        assert_eq!(message.content, "Lunch?");
+
+        let mut input = lunch(false, false);
+        input.options = vec!["pizza".to_string(), " pizza ".to_string()];
+        assert!(state.create_poll(input, 1, 1).await.is_err());
+        let mut input = lunch(false, false);
+        input.options.truncate(1);
+        assert!(state.create_poll(input, 1, 1).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn single_choice_vote_should_replace_previous() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let poll = state.create_poll(lunch(false, false), 1, 1).await?;
+        let id = poll.id as u64;
+        assert!(state.vote_poll(1, id, 2, vote(&[0, 1])).await.is_err());
+        assert!(state.vote_poll(1, id, 2, vote(&[3])).await.is_err());
+
+        state.vote_poll(1, id, 2, vote(&[0])).await?;
+        let poll = state.vote_poll(1, id, 2, vote(&[1])).await?;
+        assert_eq!(poll.voted, vec![1]);
+        assert_eq!(poll.options[0].votes, 0);
+        assert_eq!(poll.options[1].voters, vec![2]);
+
+        let poll = state.vote_poll(1, id, 3, vote(&[1])).await?;
+        assert_eq!(poll.options[1].votes, 2);
+        assert_eq!(poll.voters, 2);
+
+        let poll = state.unvote_poll(1, id, 3, UnvotePoll::default()).await?;
+        assert!(poll.voted.is_empty());
+        assert_eq!(poll.options[1].voters, vec![2]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn multiple_choice_anonymous_poll_should_hide_voters() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let poll = state.create_poll(lunch(true, true), 1, 1).await?;
+        let id = poll.id as u64;
+        state.vote_poll(1, id, 2, vote(&[0, 2])).await?;
+        let poll = state.vote_poll(1, id, 2, vote(&[1])).await?;
+        assert_eq!(poll.voted, vec![0, 1, 2]);
+        assert_eq!(poll.voters, 1);
+        assert!(poll
+            .options
+            .iter()
+            .all(|v| v.votes == 1 && v.voters.is_empty()));
+
+        let input = UnvotePoll { option: Some(2) };
+        let poll = state.unvote_poll(1, id, 2, input).await?;
+        assert_eq!(poll.voted, vec![0, 1]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn closed_poll_should_reject_votes() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let poll = state.create_poll(lunch(false, false), 1, 1).await?;
+        let id = poll.id as u64;
+        assert!(state.close_poll(1, id, 2).await.is_err());
+        let poll = state.close_poll(1, id, 1).await?;
+        assert!(poll.closed_at.is_some());
+        assert!(state.vote_poll(1, id, 2, vote(&[0])).await.is_err());
+
+        let mut input = lunch(false, false);
+        input.closes_at = Some(Utc::now() + chrono::Duration::hours(1));
+        let poll = state.create_poll(input, 1, 1).await?;
+        assert_eq!(state.close_due_polls().await?, 0);
+        sqlx::query("UPDATE polls SET closes_at = now() - interval '1 second' WHERE id = $1")
+            .bind(poll.id)
+            .execute(&state.pool)
+            .await?;
+        assert_eq!(state.close_due_polls().await?, 1);
+        let poll = state.get_poll(1, poll.id as _, 1).await?;
+        assert!(poll.closed_at.is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use crate::{AppError, AppState};
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "notification_level", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum NotificationLevel {
+    #[default]
+    All,
+    /// only messages mentioning `@fullname` alert the user
+    Mentions,
+    None,
+}
+
+/// Settings of a chat for one member
+#[derive(Debug, Clone, Default, Serialize, Deserialize, FromRow, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatPreferences {
+    pub chat_id: i64,
+    pub user_id: i64,
+    /// no alerts for new messages until this time
+    pub muted_until: Option<DateTime<Utc>>,
+    pub notification_level: NotificationLevel,
+    /// pinned chats are listed first
+    pub pinned: bool,
+    /// manual position in the chat list, before chats ordered by last activity
+    pub sort_order: Option<i32>,
+    /// hidden chats are left out of the chat list
+    pub hidden: bool,
+}
+
+/// Fields not given are kept, `null` clears `mutedUntil` and `sortOrder`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateChatPreferences {
+    #[serde(default, deserialize_with = "double_option")]
+    #[schema(value_type = Option<DateTime<Utc>>)]
+    pub muted_until: Option<Option<DateTime<Utc>>>,
+    #[serde(default)]
+    pub notification_level: Option<NotificationLevel>,
+    #[serde(default)]
+    pub pinned: Option<bool>,
+    #[serde(default, deserialize_with = "double_option")]
+    #[schema(value_type = Option<i32>)]
+    pub sort_order: Option<Option<i32>>,
+    #[serde(default)]
+    pub hidden: Option<bool>,
+}
+
+impl AppState {
+    pub async fn get_chat_preferences(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<ChatPreferences, AppError> {
+        let preferences = sqlx::query_as(
+            r#"
+        SELECT chat_id, user_id, muted_until, notification_level, pinned, sort_order, hidden
+        FROM chat_members
+        WHERE chat_id = $1 AND user_id = $2
+        "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        preferences.ok_or_else(|| {
+            AppError::NotFound(format!(
+                "user {} is not a member of chat {}",
+                user_id, chat_id
+            ))
+        })
+    }
+
+    pub async fn update_chat_preferences(
+        &self,
+        input: UpdateChatPreferences,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<ChatPreferences, AppError> {
+        let mut preferences = self.get_chat_preferences(chat_id, user_id).await?;
+        if let Some(muted_until) = input.muted_until {
+            preferences.muted_until = muted_until;
+        }
+        if let Some(level) = input.notification_level {
+            preferences.notification_level = level;
+        }
+        if let Some(pinned) = input.pinned {
+            preferences.pinned = pinned;
+        }
+        if let Some(sort_order) = input.sort_order {
+            preferences.sort_order = sort_order;
+        }
+        if let Some(hidden) = input.hidden {
+            preferences.hidden = hidden;
+        }
+        let preferences = sqlx::query_as(
+            r#"
+        UPDATE chat_members SET
+            muted_until = $3,
+            notification_level = $4,
+            pinned = $5,
+            sort_order = $6,
+            hidden = $7,
+            updated_at = now()
+        WHERE chat_id = $1 AND user_id = $2
+        RETURNING chat_id, user_id, muted_until, notification_level, pinned, sort_order, hidden
+        "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(preferences.muted_until)
+        .bind(preferences.notification_level)
+        .bind(preferences.pinned)
+        .bind(preferences.sort_order)
+        .bind(preferences.hidden)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(preferences)
+    }
+}
+
+/// Tell a missing field (`None`) from an explicit `null` (`Some(None)`)
+fn double_option<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::{CreateMessage, ListChats};
+    use anyhow::Result;
+
+    #[test]
+    fn update_chat_preferences_should_tell_null_from_missing() -> Result<()> {
+        let input: UpdateChatPreferences = serde_json::from_str(r#"{"mutedUntil": null}"#)?;
+        assert_eq!(input.muted_until, Some(None));
+        assert_eq!(input.sort_order, None);
+        let input: UpdateChatPreferences = serde_json::from_str(r#"{"sortOrder": 2}"#)?;
+        assert_eq!(input.sort_order, Some(Some(2)));
+        assert_eq!(input.muted_until, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_chat_preferences_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let preferences = state.get_chat_preferences(1, 1).await?;
+        assert_eq!(preferences.notification_level, NotificationLevel::All);
+        assert!(!preferences.pinned);
+
+        let muted_until = Utc::now() + Duration::hours(1);
+        let input = UpdateChatPreferences {
+            muted_until: Some(Some(muted_until)),
+            notification_level: Some(NotificationLevel::Mentions),
+            ..Default::default()
+        };
+        let preferences = state.update_chat_preferences(input, 1, 1).await?;
+        assert!(preferences.muted_until.is_some());
+        assert_eq!(preferences.notification_level, NotificationLevel::Mentions);
+
+        // unset fields are kept, null clears
+        let input = UpdateChatPreferences {
+            muted_until: Some(None),
+            pinned: Some(true),
+            ..Default::default()
+        };
+        let preferences = state.update_chat_preferences(input, 1, 1).await?;
+        assert_eq!(preferences.muted_until, None);
+        assert_eq!(preferences.notification_level, NotificationLevel::Mentions);
+        assert!(preferences.pinned);
+        assert_eq!(state.get_chat_preferences(1, 1).await?, preferences);
+        // other members are not affected
+        assert!(!state.get_chat_preferences(1, 2).await?.pinned);
+        // only members have preferences
+        assert!(state.get_chat_preferences(2, 4).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fetch_chats_should_follow_preferences() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // latest activity first
+        let input = CreateMessage {
+            content: "bump".to_string(),
+            ..Default::default()
+        };
+        state.create_message(input, 3, 1).await?;
+        let chats = state.fetch_chats(Default::default(), 1, 1).await?;
+        assert_eq!(chats[0].id, 3);
+
+        // pinned chats come first, then manual order
+        let input = UpdateChatPreferences {
+            pinned: Some(true),
+            ..Default::default()
+        };
+        state.update_chat_preferences(input, 4, 1).await?;
+        let input = UpdateChatPreferences {
+            pinned: Some(true),
+            sort_order: Some(Some(0)),
+            ..Default::default()
+        };
+        state.update_chat_preferences(input, 2, 1).await?;
+        let chats = state.fetch_chats(Default::default(), 1, 1).await?;
+        let ids: Vec<_> = chats.iter().map(|c| c.id).collect();
+        assert_eq!(&ids[..3], &[2, 4, 3]);
+
+        // hidden chats are left out
+        let input = UpdateChatPreferences {
+            hidden: Some(true),
+            ..Default::default()
+        };
+        state.update_chat_preferences(input, 3, 1).await?;
+        let chats = state.fetch_chats(Default::default(), 1, 1).await?;
+        assert_eq!(chats.len(), 3);
+        let input = ListChats {
+            include_hidden: true,
+            ..Default::default()
+        };
+        let chats = state.fetch_chats(input, 1, 1).await?;
+        assert_eq!(chats.len(), 4);
+        Ok(())
+    }
+}
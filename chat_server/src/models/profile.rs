@@ -0,0 +1,356 @@
+use argon2::password_hash::{rand_core::OsRng, SaltString};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use utoipa::ToSchema;
+
+use crate::{AppError, AppState};
+use chat_core::User;
+
+use super::user::{hash_password, verify_password};
+
+const MAX_STATUS_MESSAGE_LEN: usize = 140;
+pub(crate) const MIN_PASSWORD_LEN: usize = 6;
+const EMAIL_TOKEN_TTL_HOURS: i64 = 24;
+
+/// Fields not given are kept, an empty string clears `avatar`, `statusMessage` and `timeZone`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateProfile {
+    #[serde(default)]
+    pub fullname: Option<String>,
+    /// url of an uploaded image, e.g. `/files/1/abc/def/0123.png`
+    #[serde(default)]
+    pub avatar: Option<String>,
+    #[serde(default)]
+    pub status_message: Option<String>,
+    /// IANA time zone name, e.g. `Europe/Berlin`
+    #[serde(default)]
+    pub time_zone: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangePassword {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+/// The new email is only used once the link sent to it is confirmed
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeEmail {
+    pub email: String,
+    pub current_password: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ConfirmEmail {
+    pub token: String,
+}
+
+impl AppState {
+    /// The current profile, the token only has the values from signin
+    pub async fn get_profile(&self, user_id: u64) -> Result<User, AppError> {
+        let Some(mut user) = self.find_user_by_id(user_id as _).await? else {
+            return Err(AppError::NotFound(format!("user {}", user_id)));
+        };
+        if let Some(ws) = self.find_workspace_by_id(user.ws_id as _).await? {
+            user.ws_name = ws.name;
+        }
+        Ok(user)
+    }
+
+    pub async fn update_profile(
+        &self,
+        input: UpdateProfile,
+        user_id: u64,
+    ) -> Result<User, AppError> {
+        let mut user = self.get_profile(user_id).await?;
+        if let Some(fullname) = input.fullname {
+            let fullname = fullname.trim();
+            if fullname.is_empty() || fullname.chars().count() > 64 {
+                return Err(AppError::UpdateUserError(
+                    "Full name must be between 1 and 64 characters".to_string(),
+                ));
+            }
+            user.fullname = fullname.to_string();
+        }
+        let old_avatar = user.avatar.clone();
+        if let Some(avatar) = input.avatar {
+            if !avatar.is_empty() {
+                self.verify_image_file(&avatar, user.ws_id as _).await?;
+            }
+            user.avatar = Some(avatar).filter(|v| !v.is_empty());
+        }
+        if let Some(status_message) = input.status_message {
+            if status_message.chars().count() > MAX_STATUS_MESSAGE_LEN {
+                return Err(AppError::UpdateUserError(format!(
+                    "Status message must be at most {} characters",
+                    MAX_STATUS_MESSAGE_LEN
+                )));
+            }
+            user.status_message = Some(status_message).filter(|v| !v.is_empty());
+        }
+        if let Some(time_zone) = input.time_zone {
+            if !time_zone.is_empty() && !self.is_time_zone_name(&time_zone).await? {
+                return Err(AppError::UpdateUserError(format!(
+                    "Invalid time zone: {}",
+                    time_zone
+                )));
+            }
+            user.time_zone = Some(time_zone).filter(|v| !v.is_empty());
+        }
+        sqlx::query(
+            r#"
+        UPDATE users SET fullname = $2, avatar = $3, status_message = $4, time_zone = $5
+        WHERE id = $1
+        "#,
+        )
+        .bind(user_id as i64)
+        .bind(&user.fullname)
+        .bind(&user.avatar)
+        .bind(&user.status_message)
+        .bind(&user.time_zone)
+        .execute(&self.pool)
+        .await?;
+        if let Some(url) = old_avatar.filter(|url| user.avatar.as_ref() != Some(url)) {
+            self.remove_orphaned_file(&url).await?;
+        }
+        Ok(user)
+    }
+
+    /// A name of the time zone database, e.g. `Europe/London` or `UTC`, as Postgres knows them
+    async fn is_time_zone_name(&self, name: &str) -> Result<bool, AppError> {
+        let (exists,): (bool,) =
+            sqlx::query_as("SELECT EXISTS (SELECT 1 FROM pg_timezone_names WHERE name = $1)")
+                .bind(name)
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(exists)
+    }
+
+    pub async fn change_password(
+        &self,
+        input: ChangePassword,
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        self.verify_current_password(&input.current_password, user_id)
+            .await?;
+        if input.new_password.chars().count() < MIN_PASSWORD_LEN {
+            return Err(AppError::UpdateUserError(format!(
+                "Password must be at least {} characters",
+                MIN_PASSWORD_LEN
+            )));
+        }
+        let password_hash = hash_password(&input.new_password)?;
+        sqlx::query("UPDATE users SET password_hash = $2 WHERE id = $1")
+            .bind(user_id as i64)
+            .bind(password_hash)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Keep the new email as pending and return the token that confirms it
+    pub async fn request_email_change(
+        &self,
+        input: ChangeEmail,
+        user_id: u64,
+    ) -> Result<String, AppError> {
+        self.verify_current_password(&input.current_password, user_id)
+            .await?;
+        let email = input.email.trim();
+        if !email.contains('@') || email.len() > 64 {
+            return Err(AppError::UpdateUserError(format!(
+                "Invalid email: {}",
+                email
+            )));
+        }
+        if self.find_user_by_email(email).await?.is_some() {
+            return Err(AppError::EmailAlreadyExists(email.to_string()));
+        }
+        let token = SaltString::generate(&mut OsRng).as_str().to_string();
+        let expires_at = Utc::now() + Duration::hours(EMAIL_TOKEN_TTL_HOURS);
+        sqlx::query(
+            r#"
+        UPDATE users SET pending_email = $2, email_token = $3, email_token_expires_at = $4
+        WHERE id = $1
+        "#,
+        )
+        .bind(user_id as i64)
+        .bind(email)
+        .bind(hash_token(&token))
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(token)
+    }
+
+    /// Switch to the pending email of the user the token was sent to
+    pub async fn confirm_email_change(&self, token: &str) -> Result<User, AppError> {
+        let ret: Result<Option<(i64,)>, _> = sqlx::query_as(
+            r#"
+        UPDATE users SET email = pending_email, pending_email = NULL, email_token = NULL,
+            email_token_expires_at = NULL, email_verified_at = now()
+        WHERE email_token = $1 AND email_token_expires_at > now()
+        RETURNING id
+        "#,
+        )
+        .bind(hash_token(token))
+        .fetch_optional(&self.pool)
+        .await;
+        match ret {
+            Ok(Some((id,))) => self.get_profile(id as _).await,
+            Ok(None) => Err(AppError::UpdateUserError(
+                "Invalid or expired email confirmation token".to_string(),
+            )),
+            // someone took the email since the change was requested
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Err(
+                AppError::UpdateUserError("Email is already in use".to_string()),
+            ),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub(crate) async fn verify_current_password(
+        &self,
+        password: &str,
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        let (password_hash,): (String,) =
+            sqlx::query_as("SELECT password_hash FROM users WHERE id = $1")
+                .bind(user_id as i64)
+                .fetch_one(&self.pool)
+                .await?;
+        if !verify_password(password, &password_hash)? {
+            return Err(AppError::PermissionDenied(
+                "current password is incorrect".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn hash_token(token: &str) -> String {
+    hex::encode(Sha1::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChatFile, CreateUser, SigninUser};
+    use anyhow::Result;
+
+    async fn create_user(state: &AppState) -> Result<User> {
+        let input = CreateUser::new("acme", "Alice", "alice@acme.org", "secret1");
+        Ok(state.create_user(&input).await?)
+    }
+
+    #[tokio::test]
+    async fn update_profile_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = create_user(&state).await?;
+        let file = ChatFile::new(user.ws_id as _, "me.png", b"me");
+        let path = file.path(&state.config.server.base_dir);
+        tokio::fs::create_dir_all(path.parent().unwrap()).await?;
+        tokio::fs::write(&path, b"me").await?;
+
+        let input = UpdateProfile {
+            fullname: Some("Alice Liddell".to_string()),
+            avatar: Some(file.url()),
+            status_message: Some("in wonderland".to_string()),
+            time_zone: Some("Europe/London".to_string()),
+        };
+        let profile = state.update_profile(input, user.id as _).await?;
+        assert_eq!(profile.fullname, "Alice Liddell");
+        assert_eq!(profile.avatar, Some(file.url()));
+        assert_eq!(state.get_profile(user.id as _).await?, profile);
+        let users = state.fetch_chat_user_by_ids(&[user.id]).await?;
+        assert_eq!(users[0].status_message.as_deref(), Some("in wonderland"));
+
+        // empty strings clear, missing fields are kept
+        let input = UpdateProfile {
+            avatar: Some("".to_string()),
+            time_zone: Some("".to_string()),
+            ..Default::default()
+        };
+        let profile = state.update_profile(input, user.id as _).await?;
+        assert_eq!(profile.avatar, None);
+        assert_eq!(profile.time_zone, None);
+        assert_eq!(profile.status_message.as_deref(), Some("in wonderland"));
+        assert!(!path.exists());
+
+        for input in [
+            UpdateProfile {
+                fullname: Some(" ".to_string()),
+                ..Default::default()
+            },
+            UpdateProfile {
+                time_zone: Some("Mars; drop".to_string()),
+                ..Default::default()
+            },
+            UpdateProfile {
+                time_zone: Some("Mars/Olympus_Mons".to_string()),
+                ..Default::default()
+            },
+            UpdateProfile {
+                avatar: Some("/files/1/abc/def/missing.png".to_string()),
+                ..Default::default()
+            },
+        ] {
+            assert!(state.update_profile(input, user.id as _).await.is_err());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn change_password_should_verify_current_password() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = create_user(&state).await?;
+        let input = ChangePassword {
+            current_password: "wrong".to_string(),
+            new_password: "secret2".to_string(),
+        };
+        let ret = state.change_password(input, user.id as _).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        let input = ChangePassword {
+            current_password: "secret1".to_string(),
+            new_password: "secret2".to_string(),
+        };
+        state.change_password(input, user.id as _).await?;
+        let signin = SigninUser::new(&user.email, "secret1");
+        assert!(state.verify_user(&signin).await?.is_none());
+        let signin = SigninUser::new(&user.email, "secret2");
+        assert!(state.verify_user(&signin).await?.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn change_email_should_need_confirmation() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = create_user(&state).await?;
+        let input = ChangeEmail {
+            email: "zzq@zzq.com".to_string(),
+            current_password: "secret1".to_string(),
+        };
+        let ret = state.request_email_change(input, user.id as _).await;
+        assert!(matches!(ret, Err(AppError::EmailAlreadyExists(_))));
+
+        let input = ChangeEmail {
+            email: "alice@wonderland.org".to_string(),
+            current_password: "secret1".to_string(),
+        };
+        let token = state.request_email_change(input, user.id as _).await?;
+        // nothing changes until confirmed
+        assert_eq!(state.get_profile(user.id as _).await?.email, user.email);
+        assert!(state.confirm_email_change("bogus").await.is_err());
+
+        let profile = state.confirm_email_change(&token).await?;
+        assert_eq!(profile.email, "alice@wonderland.org");
+        // tokens work once
+        assert!(state.confirm_email_change(&token).await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::{AppError, AppState};
+
+/// Max number of messages deleted in one statement
+const PURGE_BATCH_SIZE: i64 = 1000;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, FromRow, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionPolicy {
+    /// delete messages older than this many days, keep forever if not set
+    pub retention_days: Option<i32>,
+    /// keep everything regardless of retention, e.g. during litigation
+    #[serde(default)]
+    pub legal_hold: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PurgeReport {
+    pub chats: usize,
+    pub messages: u64,
+    pub files: u64,
+}
+
+impl AppState {
+    pub async fn get_workspace_retention(&self, ws_id: u64) -> Result<RetentionPolicy, AppError> {
+        let policy =
+            sqlx::query_as(r#"SELECT retention_days, legal_hold FROM workspaces WHERE id = $1"#)
+                .bind(ws_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+        policy.ok_or_else(|| AppError::NotFound(format!("workspace {} not found", ws_id)))
+    }
+
+    pub async fn update_workspace_retention(
+        &self,
+        ws_id: u64,
+        input: RetentionPolicy,
+    ) -> Result<RetentionPolicy, AppError> {
+        verify_retention(&input)?;
+        let policy = sqlx::query_as(
+            r#"UPDATE workspaces SET retention_days = $1, legal_hold = $2 WHERE id = $3
+            RETURNING retention_days, legal_hold"#,
+        )
+        .bind(input.retention_days)
+        .bind(input.legal_hold)
+        .bind(ws_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        policy.ok_or_else(|| AppError::NotFound(format!("workspace {} not found", ws_id)))
+    }
+
+    pub async fn get_chat_retention(&self, chat_id: u64) -> Result<RetentionPolicy, AppError> {
+        let policy =
+            sqlx::query_as(r#"SELECT retention_days, legal_hold FROM chats WHERE id = $1"#)
+                .bind(chat_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+        policy.ok_or(AppError::ChatDoesNotExist)
+    }
+
+    pub async fn update_chat_retention(
+        &self,
+        chat_id: u64,
+        input: RetentionPolicy,
+    ) -> Result<RetentionPolicy, AppError> {
+        verify_retention(&input)?;
+        let policy = sqlx::query_as(
+            r#"UPDATE chats SET retention_days = $1, legal_hold = $2 WHERE id = $3
+            RETURNING retention_days, legal_hold"#,
+        )
+        .bind(input.retention_days)
+        .bind(input.legal_hold)
+        .bind(chat_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        policy.ok_or(AppError::ChatDoesNotExist)
+    }
+
+    /// Delete messages past their retention in batches, then remove files no longer referenced
+    pub async fn purge_expired_messages(&self) -> Result<PurgeReport, AppError> {
+        let mut report = PurgeReport::default();
+        let mut chats = HashSet::new();
+        let mut files = HashSet::new();
+        loop {
+            let deleted: Vec<(i64, Vec<String>)> = sqlx::query_as(
+                r#"
+            DELETE FROM messages WHERE id IN (
+                SELECT m.id FROM messages m
+                JOIN chats c ON c.id = m.chat_id
+                JOIN workspaces w ON w.id = c.ws_id
+                WHERE NOT c.legal_hold AND NOT w.legal_hold
+                AND COALESCE(c.retention_days, w.retention_days) IS NOT NULL
+                AND m.created_at < now() - make_interval(days => COALESCE(c.retention_days, w.retention_days))
+                LIMIT $1
+                FOR UPDATE OF m SKIP LOCKED
+            )
+            RETURNING chat_id, files
+            "#,
+            )
+            .bind(PURGE_BATCH_SIZE)
+            .fetch_all(&self.pool)
+            .await?;
+            let count = deleted.len();
+            report.messages += count as u64;
+            for (chat_id, urls) in deleted {
+                chats.insert(chat_id);
+                files.extend(urls);
+            }
+            if count < PURGE_BATCH_SIZE as usize {
+                break;
+            }
+        }
+        report.chats = chats.len();
+        for url in files {
+            if self.remove_orphaned_file(&url).await? {
+                report.files += 1;
+            }
+        }
+        self.metrics.record_purge(&report);
+        if report.messages > 0 {
+            info!(
+                "retention purge: deleted {} messages in {} chats, removed {} files",
+                report.messages, report.chats, report.files
+            );
+        }
+        Ok(report)
+    }
+}
+
+fn verify_retention(input: &RetentionPolicy) -> Result<(), AppError> {
+    match input.retention_days {
+        Some(days) if days < 1 => Err(AppError::RetentionError(
+            "retention days must be at least 1".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ChatFile, CreateMessage, ListMessages};
+
+    use super::*;
+    use anyhow::Result;
+
+    async fn age_messages(state: &AppState, chat_id: i64, days: i32) -> Result<()> {
+        sqlx::query(
+            "UPDATE messages SET created_at = now() - make_interval(days => $2) WHERE chat_id = $1",
+        )
+        .bind(chat_id)
+        .bind(days)
+        .execute(&state.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_retention_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let policy = RetentionPolicy {
+            retention_days: Some(30),
+            legal_hold: false,
+        };
+        let ret = state.update_workspace_retention(1, policy.clone()).await?;
+        assert_eq!(ret, policy);
+        assert_eq!(state.get_workspace_retention(1).await?, policy);
+
+        let ret = state.update_chat_retention(1, policy.clone()).await?;
+        assert_eq!(ret, policy);
+        assert_eq!(state.get_chat_retention(1).await?, policy);
+
+        let invalid = RetentionPolicy {
+            retention_days: Some(0),
+            legal_hold: false,
+        };
+        assert!(state.update_chat_retention(1, invalid).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn purge_expired_messages_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // chat 1 has 10 messages from fixtures, make them 10 days old
+        age_messages(&state, 1, 10).await?;
+        let policy = RetentionPolicy {
+            retention_days: Some(7),
+            legal_hold: false,
+        };
+        state.update_chat_retention(1, policy).await?;
+        let input = CreateMessage {
+            content: "fresh".to_string(),
+            ..Default::default()
+        };
+        state.create_message(input, 1, 1).await?;
+
+        let report = state.purge_expired_messages().await?;
+        assert_eq!(report.messages, 10);
+        assert_eq!(report.chats, 1);
+        assert!(state
+            .metrics
+            .render()
+            .contains("\nchat_retention_purged_messages_total 10\n"));
+
+        let input = ListMessages {
+            last_id: None,
+            limit: 0,
+        };
+        let messages = state.list_messages(input, 1).await?;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, "fresh");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn purge_should_respect_legal_hold() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        age_messages(&state, 1, 10).await?;
+        // workspace retention applies to all chats, but the workspace is on hold
+        let policy = RetentionPolicy {
+            retention_days: Some(7),
+            legal_hold: true,
+        };
+        state.update_workspace_retention(1, policy).await?;
+        let report = state.purge_expired_messages().await?;
+        assert_eq!(report.messages, 0);
+
+        // hold lifted, chat level hold still keeps chat 1
+        let policy = RetentionPolicy {
+            retention_days: Some(7),
+            legal_hold: false,
+        };
+        state.update_workspace_retention(1, policy).await?;
+        let policy = RetentionPolicy {
+            retention_days: None,
+            legal_hold: true,
+        };
+        state.update_chat_retention(1, policy).await?;
+        let report = state.purge_expired_messages().await?;
+        assert_eq!(report.messages, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn purge_should_remove_orphaned_files() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let file = ChatFile::new(1, "purge.txt", b"purge me");
+        let path = file.path(&state.config.server.base_dir);
+        tokio::fs::create_dir_all(path.parent().unwrap()).await?;
+        tokio::fs::write(&path, b"purge me").await?;
+        let input = CreateMessage {
+            content: "with file".to_string(),
+            files: vec![file.url()],
+            ..Default::default()
+        };
+        state.create_message(input, 3, 1).await?;
+        age_messages(&state, 3, 10).await?;
+        let policy = RetentionPolicy {
+            retention_days: Some(1),
+            legal_hold: false,
+        };
+        state.update_chat_retention(3, policy).await?;
+
+        let report = state.purge_expired_messages().await?;
+        assert_eq!(report.messages, 1);
+        assert_eq!(report.files, 1);
+        assert!(!path.exists());
+        Ok(())
+    }
+}
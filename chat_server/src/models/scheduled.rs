@@ -0,0 +1,319 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json, Acquire, FromRow};
+use utoipa::ToSchema;
+
+use crate::{AppError, AppState, CreateMessage};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "scheduled_message_status", rename_all = "snake_case")]
+#[serde(rename_all = "camelCase")]
+pub enum ScheduledMessageStatus {
+    Pending,
+    Sent,
+    Canceled,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduledMessage {
+    pub id: i64,
+    pub chat_id: i64,
+    pub sender_id: i64,
+    #[sqlx(json)]
+    pub message: CreateMessage,
+    pub send_at: DateTime<Utc>,
+    pub status: ScheduledMessageStatus,
+    /// id of the posted message once sent
+    pub message_id: Option<i64>,
+    /// why delivery failed
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateScheduledMessage {
+    #[serde(flatten)]
+    pub message: CreateMessage,
+    pub send_at: DateTime<Utc>,
+}
+
+impl AppState {
+    pub async fn create_scheduled_message(
+        &self,
+        input: CreateScheduledMessage,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<ScheduledMessage, AppError> {
+        if input.send_at <= Utc::now() {
+            return Err(AppError::MessageCreateError(
+                "send_at must be in the future".to_string(),
+            ));
+        }
+        // fail early, chat and files are checked again on delivery
+        self.verify_chat_writable(chat_id, &self.pool).await?;
+        self.verify_message(&input.message).await?;
+        let scheduled = sqlx::query_as(
+            r#"
+        INSERT INTO scheduled_messages (chat_id, sender_id, message, send_at)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, chat_id, sender_id, message, send_at, status, message_id, error, created_at
+        "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(Json(&input.message))
+        .bind(input.send_at)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(scheduled)
+    }
+
+    /// List pending scheduled messages of the user in the chat
+    pub async fn list_scheduled_messages(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<Vec<ScheduledMessage>, AppError> {
+        let scheduled = sqlx::query_as(
+            r#"
+        SELECT id, chat_id, sender_id, message, send_at, status, message_id, error, created_at
+        FROM scheduled_messages
+        WHERE chat_id = $1 AND sender_id = $2 AND status = 'pending'
+        ORDER BY send_at
+        "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(scheduled)
+    }
+
+    pub async fn cancel_scheduled_message(
+        &self,
+        id: u64,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<ScheduledMessage, AppError> {
+        let scheduled = sqlx::query_as(
+            r#"
+        UPDATE scheduled_messages SET status = 'canceled'
+        WHERE id = $1 AND chat_id = $2 AND sender_id = $3 AND status = 'pending'
+        RETURNING id, chat_id, sender_id, message, send_at, status, message_id, error, created_at
+        "#,
+        )
+        .bind(id as i64)
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        scheduled.ok_or_else(|| {
+            AppError::NotFound(format!("pending scheduled message {} not found", id))
+        })
+    }
+
+    /// Post due scheduled messages through the normal message path.
+    /// Rows are locked with `SKIP LOCKED` and updated in the same transaction as the insert,
+    /// so each message is posted exactly once even with multiple server instances. Messages
+    /// that can not be posted are marked failed with the error.
+    pub async fn deliver_scheduled_messages(&self) -> Result<u64, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let due: Vec<ScheduledMessage> = sqlx::query_as(
+            r#"
+        SELECT id, chat_id, sender_id, message, send_at, status, message_id, error, created_at
+        FROM scheduled_messages
+        WHERE status = 'pending' AND send_at <= now()
+        ORDER BY send_at
+        LIMIT 100
+        FOR UPDATE SKIP LOCKED
+        "#,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let count = due.len() as u64;
+        for scheduled in due {
+            let (chat_id, sender_id) = (scheduled.chat_id as u64, scheduled.sender_id as u64);
+            // the sender may have left the chat since
+            let ret = if self.is_chat_member(chat_id, sender_id).await? {
+                // a failing insert only rolls back to the savepoint, the row is marked failed
+                // instead of blocking the rest of the batch
+                let mut savepoint = (&mut tx).begin().await?;
+                match self
+                    .insert_message(scheduled.message, chat_id, sender_id, &mut *savepoint)
+                    .await
+                {
+                    Ok(message) => {
+                        savepoint.commit().await?;
+                        Ok(message)
+                    }
+                    Err(e) => {
+                        savepoint.rollback().await?;
+                        Err(e)
+                    }
+                }
+            } else {
+                Err(AppError::PermissionDenied(format!(
+                    "User {} is not a member of chat {}",
+                    sender_id, chat_id
+                )))
+            };
+            let (message_id, error) = match ret {
+                Ok(message) => (Some(message.id), None),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            sqlx::query(
+                r#"
+            UPDATE scheduled_messages
+            SET status = CASE WHEN $2::bigint IS NULL THEN 'failed' ELSE 'sent' END::scheduled_message_status,
+                message_id = $2, error = $3
+            WHERE id = $1
+            "#,
+            )
+            .bind(scheduled.id)
+            .bind(message_id)
+            .bind(error)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn scheduled_input(content: &str, send_at: DateTime<Utc>) -> CreateScheduledMessage {
+        CreateScheduledMessage {
+            message: CreateMessage {
+                content: content.to_string(),
+                ..Default::default()
+            },
+            send_at,
+        }
+    }
+
+    #[tokio::test]
+    async fn schedule_and_cancel_message_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let send_at = Utc::now() + chrono::Duration::hours(1);
+        let scheduled = state
+            .create_scheduled_message(scheduled_input("standup", send_at), 1, 1)
+            .await?;
+        assert_eq!(scheduled.status, ScheduledMessageStatus::Pending);
+        assert_eq!(scheduled.message.content, "standup");
+
+        let list = state.list_scheduled_messages(1, 1).await?;
+        assert_eq!(list.len(), 1);
+        // only the sender can see and cancel it
+        assert!(state.list_scheduled_messages(1, 2).await?.is_empty());
+        assert!(state
+            .cancel_scheduled_message(scheduled.id as _, 1, 2)
+            .await
+            .is_err());
+
+        let scheduled = state
+            .cancel_scheduled_message(scheduled.id as _, 1, 1)
+            .await?;
+        assert_eq!(scheduled.status, ScheduledMessageStatus::Canceled);
+        assert!(state.list_scheduled_messages(1, 1).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn schedule_message_in_the_past_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let send_at = Utc::now() - chrono::Duration::minutes(1);
+        let err = state
+            .create_scheduled_message(scheduled_input("too late", send_at), 1, 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::MessageCreateError(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn deliver_scheduled_messages_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let send_at = Utc::now() + chrono::Duration::hours(1);
+        let due = state
+            .create_scheduled_message(scheduled_input("due", send_at), 1, 1)
+            .await?;
+        state
+            .create_scheduled_message(scheduled_input("later", send_at), 1, 1)
+            .await?;
+        sqlx::query("UPDATE scheduled_messages SET send_at = now() WHERE id = $1")
+            .bind(due.id)
+            .execute(&state.pool)
+            .await?;
+
+        assert_eq!(state.deliver_scheduled_messages().await?, 1);
+        assert_eq!(state.deliver_scheduled_messages().await?, 0);
+
+        let (status, message_id): (ScheduledMessageStatus, Option<i64>) =
+            sqlx::query_as("SELECT status, message_id FROM scheduled_messages WHERE id = $1")
+                .bind(due.id)
+                .fetch_one(&state.pool)
+                .await?;
+        assert_eq!(status, ScheduledMessageStatus::Sent);
+        let message = state
+            .get_message_by_id(message_id.unwrap() as _, 1)
+            .await?
+            .unwrap();
+        assert_eq!(message.content, "due");
+        assert_eq!(message.sender_id, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_scheduled_message_should_not_block_others() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        sqlx::query(
+            r#"
+        CREATE FUNCTION reject_boom() RETURNS TRIGGER AS $$
+        BEGIN
+            IF NEW.content = 'boom' THEN RAISE EXCEPTION 'boom rejected'; END IF;
+            RETURN NEW;
+        END;
+        $$ LANGUAGE plpgsql
+        "#,
+        )
+        .execute(&state.pool)
+        .await?;
+        sqlx::query(
+            "CREATE TRIGGER reject_boom BEFORE INSERT ON messages FOR EACH ROW EXECUTE FUNCTION reject_boom()",
+        )
+        .execute(&state.pool)
+        .await?;
+
+        let send_at = Utc::now() + chrono::Duration::hours(1);
+        let boom = state
+            .create_scheduled_message(scheduled_input("boom", send_at), 1, 1)
+            .await?;
+        let fine = state
+            .create_scheduled_message(scheduled_input("fine", send_at), 1, 1)
+            .await?;
+        sqlx::query("UPDATE scheduled_messages SET send_at = now() - (id || ' seconds')::interval")
+            .execute(&state.pool)
+            .await?;
+
+        assert_eq!(state.deliver_scheduled_messages().await?, 2);
+        assert_eq!(state.deliver_scheduled_messages().await?, 0);
+
+        let rows: Vec<(i64, ScheduledMessageStatus, Option<String>)> =
+            sqlx::query_as("SELECT id, status, error FROM scheduled_messages ORDER BY id")
+                .fetch_all(&state.pool)
+                .await?;
+        assert_eq!(rows[0].0, boom.id);
+        assert_eq!(rows[0].1, ScheduledMessageStatus::Failed);
+        assert!(rows[0].2.as_deref().unwrap().contains("boom rejected"));
+        assert_eq!(rows[1].0, fine.id);
+        assert_eq!(rows[1].1, ScheduledMessageStatus::Sent);
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+use std::net::IpAddr;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{AppError, AppState};
+use chat_core::User;
+
+/// failures of an email before it is locked, each further failure doubles the lock
+const MAX_EMAIL_FAILURES: i64 = 5;
+const MAX_LOCK_MINUTES: i64 = 24 * 60;
+/// failures from one address within the window before it has to wait
+const MAX_IP_FAILURES: i64 = 20;
+const IP_WINDOW_MINUTES: i64 = 15;
+const MAX_AUDIT_ATTEMPTS: i64 = 200;
+/// attempts are kept for the audit well past the longest lock
+const KEEP_ATTEMPT_DAYS: i64 = 30;
+/// the length of the email column, longer input is cut to fit
+const MAX_EMAIL_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "signin_outcome", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum SigninOutcome {
+    Failed,
+    Succeeded,
+    Unlocked,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SigninAttempt {
+    pub id: i64,
+    pub email: String,
+    pub user_id: Option<i64>,
+    pub ip: Option<String>,
+    pub outcome: SigninOutcome,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AppState {
+    /// Seconds until the next attempt for the email or from the address is allowed, `None` if
+    /// it is allowed now. Unknown emails lock like known ones.
+    pub async fn signin_retry_after(
+        &self,
+        email: &str,
+        ip: Option<IpAddr>,
+    ) -> Result<Option<u64>, AppError> {
+        let now = Utc::now();
+        let (failures, last_failure): (i64, Option<DateTime<Utc>>) = sqlx::query_as(
+            r#"
+        SELECT count(*), max(created_at) FROM signin_attempts
+        WHERE email = $1 AND outcome = 'failed' AND created_at > $2
+          AND created_at > COALESCE(
+            (SELECT max(created_at) FROM signin_attempts WHERE email = $1 AND outcome <> 'failed'),
+            '-infinity')
+        "#,
+        )
+        .bind(normalize_email(email))
+        .bind(now - Duration::minutes(MAX_LOCK_MINUTES))
+        .fetch_one(&self.pool)
+        .await?;
+        let mut locked_until = match last_failure {
+            Some(last) if failures >= MAX_EMAIL_FAILURES => last + lock_duration(failures),
+            _ => now,
+        };
+
+        if let Some(ip) = ip {
+            let (failures, first_failure): (i64, Option<DateTime<Utc>>) = sqlx::query_as(
+                r#"
+            SELECT count(*), min(created_at) FROM signin_attempts
+            WHERE ip = $1 AND outcome = 'failed' AND created_at > $2
+            "#,
+            )
+            .bind(ip.to_string())
+            .bind(now - Duration::minutes(IP_WINDOW_MINUTES))
+            .fetch_one(&self.pool)
+            .await?;
+            if let Some(first) = first_failure.filter(|_| failures >= MAX_IP_FAILURES) {
+                locked_until = locked_until.max(first + Duration::minutes(IP_WINDOW_MINUTES));
+            }
+        }
+        let secs = (locked_until - now).num_seconds();
+        Ok((secs > 0).then_some(secs as u64))
+    }
+
+    /// Keep the attempt for the lockout and the audit, `user` is the user the email belongs to
+    pub async fn record_signin(
+        &self,
+        email: &str,
+        user: Option<&User>,
+        ip: Option<IpAddr>,
+        outcome: SigninOutcome,
+    ) -> Result<(), AppError> {
+        if outcome == SigninOutcome::Failed {
+            warn!(
+                "failed signin for {} from {}",
+                email,
+                ip.map(|ip| ip.to_string()).unwrap_or_default()
+            );
+        }
+        sqlx::query(
+            "INSERT INTO signin_attempts (email, user_id, ip, outcome) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(normalize_email(email))
+        .bind(user.map(|user| user.id))
+        .bind(ip.map(|ip| ip.to_string()))
+        .bind(outcome)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Lift the lockout of a member of the workspace
+    pub async fn unlock_user(&self, ws_id: u64, user_id: u64) -> Result<(), AppError> {
+        let user = match self.find_user_by_id(user_id as _).await? {
+            Some(user) if user.ws_id == ws_id as i64 => user,
+            _ => return Err(AppError::NotFound(format!("user {}", user_id))),
+        };
+        self.record_signin(&user.email, Some(&user), None, SigninOutcome::Unlocked)
+            .await
+    }
+
+    /// Remove attempts past the audit period, they no longer count for any lockout
+    pub async fn prune_signin_attempts(&self) -> Result<u64, AppError> {
+        let ret = sqlx::query("DELETE FROM signin_attempts WHERE created_at < $1")
+            .bind(Utc::now() - Duration::days(KEEP_ATTEMPT_DAYS))
+            .execute(&self.pool)
+            .await?;
+        Ok(ret.rows_affected())
+    }
+
+    /// Recent signin attempts for members of the workspace, newest first
+    pub async fn list_signin_attempts(&self, ws_id: u64) -> Result<Vec<SigninAttempt>, AppError> {
+        let attempts = sqlx::query_as(
+            r#"
+        SELECT a.id, a.email, a.user_id, a.ip, a.outcome, a.created_at
+        FROM signin_attempts a JOIN users u ON u.id = a.user_id
+        WHERE u.ws_id = $1
+        ORDER BY a.id DESC
+        LIMIT $2
+        "#,
+        )
+        .bind(ws_id as i64)
+        .bind(MAX_AUDIT_ATTEMPTS)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(attempts)
+    }
+}
+
+/// 1 minute at the limit, doubling with each further failure up to a day
+fn lock_duration(failures: i64) -> Duration {
+    let exp = (failures - MAX_EMAIL_FAILURES).clamp(0, 20) as u32;
+    Duration::minutes(2i64.pow(exp).min(MAX_LOCK_MINUTES))
+}
+
+fn normalize_email(email: &str) -> String {
+    email
+        .trim()
+        .to_lowercase()
+        .chars()
+        .take(MAX_EMAIL_LEN)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateUser;
+    use anyhow::Result;
+
+    #[test]
+    fn lock_duration_should_double_up_to_a_day() {
+        assert_eq!(lock_duration(5), Duration::minutes(1));
+        assert_eq!(lock_duration(7), Duration::minutes(4));
+        assert_eq!(lock_duration(100), Duration::minutes(MAX_LOCK_MINUTES));
+    }
+
+    #[tokio::test]
+    async fn email_should_lock_after_failures_until_unlocked() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateUser::new("acme", "Alice", "alice@acme.org", "secret1");
+        let user = state.create_user(&input).await?;
+        for email in ["alice@acme.org", "nobody@acme.org"] {
+            let user = Some(&user).filter(|user| user.email == email);
+            for _ in 0..MAX_EMAIL_FAILURES {
+                assert_eq!(state.signin_retry_after(email, None).await?, None);
+                state
+                    .record_signin(email, user, None, SigninOutcome::Failed)
+                    .await?;
+            }
+            let secs = state.signin_retry_after(email, None).await?;
+            assert!(matches!(secs, Some(1..=60)));
+        }
+
+        // a success or an unlock resets the count
+        state.unlock_user(user.ws_id as _, user.id as _).await?;
+        assert_eq!(
+            state.signin_retry_after("Alice@acme.org", None).await?,
+            None
+        );
+        assert!(state
+            .unlock_user(user.ws_id as u64 + 1, user.id as _)
+            .await
+            .is_err());
+
+        let attempts = state.list_signin_attempts(user.ws_id as _).await?;
+        assert_eq!(attempts.len(), MAX_EMAIL_FAILURES as usize + 1);
+        assert_eq!(attempts[0].outcome, SigninOutcome::Unlocked);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn long_emails_should_be_recorded_and_old_attempts_pruned() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let email = format!("{}@acme.org", "a".repeat(100));
+        state
+            .record_signin(&email, None, None, SigninOutcome::Failed)
+            .await?;
+        assert_eq!(state.signin_retry_after(&email, None).await?, None);
+
+        sqlx::query("UPDATE signin_attempts SET created_at = $1")
+            .bind(Utc::now() - Duration::days(KEEP_ATTEMPT_DAYS + 1))
+            .execute(&state.pool)
+            .await?;
+        assert_eq!(state.prune_signin_attempts().await?, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ip_should_wait_after_failures() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let ip: IpAddr = "10.0.0.1".parse()?;
+        for i in 0..MAX_IP_FAILURES {
+            let email = format!("user{}@acme.org", i);
+            state
+                .record_signin(&email, None, Some(ip), SigninOutcome::Failed)
+                .await?;
+        }
+        let secs = state.signin_retry_after("other@acme.org", Some(ip)).await?;
+        assert!(secs.is_some());
+        let other: IpAddr = "10.0.0.2".parse()?;
+        assert_eq!(
+            state
+                .signin_retry_after("other@acme.org", Some(other))
+                .await?,
+            None
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,506 @@
+use std::net::IpAddr;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::Utc;
+use jwt_simple::prelude::Duration;
+use serde::{Deserialize, Serialize};
+use totp_rs::{Algorithm, Secret, TOTP};
+use utoipa::ToSchema;
+
+use crate::{AppError, AppState, SigninOutcome};
+use chat_core::User;
+
+use super::access_token::random_hex;
+use super::user::{hash_password, verify_password};
+
+const TOTP_ISSUER: &str = "Chat";
+const RECOVERY_CODES: usize = 10;
+const SIGNIN_TWO_FACTOR: &str = "signin_two_factor";
+const CHALLENGE_MINUTES: u64 = 5;
+
+/// Add the secret to an authenticator app, e.g. by showing the uri as a QR code
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TwoFactorEnrollment {
+    /// base32 encoded
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TwoFactorCode {
+    /// code from the authenticator app, or a recovery code
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DisableTwoFactor {
+    pub current_password: String,
+    pub code: String,
+}
+
+/// Returned by signin instead of a token when 2FA is enabled
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TwoFactorChallenge {
+    pub challenge: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TwoFactorSignin {
+    pub challenge: String,
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct WorkspaceTwoFactor {
+    /// all members must enroll before they can use the app
+    pub required: bool,
+}
+
+impl AppState {
+    /// Start over with a new secret, it is only used once a code is verified
+    pub async fn enroll_two_factor(&self, user_id: u64) -> Result<TwoFactorEnrollment, AppError> {
+        let (email, enabled): (String, bool) =
+            sqlx::query_as("SELECT email, totp_enabled_at IS NOT NULL FROM users WHERE id = $1")
+                .bind(user_id as i64)
+                .fetch_one(&self.pool)
+                .await?;
+        if enabled {
+            return Err(AppError::TwoFactorError(
+                "two-factor authentication is already enabled".to_string(),
+            ));
+        }
+        let secret = Secret::generate_secret().to_encoded().to_string();
+        let otpauth_uri = totp(&secret, &email)?.get_url();
+        sqlx::query("UPDATE users SET totp_secret = $2 WHERE id = $1")
+            .bind(user_id as i64)
+            .bind(&secret)
+            .execute(&self.pool)
+            .await?;
+        Ok(TwoFactorEnrollment {
+            secret,
+            otpauth_uri,
+        })
+    }
+
+    /// Turn 2FA on with a code for the enrolled secret, returns the recovery codes
+    pub async fn enable_two_factor(
+        &self,
+        user_id: u64,
+        code: &str,
+    ) -> Result<Vec<String>, AppError> {
+        let (email, secret, enabled): (String, Option<String>, bool) = sqlx::query_as(
+            "SELECT email, totp_secret, totp_enabled_at IS NOT NULL FROM users WHERE id = $1",
+        )
+        .bind(user_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+        let secret = match (secret, enabled) {
+            (_, true) => {
+                return Err(AppError::TwoFactorError(
+                    "two-factor authentication is already enabled".to_string(),
+                ))
+            }
+            (None, _) => {
+                return Err(AppError::TwoFactorError(
+                    "enroll before enabling two-factor authentication".to_string(),
+                ))
+            }
+            (Some(secret), false) => secret,
+        };
+        let Some(step) = totp_step(&secret, &email, code)? else {
+            return Err(AppError::TwoFactorError("invalid code".to_string()));
+        };
+        let codes: Vec<String> = (0..RECOVERY_CODES).map(|_| recovery_code()).collect();
+        let hashes = codes
+            .iter()
+            .map(|code| hash_password(&normalize_recovery_code(code)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("UPDATE users SET totp_enabled_at = now(), totp_last_step = $2 WHERE id = $1")
+            .bind(user_id as i64)
+            .bind(step as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO recovery_codes (user_id, code_hash) SELECT $1, unnest($2::text[])",
+        )
+        .bind(user_id as i64)
+        .bind(&hashes)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(codes)
+    }
+
+    /// Needs the password and a code, not allowed where the workspace requires 2FA
+    pub async fn disable_two_factor(
+        &self,
+        user: &User,
+        input: DisableTwoFactor,
+    ) -> Result<(), AppError> {
+        if self.is_two_factor_required(user.ws_id as _).await? {
+            return Err(AppError::PermissionDenied(format!(
+                "workspace {} requires two-factor authentication",
+                user.ws_id
+            )));
+        }
+        self.verify_current_password(&input.current_password, user.id as _)
+            .await?;
+        if !self
+            .verify_two_factor_code(user.id as _, &input.code)
+            .await?
+        {
+            return Err(AppError::TwoFactorError("invalid code".to_string()));
+        }
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("UPDATE users SET totp_secret = NULL, totp_enabled_at = NULL WHERE id = $1")
+            .bind(user.id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+            .bind(user.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn is_two_factor_enabled(&self, user_id: u64) -> Result<bool, AppError> {
+        let (enabled,): (bool,) =
+            sqlx::query_as("SELECT totp_enabled_at IS NOT NULL FROM users WHERE id = $1")
+                .bind(user_id as i64)
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(enabled)
+    }
+
+    pub async fn is_two_factor_required(&self, ws_id: u64) -> Result<bool, AppError> {
+        let required: Option<(bool,)> =
+            sqlx::query_as("SELECT require_two_factor FROM workspaces WHERE id = $1")
+                .bind(ws_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(required.is_some_and(|(required,)| required))
+    }
+
+    /// The workspace requires 2FA and the user has not enabled it, also for unknown users. Bots
+    /// can not enroll, they only use access tokens.
+    pub async fn is_two_factor_pending(&self, user_id: u64) -> Result<bool, AppError> {
+        let pending: Option<(bool,)> = sqlx::query_as(
+            r#"
+        SELECT w.require_two_factor AND u.totp_enabled_at IS NULL AND NOT u.is_bot
+        FROM users u JOIN workspaces w ON w.id = u.ws_id
+        WHERE u.id = $1
+        "#,
+        )
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(pending.map_or(true, |(pending,)| pending))
+    }
+
+    /// Short-lived proof that the password was right, exchanged for a token with a code. Only
+    /// the latest challenge of the user is valid.
+    pub async fn two_factor_challenge(&self, user: &User) -> Result<String, AppError> {
+        let nonce = random_hex(16);
+        sqlx::query("UPDATE users SET totp_nonce = $2 WHERE id = $1")
+            .bind(user.id)
+            .bind(&nonce)
+            .execute(&self.pool)
+            .await?;
+        self.sign_action(
+            SIGNIN_TWO_FACTOR,
+            user.id,
+            &nonce,
+            Duration::from_mins(CHALLENGE_MINUTES),
+        )
+    }
+
+    /// The user a challenge was issued to, the challenge can not be used again, so a wrong code
+    /// needs a new signin
+    pub async fn take_two_factor_challenge(&self, challenge: &str) -> Result<User, AppError> {
+        let invalid = || AppError::PermissionDenied("invalid or expired challenge".to_string());
+        let claims = self
+            .verify_action(SIGNIN_TWO_FACTOR, challenge)
+            .map_err(|_| invalid())?;
+        let ret =
+            sqlx::query("UPDATE users SET totp_nonce = NULL WHERE id = $1 AND totp_nonce = $2")
+                .bind(claims.uid)
+                .bind(&claims.bind)
+                .execute(&self.pool)
+                .await?;
+        if ret.rows_affected() == 0 {
+            return Err(invalid());
+        }
+        self.get_profile(claims.uid as _).await
+    }
+
+    /// Second signin step, the attempt counts for the signin lockout like a password
+    pub async fn verify_two_factor_signin(
+        &self,
+        user: &User,
+        code: &str,
+        ip: Option<IpAddr>,
+    ) -> Result<(), AppError> {
+        let valid = self.verify_two_factor_code(user.id as _, code).await?;
+        let outcome = if valid {
+            SigninOutcome::Succeeded
+        } else {
+            SigninOutcome::Failed
+        };
+        self.record_signin(&user.email, Some(user), ip, outcome)
+            .await?;
+        if !valid {
+            return Err(AppError::PermissionDenied(
+                "invalid two-factor code".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// A TOTP code of a later time step than the last one used, or an unused recovery code
+    /// which is then used up
+    async fn verify_two_factor_code(&self, user_id: u64, code: &str) -> Result<bool, AppError> {
+        let user: Option<(String, String)> = sqlx::query_as(
+            r#"
+        SELECT email, totp_secret FROM users
+        WHERE id = $1 AND totp_enabled_at IS NOT NULL AND totp_secret IS NOT NULL
+        "#,
+        )
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some((email, secret)) = user else {
+            return Ok(false);
+        };
+        let code = code.trim();
+        if code.len() == 6 && code.chars().all(|c| c.is_ascii_digit()) {
+            let Some(step) = totp_step(&secret, &email, code)? else {
+                return Ok(false);
+            };
+            let ret = sqlx::query(
+                r#"
+            UPDATE users SET totp_last_step = $2
+            WHERE id = $1 AND (totp_last_step IS NULL OR totp_last_step < $2)
+            "#,
+            )
+            .bind(user_id as i64)
+            .bind(step as i64)
+            .execute(&self.pool)
+            .await?;
+            return Ok(ret.rows_affected() == 1);
+        }
+        let code = normalize_recovery_code(code);
+        let hashes: Vec<(i64, String)> = sqlx::query_as(
+            "SELECT id, code_hash FROM recovery_codes WHERE user_id = $1 AND used_at IS NULL",
+        )
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        for (id, hash) in hashes {
+            if verify_password(&code, &hash)? {
+                let ret = sqlx::query(
+                    "UPDATE recovery_codes SET used_at = now() WHERE id = $1 AND used_at IS NULL",
+                )
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+                return Ok(ret.rows_affected() == 1);
+            }
+        }
+        Ok(false)
+    }
+
+    pub async fn get_workspace_two_factor(
+        &self,
+        ws_id: u64,
+    ) -> Result<WorkspaceTwoFactor, AppError> {
+        let required = self.is_two_factor_required(ws_id).await?;
+        Ok(WorkspaceTwoFactor { required })
+    }
+
+    pub async fn update_workspace_two_factor(
+        &self,
+        ws_id: u64,
+        input: WorkspaceTwoFactor,
+    ) -> Result<WorkspaceTwoFactor, AppError> {
+        sqlx::query("UPDATE workspaces SET require_two_factor = $2 WHERE id = $1")
+            .bind(ws_id as i64)
+            .bind(input.required)
+            .execute(&self.pool)
+            .await?;
+        Ok(input)
+    }
+}
+
+fn totp(secret: &str, email: &str) -> Result<TOTP, AppError> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| AppError::TwoFactorError(format!("invalid secret: {:?}", e)))?;
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        secret,
+        Some(TOTP_ISSUER.to_string()),
+        email.to_string(),
+    )
+    .map_err(|e| AppError::TwoFactorError(e.to_string()))
+}
+
+/// The time step the code is for, the current one or one next to it for clock drift
+fn totp_step(secret: &str, email: &str, code: &str) -> Result<Option<u64>, AppError> {
+    let mut totp = totp(secret, email)?;
+    let skew = totp.skew as u64;
+    // check one step at a time
+    totp.skew = 0;
+    let current = Utc::now().timestamp() as u64 / totp.step;
+    let step = (current.saturating_sub(skew)..=current + skew)
+        .find(|step| totp.check(code.trim(), step * totp.step));
+    Ok(step)
+}
+
+/// 10 hex digits, shown as `xxxxx-xxxxx`
+fn recovery_code() -> String {
+    let mut bytes = [0u8; 5];
+    OsRng.fill_bytes(&mut bytes);
+    let code = hex::encode(bytes);
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateUser;
+    use anyhow::Result;
+
+    fn current_code(enrollment: &TwoFactorEnrollment, email: &str) -> Result<String> {
+        Ok(totp(&enrollment.secret, email)?.generate_current()?)
+    }
+
+    fn next_code(enrollment: &TwoFactorEnrollment, email: &str) -> Result<String> {
+        let totp = totp(&enrollment.secret, email)?;
+        Ok(totp.generate(Utc::now().timestamp() as u64 + totp.step))
+    }
+
+    #[tokio::test]
+    async fn two_factor_enrollment_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateUser::new("acme", "Alice", "alice@acme.org", "secret1");
+        let user = state.create_user(&input).await?;
+        let enrollment = state.enroll_two_factor(user.id as _).await?;
+        assert!(enrollment.otpauth_uri.starts_with("otpauth://totp/Chat:"));
+        assert!(!state.is_two_factor_enabled(user.id as _).await?);
+        assert!(state
+            .enable_two_factor(user.id as _, "000000")
+            .await
+            .is_err());
+
+        let code = current_code(&enrollment, &user.email)?;
+        let codes = state.enable_two_factor(user.id as _, &code).await?;
+        assert_eq!(codes.len(), RECOVERY_CODES);
+        assert!(state.is_two_factor_enabled(user.id as _).await?);
+        assert!(state.enroll_two_factor(user.id as _).await.is_err());
+
+        // a challenge works once and only the latest one does
+        let first = state.two_factor_challenge(&user).await?;
+        let challenge = state.two_factor_challenge(&user).await?;
+        assert!(state.take_two_factor_challenge(&first).await.is_err());
+        let signed_in = state.take_two_factor_challenge(&challenge).await?;
+        assert_eq!(signed_in.id, user.id);
+        assert!(state.take_two_factor_challenge(&challenge).await.is_err());
+        assert!(state.take_two_factor_challenge("bogus").await.is_err());
+
+        // a time step signs in once, the one enabling 2FA is used up
+        assert!(state
+            .verify_two_factor_signin(&user, &code, None)
+            .await
+            .is_err());
+        let code = next_code(&enrollment, &user.email)?;
+        state.verify_two_factor_signin(&user, &code, None).await?;
+        assert!(state
+            .verify_two_factor_signin(&user, &code, None)
+            .await
+            .is_err());
+
+        // recovery codes work once, in any case and with or without the dash
+        let code = codes[0].to_uppercase().replace('-', "");
+        state.verify_two_factor_signin(&user, &code, None).await?;
+        assert!(state
+            .verify_two_factor_signin(&user, &code, None)
+            .await
+            .is_err());
+
+        // wrong codes lock the signin like wrong passwords
+        for _ in 0..4 {
+            assert!(state
+                .verify_two_factor_signin(&user, "invalid", None)
+                .await
+                .is_err());
+        }
+        assert!(state.signin_retry_after(&user.email, None).await?.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn two_factor_pending_should_be_checked_live() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateUser::new("acme", "Alice", "alice@acme.org", "secret1");
+        let user = state.create_user(&input).await?;
+        assert!(!state.is_two_factor_pending(user.id as _).await?);
+
+        let input = WorkspaceTwoFactor { required: true };
+        state
+            .update_workspace_two_factor(user.ws_id as _, input)
+            .await?;
+        assert!(state.is_two_factor_pending(user.id as _).await?);
+        let enrollment = state.enroll_two_factor(user.id as _).await?;
+        let code = current_code(&enrollment, &user.email)?;
+        state.enable_two_factor(user.id as _, &code).await?;
+        assert!(!state.is_two_factor_pending(user.id as _).await?);
+        assert!(state.is_two_factor_pending(10000).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn disable_two_factor_should_follow_workspace() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateUser::new("acme", "Alice", "alice@acme.org", "secret1");
+        let user = state.create_user(&input).await?;
+        let enrollment = state.enroll_two_factor(user.id as _).await?;
+        let code = current_code(&enrollment, &user.email)?;
+        let codes = state.enable_two_factor(user.id as _, &code).await?;
+
+        let input = WorkspaceTwoFactor { required: true };
+        state
+            .update_workspace_two_factor(user.ws_id as _, input)
+            .await?;
+        let input = DisableTwoFactor {
+            current_password: "secret1".to_string(),
+            code: codes[1].clone(),
+        };
+        let ret = state.disable_two_factor(&user, input.clone()).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        state
+            .update_workspace_two_factor(user.ws_id as _, Default::default())
+            .await?;
+        state.disable_two_factor(&user, input).await?;
+        assert!(!state.is_two_factor_enabled(user.id as _).await?);
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use std::mem;
+use std::{mem, sync::OnceLock};
 
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
@@ -36,7 +36,7 @@ impl AppState {
     /// Find a user by email
     pub async fn find_user_by_email(&self, email: &str) -> Result<Option<User>, AppError> {
         let user = sqlx::query_as(
-            "select id, ws_id, fullname, email, created_at from users where email = $1",
+            "select id, ws_id, fullname, email, avatar, status_message, time_zone, is_bot, created_at from users where email = $1",
         )
         .bind(email)
         .fetch_optional(&self.pool)
@@ -46,7 +46,7 @@ impl AppState {
     /// Find user by id
     pub async fn find_user_by_id(&self, id: i64) -> Result<Option<User>, AppError> {
         let user = sqlx::query_as(
-            "select id, ws_id, fullname, email, created_at from users where id = $1",
+            "select id, ws_id, fullname, email, avatar, status_message, time_zone, is_bot, created_at from users where id = $1",
         )
         .bind(id)
         .fetch_optional(&self.pool)
@@ -95,7 +95,7 @@ impl AppState {
     /// Verify email and password
     pub async fn verify_user(&self, input: &SigninUser) -> Result<Option<User>, AppError> {
         let user: Option<User> = sqlx::query_as(
-            "select id, ws_id, fullname, email, password_hash, created_at from users where email = $1",
+            "select id, ws_id, fullname, email, avatar, status_message, time_zone, password_hash, created_at from users where email = $1 and not is_bot",
         )
         .bind(&input.email)
         .fetch_optional(&self.pool)
@@ -112,12 +112,16 @@ impl AppState {
                     Ok(None)
                 }
             }
-            None => Ok(None),
+            None => {
+                // take as long as a wrong password, so timing does not reveal unknown emails
+                verify_password(&input.password, dummy_password_hash())?;
+                Ok(None)
+            }
         }
     }
     pub async fn fetch_chat_user_by_ids(&self, ids: &[i64]) -> Result<Vec<ChatUser>, AppError> {
         let users =
-            sqlx::query_as("select id, fullname, email from users where id = any($1) order by id")
+            sqlx::query_as("select id, fullname, email, avatar, status_message, time_zone, is_bot from users where id = any($1) order by id")
                 .bind(ids)
                 .fetch_all(&self.pool)
                 .await?;
@@ -125,7 +129,7 @@ impl AppState {
     }
     pub async fn fetch_chat_users(&self, ws_id: u64) -> Result<Vec<ChatUser>, AppError> {
         let users =
-            sqlx::query_as("select id, fullname, email from users where ws_id = $1 order by id")
+            sqlx::query_as("select id, fullname, email, avatar, status_message, time_zone, is_bot from users where ws_id = $1 order by id")
                 .bind(ws_id as i64)
                 .fetch_all(&self.pool)
                 .await?;
@@ -136,14 +140,21 @@ impl AppState {
 // #[allow(dead_code)]
 // impl ChatUser {}
 
-fn hash_password(password: &str) -> Result<String, AppError> {
+pub(crate) fn hash_password(password: &str) -> Result<String, AppError> {
     let argon2 = Argon2::default();
     let salt = SaltString::generate(&mut OsRng);
     let hash = argon2.hash_password(password.as_bytes(), &salt)?;
     Ok(hash.to_string())
 }
 
-fn verify_password(password: &str, password_hash: &str) -> Result<bool, AppError> {
+fn dummy_password_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| {
+        hash_password(SaltString::generate(&mut OsRng).as_str()).expect("hash dummy password")
+    })
+}
+
+pub(crate) fn verify_password(password: &str, password_hash: &str) -> Result<bool, AppError> {
     let argon2 = Argon2::default();
     let parsed_hash = PasswordHash::new(password_hash)?;
     let matches = argon2
@@ -0,0 +1,284 @@
+use std::ops::Deref;
+
+use jwt_simple::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use utoipa::ToSchema;
+
+use crate::{AppError, AppState, Mail};
+
+use super::profile::MIN_PASSWORD_LEN;
+use super::user::hash_password;
+
+const TOKEN_ISS: &str = "chat_server";
+const VERIFY_EMAIL: &str = "verify_email";
+const RESET_PASSWORD: &str = "reset_password";
+const VERIFY_EMAIL_HOURS: u64 = 72;
+const RESET_PASSWORD_HOURS: u64 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ForgotPassword {
+    pub email: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ResetPassword {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct VerifyEmail {
+    pub token: String,
+}
+
+/// Signed with the server key, the audience tells what the token is for. `bind` is the state the
+/// token is valid for, once it changes the token is used up.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ActionClaims {
+    pub(crate) uid: i64,
+    pub(crate) bind: String,
+}
+
+impl AppState {
+    /// Mail a verification link unless the email is verified already
+    pub async fn send_email_verification(&self, user_id: u64) -> Result<(), AppError> {
+        let user: Option<(String, bool)> =
+            sqlx::query_as("SELECT email, email_verified_at IS NOT NULL FROM users WHERE id = $1")
+                .bind(user_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+        let Some((email, false)) = user else {
+            return Ok(());
+        };
+        let token = self.sign_action(
+            VERIFY_EMAIL,
+            user_id as _,
+            &email,
+            Duration::from_hours(VERIFY_EMAIL_HOURS),
+        )?;
+        let mail = Mail {
+            to: email,
+            subject: "Verify your email".to_string(),
+            body: format!(
+                "Open this link to verify your email:\n\n{}/verify-email?token={}\n\nThe link is valid for {} hours.",
+                self.config.mail.base_url, token, VERIFY_EMAIL_HOURS
+            ),
+        };
+        self.mailer.send(mail).await
+    }
+
+    pub async fn verify_email(&self, token: &str) -> Result<(), AppError> {
+        let claims = self.verify_action(VERIFY_EMAIL, token)?;
+        // the email may have changed since the token was sent
+        let ret = sqlx::query(
+            r#"
+        UPDATE users SET email_verified_at = now()
+        WHERE id = $1 AND email = $2 AND email_verified_at IS NULL
+        "#,
+        )
+        .bind(claims.uid)
+        .bind(&claims.bind)
+        .execute(&self.pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(invalid_token());
+        }
+        Ok(())
+    }
+
+    pub async fn is_email_verified(&self, user_id: u64) -> Result<bool, AppError> {
+        let (verified,): (bool,) =
+            sqlx::query_as("SELECT email_verified_at IS NOT NULL FROM users WHERE id = $1")
+                .bind(user_id as i64)
+                .fetch_one(&self.pool)
+                .await?;
+        Ok(verified)
+    }
+
+    /// Mail a reset link, unknown emails are ignored so they can not be probed
+    pub async fn forgot_password(&self, input: ForgotPassword) -> Result<(), AppError> {
+        let user: Option<(i64, String)> =
+            sqlx::query_as("SELECT id, password_hash FROM users WHERE email = $1")
+                .bind(input.email.trim())
+                .fetch_optional(&self.pool)
+                .await?;
+        let Some((id, password_hash)) = user else {
+            return Ok(());
+        };
+        let token = self.sign_action(
+            RESET_PASSWORD,
+            id,
+            &fingerprint(&password_hash),
+            Duration::from_hours(RESET_PASSWORD_HOURS),
+        )?;
+        let mail = Mail {
+            to: input.email.trim().to_string(),
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "Open this link to choose a new password:\n\n{}/reset-password?token={}\n\nThe link is valid for {} hour. If you did not ask for it, ignore this mail.",
+                self.config.mail.base_url, token, RESET_PASSWORD_HOURS
+            ),
+        };
+        self.mailer.send(mail).await
+    }
+
+    /// Set a new password, the link also proves the user owns the email
+    pub async fn reset_password(&self, input: ResetPassword) -> Result<(), AppError> {
+        let claims = self.verify_action(RESET_PASSWORD, &input.token)?;
+        if input.password.chars().count() < MIN_PASSWORD_LEN {
+            return Err(AppError::UpdateUserError(format!(
+                "Password must be at least {} characters",
+                MIN_PASSWORD_LEN
+            )));
+        }
+        let user: Option<(String,)> =
+            sqlx::query_as("SELECT password_hash FROM users WHERE id = $1")
+                .bind(claims.uid)
+                .fetch_optional(&self.pool)
+                .await?;
+        let Some((old_hash,)) = user.filter(|(hash,)| fingerprint(hash) == claims.bind) else {
+            return Err(invalid_token());
+        };
+        let ret = sqlx::query(
+            r#"
+        UPDATE users SET password_hash = $3, email_verified_at = COALESCE(email_verified_at, now())
+        WHERE id = $1 AND password_hash = $2
+        "#,
+        )
+        .bind(claims.uid)
+        .bind(&old_hash)
+        .bind(hash_password(&input.password)?)
+        .execute(&self.pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Err(invalid_token());
+        }
+        Ok(())
+    }
+
+    /// Mail the token confirming a change to `email`
+    pub(crate) async fn send_email_change(&self, email: &str, token: &str) -> Result<(), AppError> {
+        let mail = Mail {
+            to: email.to_string(),
+            subject: "Confirm your new email".to_string(),
+            body: format!(
+                "Open this link to use this email for your account:\n\n{}/confirm-email?token={}",
+                self.config.mail.base_url, token
+            ),
+        };
+        self.mailer.send(mail).await
+    }
+
+    /// Sign a token for one action (the audience) of the user
+    pub(crate) fn sign_action(
+        &self,
+        audience: &str,
+        uid: i64,
+        bind: &str,
+        valid_for: Duration,
+    ) -> Result<String, AppError> {
+        let claims = ActionClaims {
+            uid,
+            bind: bind.to_string(),
+        };
+        let claims = Claims::with_custom_claims(claims, valid_for)
+            .with_issuer(TOKEN_ISS)
+            .with_audience(audience);
+        Ok(self.ek.deref().sign(claims)?)
+    }
+
+    pub(crate) fn verify_action(
+        &self,
+        audience: &str,
+        token: &str,
+    ) -> Result<ActionClaims, AppError> {
+        let options = VerificationOptions {
+            allowed_issuers: Some(HashSet::from_strings(&[TOKEN_ISS])),
+            allowed_audiences: Some(HashSet::from_strings(&[audience])),
+            ..Default::default()
+        };
+        let claims = self
+            .dk
+            .verify_token::<ActionClaims>(token, Some(options))
+            .map_err(|_| invalid_token())?;
+        Ok(claims.custom)
+    }
+}
+
+fn invalid_token() -> AppError {
+    AppError::UpdateUserError("Invalid or expired token".to_string())
+}
+
+/// Changes with the password, so a reset link works once
+pub(crate) fn fingerprint(password_hash: &str) -> String {
+    hex::encode(Sha1::digest(password_hash.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CreateUser, SigninUser};
+    use anyhow::Result;
+
+    fn token_from(mail: &Mail) -> String {
+        let (_, token) = mail.body.split_once("token=").expect("no token in mail");
+        token.split_whitespace().next().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn verify_email_should_work_once() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateUser::new("acme", "Alice", "alice@acme.org", "secret1");
+        let user = state.create_user(&input).await?;
+        assert!(!state.is_email_verified(user.id as _).await?);
+
+        state.send_email_verification(user.id as _).await?;
+        let mails = state.mailer.sent();
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0].to, "alice@acme.org");
+        let token = token_from(&mails[0]);
+        assert!(state.verify_email("bogus").await.is_err());
+        state.verify_email(&token).await?;
+        assert!(state.is_email_verified(user.id as _).await?);
+        assert!(state.verify_email(&token).await.is_err());
+
+        // nothing to send once verified
+        state.send_email_verification(user.id as _).await?;
+        assert_eq!(state.mailer.sent().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reset_password_should_work_once() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateUser::new("acme", "Alice", "alice@acme.org", "secret1");
+        let user = state.create_user(&input).await?;
+
+        // unknown emails are not revealed
+        let input = ForgotPassword {
+            email: "nobody@acme.org".to_string(),
+        };
+        state.forgot_password(input).await?;
+        assert!(state.mailer.sent().is_empty());
+
+        let input = ForgotPassword {
+            email: user.email.clone(),
+        };
+        state.forgot_password(input).await?;
+        let token = token_from(&state.mailer.sent()[0]);
+        // a reset token does not verify emails
+        assert!(state.verify_email(&token).await.is_err());
+
+        let input = ResetPassword {
+            token: token.clone(),
+            password: "secret2".to_string(),
+        };
+        state.reset_password(input.clone()).await?;
+        let signin = SigninUser::new(&user.email, "secret2");
+        assert!(state.verify_user(&signin).await?.is_some());
+        assert!(state.is_email_verified(user.id as _).await?);
+        assert!(state.reset_password(input).await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,451 @@
+use std::str::FromStr;
+
+use chat_core::{Message, MessageFormat, RateLimitQuota, User};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{AppError, AppState, ChatFile, CreateAttachment, CreateMessage};
+
+use super::access_token::random_hex;
+use super::profile::hash_token;
+
+const MAX_WEBHOOK_NAME_LEN: usize = 64;
+const MAX_SENDER_NAME_LEN: usize = 64;
+/// per hook, unless the config has a quota for `incoming_webhook`
+const DEFAULT_WEBHOOK_QUOTA: RateLimitQuota = RateLimitQuota {
+    requests: 60,
+    per_secs: 60,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreateIncomingWebhook {
+    /// also the name of the bot the hook posts as
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct UpdateIncomingWebhook {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// a disabled hook refuses payloads until enabled again
+    #[serde(default)]
+    pub disabled: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct IncomingWebhook {
+    pub id: i64,
+    pub chat_id: i64,
+    /// the bot user messages of the hook are sent as
+    pub bot_id: i64,
+    pub name: String,
+    pub created_by: i64,
+    pub disabled_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedIncomingWebhook {
+    #[serde(flatten)]
+    pub webhook: IncomingWebhook,
+    /// only shown once, relative to the server, e.g. `/hooks/1/<secret>`
+    pub url: String,
+}
+
+/// What scripts post to a hook
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct IncomingWebhookPayload {
+    pub text: String,
+    /// shown instead of the name of the hook
+    #[serde(default)]
+    pub username: Option<String>,
+    /// files already uploaded to the workspace of the chat
+    #[serde(default)]
+    pub attachments: Vec<CreateAttachment>,
+    #[serde(default)]
+    pub format: MessageFormat,
+}
+
+impl AppState {
+    /// Add a hook to the chat, it posts as a new bot of the workspace of the chat
+    pub async fn create_incoming_webhook(
+        &self,
+        chat_id: u64,
+        user: &User,
+        input: CreateIncomingWebhook,
+    ) -> Result<CreatedIncomingWebhook, AppError> {
+        let name = verify_webhook_name(&input.name)?;
+        let Some(chat) = self.get_chat_by_id(chat_id).await? else {
+            return Err(AppError::ChatDoesNotExist);
+        };
+        let bot_id = self.insert_bot(chat.ws_id, name).await?;
+        let secret = random_hex(24);
+        let webhook = sqlx::query_as(
+            r#"
+        INSERT INTO incoming_webhooks (chat_id, bot_id, name, secret_hash, created_by)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, chat_id, bot_id, name, created_by, disabled_at, created_at
+        "#,
+        )
+        .bind(chat_id as i64)
+        .bind(bot_id)
+        .bind(name)
+        .bind(hash_token(&secret))
+        .bind(user.id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(created_webhook(webhook, &secret))
+    }
+
+    pub async fn list_incoming_webhooks(
+        &self,
+        chat_id: u64,
+    ) -> Result<Vec<IncomingWebhook>, AppError> {
+        let webhooks = sqlx::query_as(
+            r#"
+        SELECT id, chat_id, bot_id, name, created_by, disabled_at, created_at
+        FROM incoming_webhooks
+        WHERE chat_id = $1
+        ORDER BY id
+        "#,
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(webhooks)
+    }
+
+    /// Rename, disable or enable a hook, only its creator or admins of the chat can
+    pub async fn update_incoming_webhook(
+        &self,
+        chat_id: u64,
+        id: u64,
+        user: &User,
+        input: UpdateIncomingWebhook,
+    ) -> Result<IncomingWebhook, AppError> {
+        let webhook = self.get_managed_webhook(chat_id, id, user).await?;
+        let name = match input.name.as_deref() {
+            Some(name) => verify_webhook_name(name)?,
+            None => &webhook.name,
+        };
+        let webhook = sqlx::query_as(
+            r#"
+        UPDATE incoming_webhooks SET
+            name = $2,
+            disabled_at = CASE
+                WHEN $3::boolean IS NULL THEN disabled_at
+                WHEN $3 THEN COALESCE(disabled_at, now())
+                ELSE NULL END
+        WHERE id = $1
+        RETURNING id, chat_id, bot_id, name, created_by, disabled_at, created_at
+        "#,
+        )
+        .bind(webhook.id)
+        .bind(name)
+        .bind(input.disabled)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(webhook)
+    }
+
+    /// Replace the secret of a hook, the old url stops working
+    pub async fn regenerate_incoming_webhook(
+        &self,
+        chat_id: u64,
+        id: u64,
+        user: &User,
+    ) -> Result<CreatedIncomingWebhook, AppError> {
+        let webhook = self.get_managed_webhook(chat_id, id, user).await?;
+        let secret = random_hex(24);
+        sqlx::query("UPDATE incoming_webhooks SET secret_hash = $2 WHERE id = $1")
+            .bind(webhook.id)
+            .bind(hash_token(&secret))
+            .execute(&self.pool)
+            .await?;
+        Ok(created_webhook(webhook, &secret))
+    }
+
+    /// The bot of the hook goes with it. If it posted messages or acted through access tokens,
+    /// it stays as their sender but its tokens are revoked.
+    pub async fn delete_incoming_webhook(
+        &self,
+        chat_id: u64,
+        id: u64,
+        user: &User,
+    ) -> Result<(), AppError> {
+        let webhook = self.get_managed_webhook(chat_id, id, user).await?;
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM incoming_webhooks WHERE id = $1")
+            .bind(webhook.id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM access_tokens WHERE user_id = $1")
+            .bind(webhook.bot_id)
+            .execute(&mut *tx)
+            .await?;
+        // in a savepoint, so the bot can be kept when something still references it
+        let mut savepoint = tx.begin().await?;
+        let ret = sqlx::query("DELETE FROM users WHERE id = $1 AND is_bot")
+            .bind(webhook.bot_id)
+            .execute(&mut *savepoint)
+            .await;
+        match ret {
+            Ok(_) => savepoint.commit().await?,
+            Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => {
+                savepoint.rollback().await?
+            }
+            Err(e) => return Err(e.into()),
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// The hook of the url, a wrong secret looks like a missing hook
+    pub async fn verify_incoming_webhook(
+        &self,
+        id: u64,
+        secret: &str,
+    ) -> Result<IncomingWebhook, AppError> {
+        let webhook: Option<IncomingWebhook> = sqlx::query_as(
+            r#"
+        SELECT id, chat_id, bot_id, name, created_by, disabled_at, created_at
+        FROM incoming_webhooks
+        WHERE id = $1 AND secret_hash = $2
+        "#,
+        )
+        .bind(id as i64)
+        .bind(hash_token(secret))
+        .fetch_optional(&self.pool)
+        .await?;
+        match webhook {
+            Some(webhook) if webhook.disabled_at.is_some() => Err(AppError::PermissionDenied(
+                format!("webhook {} is disabled", id),
+            )),
+            Some(webhook) => Ok(webhook),
+            None => Err(AppError::NotFound(format!("webhook {}", id))),
+        }
+    }
+
+    /// Seconds until the hook may post again, `None` if it may post now
+    pub async fn incoming_webhook_retry_after(&self, id: i64) -> Result<Option<u64>, AppError> {
+        let quota = self
+            .config
+            .rate_limit
+            .routes
+            .get("incoming_webhook")
+            .copied()
+            .unwrap_or(DEFAULT_WEBHOOK_QUOTA);
+        let key = format!("incoming_webhook:hook:{}", id);
+        match self.rate_limits.check(&key, quota).await {
+            Ok(state) if state.allowed => Ok(None),
+            Ok(state) => Ok(Some(state.retry_after_secs)),
+            Err(e) => {
+                // same as the rate limit layer, rather serve while the store is down
+                warn!("rate limit check for {} failed: {}", key, e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Post the payload into the chat of the hook as its bot
+    pub async fn post_incoming_webhook(
+        &self,
+        webhook: &IncomingWebhook,
+        payload: IncomingWebhookPayload,
+    ) -> Result<Message, AppError> {
+        let sender_name = match payload.username.as_deref().map(str::trim) {
+            Some(name) if name.chars().count() > MAX_SENDER_NAME_LEN => {
+                return Err(AppError::WebhookError(format!(
+                    "username must be at most {} characters",
+                    MAX_SENDER_NAME_LEN
+                )));
+            }
+            Some(name) if !name.is_empty() => Some(name.to_string()),
+            _ => None,
+        };
+        let Some(chat) = self.get_chat_by_id(webhook.chat_id as _).await? else {
+            return Err(AppError::ChatDoesNotExist);
+        };
+        // the hook is not signed in, it may only attach files of its own workspace
+        for attachment in &payload.attachments {
+            let file = ChatFile::from_str(&attachment.url)?;
+            if file.ws_id != chat.ws_id as u64 {
+                return Err(AppError::WebhookError(format!(
+                    "file {} is not in the workspace of the chat",
+                    attachment.url
+                )));
+            }
+        }
+        let input = CreateMessage {
+            content: payload.text,
+            format: payload.format,
+            attachments: payload.attachments,
+            sender_name,
+            ..Default::default()
+        };
+        self.create_message(input, webhook.chat_id as _, webhook.bot_id as _)
+            .await
+    }
+
+    async fn get_managed_webhook(
+        &self,
+        chat_id: u64,
+        id: u64,
+        user: &User,
+    ) -> Result<IncomingWebhook, AppError> {
+        let webhook: Option<IncomingWebhook> = sqlx::query_as(
+            r#"
+        SELECT id, chat_id, bot_id, name, created_by, disabled_at, created_at
+        FROM incoming_webhooks
+        WHERE id = $1 AND chat_id = $2
+        "#,
+        )
+        .bind(id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(webhook) = webhook else {
+            return Err(AppError::NotFound(format!("webhook {}", id)));
+        };
+        if webhook.created_by != user.id {
+            self.verify_chat_admin(chat_id, user.id as _).await?;
+        }
+        Ok(webhook)
+    }
+}
+
+fn verify_webhook_name(name: &str) -> Result<&str, AppError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_WEBHOOK_NAME_LEN {
+        return Err(AppError::WebhookError(format!(
+            "Name must be between 1 and {} characters",
+            MAX_WEBHOOK_NAME_LEN
+        )));
+    }
+    Ok(name)
+}
+
+fn created_webhook(webhook: IncomingWebhook, secret: &str) -> CreatedIncomingWebhook {
+    let url = format!("/hooks/{}/{}", webhook.id, secret);
+    CreatedIncomingWebhook { webhook, url }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn secret_of(created: &CreatedIncomingWebhook) -> &str {
+        created.url.rsplit('/').next().unwrap()
+    }
+
+    #[tokio::test]
+    async fn incoming_webhook_should_post_as_its_bot() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        let input = CreateIncomingWebhook {
+            name: "Deploys".to_string(),
+        };
+        let created = state.create_incoming_webhook(1, &user, input).await?;
+        let webhook = state
+            .verify_incoming_webhook(created.webhook.id as _, secret_of(&created))
+            .await?;
+        let bot = state.find_user_by_id(webhook.bot_id).await?.unwrap();
+        assert!(bot.is_bot);
+        assert_eq!(bot.fullname, "Deploys");
+
+        let payload = IncomingWebhookPayload {
+            text: "deployed v1.2".to_string(),
+            username: Some("CI".to_string()),
+            ..Default::default()
+        };
+        let message = state.post_incoming_webhook(&webhook, payload).await?;
+        assert_eq!(message.chat_id, 1);
+        assert_eq!(message.sender_id, bot.id);
+        assert_eq!(message.sender_name.as_deref(), Some("CI"));
+
+        assert!(state
+            .verify_incoming_webhook(created.webhook.id as _, "wrong")
+            .await
+            .is_err());
+        assert_eq!(state.list_incoming_webhooks(1).await?.len(), 1);
+
+        // the bot stays as the sender of its messages
+        state
+            .delete_incoming_webhook(1, webhook.id as _, &user)
+            .await?;
+        assert!(state.find_user_by_id(bot.id).await?.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn incoming_webhook_should_disable_and_regenerate() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        let other = state.find_user_by_id(2).await?.unwrap();
+        let input = CreateIncomingWebhook {
+            name: "Alerts".to_string(),
+        };
+        let created = state.create_incoming_webhook(1, &user, input).await?;
+        let id = created.webhook.id as u64;
+
+        // only the creator or admins of the chat manage it
+        let disable = UpdateIncomingWebhook {
+            name: None,
+            disabled: Some(true),
+        };
+        assert!(state
+            .update_incoming_webhook(1, id, &other, disable.clone())
+            .await
+            .is_err());
+        sqlx::query("update chat_members set role = 'admin' where chat_id = 1 and user_id = 2")
+            .execute(&state.pool)
+            .await?;
+        let webhook = state
+            .update_incoming_webhook(1, id, &other, disable)
+            .await?;
+        assert!(webhook.disabled_at.is_some());
+        let ret = state.verify_incoming_webhook(id, secret_of(&created)).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        let enable = UpdateIncomingWebhook {
+            name: Some("Pager".to_string()),
+            disabled: Some(false),
+        };
+        let webhook = state.update_incoming_webhook(1, id, &user, enable).await?;
+        assert_eq!(webhook.name, "Pager");
+        assert!(webhook.disabled_at.is_none());
+
+        let regenerated = state.regenerate_incoming_webhook(1, id, &user).await?;
+        assert!(state
+            .verify_incoming_webhook(id, secret_of(&created))
+            .await
+            .is_err());
+        state
+            .verify_incoming_webhook(id, secret_of(&regenerated))
+            .await?;
+
+        state.delete_incoming_webhook(1, id, &user).await?;
+        assert!(state.list_incoming_webhooks(1).await?.is_empty());
+        // it never posted, so its bot is gone with it
+        assert!(state.find_user_by_id(webhook.bot_id).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn incoming_webhook_should_be_rate_limited() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        for _ in 0..DEFAULT_WEBHOOK_QUOTA.requests {
+            assert_eq!(state.incoming_webhook_retry_after(1).await?, None);
+        }
+        assert!(state.incoming_webhook_retry_after(1).await?.is_some());
+        // other hooks have their own bucket
+        assert_eq!(state.incoming_webhook_retry_after(2).await?, None);
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
 use crate::{AppError, AppState};
 
-use chat_core::Workspace;
+use chat_core::{User, Workspace};
 
 impl AppState {
     pub async fn create_workspace(&self, name: &str, user_id: u64) -> Result<Workspace, AppError> {
@@ -48,6 +48,16 @@ impl AppState {
         .await?;
         Ok(ws)
     }
+    /// Make sure the user is the owner of their workspace
+    pub async fn verify_workspace_owner(&self, user: &User) -> Result<(), AppError> {
+        match self.find_workspace_by_id(user.ws_id as _).await? {
+            Some(ws) if ws.owner_id == user.id => Ok(()),
+            _ => Err(AppError::PermissionDenied(format!(
+                "User {} is not the owner of workspace {}",
+                user.id, user.ws_id
+            ))),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -75,6 +85,17 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn verify_workspace_owner_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        assert!(state.verify_workspace_owner(&user).await.is_err());
+
+        state.update_workspace_owner(1, 1).await?;
+        assert!(state.verify_workspace_owner(&user).await.is_ok());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn workspace_should_find_by_name() -> anyhow::Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
@@ -1,5 +1,7 @@
 use axum::Router;
-use chat_core::{Chat, ChatType, ChatUser, Message, User, Workspace};
+use chat_core::{
+    Attachment, Chat, ChatType, ChatUser, Message, MessageFormat, TokenScope, User, Workspace,
+};
 use utoipa::{
     openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
     Modify, OpenApi,
@@ -9,7 +11,19 @@ use utoipa_redoc::{Redoc, Servable};
 use utoipa_swagger_ui::SwaggerUi;
 
 use crate::{
-    handlers::*, CreateChat, CreateMessage, CreateUser, ListMessages, SigninUser, UpdateChat,
+    handlers::*, AccessToken, Bookmark, ChangeEmail, ChangePassword, ChatMemberRole,
+    ChatPreferences, CommandInvocation, CommandResponse, CommandResponseType, ConfirmEmail,
+    CreateAccessToken, CreateAttachment, CreateBookmark, CreateBot, CreateChat,
+    CreateIncomingWebhook, CreateMessage, CreatePoll, CreateScheduledMessage, CreateUser,
+    CreateWebhookSubscription, CreatedAccessToken, CreatedCommand, CreatedIncomingWebhook,
+    CreatedWebhookSubscription, DisableTwoFactor, ExportFormat, ForgotPassword, ImportSkipped,
+    ImportSummary, IncomingWebhook, IncomingWebhookPayload, ListChats, ListMessages,
+    ListWebhookDeliveries, NotificationLevel, PinnedMessage, Poll, PollOption, RegisterCommand,
+    RegisteredCommand, ResetPassword, RetentionPolicy, ScheduledMessage, ScheduledMessageStatus,
+    SigninAttempt, SigninOutcome, SigninUser, SlashCommand, TwoFactorChallenge, TwoFactorCode,
+    TwoFactorEnrollment, TwoFactorSignin, UnvotePoll, UpdateChat, UpdateChatMember,
+    UpdateChatPreferences, UpdateIncomingWebhook, UpdateProfile, VerifyEmail, VotePoll,
+    WebhookDelivery, WebhookDeliveryStatus, WebhookSubscription, WorkspaceTwoFactor,
 };
 use crate::{AppState, ErrorOutput};
 
@@ -28,12 +42,90 @@ pub(crate) trait OpenApiRouter {
         get_chat_handler,
         list_message_handler,
         update_chat_handler,
+        archive_chat_handler,
+        unarchive_chat_handler,
+        update_chat_member_handler,
         delete_chat_handler,
         upload_handler,
         file_handler,
+        list_pins_handler,
+        pin_message_handler,
+        unpin_message_handler,
+        get_chat_preferences_handler,
+        update_chat_preferences_handler,
+        list_bookmarks_handler,
+        create_bookmark_handler,
+        delete_bookmark_handler,
+        list_scheduled_messages_handler,
+        create_scheduled_message_handler,
+        cancel_scheduled_message_handler,
+        get_workspace_retention_handler,
+        update_workspace_retention_handler,
+        get_chat_retention_handler,
+        update_chat_retention_handler,
+        get_workspace_chat_retention_handler,
+        update_workspace_chat_retention_handler,
+        export_chat_handler,
+        import_slack_handler,
+        get_profile_handler,
+        update_profile_handler,
+        change_password_handler,
+        change_email_handler,
+        confirm_email_handler,
+        forgot_password_handler,
+        reset_password_handler,
+        verify_email_handler,
+        enroll_two_factor_handler,
+        enable_two_factor_handler,
+        disable_two_factor_handler,
+        signin_two_factor_handler,
+        get_workspace_two_factor_handler,
+        update_workspace_two_factor_handler,
+        oidc_login_handler,
+        oidc_callback_handler,
+        list_signin_attempts_handler,
+        unlock_user_handler,
+        create_access_token_handler,
+        list_access_tokens_handler,
+        revoke_access_token_handler,
+        create_bot_handler,
+        list_bots_handler,
+        create_incoming_webhook_handler,
+        list_incoming_webhooks_handler,
+        update_incoming_webhook_handler,
+        regenerate_incoming_webhook_handler,
+        delete_incoming_webhook_handler,
+        post_incoming_webhook_handler,
+        create_webhook_subscription_handler,
+        list_webhook_subscriptions_handler,
+        delete_webhook_subscription_handler,
+        list_webhook_deliveries_handler,
+        redeliver_webhook_handler,
+        list_commands_handler,
+        register_command_handler,
+        delete_command_handler,
+        create_poll_handler,
+        get_poll_handler,
+        vote_poll_handler,
+        unvote_poll_handler,
+        close_poll_handler,
     ),
-    components(schemas(User, Chat, ChatType, ChatUser, Message, Workspace,
-        SigninUser, CreateUser, AuthOutput, ErrorOutput, CreateChat, CreateMessage, ListMessages, UpdateChat)),
+    components(schemas(User, Chat, ChatType, ChatUser, Message, MessageFormat, Attachment, Workspace,
+        SigninUser, CreateUser, AuthOutput, SignupPending, ErrorOutput, CreateChat, CreateMessage, CreateAttachment, ListChats, ListMessages, UpdateChat,
+        ChatMemberRole, UpdateChatMember,
+        PinnedMessage, ChatPreferences, NotificationLevel, UpdateChatPreferences,
+        Bookmark, CreateBookmark, ScheduledMessage, ScheduledMessageStatus,
+        CreateScheduledMessage, RetentionPolicy, ExportFormat, ImportSummary, ImportSkipped,
+        UpdateProfile, ChangePassword, ChangeEmail, ConfirmEmail, ForgotPassword, ResetPassword,
+        VerifyEmail, TwoFactorEnrollment, TwoFactorCode, TwoFactorEnabled, DisableTwoFactor,
+        TwoFactorChallenge, TwoFactorSignin, WorkspaceTwoFactor, SigninAttempt, SigninOutcome,
+        TokenScope, CreateAccessToken, AccessToken, CreatedAccessToken, CreateBot,
+        CreateIncomingWebhook, UpdateIncomingWebhook, IncomingWebhook, CreatedIncomingWebhook,
+        IncomingWebhookPayload, CreateWebhookSubscription, WebhookSubscription,
+        CreatedWebhookSubscription, WebhookDelivery, WebhookDeliveryStatus,
+        ListWebhookDeliveries, SlashCommand, RegisterCommand, RegisteredCommand, CreatedCommand,
+        CommandInvocation, CommandResponse, CommandResponseType, CreatePoll, VotePoll, UnvotePoll,
+        Poll, PollOption)),
     modifiers(&SecurityAddon),
     tags((name="chat", description="Chat operations")),
 )]
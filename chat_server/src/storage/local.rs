@@ -0,0 +1,69 @@
+use std::{io::ErrorKind, path::PathBuf};
+
+use tokio::fs;
+
+use super::FileStorage;
+use crate::AppError;
+
+/// Files under `base_dir`, only for a single instance of chat server
+pub(crate) struct LocalStorage {
+    base_dir: PathBuf,
+}
+
+impl LocalStorage {
+    pub(crate) async fn try_new(base_dir: PathBuf) -> Result<Self, AppError> {
+        fs::create_dir_all(&base_dir).await?;
+        Ok(Self { base_dir })
+    }
+}
+
+impl FileStorage for LocalStorage {
+    async fn put(&self, key: &str, data: &[u8]) -> Result<(), AppError> {
+        let path = self.base_dir.join(key);
+        fs::create_dir_all(path.parent().expect("file path parent should exists")).await?;
+        fs::write(path, data).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, AppError> {
+        match fs::read(self.base_dir.join(key)).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, AppError> {
+        Ok(fs::try_exists(self.base_dir.join(key)).await?)
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool, AppError> {
+        match fs::remove_file(self.base_dir.join(key)).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn local_storage_should_work() -> anyhow::Result<()> {
+        let base_dir = std::env::temp_dir().join("chat_local_storage");
+        let storage = LocalStorage::try_new(base_dir.clone()).await?;
+        let key = "1/abc/def/0123.txt";
+        storage.put(key, b"hello").await?;
+        assert!(base_dir.join(key).exists());
+        assert!(storage.exists(key).await?);
+        assert_eq!(storage.get(key).await?, Some(b"hello".to_vec()));
+
+        assert!(storage.delete(key).await?);
+        assert!(!storage.delete(key).await?);
+        assert!(!storage.exists(key).await?);
+        assert_eq!(storage.get(key).await?, None);
+        Ok(())
+    }
+}
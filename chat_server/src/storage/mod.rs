@@ -0,0 +1,77 @@
+mod local;
+mod s3;
+
+use std::future::Future;
+
+pub(crate) use local::LocalStorage;
+pub(crate) use s3::S3Storage;
+
+use crate::{config::StorageConfig, AppConfig, AppError};
+
+/// Where uploaded files live. Keys are the paths of `ChatFile::hash_to_path`, e.g.
+/// `1/a94/a8f/e5ccb19ba61c4c0873d391e987982fbbd3.txt`.
+pub(crate) trait FileStorage {
+    /// Store the content under the key, replacing what is there
+    fn put(&self, key: &str, data: &[u8]) -> impl Future<Output = Result<(), AppError>> + Send;
+    /// The content, none if nothing is stored under the key
+    fn get(&self, key: &str) -> impl Future<Output = Result<Option<Vec<u8>>, AppError>> + Send;
+    fn exists(&self, key: &str) -> impl Future<Output = Result<bool, AppError>> + Send;
+    /// Whether there was something to remove
+    fn delete(&self, key: &str) -> impl Future<Output = Result<bool, AppError>> + Send;
+}
+
+/// The storage backend chosen in the config
+pub(crate) enum Storage {
+    Local(LocalStorage),
+    S3(S3Storage),
+}
+
+impl Storage {
+    pub(crate) async fn try_new(config: &AppConfig) -> Result<Self, AppError> {
+        let storage = match &config.storage {
+            StorageConfig::Local => {
+                Self::Local(LocalStorage::try_new(config.server.base_dir.clone()).await?)
+            }
+            StorageConfig::S3 {
+                endpoint,
+                region,
+                bucket,
+                access_key,
+                secret_key,
+            } => Self::S3(S3Storage::try_new(
+                endpoint, region, bucket, access_key, secret_key,
+            )?),
+        };
+        Ok(storage)
+    }
+}
+
+impl FileStorage for Storage {
+    async fn put(&self, key: &str, data: &[u8]) -> Result<(), AppError> {
+        match self {
+            Self::Local(storage) => storage.put(key, data).await,
+            Self::S3(storage) => storage.put(key, data).await,
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, AppError> {
+        match self {
+            Self::Local(storage) => storage.get(key).await,
+            Self::S3(storage) => storage.get(key).await,
+        }
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, AppError> {
+        match self {
+            Self::Local(storage) => storage.exists(key).await,
+            Self::S3(storage) => storage.exists(key).await,
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool, AppError> {
+        match self {
+            Self::Local(storage) => storage.delete(key).await,
+            Self::S3(storage) => storage.delete(key).await,
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::{header::AUTHORIZATION, Method, Response, StatusCode, Url};
+use sha2::{Digest, Sha256};
+
+use super::FileStorage;
+use crate::AppError;
+
+const REQUEST_TIMEOUT_SECS: u64 = 60;
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+const MAX_ERROR_LEN: usize = 256;
+
+/// Objects in a bucket of an S3-compatible service, e.g. AWS S3 or MinIO. Requests address the
+/// bucket in the path and are signed with AWS signature version 4.
+pub(crate) struct S3Storage {
+    client: reqwest::Client,
+    endpoint: Url,
+    region: String,
+    bucket: String,
+    access_key: String,
+    secret_key: String,
+}
+
+impl S3Storage {
+    pub(crate) fn try_new(
+        endpoint: &str,
+        region: &str,
+        bucket: &str,
+        access_key: &str,
+        secret_key: &str,
+    ) -> Result<Self, AppError> {
+        let endpoint = match Url::parse(endpoint) {
+            Ok(url) if url.has_host() && matches!(url.scheme(), "http" | "https") => url,
+            _ => {
+                return Err(AppError::StorageError(format!(
+                    "invalid s3 endpoint {}",
+                    endpoint
+                )))
+            }
+        };
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .build()
+            .map_err(|e| AppError::StorageError(e.to_string()))?;
+        Ok(Self {
+            client,
+            endpoint,
+            region: region.to_string(),
+            bucket: bucket.to_string(),
+            access_key: access_key.to_string(),
+            secret_key: secret_key.to_string(),
+        })
+    }
+
+    /// The url of an object, under the path of the endpoint, e.g. behind a proxy
+    fn object_url(&self, key: &str) -> Result<Url, AppError> {
+        // urls resolve these, the request would address another object than the key
+        if key
+            .split('/')
+            .any(|segment| matches!(segment, "" | "." | ".."))
+        {
+            return Err(AppError::StorageError(format!("invalid key {}", key)));
+        }
+        let mut url = self.endpoint.clone();
+        url.set_path(&format!(
+            "{}/{}/{}",
+            self.endpoint.path().trim_end_matches('/'),
+            self.bucket,
+            uri_encode(key)
+        ));
+        Ok(url)
+    }
+
+    async fn send(&self, method: Method, key: &str, body: &[u8]) -> Result<Response, AppError> {
+        let url = self.object_url(key)?;
+        let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let payload_hash = hex::encode(Sha256::digest(body));
+        let authorization = self.authorization(
+            method.as_str(),
+            url.path(),
+            &host(&url),
+            &amz_date,
+            &payload_hash,
+        );
+        self.client
+            .request(method, url)
+            .header("x-amz-date", amz_date)
+            .header("x-amz-content-sha256", payload_hash)
+            .header(AUTHORIZATION, authorization)
+            .body(body.to_vec())
+            .send()
+            .await
+            .map_err(|e| AppError::StorageError(e.to_string()))
+    }
+
+    /// The `Authorization` header of a request without query, signing the headers it sends
+    fn authorization(
+        &self,
+        method: &str,
+        path: &str,
+        host: &str,
+        amz_date: &str,
+        payload_hash: &str,
+    ) -> String {
+        let date = &amz_date[..8];
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let canonical_request = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method, path, host, payload_hash, amz_date, SIGNED_HEADERS, payload_hash
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = signing_key(&self.secret_key, date, &self.region, "s3");
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key,
+            scope,
+            SIGNED_HEADERS,
+            hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()))
+        )
+    }
+}
+
+impl FileStorage for S3Storage {
+    async fn put(&self, key: &str, data: &[u8]) -> Result<(), AppError> {
+        let res = self.send(Method::PUT, key, data).await?;
+        check_status(key, res).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, AppError> {
+        let res = self.send(Method::GET, key, b"").await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let data = check_status(key, res)
+            .await?
+            .bytes()
+            .await
+            .map_err(|e| AppError::StorageError(e.to_string()))?;
+        Ok(Some(data.to_vec()))
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, AppError> {
+        let res = self.send(Method::HEAD, key, b"").await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        check_status(key, res).await?;
+        Ok(true)
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool, AppError> {
+        // deleting a missing object succeeds as well, so ask first
+        if !self.exists(key).await? {
+            return Ok(false);
+        }
+        let res = self.send(Method::DELETE, key, b"").await?;
+        check_status(key, res).await?;
+        Ok(true)
+    }
+}
+
+async fn check_status(key: &str, res: Response) -> Result<Response, AppError> {
+    if res.status().is_success() {
+        return Ok(res);
+    }
+    let status = res.status();
+    let body = res.text().await.unwrap_or_default();
+    let body: String = body.chars().take(MAX_ERROR_LEN).collect();
+    Err(AppError::StorageError(format!(
+        "s3 request for {} failed with {}: {}",
+        key, status, body
+    )))
+}
+
+/// `Host` as reqwest sends it, with the port only if it is not the default of the scheme
+fn host(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    }
+}
+
+/// Percent encode a key the way the canonical request expects it, keeping the `/` between
+/// segments
+fn uri_encode(key: &str) -> String {
+    let mut encoded = String::with_capacity(key.len());
+    for b in key.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac_sha256(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac takes keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+    use anyhow::Result;
+    use axum::{
+        body::Bytes,
+        extract::State,
+        http::{HeaderMap, Uri},
+        Router,
+    };
+    use tokio::net::TcpListener;
+
+    /// A bucket of a MinIO-like service, it checks the signature of every request
+    #[derive(Clone)]
+    struct StandIn {
+        signer: Arc<S3Storage>,
+        objects: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    }
+
+    #[test]
+    fn signing_key_should_match_aws_example() {
+        // from the AWS documentation on deriving the signing key
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex::encode(key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[test]
+    fn object_url_should_keep_endpoint_path() -> Result<()> {
+        let storage = S3Storage::try_new("http://localhost:9000", "local", "chat", "k", "s")?;
+        assert_eq!(
+            storage.object_url("1/a94/a8f/e5cc.txt")?.as_str(),
+            "http://localhost:9000/chat/1/a94/a8f/e5cc.txt"
+        );
+        let storage = S3Storage::try_new("https://acme.org/s3/", "local", "chat", "k", "s")?;
+        assert_eq!(
+            storage.object_url("1/a94/a8f/e5cc.txt")?.as_str(),
+            "https://acme.org/s3/chat/1/a94/a8f/e5cc.txt"
+        );
+        for key in ["1/../../other/key", "./key", "1//key"] {
+            assert!(storage.object_url(key).is_err(), "{}", key);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn uri_encode_should_keep_slashes() {
+        assert_eq!(uri_encode("1/a94/a8f/e5cc.txt"), "1/a94/a8f/e5cc.txt");
+        assert_eq!(
+            uri_encode("1/a94/a8f/e5cc.tar gz"),
+            "1/a94/a8f/e5cc.tar%20gz"
+        );
+        assert_eq!(uri_encode("1/ü"), "1/%C3%BC");
+    }
+
+    #[tokio::test]
+    async fn s3_storage_should_work() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let endpoint = format!("http://{}", listener.local_addr()?);
+        let stand_in = StandIn {
+            signer: Arc::new(S3Storage::try_new(
+                &endpoint, "local", "chat", "minio", "minio123",
+            )?),
+            objects: Default::default(),
+        };
+        let app = Router::new().fallback(object).with_state(stand_in);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let storage = S3Storage::try_new(&endpoint, "local", "chat", "minio", "minio123")?;
+        let key = "1/a94/a8f/e5cc.tar gz";
+        storage.put(key, b"hello").await?;
+        assert!(storage.exists(key).await?);
+        assert_eq!(storage.get(key).await?, Some(b"hello".to_vec()));
+        assert!(storage.delete(key).await?);
+        assert!(!storage.delete(key).await?);
+        assert_eq!(storage.get(key).await?, None);
+
+        let wrong = S3Storage::try_new(&endpoint, "local", "chat", "minio", "wrong")?;
+        assert!(wrong.put(key, b"hello").await.is_err());
+        Ok(())
+    }
+
+    async fn object(
+        State(stand_in): State<StandIn>,
+        method: Method,
+        uri: Uri,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> (StatusCode, Vec<u8>) {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let payload_hash = header("x-amz-content-sha256");
+        let expected = stand_in.signer.authorization(
+            method.as_str(),
+            uri.path(),
+            &header("host"),
+            &header("x-amz-date"),
+            &payload_hash,
+        );
+        if header("authorization") != expected || payload_hash != hex::encode(Sha256::digest(&body))
+        {
+            return (StatusCode::FORBIDDEN, vec![]);
+        }
+        let mut objects = stand_in.objects.lock().unwrap();
+        let key = uri.path().to_string();
+        match method {
+            Method::PUT => {
+                objects.insert(key, body.to_vec());
+                (StatusCode::OK, vec![])
+            }
+            Method::GET | Method::HEAD => match objects.get(&key) {
+                Some(data) => (StatusCode::OK, data.clone()),
+                None => (StatusCode::NOT_FOUND, vec![]),
+            },
+            Method::DELETE => {
+                objects.remove(&key);
+                (StatusCode::NO_CONTENT, vec![])
+            }
+            _ => (StatusCode::METHOD_NOT_ALLOWED, vec![]),
+        }
+    }
+}
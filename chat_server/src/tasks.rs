@@ -0,0 +1,80 @@
+use std::{future::Future, time::Duration};
+
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+
+use crate::{AppError, AppState};
+
+const BOOKMARK_REMINDER_INTERVAL: Duration = Duration::from_secs(10);
+const SCHEDULED_MESSAGE_INTERVAL: Duration = Duration::from_secs(1);
+const RETENTION_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const EPHEMERAL_REAPER_INTERVAL: Duration = Duration::from_secs(5);
+const POLL_CLOSE_INTERVAL: Duration = Duration::from_secs(5);
+const FILE_GC_INTERVAL: Duration = Duration::from_secs(60 * 10);
+const SIGNIN_ATTEMPT_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Spawn the periodic background jobs of chat server, they run until the process exits
+pub fn spawn_background_tasks(state: AppState) {
+    spawn_periodic(
+        "bookmark reminder",
+        BOOKMARK_REMINDER_INTERVAL,
+        state.clone(),
+        |state| async move { state.send_due_bookmark_reminders().await },
+    );
+    spawn_periodic(
+        "scheduled message",
+        SCHEDULED_MESSAGE_INTERVAL,
+        state.clone(),
+        |state| async move { state.deliver_scheduled_messages().await },
+    );
+    spawn_periodic(
+        "ephemeral message reaper",
+        EPHEMERAL_REAPER_INTERVAL,
+        state.clone(),
+        |state| async move { state.reap_expired_messages().await },
+    );
+    spawn_periodic(
+        "poll closer",
+        POLL_CLOSE_INTERVAL,
+        state.clone(),
+        |state| async move { state.close_due_polls().await },
+    );
+    spawn_periodic(
+        "file garbage collection",
+        FILE_GC_INTERVAL,
+        state.clone(),
+        |state| async move { state.collect_unreferenced_files().await },
+    );
+    spawn_periodic(
+        "signin attempt pruning",
+        SIGNIN_ATTEMPT_PRUNE_INTERVAL,
+        state.clone(),
+        |state| async move { state.prune_signin_attempts().await },
+    );
+    spawn_periodic(
+        "retention purge",
+        RETENTION_PURGE_INTERVAL,
+        state,
+        |state| async move { Ok(state.purge_expired_messages().await?.messages) },
+    );
+}
+
+/// Run `f` every `period`, the job returns how many items it processed
+fn spawn_periodic<F, Fut>(name: &'static str, period: Duration, state: AppState, f: F)
+where
+    F: Fn(AppState) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<u64, AppError>> + Send,
+{
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            match f(state.clone()).await {
+                Ok(0) => {}
+                Ok(n) => info!("{}: processed {} items", name, n),
+                Err(e) => warn!("{} failed: {}", name, e),
+            }
+        }
+    });
+}
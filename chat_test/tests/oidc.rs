@@ -0,0 +1,254 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Redirect},
+    routing::{get, post},
+    Form, Json, Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chat_core::User;
+use chat_server::{AppConfig, AppState, OidcConfig};
+use jwt_simple::prelude::*;
+use reqwest::{header, redirect::Policy};
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+
+const CLIENT_ID: &str = "chat";
+const CLIENT_SECRET: &str = "chat-secret";
+const KEY_ID: &str = "test-key";
+
+/// A minimal identity provider, it signs in whoever `identity` is
+#[derive(Clone)]
+struct MockIdp {
+    issuer: String,
+    key: Arc<RS256KeyPair>,
+    identity: Arc<Mutex<Value>>,
+    // code -> nonce of the authorization request
+    codes: Arc<Mutex<HashMap<String, String>>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthToken {
+    token: String,
+}
+
+#[tokio::test]
+async fn oidc_login_should_provision_and_link_users() -> Result<()> {
+    let idp = MockIdp::start().await?;
+    let chat_listener = TcpListener::bind("127.0.0.1:0").await?;
+    let chat_addr = chat_listener.local_addr()?;
+
+    let mut config = AppConfig::load()?;
+    config.oidc = Some(OidcConfig {
+        issuer: idp.issuer.clone(),
+        client_id: CLIENT_ID.to_string(),
+        client_secret: CLIENT_SECRET.to_string(),
+        redirect_url: format!("http://{}/api/oidc/callback", chat_addr),
+        scopes: vec!["openid".to_string(), "email".to_string()],
+        post_login_url: None,
+        workspace_claim: Some("org".to_string()),
+        domain_workspaces: HashMap::new(),
+        default_workspace: None,
+        trust_email: false,
+    });
+    let (_tdb, state) = AppState::new_for_test_with(config).await?;
+    let app = chat_server::get_router(state).await?;
+    tokio::spawn(async move {
+        axum::serve(chat_listener, app.into_make_service())
+            .await
+            .unwrap();
+    });
+    let client = reqwest::Client::builder()
+        .redirect(Policy::none())
+        .build()?;
+
+    // a new identity is provisioned into the workspace from the claim
+    idp.sign_in_as(json!({
+        "sub": "alice-1",
+        "email": "alice@acme.org",
+        "email_verified": true,
+        "name": "Alice",
+        "org": "acme",
+    }));
+    let token = login(&client, chat_addr).await?;
+    let alice = me(&client, chat_addr, &token).await?;
+    assert_eq!(alice.email, "alice@acme.org");
+    assert_eq!(alice.fullname, "Alice");
+    assert_eq!(alice.ws_name, "acme");
+
+    // the same identity signs in as the same user
+    let token = login(&client, chat_addr).await?;
+    assert_eq!(me(&client, chat_addr, &token).await?.id, alice.id);
+
+    // a verified email links the existing account
+    idp.sign_in_as(json!({
+        "sub": "zzq-1",
+        "email": "zzq@zzq.com",
+        "email_verified": true,
+    }));
+    let token = login(&client, chat_addr).await?;
+    assert_eq!(me(&client, chat_addr, &token).await?.id, 1);
+
+    // but an unverified one does not
+    idp.sign_in_as(json!({
+        "sub": "mallory-1",
+        "email": "zzq@zzq.com",
+        "email_verified": false,
+    }));
+    let res = callback(&client, chat_addr).await?;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    // the callback only works in the browser that started the login
+    let res = client
+        .get(format!("http://{}/api/oidc/login", chat_addr))
+        .send()
+        .await?;
+    let location = res.headers()[header::LOCATION].to_str()?.to_string();
+    let res = client.get(location).send().await?;
+    let callback_url = res.headers()[header::LOCATION].to_str()?.to_string();
+    let res = client.get(callback_url).send().await?;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    Ok(())
+}
+
+/// Run the browser side of the flow, returning the callback response
+async fn callback(client: &reqwest::Client, chat_addr: SocketAddr) -> Result<reqwest::Response> {
+    let res = client
+        .get(format!("http://{}/api/oidc/login", chat_addr))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::FOUND);
+    let cookie = res.headers()[header::SET_COOKIE].to_str()?;
+    let cookie = cookie.split(';').next().unwrap().to_string();
+    let location = res.headers()[header::LOCATION].to_str()?.to_string();
+
+    let res = client.get(location).send().await?;
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    let callback_url = res.headers()[header::LOCATION].to_str()?.to_string();
+    Ok(client
+        .get(callback_url)
+        .header(header::COOKIE, cookie)
+        .send()
+        .await?)
+}
+
+async fn login(client: &reqwest::Client, chat_addr: SocketAddr) -> Result<String> {
+    let res = callback(client, chat_addr).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let ret: AuthToken = res.json().await?;
+    Ok(ret.token)
+}
+
+async fn me(client: &reqwest::Client, chat_addr: SocketAddr, token: &str) -> Result<User> {
+    let res = client
+        .get(format!("http://{}/api/me", chat_addr))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    Ok(res.json().await?)
+}
+
+impl MockIdp {
+    async fn start() -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let idp = Self {
+            issuer: format!("http://{}", listener.local_addr()?),
+            key: Arc::new(RS256KeyPair::generate(2048)?.with_key_id(KEY_ID)),
+            identity: Arc::new(Mutex::new(Value::Null)),
+            codes: Arc::new(Mutex::new(HashMap::new())),
+        };
+        let app = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/authorize", get(authorize))
+            .route("/token", post(token))
+            .route("/jwks", get(jwks))
+            .with_state(idp.clone());
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service())
+                .await
+                .unwrap();
+        });
+        Ok(idp)
+    }
+
+    fn sign_in_as(&self, identity: Value) {
+        *self.identity.lock().unwrap() = identity;
+    }
+}
+
+async fn discovery(State(idp): State<MockIdp>) -> impl IntoResponse {
+    Json(json!({
+        "issuer": idp.issuer,
+        "authorization_endpoint": format!("{}/authorize", idp.issuer),
+        "token_endpoint": format!("{}/token", idp.issuer),
+        "jwks_uri": format!("{}/jwks", idp.issuer),
+    }))
+}
+
+async fn authorize(
+    State(idp): State<MockIdp>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    assert_eq!(params["client_id"], CLIENT_ID);
+    assert_eq!(params["response_type"], "code");
+    let mut codes = idp.codes.lock().unwrap();
+    let code = format!("code-{}", codes.len());
+    codes.insert(code.clone(), params["nonce"].clone());
+    Redirect::to(&format!(
+        "{}?code={}&state={}",
+        params["redirect_uri"], code, params["state"]
+    ))
+}
+
+async fn token(
+    State(idp): State<MockIdp>,
+    Form(params): Form<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if params.get("client_secret").map(String::as_str) != Some(CLIENT_SECRET) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({"error": "invalid_client"})),
+        );
+    }
+    let Some(nonce) = idp.codes.lock().unwrap().remove(&params["code"]) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "invalid_grant"})),
+        );
+    };
+    let mut identity = idp.identity.lock().unwrap().clone();
+    let subject = identity["sub"].as_str().unwrap().to_string();
+    identity.as_object_mut().unwrap().remove("sub");
+    let claims = Claims::with_custom_claims(identity, Duration::from_mins(5))
+        .with_issuer(&idp.issuer)
+        .with_audience(CLIENT_ID)
+        .with_subject(subject)
+        .with_nonce(nonce);
+    let id_token = idp.key.sign(claims).unwrap();
+    (
+        StatusCode::OK,
+        Json(json!({"access_token": "unused", "token_type": "Bearer", "id_token": id_token})),
+    )
+}
+
+async fn jwks(State(idp): State<MockIdp>) -> impl IntoResponse {
+    let components = idp.key.public_key().to_components();
+    Json(json!({
+        "keys": [{
+            "kty": "RSA",
+            "kid": KEY_ID,
+            "alg": "RS256",
+            "use": "sig",
+            "n": URL_SAFE_NO_PAD.encode(components.n),
+            "e": URL_SAFE_NO_PAD.encode(components.e),
+        }]
+    }))
+}
@@ -0,0 +1,212 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use axum::{body::Bytes, extract::State, http::HeaderMap, routing::post, Router};
+use hmac::{Hmac, Mac};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use tokio::{
+    net::TcpListener,
+    sync::mpsc,
+    time::{sleep, timeout},
+};
+
+const WILD_ADDR: &str = "127.0.0.1:0";
+
+#[derive(Debug, Deserialize)]
+struct AuthToken {
+    token: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Subscription {
+    id: i64,
+    secret: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Delivery {
+    id: i64,
+    attempts: i32,
+}
+
+type Received = mpsc::UnboundedReceiver<(HeaderMap, String)>;
+
+/// A service receiving webhooks, it fails until `healthy` is set
+#[derive(Clone)]
+struct Receiver {
+    healthy: Arc<AtomicBool>,
+    tx: mpsc::UnboundedSender<(HeaderMap, String)>,
+}
+
+#[tokio::test]
+async fn webhooks_should_be_signed_retried_and_redelivered() -> Result<()> {
+    let (tdb, state) = chat_server::AppState::new_for_test().await?;
+    let chat_addr = serve(chat_server::get_router(state).await?).await?;
+    let (good_addr, _, mut good) = Receiver::start(true).await?;
+    let (bad_addr, healthy, mut bad) = Receiver::start(false).await?;
+
+    let client = reqwest::Client::new();
+    let token = signin(&client, chat_addr).await?;
+    let good_sub = subscribe(&client, chat_addr, &token, good_addr).await?;
+    let bad_sub = subscribe(&client, chat_addr, &token, bad_addr).await?;
+
+    let mut config = notify_server::AppConfig::load()?;
+    config.server.db_url = tdb.url();
+    config.webhooks.max_attempts = 2;
+    config.webhooks.retry_base_secs = 1;
+    config.webhooks.poll_secs = 1;
+    config.outbound.allow = vec!["localhost".to_string()];
+    serve(notify_server::get_router(config).await?).await?;
+
+    let res = client
+        .post(format!("http://{}/api/chats/1", chat_addr))
+        .bearer_auth(&token)
+        .json(&json!({"content": "deploy done"}))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::CREATED);
+
+    // the healthy receiver gets the event signed with its secret
+    let (headers, body) = timeout(Duration::from_secs(10), good.recv())
+        .await?
+        .unwrap();
+    assert_eq!(headers["x-webhook-event"], "NewMessage");
+    let timestamp = headers["x-webhook-timestamp"].to_str()?;
+    let signature = headers["x-webhook-signature"].to_str()?;
+    assert_eq!(signature, sign(&good_sub.secret, timestamp, &body));
+    let event: Value = serde_json::from_str(&body)?;
+    assert_eq!(event["event"], "NewMessage");
+    assert_eq!(event["content"], "deploy done");
+
+    // the failing one is retried until the delivery is dead
+    for _ in 0..2 {
+        timeout(Duration::from_secs(10), bad.recv()).await?.unwrap();
+    }
+    let url = format!(
+        "http://{}/api/webhooks/{}/deliveries",
+        chat_addr, bad_sub.id
+    );
+    let dead = wait_for_delivery(&client, &url, &token, "dead").await?;
+    assert_eq!(dead.attempts, 2);
+
+    // and sent again once redelivered
+    healthy.store(true, Ordering::SeqCst);
+    let res = client
+        .post(format!("{}/{}/redeliver", url, dead.id))
+        .bearer_auth(&token)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+    timeout(Duration::from_secs(10), bad.recv()).await?.unwrap();
+    let delivered = wait_for_delivery(&client, &url, &token, "delivered").await?;
+    assert_eq!(delivered.id, dead.id);
+    Ok(())
+}
+
+impl Receiver {
+    async fn start(healthy: bool) -> Result<(SocketAddr, Arc<AtomicBool>, Received)> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let receiver = Self {
+            healthy: Arc::new(AtomicBool::new(healthy)),
+            tx,
+        };
+        let healthy = receiver.healthy.clone();
+        let app = Router::new().route("/", post(receive)).with_state(receiver);
+        let addr = serve(app).await?;
+        Ok((addr, healthy, rx))
+    }
+}
+
+async fn receive(State(receiver): State<Receiver>, headers: HeaderMap, body: Bytes) -> StatusCode {
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    receiver.tx.send((headers, body)).unwrap();
+    if receiver.healthy.load(Ordering::SeqCst) {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+async fn serve(app: Router) -> Result<SocketAddr> {
+    let listener = TcpListener::bind(WILD_ADDR).await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap();
+    });
+    Ok(addr)
+}
+
+async fn signin(client: &reqwest::Client, chat_addr: SocketAddr) -> Result<String> {
+    let res = client
+        .post(format!("http://{}/api/signin", chat_addr))
+        .json(&json!({"email": "zzq@zzq.com", "password": "123456"}))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let ret: AuthToken = res.json().await?;
+    Ok(ret.token)
+}
+
+async fn subscribe(
+    client: &reqwest::Client,
+    chat_addr: SocketAddr,
+    token: &str,
+    receiver: SocketAddr,
+) -> Result<Subscription> {
+    let res = client
+        .post(format!("http://{}/api/webhooks", chat_addr))
+        .bearer_auth(token)
+        .json(&json!({
+            // a host name, chat_server blocks loopback addresses by default
+            "url": format!("http://localhost:{}/", receiver.port()),
+            "chatId": 1,
+            "events": ["NewMessage"],
+        }))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    Ok(res.json().await?)
+}
+
+fn sign(secret: &str, timestamp: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    format!("v1={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Poll the deliveries of a subscription until one has the status, delivery runs in the background
+async fn wait_for_delivery(
+    client: &reqwest::Client,
+    url: &str,
+    token: &str,
+    status: &str,
+) -> Result<Delivery> {
+    for _ in 0..50 {
+        let deliveries: Vec<Delivery> = client
+            .get(url)
+            .bearer_auth(token)
+            .query(&[("status", status)])
+            .send()
+            .await?
+            .json()
+            .await?;
+        if let Some(delivery) = deliveries.into_iter().next() {
+            return Ok(delivery);
+        }
+        sleep(Duration::from_millis(200)).await;
+    }
+    anyhow::bail!("no {} delivery at {}", status, url)
+}
@@ -0,0 +1,13 @@
+-- Add migration script here
+-- message body format: plain text or markdown
+CREATE TYPE message_format AS ENUM ('plain', 'markdown');
+
+-- sanitized html rendered from content and attachment metadata for files
+ALTER TABLE
+    messages
+ADD
+    COLUMN format message_format NOT NULL DEFAULT 'plain',
+ADD
+    COLUMN html TEXT,
+ADD
+    COLUMN attachments JSONB NOT NULL DEFAULT '[]';
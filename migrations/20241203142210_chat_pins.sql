@@ -0,0 +1,55 @@
+-- Add migration script here
+-- pinned messages per chat
+CREATE TABLE IF NOT EXISTS chat_pins (
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    message_id BIGINT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    pinned_by BIGINT NOT NULL REFERENCES users(id),
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (chat_id, message_id)
+);
+
+-- if a message is pinned or unpinned, notify chat members
+CREATE
+OR REPLACE FUNCTION notify_chat_pins_changed() RETURNS TRIGGER AS $$ DECLARE USERS bigint [];
+
+PIN chat_pins;
+
+BEGIN IF TG_OP = 'DELETE' THEN PIN := OLD;
+
+ELSE PIN := NEW;
+
+END IF;
+
+SELECT
+    members INTO USERS
+FROM
+    chats
+WHERE
+    id = PIN.chat_id;
+
+PERFORM pg_notify(
+    'chat_pins_changed',
+    json_build_object(
+        'chat_id',
+        PIN.chat_id,
+        'message_id',
+        PIN.message_id,
+        'pinned',
+        TG_OP = 'INSERT',
+        'pinned_by',
+        PIN.pinned_by,
+        'members',
+        USERS
+    ) :: text
+);
+
+RETURN PIN;
+
+END;
+
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notify_chat_pins_changed_trigger
+AFTER
+INSERT
+    OR DELETE ON chat_pins FOR EACH ROW EXECUTE FUNCTION notify_chat_pins_changed();
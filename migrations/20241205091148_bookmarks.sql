@@ -0,0 +1,66 @@
+-- Add migration script here
+-- personal saved messages
+CREATE TABLE IF NOT EXISTS bookmarks (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    message_id BIGINT NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    note VARCHAR(256),
+    remind_at timestamptz,
+    reminded_at timestamptz,
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, message_id)
+);
+
+-- create index for bookmarks for user_id order by created_at desc
+CREATE INDEX IF NOT EXISTS bookmarks_user_id_index ON bookmarks(user_id, created_at DESC);
+
+-- create index for pending reminders
+CREATE INDEX IF NOT EXISTS bookmarks_remind_at_index ON bookmarks(remind_at)
+WHERE
+    reminded_at IS NULL;
+
+-- if a bookmark reminder is due, notify its owner
+CREATE
+OR REPLACE FUNCTION notify_bookmark_reminder() RETURNS TRIGGER AS $$ DECLARE CHAT bigint;
+
+BEGIN IF (
+    OLD.reminded_at IS NULL
+    AND NEW.reminded_at IS NOT NULL
+) THEN
+SELECT
+    chat_id INTO CHAT
+FROM
+    messages
+WHERE
+    id = NEW.message_id;
+
+PERFORM pg_notify(
+    'bookmark_reminder',
+    json_build_object(
+        'id',
+        NEW.id,
+        'user_id',
+        NEW.user_id,
+        'chat_id',
+        CHAT,
+        'message_id',
+        NEW.message_id,
+        'note',
+        NEW.note,
+        'remind_at',
+        NEW.remind_at
+    ) :: text
+);
+
+END IF;
+
+RETURN NEW;
+
+END;
+
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notify_bookmark_reminder_trigger
+AFTER
+UPDATE
+    ON bookmarks FOR EACH ROW EXECUTE FUNCTION notify_bookmark_reminder();
@@ -0,0 +1,22 @@
+-- Add migration script here
+-- create scheduled message status type
+CREATE TYPE scheduled_message_status AS ENUM ('pending', 'sent', 'canceled', 'failed');
+
+-- messages written now and posted at send_at
+CREATE TABLE IF NOT EXISTS scheduled_messages (
+    id BIGSERIAL PRIMARY KEY,
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    sender_id BIGINT NOT NULL REFERENCES users(id),
+    -- the CreateMessage payload, delivered as is
+    message JSONB NOT NULL,
+    send_at timestamptz NOT NULL,
+    status scheduled_message_status NOT NULL DEFAULT 'pending',
+    message_id BIGINT REFERENCES messages(id) ON DELETE SET NULL,
+    error TEXT,
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for pending scheduled messages order by send_at
+CREATE INDEX IF NOT EXISTS scheduled_messages_send_at_index ON scheduled_messages(send_at)
+WHERE
+    status = 'pending';
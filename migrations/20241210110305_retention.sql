@@ -0,0 +1,16 @@
+-- Add migration script here
+-- delete messages older than retention_days, chat settings override workspace settings
+-- legal hold keeps everything regardless of retention
+ALTER TABLE
+    workspaces
+ADD
+    COLUMN retention_days INT CHECK (retention_days > 0),
+ADD
+    COLUMN legal_hold BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE
+    chats
+ADD
+    COLUMN retention_days INT CHECK (retention_days > 0),
+ADD
+    COLUMN legal_hold BOOLEAN NOT NULL DEFAULT FALSE;
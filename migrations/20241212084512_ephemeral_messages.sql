@@ -0,0 +1,46 @@
+-- Add migration script here
+-- self-destructing messages, hard deleted once expired
+ALTER TABLE
+    messages
+ADD
+    COLUMN expires_at timestamptz;
+
+-- create index for messages to be reaped
+CREATE INDEX IF NOT EXISTS messages_expires_at_index ON messages(expires_at)
+WHERE
+    expires_at IS NOT NULL;
+
+-- if an ephemeral message is deleted, notify chat members so clients remove it
+CREATE
+OR REPLACE FUNCTION notify_message_deleted() RETURNS TRIGGER AS $$ DECLARE USERS bigint [];
+
+BEGIN
+SELECT
+    members INTO USERS
+FROM
+    chats
+WHERE
+    id = OLD.chat_id;
+
+PERFORM pg_notify(
+    'chat_message_deleted',
+    json_build_object(
+        'chat_id',
+        OLD.chat_id,
+        'message_id',
+        OLD.id,
+        'members',
+        USERS
+    ) :: text
+);
+
+RETURN OLD;
+
+END;
+
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notify_message_deleted_trigger
+AFTER
+    DELETE ON messages FOR EACH ROW
+    WHEN (OLD.expires_at IS NOT NULL) EXECUTE FUNCTION notify_message_deleted();
@@ -0,0 +1,13 @@
+-- Add migration script here
+-- map ids of imported slack objects to local rows, so re-running an import skips them
+CREATE TYPE import_kind AS ENUM ('user', 'chat', 'message');
+
+CREATE TABLE IF NOT EXISTS import_mappings (
+    ws_id BIGINT NOT NULL REFERENCES workspaces(id),
+    kind import_kind NOT NULL,
+    -- slack id, for messages `<channel id>:<ts>`
+    external_id VARCHAR(128) NOT NULL,
+    local_id BIGINT NOT NULL,
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (ws_id, kind, external_id)
+);
@@ -0,0 +1,6 @@
+-- Add migration script here
+-- archived chats are read-only and hidden from the chat list by default
+ALTER TABLE
+    chats
+ADD
+    COLUMN archived_at timestamptz;
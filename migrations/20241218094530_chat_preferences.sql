@@ -0,0 +1,71 @@
+-- Add migration script here
+-- per user chat settings, a row only exists once the user changed something
+CREATE TYPE notification_level AS ENUM ('all', 'mentions', 'none');
+
+CREATE TABLE IF NOT EXISTS chat_members (
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    muted_until timestamptz,
+    notification_level notification_level NOT NULL DEFAULT 'all',
+    -- pinned chats are listed first
+    pinned BOOLEAN NOT NULL DEFAULT FALSE,
+    -- manual position in the chat list, before the chats ordered by last activity
+    sort_order INT,
+    hidden BOOLEAN NOT NULL DEFAULT FALSE,
+    updated_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (chat_id, user_id)
+);
+
+-- if new message added, notify with message data, members who muted the chat (or only want
+-- mentions and are not mentioned) are listed in muted so they do not get alerted
+CREATE
+OR REPLACE FUNCTION add_to_message() RETURNS TRIGGER AS $$ DECLARE USERS bigint [];
+
+MUTED bigint [];
+
+BEGIN IF TG_OP = 'INSERT' THEN RAISE NOTICE 'add_to_message: %',
+NEW;
+
+SELECT
+    members INTO USERS
+FROM
+    chats
+WHERE
+    id = NEW.chat_id;
+
+SELECT
+    COALESCE(array_agg(cm.user_id), '{}') INTO MUTED
+FROM
+    chat_members cm
+    JOIN users u ON u.id = cm.user_id
+WHERE
+    cm.chat_id = NEW.chat_id
+    AND cm.user_id <> NEW.sender_id
+    AND (
+        cm.muted_until > now()
+        OR cm.notification_level = 'none'
+        OR (
+            cm.notification_level = 'mentions'
+            AND position('@' || u.fullname IN NEW.content) = 0
+        )
+    );
+
+PERFORM pg_notify(
+    'chat_message_added',
+    json_build_object(
+        'message',
+        NEW,
+        'members',
+        USERS,
+        'muted',
+        MUTED
+    ) :: text
+);
+
+END IF;
+
+RETURN NEW;
+
+END;
+
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,304 @@
+-- Add migration script here
+-- chat membership moves from the chats.members array to chat_members, one row per member
+CREATE TYPE chat_member_role AS ENUM ('owner', 'admin', 'member');
+
+ALTER TABLE
+    chat_members
+ADD
+    COLUMN role chat_member_role NOT NULL DEFAULT 'member',
+ADD
+    COLUMN joined_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+ADD
+    COLUMN last_read_message_id BIGINT REFERENCES messages(id) ON DELETE SET NULL;
+
+-- deleted users leave their chats
+ALTER TABLE
+    chat_members DROP CONSTRAINT chat_members_user_id_fkey,
+ADD
+    CONSTRAINT chat_members_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+
+-- backfill, settings of users no longer in the chat are dropped
+DELETE FROM
+    chat_members cm USING chats c
+WHERE
+    c.id = cm.chat_id
+    AND NOT cm.user_id = ANY(c.members);
+
+INSERT INTO
+    chat_members (chat_id, user_id, joined_at)
+SELECT
+    c.id,
+    m.user_id,
+    c.created_at
+FROM
+    chats c,
+    unnest(c.members) AS m(user_id)
+WHERE
+    EXISTS (
+        SELECT
+            1
+        FROM
+            users u
+        WHERE
+            u.id = m.user_id
+    ) ON CONFLICT (chat_id, user_id) DO NOTHING;
+
+CREATE INDEX IF NOT EXISTS chat_members_user_id_index ON chat_members(user_id);
+
+-- member ids of a chat, used wherever the members array was
+CREATE
+OR REPLACE FUNCTION chat_member_ids(id bigint) RETURNS bigint [] AS $$
+SELECT
+    COALESCE(array_agg(user_id ORDER BY user_id), '{}')
+FROM
+    chat_members
+WHERE
+    chat_id = $1 $$ LANGUAGE sql STABLE;
+
+-- chat events carry the chat with its members and who was added or removed
+DROP TRIGGER add_to_chat_trigger ON chats;
+
+DROP FUNCTION add_to_chat();
+
+-- members are still there before the delete cascades
+CREATE
+OR REPLACE FUNCTION notify_chat_deleted() RETURNS TRIGGER AS $$ DECLARE USERS bigint [] := chat_member_ids(OLD.id);
+
+BEGIN PERFORM pg_notify(
+    'chat_updated',
+    json_build_object(
+        'op',
+        'DELETE',
+        'chat',
+        to_jsonb(OLD) || jsonb_build_object('members', USERS),
+        'added',
+        '{}' :: bigint [],
+        'removed',
+        USERS
+    ) :: text
+);
+
+RETURN OLD;
+
+END;
+
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notify_chat_deleted_trigger BEFORE DELETE ON chats FOR EACH ROW EXECUTE FUNCTION notify_chat_deleted();
+
+-- a chat whose members were all just added is new
+CREATE
+OR REPLACE FUNCTION notify_chat_members_added() RETURNS TRIGGER AS $$ DECLARE R record;
+
+BEGIN FOR R IN
+SELECT
+    a.chat_id,
+    array_agg(a.user_id ORDER BY a.user_id) AS users
+FROM
+    added a
+GROUP BY
+    a.chat_id LOOP PERFORM pg_notify(
+        'chat_updated',
+        json_build_object(
+            'op',
+            CASE
+                WHEN chat_member_ids(R.chat_id) = R.users THEN 'INSERT'
+                ELSE 'UPDATE'
+            END,
+            'chat',
+            (
+                SELECT
+                    to_jsonb(c) || jsonb_build_object('members', chat_member_ids(c.id))
+                FROM
+                    chats c
+                WHERE
+                    c.id = R.chat_id
+            ),
+            'added',
+            R.users,
+            'removed',
+            '{}' :: bigint []
+        ) :: text
+    );
+
+END LOOP;
+
+RETURN NULL;
+
+END;
+
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notify_chat_members_added_trigger
+AFTER
+INSERT
+    ON chat_members REFERENCING NEW TABLE AS added FOR EACH STATEMENT EXECUTE FUNCTION notify_chat_members_added();
+
+-- members removed by deleting the chat are notified by notify_chat_deleted
+CREATE
+OR REPLACE FUNCTION notify_chat_members_removed() RETURNS TRIGGER AS $$ DECLARE R record;
+
+BEGIN FOR R IN
+SELECT
+    r.chat_id,
+    array_agg(r.user_id ORDER BY r.user_id) AS users,
+    to_jsonb(c) || jsonb_build_object('members', chat_member_ids(c.id)) AS chat
+FROM
+    removed r
+    JOIN chats c ON c.id = r.chat_id
+GROUP BY
+    r.chat_id,
+    c.id LOOP PERFORM pg_notify(
+        'chat_updated',
+        json_build_object(
+            'op',
+            'UPDATE',
+            'chat',
+            R.chat,
+            'added',
+            '{}' :: bigint [],
+            'removed',
+            R.users
+        ) :: text
+    );
+
+END LOOP;
+
+RETURN NULL;
+
+END;
+
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notify_chat_members_removed_trigger
+AFTER
+    DELETE ON chat_members REFERENCING OLD TABLE AS removed FOR EACH STATEMENT EXECUTE FUNCTION notify_chat_members_removed();
+
+-- the other notifications look members up in chat_members
+CREATE
+OR REPLACE FUNCTION add_to_message() RETURNS TRIGGER AS $$ DECLARE USERS bigint [];
+
+MUTED bigint [];
+
+BEGIN IF TG_OP = 'INSERT' THEN RAISE NOTICE 'add_to_message: %',
+NEW;
+
+USERS := chat_member_ids(NEW.chat_id);
+
+SELECT
+    COALESCE(array_agg(cm.user_id), '{}') INTO MUTED
+FROM
+    chat_members cm
+    JOIN users u ON u.id = cm.user_id
+WHERE
+    cm.chat_id = NEW.chat_id
+    AND cm.user_id <> NEW.sender_id
+    AND (
+        cm.muted_until > now()
+        OR cm.notification_level = 'none'
+        OR (
+            cm.notification_level = 'mentions'
+            AND position('@' || u.fullname IN NEW.content) = 0
+        )
+    );
+
+PERFORM pg_notify(
+    'chat_message_added',
+    json_build_object(
+        'message',
+        NEW,
+        'members',
+        USERS,
+        'muted',
+        MUTED
+    ) :: text
+);
+
+END IF;
+
+RETURN NEW;
+
+END;
+
+$$ LANGUAGE plpgsql;
+
+CREATE
+OR REPLACE FUNCTION notify_chat_name_change() RETURNS TRIGGER AS $$ BEGIN IF (
+    TG_OP = 'UPDATE'
+    AND OLD.name IS DISTINCT
+    FROM
+        NEW.name
+) THEN PERFORM pg_notify(
+    'chat_name_updated',
+    json_build_object(
+        'chat_id',
+        NEW.id,
+        'old_name',
+        OLD.name,
+        'new_name',
+        NEW.name,
+        'members',
+        chat_member_ids(NEW.id)
+    ) :: text
+);
+
+END IF;
+
+RETURN NEW;
+
+END;
+
+$$ LANGUAGE plpgsql;
+
+CREATE
+OR REPLACE FUNCTION notify_chat_pins_changed() RETURNS TRIGGER AS $$ DECLARE PIN chat_pins;
+
+BEGIN IF TG_OP = 'DELETE' THEN PIN := OLD;
+
+ELSE PIN := NEW;
+
+END IF;
+
+PERFORM pg_notify(
+    'chat_pins_changed',
+    json_build_object(
+        'chat_id',
+        PIN.chat_id,
+        'message_id',
+        PIN.message_id,
+        'pinned',
+        TG_OP = 'INSERT',
+        'pinned_by',
+        PIN.pinned_by,
+        'members',
+        chat_member_ids(PIN.chat_id)
+    ) :: text
+);
+
+RETURN PIN;
+
+END;
+
+$$ LANGUAGE plpgsql;
+
+CREATE
+OR REPLACE FUNCTION notify_message_deleted() RETURNS TRIGGER AS $$ BEGIN PERFORM pg_notify(
+    'chat_message_deleted',
+    json_build_object(
+        'chat_id',
+        OLD.chat_id,
+        'message_id',
+        OLD.id,
+        'members',
+        chat_member_ids(OLD.chat_id)
+    ) :: text
+);
+
+RETURN OLD;
+
+END;
+
+$$ LANGUAGE plpgsql;
+
+ALTER TABLE
+    chats DROP COLUMN members;
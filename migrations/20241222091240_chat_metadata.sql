@@ -0,0 +1,75 @@
+-- Add migration script here
+-- topic line, longer description and avatar (a ChatFile url) of a chat
+ALTER TABLE
+    chats
+ADD
+    COLUMN topic VARCHAR(250),
+ADD
+    COLUMN description TEXT,
+ADD
+    COLUMN avatar VARCHAR(256);
+
+-- chat_name_updated becomes chat_metadata_updated, listing which fields changed
+DROP TRIGGER notify_chat_name_change_trigger ON chats;
+
+DROP FUNCTION notify_chat_name_change();
+
+CREATE
+OR REPLACE FUNCTION notify_chat_metadata_updated() RETURNS TRIGGER AS $$ DECLARE CHANGED text [] := '{}';
+
+BEGIN IF OLD.name IS DISTINCT
+FROM
+    NEW.name THEN CHANGED := array_append(CHANGED, 'name');
+
+END IF;
+
+IF OLD.topic IS DISTINCT
+FROM
+    NEW.topic THEN CHANGED := array_append(CHANGED, 'topic');
+
+END IF;
+
+IF OLD.description IS DISTINCT
+FROM
+    NEW.description THEN CHANGED := array_append(CHANGED, 'description');
+
+END IF;
+
+IF OLD.avatar IS DISTINCT
+FROM
+    NEW.avatar THEN CHANGED := array_append(CHANGED, 'avatar');
+
+END IF;
+
+IF cardinality(CHANGED) > 0 THEN PERFORM pg_notify(
+    'chat_metadata_updated',
+    json_build_object(
+        'chat_id',
+        NEW.id,
+        'changed',
+        CHANGED,
+        'name',
+        NEW.name,
+        'topic',
+        NEW.topic,
+        'description',
+        NEW.description,
+        'avatar',
+        NEW.avatar,
+        'members',
+        chat_member_ids(NEW.id)
+    ) :: text
+);
+
+END IF;
+
+RETURN NEW;
+
+END;
+
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notify_chat_metadata_updated_trigger
+AFTER
+UPDATE
+    ON chats FOR EACH ROW EXECUTE FUNCTION notify_chat_metadata_updated();
@@ -0,0 +1,84 @@
+-- Add migration script here
+-- profile fields and a pending email change waiting for confirmation
+ALTER TABLE
+    users
+ADD
+    COLUMN avatar VARCHAR(256),
+ADD
+    COLUMN status_message VARCHAR(140),
+ADD
+    COLUMN time_zone VARCHAR(64),
+ADD
+    COLUMN pending_email VARCHAR(64),
+ADD
+    -- sha1 of the confirmation token
+    COLUMN email_token VARCHAR(40),
+ADD
+    COLUMN email_token_expires_at timestamptz;
+
+CREATE UNIQUE INDEX IF NOT EXISTS email_token_index ON users(email_token);
+
+-- users sharing a chat with the user refresh names and avatars
+CREATE
+OR REPLACE FUNCTION notify_user_updated() RETURNS TRIGGER AS $$ BEGIN IF (
+    OLD.fullname IS DISTINCT
+    FROM
+        NEW.fullname
+        OR OLD.email IS DISTINCT
+    FROM
+        NEW.email
+        OR OLD.avatar IS DISTINCT
+    FROM
+        NEW.avatar
+        OR OLD.status_message IS DISTINCT
+    FROM
+        NEW.status_message
+        OR OLD.time_zone IS DISTINCT
+    FROM
+        NEW.time_zone
+) THEN PERFORM pg_notify(
+    'user_updated',
+    json_build_object(
+        'user',
+        json_build_object(
+            'id',
+            NEW.id,
+            'fullname',
+            NEW.fullname,
+            'email',
+            NEW.email,
+            'avatar',
+            NEW.avatar,
+            'status_message',
+            NEW.status_message,
+            'time_zone',
+            NEW.time_zone
+        ),
+        'members',
+        ARRAY(
+            SELECT
+                DISTINCT other.user_id
+            FROM
+                chat_members mine
+                JOIN chat_members other ON other.chat_id = mine.chat_id
+            WHERE
+                mine.user_id = NEW.id
+            UNION
+            SELECT
+                NEW.id
+        )
+    ) :: text
+);
+
+END IF;
+
+RETURN NEW;
+
+END;
+
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notify_user_updated_trigger
+AFTER
+UPDATE
+    ON users FOR EACH ROW EXECUTE FUNCTION notify_user_updated();
@@ -0,0 +1,11 @@
+-- Add migration script here
+-- users sign up unverified, existing users are trusted
+ALTER TABLE
+    users
+ADD
+    COLUMN email_verified_at timestamptz;
+
+UPDATE
+    users
+SET
+    email_verified_at = created_at;
@@ -0,0 +1,25 @@
+-- Add migration script here
+-- TOTP secret is kept from enrollment, 2FA is on once a code was verified
+ALTER TABLE
+    users
+ADD
+    COLUMN totp_secret VARCHAR(64),
+ADD
+    COLUMN totp_enabled_at timestamptz;
+
+-- single-use recovery codes, argon2 hashed like passwords
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    code_hash VARCHAR(97) NOT NULL,
+    used_at timestamptz,
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS recovery_codes_user_id_index ON recovery_codes(user_id);
+
+-- members of these workspaces must enroll before using the app
+ALTER TABLE
+    workspaces
+ADD
+    COLUMN require_two_factor BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,11 @@
+-- Add migration script here
+-- accounts at OpenID Connect providers, a subject is only unique within its issuer
+CREATE TABLE IF NOT EXISTS user_identities (
+    issuer VARCHAR(256) NOT NULL,
+    subject VARCHAR(256) NOT NULL,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (issuer, subject)
+);
+
+CREATE INDEX IF NOT EXISTS user_identities_user_id_index ON user_identities(user_id);
@@ -0,0 +1,17 @@
+-- Add migration script here
+-- every signin attempt, kept by email even if no such user exists so lockouts do not reveal
+-- which emails are registered. failures since the last success or unlock count for the lockout
+CREATE TYPE signin_outcome AS ENUM('failed', 'succeeded', 'unlocked');
+
+CREATE TABLE IF NOT EXISTS signin_attempts (
+    id BIGSERIAL PRIMARY KEY,
+    email VARCHAR(64) NOT NULL,
+    user_id BIGINT REFERENCES users(id) ON DELETE SET NULL,
+    ip VARCHAR(45),
+    outcome signin_outcome NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS signin_attempts_email_index ON signin_attempts(email, created_at DESC);
+
+CREATE INDEX IF NOT EXISTS signin_attempts_ip_index ON signin_attempts(ip, created_at DESC);
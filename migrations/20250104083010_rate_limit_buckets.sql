@@ -0,0 +1,8 @@
+-- Add migration script here
+-- token buckets of the rate limiter, shared by all server instances
+CREATE TABLE IF NOT EXISTS rate_limit_buckets (
+    key VARCHAR(128) PRIMARY KEY,
+    tokens DOUBLE PRECISION NOT NULL,
+    allowed BOOLEAN NOT NULL,
+    updated_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,26 @@
+-- Add migration script here
+-- bots are users that can not sign in, they only use access tokens
+ALTER TABLE
+    users
+ADD
+    COLUMN is_bot BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TYPE token_scope AS ENUM('read_chats', 'post_messages', 'upload_files');
+
+-- personal access tokens, only the sha1 of the token is kept
+CREATE TABLE IF NOT EXISTS access_tokens (
+    id BIGSERIAL PRIMARY KEY,
+    -- the user the token acts as, a bot or its creator
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_by BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(64) NOT NULL,
+    token_hash CHAR(40) NOT NULL UNIQUE,
+    scopes token_scope [] NOT NULL,
+    expires_at timestamptz,
+    last_used_at timestamptz,
+    created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS access_tokens_user_id_index ON access_tokens(user_id);
+
+CREATE INDEX IF NOT EXISTS access_tokens_created_by_index ON access_tokens(created_by);
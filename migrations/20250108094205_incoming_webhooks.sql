@@ -0,0 +1,20 @@
+-- Add migration script here
+-- incoming webhooks may post under another name than their bot
+ALTER TABLE
+    messages
+ADD
+    COLUMN sender_name VARCHAR(64);
+
+-- each hook posts as its own bot, only the sha1 of the secret is kept
+CREATE TABLE IF NOT EXISTS incoming_webhooks (
+    id BIGSERIAL PRIMARY KEY,
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    bot_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(64) NOT NULL,
+    secret_hash CHAR(40) NOT NULL,
+    created_by BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    disabled_at timestamptz,
+    created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS incoming_webhooks_chat_id_index ON incoming_webhooks(chat_id);
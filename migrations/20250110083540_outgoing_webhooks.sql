@@ -0,0 +1,41 @@
+-- Add migration script here
+-- services subscribed to events of a workspace or of one chat, the secret signs deliveries
+CREATE TABLE IF NOT EXISTS webhook_subscriptions (
+    id BIGSERIAL PRIMARY KEY,
+    ws_id BIGINT NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    -- events of all public channels of the workspace if not set
+    chat_id BIGINT REFERENCES chats(id) ON DELETE CASCADE,
+    url VARCHAR(2048) NOT NULL,
+    secret VARCHAR(64) NOT NULL,
+    -- all events if empty
+    events TEXT [] NOT NULL DEFAULT '{}',
+    created_by BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS webhook_subscriptions_ws_id_index ON webhook_subscriptions(ws_id);
+
+CREATE INDEX IF NOT EXISTS webhook_subscriptions_chat_id_index ON webhook_subscriptions(chat_id);
+
+CREATE TYPE webhook_delivery_status AS ENUM('pending', 'delivered', 'dead');
+
+-- the delivery queue, dead deliveries ran out of attempts and wait for a redelivery
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    subscription_id BIGINT NOT NULL REFERENCES webhook_subscriptions(id) ON DELETE CASCADE,
+    event VARCHAR(64) NOT NULL,
+    payload JSONB NOT NULL,
+    status webhook_delivery_status NOT NULL DEFAULT 'pending',
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_status INT,
+    last_error TEXT,
+    delivered_at timestamptz,
+    created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS webhook_deliveries_pending_index ON webhook_deliveries(next_attempt_at)
+WHERE
+    status = 'pending';
+
+CREATE INDEX IF NOT EXISTS webhook_deliveries_subscription_id_index ON webhook_deliveries(subscription_id, id);
@@ -0,0 +1,15 @@
+-- Add migration script here
+-- slash commands of a workspace answered by an external service
+CREATE TABLE IF NOT EXISTS slash_commands (
+    id BIGSERIAL PRIMARY KEY,
+    ws_id BIGINT NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    -- without the leading slash
+    name VARCHAR(32) NOT NULL,
+    url VARCHAR(2048) NOT NULL,
+    description VARCHAR(256) NOT NULL DEFAULT '',
+    -- sent along with every invocation so the service knows it is us
+    token VARCHAR(64) NOT NULL,
+    created_by BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (ws_id, name)
+);
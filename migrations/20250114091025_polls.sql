@@ -0,0 +1,53 @@
+-- Add migration script here
+-- polls are asked by a message, options are addressed by their index
+CREATE TABLE IF NOT EXISTS polls (
+    id BIGSERIAL PRIMARY KEY,
+    chat_id BIGINT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    question VARCHAR(512) NOT NULL,
+    options TEXT [] NOT NULL,
+    multiple BOOLEAN NOT NULL DEFAULT FALSE,
+    -- voters are kept to prevent double votes, but never shown
+    anonymous BOOLEAN NOT NULL DEFAULT FALSE,
+    closes_at timestamptz,
+    closed_at timestamptz,
+    created_by BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- open polls by the time they close
+CREATE INDEX IF NOT EXISTS polls_closes_at_index ON polls(closes_at)
+WHERE
+    closed_at IS NULL
+    AND closes_at IS NOT NULL;
+
+CREATE TABLE IF NOT EXISTS poll_votes (
+    poll_id BIGINT NOT NULL REFERENCES polls(id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    option INT NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (poll_id, user_id, option)
+);
+
+ALTER TABLE
+    messages
+ADD
+    COLUMN poll_id BIGINT REFERENCES polls(id) ON DELETE SET NULL;
+
+-- the poll goes with its message, e.g. when it expires or is purged
+CREATE
+OR REPLACE FUNCTION delete_message_poll() RETURNS TRIGGER AS $$ BEGIN
+DELETE FROM
+    polls
+WHERE
+    id = OLD.poll_id;
+
+RETURN OLD;
+
+END;
+
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER delete_message_poll_trigger
+AFTER
+    DELETE ON messages FOR EACH ROW
+    WHEN (OLD.poll_id IS NOT NULL) EXECUTE FUNCTION delete_message_poll();
@@ -0,0 +1,58 @@
+-- Add migration script here
+-- uploaded files of the content addressed store, files stored before are not tracked and never
+-- collected
+CREATE TABLE IF NOT EXISTS files (
+    -- `/files/{ws_id}/{hash path}.{ext}` as messages reference it
+    url TEXT PRIMARY KEY,
+    ws_id BIGINT NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    hash CHAR(40) NOT NULL,
+    uploaded_by BIGINT REFERENCES users(id) ON DELETE SET NULL,
+    size BIGINT NOT NULL,
+    mime VARCHAR(255) NOT NULL,
+    -- messages with the file
+    ref_count INT NOT NULL DEFAULT 0,
+    -- the last upload, the grace period before collecting the file starts here
+    uploaded_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- candidates of the garbage collection
+CREATE INDEX IF NOT EXISTS files_unreferenced_index ON files(uploaded_at)
+WHERE
+    ref_count = 0;
+
+-- count references when messages come and go, including deletes cascading from chats
+CREATE
+OR REPLACE FUNCTION count_message_files() RETURNS TRIGGER AS $$ BEGIN IF TG_OP IN ('UPDATE', 'DELETE') THEN
+UPDATE
+    files
+SET
+    ref_count = GREATEST(ref_count - 1, 0)
+WHERE
+    url = ANY(OLD.files);
+
+END IF;
+
+IF TG_OP IN ('INSERT', 'UPDATE') THEN
+UPDATE
+    files
+SET
+    ref_count = ref_count + 1
+WHERE
+    url = ANY(NEW.files);
+
+END IF;
+
+RETURN NULL;
+
+END;
+
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER count_message_files_trigger
+AFTER
+INSERT
+    OR DELETE
+    OR
+UPDATE
+    OF files ON messages FOR EACH ROW EXECUTE FUNCTION count_message_files();
@@ -0,0 +1,28 @@
+-- Add migration script here
+-- chats backfilled from the members array have no owner, the longest standing member becomes one
+UPDATE
+    chat_members cm
+SET
+    role = 'owner'
+WHERE
+    (cm.chat_id, cm.user_id) IN (
+        SELECT
+            DISTINCT ON (chat_id) chat_id,
+            user_id
+        FROM
+            chat_members
+        ORDER BY
+            chat_id,
+            role,
+            joined_at,
+            user_id
+    )
+    AND NOT EXISTS (
+        SELECT
+            1
+        FROM
+            chat_members o
+        WHERE
+            o.chat_id = cm.chat_id
+            AND o.role = 'owner'
+    );
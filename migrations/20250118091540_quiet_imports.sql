@@ -0,0 +1,51 @@
+-- Add migration script here
+-- imports set chat.importing for their transaction, historical messages are not live events
+CREATE
+OR REPLACE FUNCTION add_to_message() RETURNS TRIGGER AS $$ DECLARE USERS bigint [];
+
+MUTED bigint [];
+
+BEGIN IF TG_OP = 'INSERT'
+AND current_setting('chat.importing', TRUE) IS DISTINCT
+FROM
+    'on' THEN RAISE NOTICE 'add_to_message: %',
+    NEW;
+
+USERS := chat_member_ids(NEW.chat_id);
+
+SELECT
+    COALESCE(array_agg(cm.user_id), '{}') INTO MUTED
+FROM
+    chat_members cm
+    JOIN users u ON u.id = cm.user_id
+WHERE
+    cm.chat_id = NEW.chat_id
+    AND cm.user_id <> NEW.sender_id
+    AND (
+        cm.muted_until > now()
+        OR cm.notification_level = 'none'
+        OR (
+            cm.notification_level = 'mentions'
+            AND position('@' || u.fullname IN NEW.content) = 0
+        )
+    );
+
+PERFORM pg_notify(
+    'chat_message_added',
+    json_build_object(
+        'message',
+        NEW,
+        'members',
+        USERS,
+        'muted',
+        MUTED
+    ) :: text
+);
+
+END IF;
+
+RETURN NEW;
+
+END;
+
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- a signin challenge is bound to the nonce, which changes when a challenge is issued or used,
+-- and a TOTP time step signs in once
+ALTER TABLE
+    users
+ADD
+    COLUMN totp_nonce VARCHAR(32),
+ADD
+    COLUMN totp_last_step BIGINT;
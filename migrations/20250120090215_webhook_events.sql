@@ -0,0 +1,12 @@
+-- Add migration script here
+-- events whose deliveries were queued, every notify server instance receives each event and the
+-- first one to claim its key queues the deliveries
+CREATE TABLE IF NOT EXISTS webhook_events (
+    key CHAR(64) PRIMARY KEY,
+    received_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- finished deliveries are pruned after a while
+CREATE INDEX IF NOT EXISTS webhook_deliveries_finished_index ON webhook_deliveries(created_at)
+WHERE
+    status <> 'pending';
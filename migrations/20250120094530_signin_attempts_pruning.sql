@@ -0,0 +1,3 @@
+-- Add migration script here
+-- old attempts are pruned periodically
+CREATE INDEX IF NOT EXISTS signin_attempts_created_at_index ON signin_attempts(created_at);
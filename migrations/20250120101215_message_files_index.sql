@@ -0,0 +1,3 @@
+-- Add migration script here
+-- finding the messages that reference a file before removing it, queries use `files @> ARRAY[url]`
+CREATE INDEX IF NOT EXISTS messages_files_index ON messages USING GIN (files);
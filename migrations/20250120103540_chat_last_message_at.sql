@@ -0,0 +1,82 @@
+-- Add migration script here
+-- when the last message of a chat was posted, chat lists are ordered by it
+ALTER TABLE
+    chats
+ADD
+    COLUMN last_message_at timestamptz;
+
+UPDATE
+    chats c
+SET
+    last_message_at = m.last_message_at
+FROM
+    (
+        SELECT
+            chat_id,
+            max(created_at) AS last_message_at
+        FROM
+            messages
+        GROUP BY
+            chat_id
+    ) m
+WHERE
+    m.chat_id = c.id;
+
+-- kept by the message trigger, also for imported history
+CREATE
+OR REPLACE FUNCTION add_to_message() RETURNS TRIGGER AS $$ DECLARE USERS bigint [];
+
+MUTED bigint [];
+
+BEGIN
+UPDATE
+    chats
+SET
+    last_message_at = GREATEST(last_message_at, NEW.created_at)
+WHERE
+    id = NEW.chat_id;
+
+IF TG_OP = 'INSERT'
+AND current_setting('chat.importing', TRUE) IS DISTINCT
+FROM
+    'on' THEN RAISE NOTICE 'add_to_message: %',
+    NEW;
+
+USERS := chat_member_ids(NEW.chat_id);
+
+SELECT
+    COALESCE(array_agg(cm.user_id), '{}') INTO MUTED
+FROM
+    chat_members cm
+    JOIN users u ON u.id = cm.user_id
+WHERE
+    cm.chat_id = NEW.chat_id
+    AND cm.user_id <> NEW.sender_id
+    AND (
+        cm.muted_until > now()
+        OR cm.notification_level = 'none'
+        OR (
+            cm.notification_level = 'mentions'
+            AND position('@' || u.fullname IN NEW.content) = 0
+        )
+    );
+
+PERFORM pg_notify(
+    'chat_message_added',
+    json_build_object(
+        'message',
+        NEW,
+        'members',
+        USERS,
+        'muted',
+        MUTED
+    ) :: text
+);
+
+END IF;
+
+RETURN NEW;
+
+END;
+
+$$ LANGUAGE plpgsql;
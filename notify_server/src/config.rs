@@ -1,12 +1,18 @@
 use std::fs::File;
 
 use anyhow::{bail, Result};
+use chat_core::OutboundPolicy;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppConfig {
     pub server: ServerConfig,
     pub auth: AuthConfig,
+    #[serde(default)]
+    pub webhooks: WebhookConfig,
+    /// where webhooks may be delivered to
+    #[serde(default)]
+    pub outbound: OutboundPolicy,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -19,6 +25,36 @@ pub struct ServerConfig {
     pub db_url: String,
 }
 
+/// Delivery of events to webhook subscriptions. Enabled instances queue each event once between
+/// them and share the deliveries.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    pub enabled: bool,
+    /// a delivery is dead after this many failed attempts
+    pub max_attempts: u32,
+    /// wait before the first retry, it doubles with every further one
+    pub retry_base_secs: u64,
+    pub timeout_secs: u64,
+    /// how often to look for due retries and redeliveries
+    pub poll_secs: u64,
+    /// delivered and dead deliveries are pruned after this many days
+    pub keep_days: u32,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_attempts: 8,
+            retry_base_secs: 30,
+            timeout_secs: 10,
+            poll_secs: 5,
+            keep_days: 7,
+        }
+    }
+}
+
 impl AppConfig {
     pub fn load() -> Result<Self> {
         // read from  ./app.yml, or /etc/config/app.yml, or from env CHAT_CONFIG
@@ -18,6 +18,9 @@ pub enum AppError {
 
     #[error("jwt error: {0}")]
     JwtError(#[from] jwt_simple::Error),
+
+    #[error("sql error: {0}")]
+    SqlxError(#[from] sqlx::Error),
 }
 
 impl ErrorOutput {
@@ -34,6 +37,7 @@ impl IntoResponse for AppError {
             AppError::JwtError(_) => StatusCode::FORBIDDEN,
 
             AppError::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::SqlxError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
         (status, Json(json!(ErrorOutput::new(self.to_string())))).into_response()
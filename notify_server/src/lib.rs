@@ -2,6 +2,7 @@ mod config;
 mod error;
 mod notify;
 mod sse;
+mod webhook;
 use std::{ops::Deref, sync::Arc};
 
 use axum::{
@@ -11,10 +12,13 @@ use axum::{
     routing::get,
     Router,
 };
-use chat_core::{verify_token, DecodingKey, TokenVerify, User};
+use chat_core::{
+    require_two_factor_enrolled, verify_token, DecodingKey, TokenVerify, TwoFactorPolicy, User,
+};
 use dashmap::DashMap;
+use sqlx::PgPool;
 use sse::sse_handler;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, Notify};
 
 pub use config::AppConfig;
 pub use error::AppError;
@@ -30,6 +34,10 @@ pub struct AppStateInner {
     pub config: AppConfig,
     users: UserMap,
     dk: DecodingKey,
+    pool: PgPool,
+    http: reqwest::Client,
+    /// wakes the webhook delivery when events were queued
+    webhook_queued: Notify,
 }
 
 pub async fn get_router(config: AppConfig) -> anyhow::Result<Router> {
@@ -45,8 +53,16 @@ pub async fn get_router(config: AppConfig) -> anyhow::Result<Router> {
         .allow_headers(Any)
         .allow_origin(Any);
     notify::setup_pg_listener(state.clone()).await?;
+    if state.config.webhooks.enabled {
+        webhook::spawn_delivery(state.clone());
+        webhook::spawn_prune(state.clone());
+    }
     let app = Router::new()
         .route("/events", get(sse_handler))
+        .layer(from_fn_with_state(
+            state.clone(),
+            require_two_factor_enrolled::<AppState>,
+        ))
         .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
         .route("/", get(index_handler))
         .layer(cors)
@@ -67,15 +83,47 @@ impl Deref for AppState {
 
 impl TokenVerify for AppState {
     type Error = AppError;
-    fn verify(&self, token: &str) -> Result<User, Self::Error> {
+    async fn verify(&self, token: &str) -> Result<User, Self::Error> {
         Ok(self.dk.verify(token)?)
     }
 }
 
+impl TwoFactorPolicy for AppState {
+    type Error = AppError;
+    /// The workspace requires 2FA and the user has not enabled it, also for unknown users. Bots
+    /// can not enroll, they only use access tokens.
+    async fn two_factor_pending(&self, user: &User) -> Result<bool, Self::Error> {
+        let pending: Option<(bool,)> = sqlx::query_as(
+            r#"
+        SELECT w.require_two_factor AND u.totp_enabled_at IS NULL AND NOT u.is_bot
+        FROM users u JOIN workspaces w ON w.id = u.ws_id
+        WHERE u.id = $1
+        "#,
+        )
+        .bind(user.id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(pending.map_or(true, |(pending,)| pending))
+    }
+}
+
 impl AppState {
     pub fn new(config: AppConfig) -> Self {
         let dk = DecodingKey::load(&config.auth.pk).expect("Failed to load pk");
         let users = Arc::new(DashMap::new());
-        Self(Arc::new(AppStateInner { config, dk, users }))
+        let pool = PgPool::connect_lazy(&config.server.db_url).expect("Failed to parse db_url");
+        let http = config
+            .outbound
+            .client_builder()
+            .build()
+            .expect("Failed to build http client");
+        Self(Arc::new(AppStateInner {
+            config,
+            dk,
+            users,
+            pool,
+            http,
+            webhook_queued: Notify::new(),
+        }))
     }
 }
@@ -1,12 +1,13 @@
-use std::{collections::HashSet, sync::Arc};
+use std::{collections::HashSet, sync::Arc, time::Duration};
 
-use chat_core::{Chat, Message};
+use chat_core::{Chat, ChatUser, Message};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgListener;
-use tokio_stream::StreamExt;
+use tokio::time::sleep;
 use tracing::{info, warn};
 
-use crate::AppState;
+use crate::{webhook, AppState};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "event")]
@@ -14,96 +15,339 @@ pub enum AppEvent {
     NewChat(Chat),
     AddToChat(Chat),
     RemoveFromChat(Chat),
-    NewMessage(Message),
-    ChatNameUpdated(ChatNameUpdated),
+    NewMessage(NewMessage),
+    ChatMetadataUpdated(ChatMetadataUpdated),
+    PinsChanged(PinsChanged),
+    BookmarkReminder(BookmarkReminder),
+    MessageDeleted(MessageDeleted),
+    UserUpdated(UserUpdated),
+    CommandReply(Message),
+    PollUpdated(PollUpdated),
+}
+
+/// What an event is about, webhook subscriptions of its chat or workspace receive it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum EventTarget {
+    Chat(i64),
+    User(i64),
 }
 
 #[derive(Debug)]
 struct Notification {
     user_ids: HashSet<u64>,
     event: Arc<AppEvent>,
+    /// recipients who get the event without being alerted, and the event they get
+    silent: Option<(HashSet<u64>, Arc<AppEvent>)>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct ChatUpdated {
     op: String,
-    old: Option<Chat>,
-    new: Option<Chat>,
+    /// the chat with its current members, or the members before it was deleted
+    chat: Chat,
+    #[serde(default)]
+    added: Vec<i64>,
+    #[serde(default)]
+    removed: Vec<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct ChatMessageAdded {
     members: Vec<i64>,
+    /// members whose notification level does not allow alerting them for this message
+    #[serde(default)]
+    muted: Vec<i64>,
+    message: Message,
+}
+
+/// A message posted to a chat, every member gets it to keep their message list current
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewMessage {
+    #[serde(flatten)]
     message: Message,
+    /// the notification level of the recipient does not allow alerting them for it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    silent: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatMetadataUpdated {
+    chat_id: i64,
+    /// the fields that changed: `name`, `topic`, `description` or `avatar`
+    changed: Vec<String>,
+    name: Option<String>,
+    topic: Option<String>,
+    description: Option<String>,
+    avatar: Option<String>,
+    members: Vec<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct ChatNameUpdated {
+pub struct PinsChanged {
     chat_id: i64,
-    old_name: String,
-    new_name: String,
+    message_id: i64,
+    pinned: bool,
+    pinned_by: i64,
     members: Vec<i64>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BookmarkReminder {
+    id: i64,
+    user_id: i64,
+    chat_id: i64,
+    message_id: i64,
+    note: Option<String>,
+    remind_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MessageDeleted {
+    chat_id: i64,
+    message_id: i64,
+    members: Vec<i64>,
+}
+
+/// Tally of a poll after votes changed or it closed, who voted is fetched from the poll
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PollUpdated {
+    poll_id: i64,
+    chat_id: i64,
+    /// votes per option, in option order
+    counts: Vec<i64>,
+    voters: i64,
+    closed_at: Option<DateTime<Utc>>,
+    members: Vec<i64>,
+}
+
+/// Reply to a slash command, only its invoker sees it
+#[derive(Debug, Serialize, Deserialize)]
+struct CommandReply {
+    user_id: i64,
+    message: Message,
+}
+
+/// Profile changes, sent to the user and everyone sharing a chat with them
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserUpdated {
+    user: ChatUser,
+    members: Vec<i64>,
+}
+
+/// Channels the database triggers and chat server notify on
+const CHANNELS: [&str; 9] = [
+    "chat_updated",
+    "chat_message_added",
+    "chat_metadata_updated",
+    "chat_pins_changed",
+    "bookmark_reminder",
+    "chat_message_deleted",
+    "user_updated",
+    "command_reply",
+    "poll_updated",
+];
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
 pub async fn setup_pg_listener(state: AppState) -> anyhow::Result<()> {
     let mut listener = PgListener::connect(&state.config.server.db_url).await?;
-    listener.listen("chat_updated").await?;
-    listener.listen("chat_message_added").await?;
-    listener.listen("chat_name_updated").await?;
-    let mut stream = listener.into_stream();
+    listener.listen_all(CHANNELS).await?;
     tokio::spawn(async move {
-        while let Some(Ok(notif)) = stream.next().await {
+        loop {
+            // a lost connection is re-established on the next recv, notifications sent
+            // meanwhile are lost to this instance, webhooks still get them if another
+            // instance received them
+            let notif = match listener.recv().await {
+                Ok(notif) => notif,
+                Err(e) => {
+                    warn!("Failed to receive notification, reconnecting: {}", e);
+                    sleep(RECONNECT_DELAY).await;
+                    continue;
+                }
+            };
             info!("Received notification: {:?}", notif);
-            let notification = Notification::load(notif.channel(), notif.payload()).unwrap();
+            let notification = match Notification::load(notif.channel(), notif.payload()) {
+                Ok(notification) => notification,
+                Err(e) => {
+                    warn!(
+                        "Skipping invalid notification on {}: {}",
+                        notif.channel(),
+                        e
+                    );
+                    continue;
+                }
+            };
             info!("Notification: {:?}", notification);
-            let users = &state.users;
-            for user_id in notification.user_ids {
-                if let Some(tx) = users.get(&user_id) {
-                    info!("Sending notification to user {}", user_id);
-                    if let Err(e) = tx.send(notification.event.clone()) {
-                        warn!("Failed to send notification to user {}: {}", user_id, e);
-                        users.remove(&user_id);
-                    }
+            send_to_users(&state, &notification.user_ids, &notification.event);
+            if let Some((user_ids, event)) = &notification.silent {
+                send_to_users(&state, user_ids, event);
+            }
+            if state.config.webhooks.enabled {
+                let key = webhook::event_key(notif.channel(), notif.payload());
+                if let Err(e) = webhook::enqueue(&state, &key, &notification.event).await {
+                    warn!(
+                        "Failed to queue webhooks for {:?}: {}",
+                        notification.event, e
+                    );
                 }
             }
         }
-        Ok::<_, anyhow::Error>(())
     });
     Ok(())
 }
 
+fn send_to_users(state: &AppState, user_ids: &HashSet<u64>, event: &Arc<AppEvent>) {
+    let users = &state.users;
+    for user_id in user_ids {
+        if let Some(tx) = users.get(user_id) {
+            info!("Sending notification to user {}", user_id);
+            if let Err(e) = tx.send(event.clone()) {
+                warn!("Failed to send notification to user {}: {}", user_id, e);
+                users.remove(user_id);
+            }
+        }
+    }
+}
+
+impl AppEvent {
+    /// The SSE event name, also the event type of webhook deliveries
+    pub fn name(&self) -> &'static str {
+        match self {
+            AppEvent::NewChat(_) => "NewChat",
+            AppEvent::AddToChat(_) => "AddToChat",
+            AppEvent::RemoveFromChat(_) => "RemoveFromChat",
+            AppEvent::NewMessage(_) => "NewMessage",
+            AppEvent::ChatMetadataUpdated(_) => "ChatMetadataUpdated",
+            AppEvent::PinsChanged(_) => "PinsChanged",
+            AppEvent::BookmarkReminder(_) => "BookmarkReminder",
+            AppEvent::MessageDeleted(_) => "MessageDeleted",
+            AppEvent::UserUpdated(_) => "UserUpdated",
+            AppEvent::CommandReply(_) => "CommandReply",
+            AppEvent::PollUpdated(_) => "PollUpdated",
+        }
+    }
+
+    /// Bookmark reminders and command replies are private to their user and never leave through
+    /// webhooks
+    pub(crate) fn target(&self) -> Option<EventTarget> {
+        match self {
+            AppEvent::NewChat(chat)
+            | AppEvent::AddToChat(chat)
+            | AppEvent::RemoveFromChat(chat) => Some(EventTarget::Chat(chat.id)),
+            AppEvent::NewMessage(payload) => Some(EventTarget::Chat(payload.message.chat_id)),
+            AppEvent::ChatMetadataUpdated(payload) => Some(EventTarget::Chat(payload.chat_id)),
+            AppEvent::PinsChanged(payload) => Some(EventTarget::Chat(payload.chat_id)),
+            AppEvent::MessageDeleted(payload) => Some(EventTarget::Chat(payload.chat_id)),
+            AppEvent::PollUpdated(payload) => Some(EventTarget::Chat(payload.chat_id)),
+            AppEvent::UserUpdated(payload) => Some(EventTarget::User(payload.user.id)),
+            AppEvent::BookmarkReminder(_) | AppEvent::CommandReply(_) => None,
+        }
+    }
+}
+
 impl Notification {
     fn load(r#type: &str, payload: &str) -> anyhow::Result<Self> {
         match r#type {
-            "chat_name_updated" => {
-                let payload = serde_json::from_str::<ChatNameUpdated>(payload)?;
+            "chat_metadata_updated" => {
+                let payload = serde_json::from_str::<ChatMetadataUpdated>(payload)?;
+                let user_ids = payload.members.iter().map(|v| *v as u64).collect();
+                Ok(Self {
+                    user_ids,
+                    event: Arc::new(AppEvent::ChatMetadataUpdated(payload)),
+                    silent: None,
+                })
+            }
+            "user_updated" => {
+                let payload = serde_json::from_str::<UserUpdated>(payload)?;
+                let user_ids = payload.members.iter().map(|v| *v as u64).collect();
+                Ok(Self {
+                    user_ids,
+                    event: Arc::new(AppEvent::UserUpdated(payload)),
+                    silent: None,
+                })
+            }
+            "chat_pins_changed" => {
+                let payload = serde_json::from_str::<PinsChanged>(payload)?;
+                let user_ids = payload.members.iter().map(|v| *v as u64).collect();
+                Ok(Self {
+                    user_ids,
+                    event: Arc::new(AppEvent::PinsChanged(payload)),
+                    silent: None,
+                })
+            }
+            "chat_message_deleted" => {
+                let payload = serde_json::from_str::<MessageDeleted>(payload)?;
+                let user_ids = payload.members.iter().map(|v| *v as u64).collect();
+                Ok(Self {
+                    user_ids,
+                    event: Arc::new(AppEvent::MessageDeleted(payload)),
+                    silent: None,
+                })
+            }
+            "poll_updated" => {
+                let payload = serde_json::from_str::<PollUpdated>(payload)?;
                 let user_ids = payload.members.iter().map(|v| *v as u64).collect();
                 Ok(Self {
                     user_ids,
-                    event: Arc::new(AppEvent::ChatNameUpdated(payload)),
+                    event: Arc::new(AppEvent::PollUpdated(payload)),
+                    silent: None,
+                })
+            }
+            "bookmark_reminder" => {
+                let payload = serde_json::from_str::<BookmarkReminder>(payload)?;
+                let user_ids = HashSet::from([payload.user_id as u64]);
+                Ok(Self {
+                    user_ids,
+                    event: Arc::new(AppEvent::BookmarkReminder(payload)),
+                    silent: None,
+                })
+            }
+            "command_reply" => {
+                let payload = serde_json::from_str::<CommandReply>(payload)?;
+                let user_ids = HashSet::from([payload.user_id as u64]);
+                Ok(Self {
+                    user_ids,
+                    event: Arc::new(AppEvent::CommandReply(payload.message)),
+                    silent: None,
                 })
             }
             "chat_updated" => {
                 let payload = serde_json::from_str::<ChatUpdated>(payload)?;
-                let user_ids =
-                    get_affected_chat_user_ids(payload.old.as_ref(), payload.new.as_ref());
+                let user_ids = get_affected_chat_user_ids(&payload);
                 let event = match payload.op.as_str() {
-                    "INSERT" => AppEvent::NewChat(payload.new.expect("new should exist")),
-                    "UPDATE" => AppEvent::AddToChat(payload.new.expect("new should exist")),
-                    "DELETE" => AppEvent::RemoveFromChat(payload.old.expect("old should exist")),
+                    "INSERT" => AppEvent::NewChat(payload.chat),
+                    "UPDATE" => AppEvent::AddToChat(payload.chat),
+                    "DELETE" => AppEvent::RemoveFromChat(payload.chat),
                     _ => return Err(anyhow::anyhow!("Unknown operation: {}", payload.op)),
                 };
                 Ok(Self {
                     user_ids,
                     event: Arc::new(event),
+                    silent: None,
                 })
             }
             "chat_message_added" => {
                 let payload = serde_json::from_str::<ChatMessageAdded>(payload)?;
-                let user_ids = payload.members.iter().map(|v| *v as u64).collect();
+                // every member gets the message, muted ones without an alert
+                let (silent_ids, user_ids): (HashSet<u64>, HashSet<u64>) = payload
+                    .members
+                    .iter()
+                    .map(|v| *v as u64)
+                    .partition(|v| payload.muted.contains(&(*v as i64)));
+                let silent = (!silent_ids.is_empty()).then(|| {
+                    let event = NewMessage {
+                        message: payload.message.clone(),
+                        silent: true,
+                    };
+                    (silent_ids, Arc::new(AppEvent::NewMessage(event)))
+                });
+                let event = NewMessage {
+                    message: payload.message,
+                    silent: false,
+                };
                 Ok(Self {
                     user_ids,
-                    event: Arc::new(AppEvent::NewMessage(payload.message)),
+                    event: Arc::new(AppEvent::NewMessage(event)),
+                    silent,
                 })
             }
             _ => Err(anyhow::anyhow!("Unknown notification type: {}", r#type)),
@@ -111,20 +355,16 @@ impl Notification {
     }
 }
 
-fn get_affected_chat_user_ids(old: Option<&Chat>, new: Option<&Chat>) -> HashSet<u64> {
-    match (old, new) {
-        (Some(old), Some(new)) => {
-            // diff old/new members, if identical, no need to notify, otherwise notify the union of both
-            let old_user_ids: HashSet<_> = old.members.iter().map(|v| *v as u64).collect();
-            let new_user_ids: HashSet<_> = new.members.iter().map(|v| *v as u64).collect();
-            if old_user_ids == new_user_ids {
-                HashSet::new()
-            } else {
-                old_user_ids.union(&new_user_ids).copied().collect()
-            }
-        }
-        (Some(old), None) => old.members.iter().map(|v| *v as u64).collect(),
-        (None, Some(new)) => new.members.iter().map(|v| *v as u64).collect(),
-        (None, None) => HashSet::new(),
+/// Current members and the members just removed, nobody if membership did not change
+fn get_affected_chat_user_ids(payload: &ChatUpdated) -> HashSet<u64> {
+    if payload.op == "UPDATE" && payload.added.is_empty() && payload.removed.is_empty() {
+        return HashSet::new();
     }
+    payload
+        .chat
+        .members
+        .iter()
+        .chain(payload.removed.iter())
+        .map(|v| *v as u64)
+        .collect()
 }
@@ -11,7 +11,7 @@ use tokio::sync::broadcast;
 use tokio_stream::{wrappers::BroadcastStream, StreamExt};
 use tracing::info;
 
-use crate::{AppEvent, AppState};
+use crate::AppState;
 
 const CHANNEL_CAPACITY: usize = 256;
 
@@ -33,13 +33,7 @@ pub(crate) async fn sse_handler(
     info!("User {} subscribed", user_id);
 
     let stream = BroadcastStream::new(rx).filter_map(|v| v.ok()).map(|v| {
-        let name = match v.as_ref() {
-            AppEvent::NewChat(_) => "NewChat",
-            AppEvent::AddToChat(_) => "AddToChat",
-            AppEvent::RemoveFromChat(_) => "RemoveFromChat",
-            AppEvent::NewMessage(_) => "NewMessage",
-            AppEvent::ChatNameUpdated(_) => "ChatNameUpdated",
-        };
+        let name = v.name();
         let v = serde_json::to_string(&v).expect("Failed to serialize event");
         info!("Sending event {}: {:?}", name, v);
         Ok(Event::default().data(v).event(name))
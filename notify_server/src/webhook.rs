@@ -0,0 +1,284 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use tokio::time::{sleep, MissedTickBehavior};
+use tracing::{info, warn};
+
+use crate::{notify::EventTarget, AppEvent, AppState};
+
+/// deliveries taken from the queue at once
+const BATCH_SIZE: i64 = 32;
+const MAX_RETRY_SECS: u64 = 60 * 60 * 6;
+const MAX_ERROR_LEN: usize = 512;
+/// instances receive an event within this window of each other, an identical event received
+/// after it is a new one
+const DEDUPE_SECS: f64 = 10.0;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, FromRow)]
+struct Delivery {
+    id: i64,
+    event: String,
+    payload: serde_json::Value,
+    attempts: i32,
+    url: String,
+    secret: String,
+}
+
+/// Identifies a notification across instances, they all receive the same channel and payload
+pub(crate) fn event_key(channel: &str, payload: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(channel.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(payload.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Queue the event for the subscriptions of its chat made by a current member, or of its
+/// workspace if the chat is a public channel. Only the first instance to claim the event key
+/// queues it, in the same transaction, so every instance may listen without duplicating
+/// deliveries and an event is only missed if all of them miss it.
+pub(crate) async fn enqueue(state: &AppState, key: &str, event: &AppEvent) -> anyhow::Result<()> {
+    let Some(target) = event.target() else {
+        return Ok(());
+    };
+    let mut tx = state.pool.begin().await?;
+    let claimed: Option<(String,)> = sqlx::query_as(
+        r#"
+        INSERT INTO webhook_events (key) VALUES ($1)
+        ON CONFLICT (key) DO UPDATE SET received_at = now()
+        WHERE webhook_events.received_at < now() - make_interval(secs => $2)
+        RETURNING key
+        "#,
+    )
+    .bind(key)
+    .bind(DEDUPE_SECS)
+    .fetch_optional(&mut *tx)
+    .await?;
+    if claimed.is_none() {
+        return Ok(());
+    }
+    let sql = match target {
+        EventTarget::Chat(_) => {
+            r#"
+        INSERT INTO webhook_deliveries (subscription_id, event, payload)
+        SELECT s.id, $1, $2
+        FROM webhook_subscriptions s JOIN chats c ON c.id = $3
+        WHERE (cardinality(s.events) = 0 OR $1 = ANY(s.events))
+          AND ((s.chat_id = c.id AND EXISTS (
+              SELECT 1 FROM chat_members cm WHERE cm.chat_id = c.id AND cm.user_id = s.created_by))
+            OR (s.chat_id IS NULL AND s.ws_id = c.ws_id AND c.type = 'public_channel'))
+        "#
+        }
+        EventTarget::User(_) => {
+            r#"
+        INSERT INTO webhook_deliveries (subscription_id, event, payload)
+        SELECT s.id, $1, $2
+        FROM webhook_subscriptions s JOIN users u ON u.id = $3
+        WHERE (cardinality(s.events) = 0 OR $1 = ANY(s.events))
+          AND s.chat_id IS NULL AND s.ws_id = u.ws_id
+        "#
+        }
+    };
+    let id = match target {
+        EventTarget::Chat(id) | EventTarget::User(id) => id,
+    };
+    let ret = sqlx::query(sql)
+        .bind(event.name())
+        .bind(serde_json::to_value(event)?)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    if ret.rows_affected() > 0 {
+        state.webhook_queued.notify_one();
+    }
+    Ok(())
+}
+
+/// The url of a subscription if the outbound policy allows it, subscriptions made before the
+/// policy changed may not
+fn checked_url(state: &AppState, url: &str) -> Result<Url, String> {
+    let url = Url::parse(url).map_err(|e| e.to_string())?;
+    state.config.outbound.check_url(&url)?;
+    Ok(url)
+}
+
+/// Deliver queued events until the process ends, right after they are queued and on every poll
+/// for retries and redeliveries
+pub(crate) fn spawn_delivery(state: AppState) {
+    tokio::spawn(async move {
+        let poll = Duration::from_secs(state.config.webhooks.poll_secs.max(1));
+        loop {
+            match deliver_due(&state).await {
+                // a full batch may leave more behind
+                Ok(n) if n as i64 == BATCH_SIZE => continue,
+                Ok(_) => {}
+                Err(e) => warn!("Failed to deliver webhooks: {}", e),
+            }
+            tokio::select! {
+                _ = state.webhook_queued.notified() => {}
+                _ = sleep(poll) => {}
+            }
+        }
+    });
+}
+
+/// Prune delivered and dead deliveries older than `keep_days`, and event keys past the dedupe
+/// window, every hour until the process ends
+pub(crate) fn spawn_prune(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            match prune(&state).await {
+                Ok(0) => {}
+                Ok(n) => info!("Pruned {} webhook deliveries", n),
+                Err(e) => warn!("Failed to prune webhook deliveries: {}", e),
+            }
+        }
+    });
+}
+
+async fn prune(state: &AppState) -> anyhow::Result<u64> {
+    let ret = sqlx::query(
+        r#"
+    DELETE FROM webhook_deliveries
+    WHERE status <> 'pending' AND created_at < now() - make_interval(days => $1)
+    "#,
+    )
+    .bind(state.config.webhooks.keep_days as i32)
+    .execute(&state.pool)
+    .await?;
+    sqlx::query("DELETE FROM webhook_events WHERE received_at < now() - make_interval(secs => $1)")
+        .bind(DEDUPE_SECS)
+        .execute(&state.pool)
+        .await?;
+    Ok(ret.rows_affected())
+}
+
+async fn deliver_due(state: &AppState) -> anyhow::Result<usize> {
+    let config = &state.config.webhooks;
+    // leased until the attempt could have timed out, so no other instance takes them meanwhile
+    // and they come back if this one dies
+    let deliveries: Vec<Delivery> = sqlx::query_as(
+        r#"
+    UPDATE webhook_deliveries d SET next_attempt_at = now() + make_interval(secs => $2)
+    FROM webhook_subscriptions s
+    WHERE s.id = d.subscription_id AND d.id IN (
+        SELECT id FROM webhook_deliveries
+        WHERE status = 'pending' AND next_attempt_at <= now()
+        ORDER BY next_attempt_at
+        LIMIT $1
+        FOR UPDATE SKIP LOCKED)
+    RETURNING d.id, d.event, d.payload, d.attempts, s.url, s.secret
+    "#,
+    )
+    .bind(BATCH_SIZE)
+    .bind((config.timeout_secs * 2) as f64)
+    .fetch_all(&state.pool)
+    .await?;
+    let n = deliveries.len();
+    futures::future::join_all(deliveries.into_iter().map(|d| deliver(state, d))).await;
+    Ok(n)
+}
+
+async fn deliver(state: &AppState, delivery: Delivery) {
+    let config = &state.config.webhooks;
+    let body = delivery.payload.to_string();
+    let timestamp = Utc::now().timestamp().to_string();
+    let ret = match checked_url(state, &delivery.url) {
+        Ok(url) => state
+            .http
+            .post(url)
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .header("content-type", "application/json")
+            .header("x-webhook-id", delivery.id.to_string())
+            .header("x-webhook-event", &delivery.event)
+            .header("x-webhook-timestamp", &timestamp)
+            .header(
+                "x-webhook-signature",
+                format!("v1={}", sign(&delivery.secret, &timestamp, &body)),
+            )
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e),
+    };
+    let (status, error) = match ret {
+        Ok(res) if res.status().is_success() => (Some(res.status().as_u16()), None),
+        Ok(res) => (Some(res.status().as_u16()), Some(res.status().to_string())),
+        Err(e) => (None, Some(e)),
+    };
+
+    let attempts = delivery.attempts + 1;
+    let ret = match error {
+        None => {
+            info!("Delivered webhook {} to {}", delivery.id, delivery.url);
+            sqlx::query(
+                r#"
+            UPDATE webhook_deliveries
+            SET status = 'delivered', attempts = $2, last_status = $3, last_error = NULL,
+                delivered_at = now()
+            WHERE id = $1
+            "#,
+            )
+            .bind(delivery.id)
+            .bind(attempts)
+            .bind(status.map(|v| v as i32))
+            .execute(&state.pool)
+            .await
+        }
+        Some(error) => {
+            warn!(
+                "Webhook {} to {} failed on attempt {}: {}",
+                delivery.id, delivery.url, attempts, error
+            );
+            let error: String = error.chars().take(MAX_ERROR_LEN).collect();
+            let dead = attempts as u32 >= config.max_attempts;
+            sqlx::query(
+                r#"
+            UPDATE webhook_deliveries
+            SET status = CASE WHEN $3 THEN 'dead' ELSE 'pending' END::webhook_delivery_status,
+                attempts = $2, last_status = $4, last_error = $5,
+                next_attempt_at = now() + make_interval(secs => $6)
+            WHERE id = $1
+            "#,
+            )
+            .bind(delivery.id)
+            .bind(attempts)
+            .bind(dead)
+            .bind(status.map(|v| v as i32))
+            .bind(error)
+            .bind(retry_after(config.retry_base_secs, attempts as u32) as f64)
+            .execute(&state.pool)
+            .await
+        }
+    };
+    if let Err(e) = ret {
+        // the lease runs out and the delivery is attempted again
+        warn!("Failed to record webhook {}: {}", delivery.id, e);
+    }
+}
+
+/// Hex HMAC-SHA256 of `{timestamp}.{body}`, receivers recompute it with the subscription secret
+fn sign(secret: &str, timestamp: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac takes keys of any size");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// The base after the first failure, doubling with every further one
+fn retry_after(base_secs: u64, attempts: u32) -> u64 {
+    let exp = attempts.saturating_sub(1).min(20);
+    base_secs.saturating_mul(2u64.pow(exp)).min(MAX_RETRY_SECS)
+}